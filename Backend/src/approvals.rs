@@ -0,0 +1,244 @@
+//! Estimate approval requests sent to customers
+//!
+//! A request records the price and description quoted for a ticket and, once the
+//! customer answers through their signed link, the decision. Requests are kept in an
+//! [`ApprovalStore`]: S3 (`approvals/<id>.json` in STATE_BUCKET) when configured,
+//! otherwise process memory.
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+
+use async_trait::async_trait;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use serde::{Deserialize, Serialize};
+
+use crate::config::state_bucket;
+
+/// The customer's answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalDecision {
+    Approve,
+    Decline,
+}
+
+/// An estimate waiting for (or answered by) the customer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApprovalRequest {
+    pub id: String,
+    pub ticket_id: i64,
+    pub ticket_number: i64,
+    pub amount_cents: i64,
+    pub description: String,
+    pub requested_by: String,
+    pub created_at: String,
+    pub expires_at: i64,
+    #[serde(default)]
+    pub decision: Option<ApprovalDecision>,
+    #[serde(default)]
+    pub decided_at: Option<String>,
+    #[serde(default)]
+    pub customer_note: Option<String>,
+}
+
+/// Format cents as a price, e.g. `$149.00`
+pub fn format_amount(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    format!("{}${}.{:02}", sign, cents.abs() / 100, cents.abs() % 100)
+}
+
+/// Where approval requests are kept
+#[async_trait]
+pub trait ApprovalStore: Send + Sync {
+    async fn save(&self, request: &ApprovalRequest) -> Result<(), String>;
+    async fn get(&self, id: &str) -> Result<Option<ApprovalRequest>, String>;
+    /// Store `request` with its decision unless one was already recorded; `false` means
+    /// another answer got there first
+    async fn record_decision(&self, request: &ApprovalRequest) -> Result<bool, String>;
+    /// Make `decided` answerable again if its decision is still the one stored; used when the
+    /// ticket couldn't be updated, so the customer's retry isn't refused as already answered
+    async fn undo_decision(&self, decided: &ApprovalRequest) -> Result<(), String>;
+}
+
+/// `request` as it was before it was answered
+fn undecided(request: &ApprovalRequest) -> ApprovalRequest {
+    ApprovalRequest {
+        decision: None,
+        decided_at: None,
+        customer_note: None,
+        ..request.clone()
+    }
+}
+
+/// Whether `stored` still holds the decision recorded in `decided`
+fn same_decision(stored: &ApprovalRequest, decided: &ApprovalRequest) -> bool {
+    stored.decision == decided.decision && stored.decided_at == decided.decided_at
+}
+
+/// Approval requests kept in process memory
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryApprovalStore {
+    requests: Arc<Mutex<HashMap<String, ApprovalRequest>>>,
+}
+
+impl InMemoryApprovalStore {
+    /// Store shared by every request this instance handles
+    pub fn shared() -> Self {
+        static SHARED: LazyLock<InMemoryApprovalStore> = LazyLock::new(InMemoryApprovalStore::default);
+        SHARED.clone()
+    }
+}
+
+#[async_trait]
+impl ApprovalStore for InMemoryApprovalStore {
+    async fn save(&self, request: &ApprovalRequest) -> Result<(), String> {
+        self.requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(request.id.clone(), request.clone());
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<ApprovalRequest>, String> {
+        Ok(self.requests.lock().unwrap_or_else(|e| e.into_inner()).get(id).cloned())
+    }
+
+    async fn record_decision(&self, request: &ApprovalRequest) -> Result<bool, String> {
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        if requests.get(&request.id).is_some_and(|stored| stored.decision.is_some()) {
+            return Ok(false);
+        }
+        requests.insert(request.id.clone(), request.clone());
+        Ok(true)
+    }
+
+    async fn undo_decision(&self, decided: &ApprovalRequest) -> Result<(), String> {
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        if requests.get(&decided.id).is_some_and(|stored| same_decision(stored, decided)) {
+            requests.insert(decided.id.clone(), undecided(decided));
+        }
+        Ok(())
+    }
+}
+
+/// Approval requests stored as JSON objects in S3
+#[derive(Debug, Clone)]
+pub struct S3ApprovalStore {
+    client: S3Client,
+    bucket: String,
+}
+
+impl S3ApprovalStore {
+    pub fn new(client: S3Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+
+    fn key(id: &str) -> String {
+        format!("approvals/{}.json", id)
+    }
+
+    /// Read an approval along with the ETag of the version read
+    async fn load(&self, id: &str) -> Result<Option<(ApprovalRequest, Option<String>)>, String> {
+        let object = match self.client.get_object().bucket(&self.bucket).key(Self::key(id)).send().await {
+            Ok(object) => object,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+            Err(e) => return Err(format!("Failed to load approval {}: {:?}", id, e)),
+        };
+        let e_tag = object.e_tag.clone();
+        let bytes = object
+            .body
+            .collect()
+            .await
+            .map_err(|e| format!("Failed to read approval {}: {:?}", id, e))?
+            .into_bytes();
+        serde_json::from_slice(&bytes)
+            .map(|request| Some((request, e_tag)))
+            .map_err(|e| format!("Invalid approval {}: {}", id, e))
+    }
+}
+
+#[async_trait]
+impl ApprovalStore for S3ApprovalStore {
+    async fn save(&self, request: &ApprovalRequest) -> Result<(), String> {
+        let body = serde_json::to_vec(request).map_err(|e| e.to_string())?;
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(Self::key(&request.id))
+            .content_type("application/json")
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to store approval {}: {:?}", request.id, e))
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<ApprovalRequest>, String> {
+        Ok(self.load(id).await?.map(|(request, _)| request))
+    }
+
+    async fn record_decision(&self, request: &ApprovalRequest) -> Result<bool, String> {
+        let Some((stored, e_tag)) = self.load(&request.id).await? else {
+            return Err(format!("Approval {} no longer exists", request.id));
+        };
+        if stored.decision.is_some() {
+            return Ok(false);
+        }
+        let e_tag = e_tag.ok_or_else(|| format!("Approval {} has no ETag", request.id))?;
+
+        // Only replace the version we just read undecided; a concurrent answer changes the ETag
+        let body = serde_json::to_vec(request).map_err(|e| e.to_string())?;
+        let result = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(Self::key(&request.id))
+            .if_match(e_tag)
+            .content_type("application/json")
+            .body(ByteStream::from(body))
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if matches!(e.code(), Some("PreconditionFailed" | "ConditionalRequestConflict")) => Ok(false),
+            Err(e) => Err(format!("Failed to record decision for approval {}: {:?}", request.id, e)),
+        }
+    }
+
+    async fn undo_decision(&self, decided: &ApprovalRequest) -> Result<(), String> {
+        let Some((stored, Some(e_tag))) = self.load(&decided.id).await? else {
+            return Ok(());
+        };
+        if !same_decision(&stored, decided) {
+            return Ok(());
+        }
+
+        // Only replace the version holding our decision
+        let body = serde_json::to_vec(&undecided(&stored)).map_err(|e| e.to_string())?;
+        let result = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(Self::key(&decided.id))
+            .if_match(e_tag)
+            .content_type("application/json")
+            .body(ByteStream::from(body))
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) if matches!(e.code(), Some("PreconditionFailed" | "ConditionalRequestConflict")) => Ok(()),
+            Err(e) => Err(format!("Failed to undo decision for approval {}: {:?}", decided.id, e)),
+        }
+    }
+}
+
+/// Pick the approval store for this deployment
+pub fn approval_store_from_env(s3_client: &S3Client) -> Box<dyn ApprovalStore> {
+    match state_bucket() {
+        Some(bucket) => Box::new(S3ApprovalStore::new(s3_client.clone(), bucket)),
+        _ => Box::new(InMemoryApprovalStore::shared()),
+    }
+}
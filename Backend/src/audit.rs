@@ -0,0 +1,125 @@
+//! Audit log of changes the backend makes on a user's behalf
+//!
+//! Entries go to an [`AuditStore`]. With AUDIT_BUCKET (or STATE_BUCKET) set they are
+//! written to S3 as one JSON object each under `audit/<date>/`; otherwise they are kept
+//! in memory and echoed to the logs, which is what tests and local runs use.
+
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::state_bucket;
+
+/// One recorded change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: String,
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub details: Value,
+}
+
+impl AuditEntry {
+    /// Create an entry timestamped now
+    pub fn new(actor: &str, action: &str, target: &str, details: Value) -> Self {
+        Self {
+            at: Utc::now().to_rfc3339(),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            target: target.to_string(),
+            details,
+        }
+    }
+}
+
+/// Destination for audit entries
+#[async_trait]
+pub trait AuditStore: Send + Sync {
+    /// Persist an entry
+    async fn record(&self, entry: AuditEntry) -> Result<(), String>;
+}
+
+/// Audit store kept in process memory
+#[derive(Debug, Default)]
+pub struct InMemoryAuditStore {
+    entries: Mutex<Vec<AuditEntry>>,
+}
+
+impl InMemoryAuditStore {
+    /// Entries recorded so far
+    #[cfg(test)]
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+#[async_trait]
+impl AuditStore for InMemoryAuditStore {
+    async fn record(&self, entry: AuditEntry) -> Result<(), String> {
+        eprintln!(
+            "[audit] {} {} {} {}",
+            entry.actor, entry.action, entry.target, entry.details
+        );
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).push(entry);
+        Ok(())
+    }
+}
+
+/// Audit store writing one JSON object per entry to S3
+#[derive(Debug, Clone)]
+pub struct S3AuditStore {
+    client: S3Client,
+    bucket: String,
+}
+
+impl S3AuditStore {
+    pub fn new(client: S3Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+}
+
+#[async_trait]
+impl AuditStore for S3AuditStore {
+    async fn record(&self, entry: AuditEntry) -> Result<(), String> {
+        use rand::Rng;
+        let date = entry.at.get(..10).unwrap_or("undated");
+        let key = format!(
+            "audit/{}/{}-{:08x}.json",
+            date,
+            Utc::now().timestamp_millis(),
+            rand::rng().random::<u32>()
+        );
+        let body = serde_json::to_vec(&entry).map_err(|e| e.to_string())?;
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .content_type("application/json")
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to write audit entry {}: {:?}", key, e))
+    }
+}
+
+/// Pick the audit store for this deployment
+pub fn audit_store_from_env(s3_client: &S3Client) -> Box<dyn AuditStore> {
+    // Checked against the public bucket at cold start, like STATE_BUCKET
+    let bucket = std::env::var("AUDIT_BUCKET")
+        .ok()
+        .map(|b| b.trim().to_string())
+        .filter(|b| !b.is_empty())
+        .or_else(state_bucket);
+    match bucket {
+        Some(bucket) => Box::new(S3AuditStore::new(s3_client.clone(), bucket)),
+        _ => Box::new(InMemoryAuditStore::default()),
+    }
+}
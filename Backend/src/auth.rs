@@ -26,6 +26,59 @@ pub fn get_user_groups_from_event(event: &Request) -> Vec<String> {
     vec![]
 }
 
+/// Identity of the signed-in user, from the Cognito authorizer claims
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserIdentity {
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub given_name: Option<String>,
+}
+
+impl UserIdentity {
+    /// Author label for comments: "Name (email)" when both are known
+    pub fn display_name(&self) -> String {
+        match (&self.given_name, &self.email) {
+            (Some(name), Some(email)) => format!("{} ({})", name, email),
+            (Some(name), None) => name.clone(),
+            (None, Some(email)) => email.clone(),
+            (None, None) => self.username.clone().unwrap_or_else(|| "Unknown user".to_string()),
+        }
+    }
+
+    /// Stable identifier for audit entries
+    pub fn actor(&self) -> String {
+        self.email
+            .clone()
+            .or_else(|| self.username.clone())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+}
+
+/// Extract the caller's identity from the Cognito authorizer context
+pub fn get_user_identity_from_event(event: &Request) -> UserIdentity {
+    let Some(claims) = event
+        .request_context_ref()
+        .and_then(|context| context.authorizer())
+        .and_then(|authorizer| authorizer.fields.get("claims"))
+    else {
+        return UserIdentity::default();
+    };
+
+    let claim = |name: &str| {
+        claims
+            .get(name)
+            .and_then(|v| v.as_str())
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+
+    UserIdentity {
+        username: claim("cognito:username"),
+        email: claim("email"),
+        given_name: claim("custom:given_name").or_else(|| claim("given_name")),
+    }
+}
+
 /// Check if user can invite other users
 pub fn can_invite_users(user_groups: &[String]) -> bool {
     let allowed_groups = [
@@ -46,6 +99,51 @@ pub fn can_manage_users(user_groups: &[String]) -> bool {
         .any(|group| allowed_groups.contains(&group.as_str()))
 }
 
+/// Check if user can post comments the customer will see
+pub fn can_message_customers(user_groups: &[String]) -> bool {
+    let allowed_groups = [
+        "TrueTickets-Cacell-ApplicationAdmin",
+        "TrueTickets-Cacell-Owner",
+        "TrueTickets-Cacell-Manager",
+    ];
+    user_groups
+        .iter()
+        .any(|group| allowed_groups.contains(&group.as_str()))
+}
+
+/// Check if user can use owner-level tools (reports, exports, customer merges)
+pub fn can_use_admin_tools(user_groups: &[String]) -> bool {
+    let allowed_groups = ["TrueTickets-Cacell-ApplicationAdmin", "TrueTickets-Cacell-Owner"];
+    user_groups
+        .iter()
+        .any(|group| allowed_groups.contains(&group.as_str()))
+}
+
+/// Check if user can make destructive or bulk changes (proxy DELETE/PATCH, bulk edits, SMS flush)
+pub fn can_modify_records(user_groups: &[String]) -> bool {
+    let allowed_groups = [
+        "TrueTickets-Cacell-ApplicationAdmin",
+        "TrueTickets-Cacell-Owner",
+        "TrueTickets-Cacell-Manager",
+    ];
+    user_groups
+        .iter()
+        .any(|group| allowed_groups.contains(&group.as_str()))
+}
+
+/// Check if user holds any TrueTickets role, as everyday writes (intake, customers, share links) require
+pub fn is_staff(user_groups: &[String]) -> bool {
+    let allowed_groups = [
+        "TrueTickets-Cacell-ApplicationAdmin",
+        "TrueTickets-Cacell-Owner",
+        "TrueTickets-Cacell-Manager",
+        "TrueTickets-Cacell-Employee",
+    ];
+    user_groups
+        .iter()
+        .any(|group| allowed_groups.contains(&group.as_str()))
+}
+
 /// Generate a secure temporary password that meets Cognito requirements
 pub fn generate_temp_password() -> String {
     use rand::Rng;
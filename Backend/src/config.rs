@@ -0,0 +1,97 @@
+//! Runtime configuration read from environment variables
+
+/// Accept-Language sent to RepairShopr unless REPAIRSHOPR_ACCEPT_LANGUAGE is set
+const DEFAULT_ACCEPT_LANGUAGE: &str = "en-US,en;q=0.9";
+
+/// Headers that identify us to the RepairShopr API
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamConfig {
+    pub user_agent: String,
+    pub accept_language: String,
+}
+
+impl UpstreamConfig {
+    /// Load the upstream configuration for the tenant behind `target_url`
+    pub fn from_env(target_url: &str) -> Self {
+        Self::resolve(target_url, |key| std::env::var(key).ok())
+    }
+
+    /// Resolve the configuration using `lookup` for variable access.
+    ///
+    /// The User-Agent comes from REPAIRSHOPR_USER_AGENT_<TENANT> (e.g.
+    /// REPAIRSHOPR_USER_AGENT_CACELL), then REPAIRSHOPR_USER_AGENT, then defaults to
+    /// `TrueTickets/<version>`.
+    pub fn resolve(target_url: &str, lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let non_empty = |key: &str| lookup(key).map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+
+        let tenant_override = tenant_from_target_url(target_url)
+            .and_then(|tenant| non_empty(&format!("REPAIRSHOPR_USER_AGENT_{}", tenant.to_uppercase())));
+
+        let user_agent = tenant_override
+            .or_else(|| non_empty("REPAIRSHOPR_USER_AGENT"))
+            .unwrap_or_else(default_user_agent);
+
+        let accept_language = non_empty("REPAIRSHOPR_ACCEPT_LANGUAGE")
+            .unwrap_or_else(|| DEFAULT_ACCEPT_LANGUAGE.to_string());
+
+        Self {
+            user_agent,
+            accept_language,
+        }
+    }
+}
+
+/// Identifying User-Agent used when no override is configured
+pub fn default_user_agent() -> String {
+    format!("TrueTickets/{}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Extract the tenant (RepairShopr subdomain) from the API base URL
+pub fn tenant_from_target_url(target_url: &str) -> Option<String> {
+    let parsed = url::Url::parse(target_url).ok()?;
+    let host = parsed.domain()?;
+    let (subdomain, rest) = host.split_once('.')?;
+    if rest.is_empty() || subdomain.is_empty() {
+        return None;
+    }
+    Some(subdomain.to_string())
+}
+
+/// Private bucket holding internal state: audit entries, share revocations, approvals, SMS
+/// opt-outs, webhook deliveries, push connections and exports.
+///
+/// This is never S3_BUCKET_NAME, whose attachments are served by public URL.
+pub fn state_bucket() -> Option<String> {
+    std::env::var("STATE_BUCKET")
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Check at cold start that deployed functions keep their state in a private bucket.
+///
+/// Outside Lambda (no AWS_LAMBDA_FUNCTION_NAME) the in-memory stores are fine, so an unset
+/// STATE_BUCKET is accepted there. AUDIT_BUCKET, which overrides where audit entries go,
+/// must not be the public bucket either.
+pub fn check_state_bucket(lookup: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+    let non_empty = |key: &str| lookup(key).map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+    if let Some(bucket) = non_empty("AUDIT_BUCKET")
+        && non_empty("S3_BUCKET_NAME").as_deref() == Some(bucket.as_str())
+    {
+        return Err(format!(
+            "AUDIT_BUCKET ({}) must not be the public attachments bucket S3_BUCKET_NAME",
+            bucket
+        ));
+    }
+    match non_empty("STATE_BUCKET") {
+        Some(bucket) if non_empty("S3_BUCKET_NAME").as_deref() == Some(bucket.as_str()) => Err(format!(
+            "STATE_BUCKET ({}) must not be the public attachments bucket S3_BUCKET_NAME",
+            bucket
+        )),
+        Some(_) => Ok(()),
+        None if non_empty("AWS_LAMBDA_FUNCTION_NAME").is_some() => {
+            Err("STATE_BUCKET must be set to a private bucket for internal state".to_string())
+        }
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,139 @@
+//! Normalization helpers for customer contact details
+
+/// Keep only the digits of a phone number ("(555) 123-4567" -> "5551234567")
+pub fn phone_digits(input: &str) -> String {
+    input.chars().filter(|c| c.is_ascii_digit()).collect()
+}
+
+/// National significant number for comparing North American phone numbers, dropping a
+/// leading country code 1 from 11-digit numbers
+pub fn national_phone_digits(input: &str) -> String {
+    let digits = phone_digits(input);
+    match digits.strip_prefix('1') {
+        Some(rest) if digits.len() == 11 => rest.to_string(),
+        _ => digits,
+    }
+}
+
+/// Whether the input is plausibly a phone number: only phone punctuation and 7-15 digits
+pub fn looks_like_phone(input: &str) -> bool {
+    let only_phone_chars = input
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, ' ' | '-' | '.' | '(' | ')' | '+'));
+    let digit_count = phone_digits(input).len();
+    only_phone_chars && (7..=15).contains(&digit_count)
+}
+
+/// Whether the input is plausibly an email address
+pub fn looks_like_email(input: &str) -> bool {
+    match input.trim().split_once('@') {
+        Some((local, domain)) => !local.is_empty() && domain.contains('.') && !domain.contains('@'),
+        None => false,
+    }
+}
+
+/// Region used for phone numbers entered without a country code (DEFAULT_PHONE_REGION)
+pub fn default_phone_region() -> String {
+    std::env::var("DEFAULT_PHONE_REGION")
+        .ok()
+        .map(|v| v.trim().to_uppercase())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "US".to_string())
+}
+
+/// Country calling code for an ISO 3166 region we expect to see at the counter
+pub fn country_calling_code(region: &str) -> Option<&'static str> {
+    let code = match region.to_uppercase().as_str() {
+        "US" | "CA" | "PR" => "1",
+        "MX" => "52",
+        "GB" | "UK" => "44",
+        "IE" => "353",
+        "AU" => "61",
+        "NZ" => "64",
+        "DE" => "49",
+        "FR" => "33",
+        "ES" => "34",
+        "IT" => "39",
+        "IN" => "91",
+        "PH" => "63",
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// Normalize a phone number to E.164 (`+15551234567`).
+///
+/// Numbers starting with `+` or `00` are taken as international; anything else is read
+/// as a national number in `region`.
+pub fn normalize_phone(input: &str, region: &str) -> Result<String, String> {
+    let trimmed = input.trim();
+    if !trimmed
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, ' ' | '-' | '.' | '(' | ')' | '+'))
+    {
+        return Err("Phone numbers may only contain digits and ()+-. separators".to_string());
+    }
+    let digits = phone_digits(trimmed);
+
+    let international = if trimmed.starts_with('+') {
+        digits
+    } else if let Some(rest) = digits.strip_prefix("00") {
+        rest.to_string()
+    } else {
+        let code = country_calling_code(region)
+            .ok_or_else(|| format!("Unsupported default phone region '{}'", region))?;
+        if code == "1" {
+            match digits.len() {
+                10 => format!("1{}", digits),
+                11 if digits.starts_with('1') => digits,
+                _ => return Err("North American numbers need 10 digits".to_string()),
+            }
+        } else {
+            format!("{}{}", code, digits.trim_start_matches('0'))
+        }
+    };
+
+    if !(8..=15).contains(&international.len()) {
+        return Err("Phone number has the wrong number of digits".to_string());
+    }
+    if let Some(national) = international.strip_prefix('1')
+        && (national.len() != 10 || national.starts_with(['0', '1']))
+    {
+        return Err("North American numbers need a valid 10-digit number".to_string());
+    }
+
+    Ok(format!("+{}", international))
+}
+
+/// Trim and lowercase an email address
+pub fn normalize_email(input: &str) -> String {
+    input.trim().to_lowercase()
+}
+
+/// Collapse whitespace in a name and fix the case of words typed all-lower or ALL-UPPER
+/// ("john SMITH" becomes "John Smith"; mixed-case words like "McDonald" are left alone)
+pub fn normalize_name(input: &str) -> String {
+    input
+        .split_whitespace()
+        .map(|word| {
+            let has_lower = word.chars().any(char::is_lowercase);
+            let has_upper = word.chars().any(char::is_uppercase);
+            if has_lower && has_upper {
+                return word.to_string();
+            }
+
+            let mut result = String::with_capacity(word.len());
+            let mut capitalize = true;
+            for c in word.chars() {
+                if capitalize {
+                    result.extend(c.to_uppercase());
+                } else {
+                    result.extend(c.to_lowercase());
+                }
+                capitalize = matches!(c, '-' | '\'');
+            }
+            result
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,108 @@
+//! Mapping of internal failures to sanitized client-facing errors
+//!
+//! Full error detail (upstream URLs, SDK messages, configuration names) is logged
+//! server-side against the request id; clients only ever see a stable code, a fixed
+//! message and the request id to quote when reporting a problem.
+
+use lambda_http::{Body, Request, RequestExt, Response};
+use serde_json::json;
+
+use crate::http::get_cors_origin_header;
+
+/// An internal failure with a stable client-facing code and message
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: u16,
+    pub code: &'static str,
+    pub message: &'static str,
+    detail: String,
+}
+
+impl ApiError {
+    /// Create an error; `detail` is only ever logged, never returned to the client
+    pub fn new(status: u16, code: &'static str, message: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message,
+            detail: detail.into(),
+        }
+    }
+
+    /// Missing or invalid server configuration (environment variables, bucket names)
+    pub fn configuration(detail: impl Into<String>) -> Self {
+        Self::new(500, "CONFIGURATION_ERROR", "The server is not configured correctly", detail)
+    }
+
+    /// RepairShopr could not be reached or the transport failed
+    pub fn upstream_unavailable(detail: impl Into<String>) -> Self {
+        Self::new(502, "UPSTREAM_UNAVAILABLE", "RepairShopr could not be reached", detail)
+    }
+
+    /// Attachment storage (S3) failed
+    pub fn storage(detail: impl Into<String>) -> Self {
+        Self::new(500, "STORAGE_ERROR", "The file could not be stored", detail)
+    }
+
+    /// Map a Cognito SDK error onto a stable code, keeping the raw error for the logs
+    pub fn from_cognito(action: &str, error: &impl std::fmt::Debug) -> Self {
+        let raw = format!("{:?}", error);
+        let detail = format!("Cognito {} failed: {}", action, raw);
+
+        if raw.contains("UsernameExistsException") {
+            Self::new(409, "USER_EXISTS", "A user with that email already exists", detail)
+        } else if raw.contains("UserNotFoundException") {
+            Self::new(404, "USER_NOT_FOUND", "That user does not exist", detail)
+        } else if raw.contains("ResourceNotFoundException") {
+            Self::new(404, "GROUP_NOT_FOUND", "That group does not exist", detail)
+        } else if raw.contains("InvalidParameterException") || raw.contains("InvalidPasswordException") {
+            Self::new(400, "INVALID_USER_DETAILS", "The user details were rejected", detail)
+        } else if raw.contains("AccessDeniedException") || raw.contains("NotAuthorizedException") {
+            Self::new(500, "IDENTITY_ACCESS_DENIED", "The server is not permitted to manage users", detail)
+        } else if raw.contains("TooManyRequestsException") || raw.contains("LimitExceededException") {
+            Self::new(429, "IDENTITY_THROTTLED", "Too many user management requests, try again shortly", detail)
+        } else {
+            Self::new(502, "IDENTITY_UNAVAILABLE", "User management is temporarily unavailable", detail)
+        }
+    }
+
+    /// Internal detail recorded for this error
+    pub fn detail(&self) -> &str {
+        &self.detail
+    }
+
+    /// Log the full detail and build the sanitized response
+    pub fn into_response(self, request_id: &str) -> Response<Body> {
+        eprintln!(
+            "[request {}] {} ({}): {}",
+            request_id, self.code, self.status, self.detail
+        );
+
+        let body = json!({
+            "error": self.message,
+            "code": self.code,
+            "request_id": request_id,
+        });
+
+        let (key, value) = get_cors_origin_header();
+        Response::builder()
+            .status(self.status)
+            .header(key, value)
+            .header("Content-Type", "application/json")
+            .header("X-Request-Id", request_id)
+            .body(body.to_string().into())
+            .expect("Couldn't create error response")
+    }
+}
+
+/// Request id used to correlate client-visible errors with server logs
+pub fn request_id(event: &Request) -> String {
+    if let Some(context) = event.lambda_context_ref()
+        && !context.request_id.is_empty()
+    {
+        return context.request_id.clone();
+    }
+
+    use rand::Rng;
+    format!("local-{:016x}", rand::rng().random::<u64>())
+}
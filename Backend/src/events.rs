@@ -0,0 +1,129 @@
+//! Ticket events raised by backend writes, and the consumers they fan out to
+//!
+//! Endpoints that change tickets publish a [`TicketEvent`] on an [`EventBus`] once the
+//! change has gone through. Consumers (notifiers, for instance) run inline before the
+//! response is sent, since Lambda freezes the instance as soon as it returns. A failing
+//! consumer is logged and never fails the request that caused the event, except for
+//! callers that can be retried (webhooks), which use [`EventBus::publish_each`] to rerun
+//! only the consumers that failed.
+
+use async_trait::async_trait;
+use aws_sdk_s3::Client as S3Client;
+
+use crate::notifications::email::EmailNotifier;
+use crate::notifications::sms::SmsNotifier;
+use crate::push::Broadcaster;
+use crate::repairshopr::{RepairShoprClient, Ticket};
+
+/// Something that happened to a ticket
+#[derive(Debug, Clone, PartialEq)]
+pub enum TicketEvent {
+    /// The ticket's RepairShopr status was set; `previous_status` is `None` when unknown
+    StatusChanged {
+        ticket: Ticket,
+        previous_status: Option<String>,
+    },
+    /// A ticket was opened for a customer
+    TicketCreated { ticket: Ticket },
+    /// An estimate was sent to the customer for approval at `url`
+    EstimateReady {
+        ticket: Ticket,
+        amount_cents: i64,
+        description: String,
+        url: String,
+    },
+    /// Anything else about the ticket changed (an edit, comment or attachment)
+    Changed { ticket_id: i64 },
+}
+
+impl TicketEvent {
+    /// The ticket the event is about, when the event carries the full record
+    pub fn ticket(&self) -> Option<&Ticket> {
+        match self {
+            TicketEvent::StatusChanged { ticket, .. }
+            | TicketEvent::TicketCreated { ticket }
+            | TicketEvent::EstimateReady { ticket, .. } => Some(ticket),
+            TicketEvent::Changed { .. } => None,
+        }
+    }
+
+    pub fn ticket_id(&self) -> i64 {
+        match self {
+            TicketEvent::StatusChanged { ticket, .. }
+            | TicketEvent::TicketCreated { ticket }
+            | TicketEvent::EstimateReady { ticket, .. } => ticket.id,
+            TicketEvent::Changed { ticket_id } => *ticket_id,
+        }
+    }
+}
+
+/// A consumer of ticket events
+#[async_trait]
+pub trait EventHandler: Send + Sync {
+    /// Short name used in logs
+    fn name(&self) -> &'static str;
+
+    async fn handle(&self, event: &TicketEvent) -> Result<(), String>;
+}
+
+/// Delivers each event to every registered handler, in registration order
+#[derive(Default)]
+pub struct EventBus {
+    handlers: Vec<Box<dyn EventHandler>>,
+}
+
+impl EventBus {
+    /// Register a handler
+    pub fn with(mut self, handler: impl EventHandler + 'static) -> Self {
+        self.handlers.push(Box::new(handler));
+        self
+    }
+
+    /// Deliver an event, logging (not propagating) handler failures
+    pub async fn publish(&self, event: &TicketEvent) {
+        if let Err(failures) = self.try_publish(event).await {
+            for (name, e) in failures {
+                eprintln!("Event handler '{}' failed for ticket {}: {}", name, event.ticket_id(), e);
+            }
+        }
+    }
+
+    /// Names of the registered handlers, in registration order
+    pub fn handler_names(&self) -> Vec<&'static str> {
+        self.handlers.iter().map(|handler| handler.name()).collect()
+    }
+
+    /// Deliver an event to every handler, returning each failed handler's name and error
+    pub async fn try_publish(&self, event: &TicketEvent) -> Result<(), Vec<(&'static str, String)>> {
+        let failures: Vec<(&'static str, String)> = self
+            .publish_each(event, &[])
+            .await
+            .into_iter()
+            .filter_map(|(name, result)| result.err().map(|e| (name, e)))
+            .collect();
+        if failures.is_empty() { Ok(()) } else { Err(failures) }
+    }
+
+    /// Deliver an event to every handler not named in `skip`, returning each one's result
+    pub async fn publish_each(&self, event: &TicketEvent, skip: &[&str]) -> Vec<(&'static str, Result<(), String>)> {
+        let mut results = vec![];
+        for handler in &self.handlers {
+            if !skip.contains(&handler.name()) {
+                results.push((handler.name(), handler.handle(event).await));
+            }
+        }
+        results
+    }
+}
+
+/// Event bus with the consumers configured for this deployment: customer notifications,
+/// plus the push channel when a WebSocket API is configured
+pub fn event_bus_from_env(rs_client: &RepairShoprClient, s3_client: &S3Client) -> EventBus {
+    let bus = EventBus::default()
+        .with(SmsNotifier::from_env(rs_client.clone(), s3_client))
+        .with(EmailNotifier::from_env(rs_client.clone(), s3_client));
+    match Broadcaster::from_env(s3_client) {
+        Some(broadcaster) => bus.with(broadcaster),
+        None => bus,
+    }
+}
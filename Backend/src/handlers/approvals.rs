@@ -0,0 +1,377 @@
+//! Estimate approval flow: staff send a price, the customer approves or declines it
+//! through a signed link, and the ticket moves on accordingly
+
+use chrono::{DateTime, Utc};
+use lambda_http::{Body, Request, RequestExt, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::board::invalidate_board_cache;
+use super::public_status::{authorize_link, no_store_response, LinkRejection, ShareLinkCheck};
+use super::share_links::share_url;
+use super::tickets::parse_ticket_id;
+use crate::approvals::{format_amount, ApprovalDecision, ApprovalRequest, ApprovalStore};
+use crate::audit::{AuditEntry, AuditStore};
+use crate::auth::UserIdentity;
+use crate::errors::ApiError;
+use crate::events::{EventBus, TicketEvent};
+use crate::http::{parse_json_body, success_response};
+use crate::rate_limit::RateLimiter;
+use crate::repairshopr::{NewComment, RepairShoprClient, TicketUpdate};
+use crate::share::{new_link_id, sign_token, ShareClaims, ShareScope};
+use crate::status::status_map;
+
+/// Display status a ticket waits in while the customer decides
+pub const AWAITING_APPROVAL_STATUS: &str = "Approval Needed";
+/// Display status after the customer approves
+pub const APPROVED_STATUS: &str = "In Progress";
+/// Display status after the customer declines
+pub const DECLINED_STATUS: &str = "Waiting (Other)";
+
+/// How long a customer has to answer
+const APPROVAL_EXPIRY_HOURS: i64 = 7 * 24;
+
+/// Largest estimate that can be sent for approval, in cents
+const MAX_AMOUNT_CENTS: i64 = 10_000_000;
+
+/// Where the approval handlers record what happened
+pub struct ApprovalContext<'a> {
+    pub approvals: &'a dyn ApprovalStore,
+    pub audit: &'a dyn AuditStore,
+    pub events: &'a EventBus,
+}
+
+/// Request body for creating an approval request
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CreateApproval {
+    /// Price in the shop's currency, e.g. `149.99`
+    pub price: Option<f64>,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub expires_in_hours: Option<i64>,
+}
+
+/// What the customer sees on the approval page
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ApprovalView {
+    pub ticket_number: i64,
+    pub amount: String,
+    pub amount_cents: i64,
+    pub description: String,
+    pub expires_at: Option<String>,
+    pub decision: Option<ApprovalDecision>,
+    pub decided_at: Option<String>,
+}
+
+impl ApprovalView {
+    pub fn from_request(request: &ApprovalRequest) -> Self {
+        Self {
+            ticket_number: request.ticket_number,
+            amount: format_amount(request.amount_cents),
+            amount_cents: request.amount_cents,
+            description: request.description.clone(),
+            expires_at: DateTime::<Utc>::from_timestamp(request.expires_at, 0).map(|t| t.to_rfc3339()),
+            decision: request.decision,
+            decided_at: request.decided_at.clone(),
+        }
+    }
+}
+
+/// Customer's answer as submitted
+#[derive(Debug, Clone, Deserialize)]
+pub struct DecisionRequest {
+    pub token: String,
+    pub decision: ApprovalDecision,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+fn invalid_approval(message: &'static str, detail: String) -> ApiError {
+    ApiError::new(400, "INVALID_APPROVAL", message, detail)
+}
+
+fn store_error(error: String) -> ApiError {
+    ApiError::new(500, "APPROVAL_STORE_ERROR", "The approval could not be saved or loaded", error)
+}
+
+/// Validate price and description, returning the amount in cents and the trimmed description
+pub fn validate_approval(request: &CreateApproval) -> Result<(i64, String), ApiError> {
+    let price = request
+        .price
+        .ok_or_else(|| invalid_approval("A price is required", "Missing 'price'".to_string()))?;
+    let amount_cents = (price * 100.0).round() as i64;
+    if !price.is_finite() || amount_cents <= 0 || amount_cents > MAX_AMOUNT_CENTS {
+        return Err(invalid_approval("The price must be a positive amount", format!("price was {}", price)));
+    }
+
+    let description = request
+        .description
+        .as_deref()
+        .map(|d| d.trim().to_string())
+        .unwrap_or_default();
+    if description.is_empty() || description.chars().count() > 500 {
+        return Err(invalid_approval(
+            "Describe the repair in 1 to 500 characters",
+            format!("description had {} characters", description.chars().count()),
+        ));
+    }
+    Ok((amount_cents, description))
+}
+
+/// Handle creating an approval request: stores it, notes it on the ticket, moves the
+/// ticket to "Approval Needed" and returns the customer's signed link
+pub async fn handle_create_approval(
+    event: &Request,
+    ticket_id: &str,
+    rs_client: &RepairShoprClient,
+    context: &ApprovalContext<'_>,
+    actor: &UserIdentity,
+    secret: &[u8],
+) -> Result<Response<Body>, ApiError> {
+    let map = status_map();
+    let ticket_id = parse_ticket_id(ticket_id)?;
+    let body = parse_json_body::<CreateApproval>(event)?;
+    let (amount_cents, description) = validate_approval(&body)?;
+    let hours = body.expires_in_hours.unwrap_or(APPROVAL_EXPIRY_HOURS);
+    if !(1..=30 * 24).contains(&hours) {
+        return Err(invalid_approval(
+            "Approvals can last between 1 hour and 30 days",
+            format!("expires_in_hours was {}", hours),
+        ));
+    }
+
+    let ticket = rs_client.get_ticket(ticket_id).await?;
+    let now = Utc::now();
+    let claims = ShareClaims {
+        link_id: new_link_id(),
+        ticket_id: ticket.id,
+        expires_at: now.timestamp() + hours * 3600,
+        scopes: vec![ShareScope::Status, ShareScope::Approval],
+    };
+    let approval = ApprovalRequest {
+        id: claims.link_id.clone(),
+        ticket_id: ticket.id,
+        ticket_number: ticket.number,
+        amount_cents,
+        description,
+        requested_by: actor.actor(),
+        created_at: now.to_rfc3339(),
+        expires_at: claims.expires_at,
+        decision: None,
+        decided_at: None,
+        customer_note: None,
+    };
+    context.approvals.save(&approval).await.map_err(store_error)?;
+
+    let comment = NewComment {
+        subject: "Approval requested".to_string(),
+        body: format!("Sent estimate for approval: {} - {}", format_amount(amount_cents), approval.description),
+        tech: Some(actor.display_name()),
+        hidden: true,
+        do_not_email: true,
+        sms_body: None,
+    };
+    rs_client.add_comment(ticket.id, &comment).await?;
+
+    let awaiting = map.to_repairshopr(AWAITING_APPROVAL_STATUS);
+    if ticket.status != awaiting {
+        let update = TicketUpdate {
+            status: Some(awaiting),
+            ..Default::default()
+        };
+        let updated = rs_client.update_ticket(ticket.id, &update).await?;
+        invalidate_board_cache();
+        let event = TicketEvent::StatusChanged {
+            ticket: updated,
+            previous_status: Some(ticket.status.clone()),
+        };
+        context.events.publish(&event).await;
+    }
+
+    let entry = AuditEntry::new(
+        &actor.actor(),
+        "approval.create",
+        &format!("ticket:{}", ticket.id),
+        json!({ "approval_id": approval.id, "amount_cents": amount_cents }),
+    );
+    if let Err(e) = context.audit.record(entry).await {
+        eprintln!("Failed to record audit entry for approval: {}", e);
+    }
+
+    let token = sign_token(&claims, secret);
+    let base_url = std::env::var("SHARE_LINK_BASE_URL").ok();
+    let url = share_url(base_url.as_deref(), &token);
+    let event = TicketEvent::EstimateReady {
+        ticket,
+        amount_cents,
+        description: approval.description.clone(),
+        url: url.clone(),
+    };
+    context.events.publish(&event).await;
+
+    let body = json!({
+        "approval": approval,
+        "token": token,
+        "url": url,
+    });
+    Ok(success_response(201, body.to_string()))
+}
+
+/// Load the approval request a link points at
+async fn load_approval(
+    event: &Request,
+    token: &str,
+    limiter: &RateLimiter,
+    check: &ShareLinkCheck<'_>,
+    approvals: &dyn ApprovalStore,
+) -> Result<ApprovalRequest, LinkRejection> {
+    let claims = authorize_link(event, token, limiter, check).await?;
+    if !claims.allows(ShareScope::Approval) {
+        let error = ApiError::new(
+            403,
+            "FORBIDDEN",
+            "This link cannot be used for approvals",
+            "Token lacks approval scope".to_string(),
+        );
+        return Err(error.into());
+    }
+    match approvals.get(&claims.link_id).await.map_err(store_error)? {
+        Some(approval) if approval.ticket_id == claims.ticket_id => Ok(approval),
+        _ => Err(ApiError::new(
+            404,
+            "NOT_FOUND",
+            "This approval request no longer exists",
+            format!("No approval {}", claims.link_id),
+        )
+        .into()),
+    }
+}
+
+/// Handle `GET /public/approvals?token=...`
+pub async fn view_approval(
+    event: &Request,
+    limiter: &RateLimiter,
+    check: &ShareLinkCheck<'_>,
+    approvals: &dyn ApprovalStore,
+    request_id: &str,
+) -> Response<Body> {
+    let token = event.query_string_parameters().first("token").unwrap_or_default().to_string();
+    match load_approval(event, &token, limiter, check, approvals).await {
+        Ok(approval) => no_store_response(json!({ "approval": ApprovalView::from_request(&approval) }).to_string()),
+        Err(rejection) => rejection.into_response(request_id),
+    }
+}
+
+/// 409 telling the customer their answer can no longer be taken
+fn already_decided(approval: &ApprovalRequest, code: &str, message: &str) -> Response<Body> {
+    let body = json!({
+        "error": message,
+        "code": code,
+        "approval": ApprovalView::from_request(approval),
+    });
+    success_response(409, body.to_string())
+}
+
+/// Handle `POST /public/approvals`: record the customer's decision, note it on the ticket
+/// and move the ticket to "In Progress" (approved) or "Waiting (Other)" (declined).
+///
+/// The decision is stored first, with a conditional write, so two answers racing each
+/// other can't both move the ticket.
+pub async fn decide_approval(
+    event: &Request,
+    rs_client: &RepairShoprClient,
+    limiter: &RateLimiter,
+    check: &ShareLinkCheck<'_>,
+    context: &ApprovalContext<'_>,
+    request_id: &str,
+) -> Result<Response<Body>, ApiError> {
+    let map = status_map();
+    let body = parse_json_body::<DecisionRequest>(event)?;
+    let mut approval = match load_approval(event, &body.token, limiter, check, context.approvals).await {
+        Ok(approval) => approval,
+        Err(rejection) => return Ok(rejection.into_response(request_id)),
+    };
+    if approval.decision.is_some() {
+        return Ok(already_decided(&approval, "ALREADY_DECIDED", "This estimate has already been answered"));
+    }
+
+    // Staff may have moved the ticket on since the estimate was sent
+    let ticket = rs_client.get_ticket(approval.ticket_id).await?;
+    if map.to_display(&ticket.status) != AWAITING_APPROVAL_STATUS {
+        return Ok(already_decided(
+            &approval,
+            "NOT_AWAITING_APPROVAL",
+            "This estimate is no longer waiting for an answer",
+        ));
+    }
+
+    let note = body
+        .note
+        .map(|n| n.trim().chars().take(500).collect::<String>())
+        .filter(|n| !n.is_empty());
+    approval.decision = Some(body.decision);
+    approval.decided_at = Some(Utc::now().to_rfc3339());
+    approval.customer_note = note.clone();
+    if !context.approvals.record_decision(&approval).await.map_err(store_error)? {
+        return Ok(already_decided(&approval, "ALREADY_DECIDED", "This estimate has already been answered"));
+    }
+
+    let (verb, next_status) = match body.decision {
+        ApprovalDecision::Approve => ("approved", APPROVED_STATUS),
+        ApprovalDecision::Decline => ("declined", DECLINED_STATUS),
+    };
+    let mut comment_body = format!(
+        "Customer {} the estimate of {} - {}",
+        verb,
+        format_amount(approval.amount_cents),
+        approval.description
+    );
+    if let Some(note) = &note {
+        comment_body.push_str(&format!("\nCustomer note: {}", note));
+    }
+    let comment = NewComment {
+        subject: format!("Estimate {}", verb),
+        body: comment_body,
+        tech: Some("Customer (approval link)".to_string()),
+        hidden: true,
+        do_not_email: true,
+        sms_body: None,
+    };
+    let update = TicketUpdate {
+        status: Some(map.to_repairshopr(next_status)),
+        ..Default::default()
+    };
+    let applied = async {
+        rs_client.add_comment(approval.ticket_id, &comment).await?;
+        rs_client.update_ticket(approval.ticket_id, &update).await
+    }
+    .await;
+    let updated = match applied {
+        Ok(updated) => updated,
+        Err(e) => {
+            // Leave the estimate open so the customer can answer again
+            if let Err(undo) = context.approvals.undo_decision(&approval).await {
+                eprintln!("Failed to reopen approval {}: {}", approval.id, undo);
+            }
+            return Err(e.into());
+        }
+    };
+
+    let entry = AuditEntry::new(
+        "customer",
+        &format!("approval.{}", verb),
+        &format!("ticket:{}", approval.ticket_id),
+        json!({ "approval_id": approval.id, "amount_cents": approval.amount_cents }),
+    );
+    if let Err(e) = context.audit.record(entry).await {
+        eprintln!("Failed to record audit entry for approval decision: {}", e);
+    }
+    invalidate_board_cache();
+    let event = TicketEvent::StatusChanged {
+        ticket: updated,
+        previous_status: Some(ticket.status),
+    };
+    context.events.publish(&event).await;
+
+    Ok(no_store_response(json!({ "approval": ApprovalView::from_request(&approval) }).to_string()))
+}
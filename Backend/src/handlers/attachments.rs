@@ -3,44 +3,37 @@
 use lambda_http::{Body, Response};
 use aws_sdk_s3::Client as S3Client;
 use aws_sdk_s3::primitives::ByteStream;
-use serde_json::json;
 
+use crate::errors::ApiError;
 use crate::http::{error_response, success_response};
+use crate::repairshopr::{AttachmentUrl, RepairShoprClient};
 
 /// Handle attachment upload to ticket
 pub async fn handle_upload_attachment(
     ticket_id: i64,
     base64_data: &str,
     file_name: &str,
-    api_key: &str,
     s3_client: &S3Client,
-    target_url: &str,
-) -> Response<Body> {
+    rs_client: &RepairShoprClient,
+) -> Result<Response<Body>, ApiError> {
     // Decode base64 data to bytes
     use base64::Engine;
     let file_bytes = match base64::engine::general_purpose::STANDARD.decode(base64_data) {
         Ok(bytes) => bytes,
         Err(e) => {
-            return error_response(
+            return Ok(error_response(
                 400,
                 "Invalid base64 data",
                 &format!("Could not decode base64 data: {}", e),
                 None,
-            )
+            ))
         }
     };
 
     // Get S3 bucket name from environment
     let bucket_name = match std::env::var("S3_BUCKET_NAME") {
         Ok(name) => name,
-        Err(_) => {
-            return error_response(
-                500,
-                "Configuration error",
-                "S3_BUCKET_NAME environment variable not set",
-                None,
-            )
-        }
+        Err(_) => return Err(ApiError::configuration("S3_BUCKET_NAME environment variable not set")),
     };
 
     // Generate unique S3 key for the file
@@ -64,50 +57,17 @@ pub async fn handle_upload_attachment(
             // Get the public URL of the uploaded file
             let s3_url = format!("https://{}.s3.amazonaws.com/{}", bucket_name, s3_key);
 
-            // Call RepairShopr attach_file_url endpoint
-            let url = format!("{}/tickets/{}/attach_file_url", target_url, ticket_id);
-
-            let attach_body = json!({
-                "files": [
-                    {
-                        "url": s3_url,
-                        "filename": file_name
-                    }
-                ]
-            });
-
-            let request_builder = reqwest::Client::new()
-                .post(&url)
-                .header("Authorization", format!("Bearer {}", api_key))
-                .header("Content-Type", "application/json")
-                .body(attach_body.to_string());
-
-            match request_builder.send().await {
-                Ok(response) => {
-                    let status = response.status().as_u16();
-                    let response_body = response
-                        .text()
-                        .await
-                        .unwrap_or_else(|_| "{}".to_string());
-                    success_response(status, response_body)
-                }
-                Err(e) => {
-                    error_response(
-                        502,
-                        "Bad Gateway",
-                        &format!("Failed to attach file to ticket: {}", e),
-                        Some("Check that the ticket ID is valid and the API key has permission"),
-                    )
-                }
-            }
-        }
-        Err(e) => {
-            error_response(
-                500,
-                "S3 upload failed",
-                &format!("Failed to upload file to S3: {}", e),
-                Some("Check that the Lambda has S3 permissions and the bucket exists"),
-            )
+            // Attach the S3 URL to the ticket in RepairShopr
+            let files = [AttachmentUrl {
+                url: s3_url,
+                filename: file_name.to_string(),
+            }];
+            let result = rs_client.attach_file_url(ticket_id, &files).await?;
+            Ok(success_response(200, result.to_string()))
         }
+        Err(e) => Err(ApiError::storage(format!(
+            "Failed to upload {} to bucket {}: {:?}",
+            s3_key, bucket_name, e
+        ))),
     }
 }
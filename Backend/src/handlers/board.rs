@@ -0,0 +1,203 @@
+//! Ticket board handler: open tickets grouped into display-status columns
+
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Utc};
+use lambda_http::{Body, Request, RequestExt, Response};
+use serde::Serialize;
+
+use crate::cache::{ttl_from_env, TtlCache};
+use crate::errors::ApiError;
+use crate::handlers::tickets::TicketView;
+use crate::http::etag_response;
+use crate::repairshopr::{Listing, RepairShoprClient, RepairShoprError, Ticket, TicketQuery, MAX_PAGES};
+use crate::status::{status_map, StatusMap};
+
+/// RepairShopr pseudo-status that matches every ticket that isn't resolved
+const OPEN_STATUS_FILTER: &str = "Not Closed";
+
+/// Device shown for tickets whose intake details don't name one
+pub const UNKNOWN_DEVICE: &str = "Other";
+
+/// Open tickets shared by every board request on this instance (BOARD_CACHE_SECONDS, default 15)
+static OPEN_TICKETS: LazyLock<TtlCache<(), Listing<Ticket>>> =
+    LazyLock::new(|| TtlCache::new(ttl_from_env("BOARD_CACHE_SECONDS", 15)));
+
+/// Filters applied to the board after the open tickets are loaded
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BoardFilters {
+    pub device: Option<String>,
+    pub user_id: Option<i64>,
+    pub min_age_days: Option<i64>,
+    pub max_age_days: Option<i64>,
+}
+
+impl BoardFilters {
+    /// Read `device`, `user_id`, `min_age_days` and `max_age_days` from the query string
+    pub fn from_event(event: &Request) -> Result<Self, ApiError> {
+        let params = event.query_string_parameters();
+        let number = |key: &str| -> Result<Option<i64>, ApiError> {
+            match params.first(key).map(str::trim).filter(|v| !v.is_empty()) {
+                Some(value) => value.parse().map(Some).map_err(|_| {
+                    ApiError::new(
+                        400,
+                        "INVALID_FILTER",
+                        "Board filters must be whole numbers",
+                        format!("{}={} is not a number", key, value),
+                    )
+                }),
+                None => Ok(None),
+            }
+        };
+
+        Ok(Self {
+            device: params
+                .first("device")
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string),
+            user_id: number("user_id")?,
+            min_age_days: number("min_age_days")?,
+            max_age_days: number("max_age_days")?,
+        })
+    }
+}
+
+/// A ticket on the board
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BoardCard {
+    #[serde(flatten)]
+    pub ticket: TicketView,
+    pub device: String,
+    pub age_days: Option<i64>,
+}
+
+/// One display-status column
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BoardColumn {
+    pub status: String,
+    pub count: usize,
+    pub tickets: Vec<BoardCard>,
+}
+
+/// The whole board
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Board {
+    pub columns: Vec<BoardColumn>,
+    pub total: usize,
+    /// There were more open tickets than the board loads, so some are missing
+    pub truncated: bool,
+}
+
+/// Whole days since an RFC 3339 timestamp
+pub fn age_in_days(timestamp: Option<&str>, now: DateTime<Utc>) -> Option<i64> {
+    let created = DateTime::parse_from_rfc3339(timestamp?).ok()?;
+    Some((now - created.with_timezone(&Utc)).num_days())
+}
+
+/// Group tickets into columns in display-status order, applying `filters`.
+///
+/// Every display status gets a column (possibly empty) so the layout is stable; statuses
+/// missing from the mapping get their own columns at the end. Cards are oldest first.
+pub fn build_board(tickets: &[Ticket], filters: &BoardFilters, map: &StatusMap, now: DateTime<Utc>) -> Board {
+    let mut columns: Vec<BoardColumn> = map
+        .display_statuses()
+        .into_iter()
+        .map(|status| BoardColumn {
+            status: status.to_string(),
+            count: 0,
+            tickets: vec![],
+        })
+        .collect();
+
+    for ticket in tickets {
+        let device = ticket
+            .properties
+            .intake_model()
+            .and_then(|intake| intake.device)
+            .unwrap_or_else(|| UNKNOWN_DEVICE.to_string());
+        let age_days = age_in_days(ticket.created_at.as_deref(), now);
+
+        if filters.device.as_ref().is_some_and(|d| !d.eq_ignore_ascii_case(&device))
+            || filters.user_id.is_some_and(|id| ticket.user_id != Some(id))
+            || filters.min_age_days.is_some_and(|min| age_days.is_none_or(|age| age < min))
+            || filters.max_age_days.is_some_and(|max| age_days.is_none_or(|age| age > max))
+        {
+            continue;
+        }
+
+        let view = TicketView::from_ticket(ticket, map);
+        let index = match columns.iter().position(|c| c.status == view.status) {
+            Some(index) => index,
+            None => {
+                columns.push(BoardColumn {
+                    status: view.status.clone(),
+                    count: 0,
+                    tickets: vec![],
+                });
+                columns.len() - 1
+            }
+        };
+        columns[index].tickets.push(BoardCard {
+            ticket: view,
+            device,
+            age_days,
+        });
+    }
+
+    let mut total = 0;
+    for column in &mut columns {
+        column.tickets.sort_by(|a, b| {
+            b.age_days
+                .cmp(&a.age_days)
+                .then_with(|| a.ticket.created_at.cmp(&b.ticket.created_at))
+        });
+        column.count = column.tickets.len();
+        total += column.count;
+    }
+
+    Board {
+        columns,
+        total,
+        truncated: false,
+    }
+}
+
+/// Load every open ticket (up to MAX_PAGES pages), served from the instance cache when fresh
+pub async fn fetch_open_tickets(rs_client: &RepairShoprClient) -> Result<Listing<Ticket>, RepairShoprError> {
+    if let Some(tickets) = OPEN_TICKETS.get(&()) {
+        return Ok(tickets);
+    }
+
+    let query = TicketQuery {
+        status: Some(OPEN_STATUS_FILTER.to_string()),
+        ..Default::default()
+    };
+    let tickets = rs_client.list_all_tickets(&query, MAX_PAGES).await?;
+    OPEN_TICKETS.insert((), tickets.clone());
+    Ok(tickets)
+}
+
+/// Forget the cached open tickets (after a ticket changes)
+pub fn invalidate_board_cache() {
+    OPEN_TICKETS.clear();
+}
+
+/// Handle fetching the ticket board
+pub async fn handle_ticket_board(event: &Request, rs_client: &RepairShoprClient) -> Result<Response<Body>, ApiError> {
+    let filters = BoardFilters::from_event(event)?;
+    let tickets = fetch_open_tickets(rs_client).await?;
+    let board = Board {
+        truncated: tickets.truncated,
+        ..build_board(&tickets.items, &filters, status_map(), Utc::now())
+    };
+
+    let body = serde_json::to_string(&board)
+        .map_err(|e| ApiError::new(500, "SERIALIZATION_ERROR", "The response could not be built", e.to_string()))?;
+    let if_none_match = event
+        .headers()
+        .get("if-none-match")
+        .and_then(|v| v.to_str().ok());
+
+    Ok(etag_response(body, if_none_match))
+}
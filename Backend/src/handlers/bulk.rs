@@ -0,0 +1,318 @@
+//! Bulk ticket operations: one status change, reassignment or comment applied to many tickets
+
+use std::time::Duration;
+
+use futures::stream::{self, StreamExt};
+use lambda_http::{Body, Request, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::board::invalidate_board_cache;
+use super::comments::{build_comment, comment_templates, CommentRequest};
+use crate::auth::UserIdentity;
+use crate::errors::ApiError;
+use crate::events::{EventBus, TicketEvent};
+use crate::http::{parse_json_body, success_response};
+use crate::repairshopr::{NewComment, RepairShoprClient, RepairShoprError, Ticket, TicketUpdate};
+use crate::status::{status_map, StatusMap};
+
+/// Most tickets one bulk request may touch
+pub const MAX_BULK_TICKETS: usize = 200;
+
+/// How hard bulk operations push on RepairShopr
+#[derive(Debug, Clone, PartialEq)]
+pub struct BulkOptions {
+    /// Tickets processed at the same time
+    pub concurrency: usize,
+    /// Retries after RepairShopr answers 429
+    pub max_retries: u32,
+    /// First retry delay; doubles on each further retry
+    pub retry_delay: Duration,
+}
+
+impl Default for BulkOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            max_retries: 3,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl BulkOptions {
+    /// Defaults, with the concurrency taken from BULK_CONCURRENCY (1-16) when set
+    pub fn from_env() -> Self {
+        let concurrency = std::env::var("BULK_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .map(|n| n.clamp(1, 16));
+        Self {
+            concurrency: concurrency.unwrap_or(4),
+            ..Self::default()
+        }
+    }
+}
+
+/// The change to apply
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BulkAction {
+    /// Move to a display status (or a raw RepairShopr status)
+    Status { status: String },
+    /// Assign to a technician
+    Assign { user_id: i64 },
+    /// Post the same comment on every ticket
+    Comment(CommentRequest),
+}
+
+/// Request body for `POST /tickets/bulk`
+#[derive(Debug, Clone, Deserialize)]
+pub struct BulkRequest {
+    pub ticket_ids: Vec<i64>,
+    pub action: BulkAction,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// What happened to one ticket
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BulkTicketResult {
+    pub ticket_id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number: Option<i64>,
+    pub ok: bool,
+    /// Value before the change (status or assignee); absent for comments
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Report for the whole batch
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BulkReport {
+    pub dry_run: bool,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub results: Vec<BulkTicketResult>,
+}
+
+/// Action resolved into the exact RepairShopr change
+#[derive(Debug, Clone)]
+enum PreparedAction {
+    Status { repairshopr_status: String, display: String },
+    Assign { user_id: i64 },
+    Comment(NewComment),
+}
+
+/// Run `call`, waiting and retrying while RepairShopr reports rate limiting
+async fn with_rate_limit_retry<T, F, Fut>(options: &BulkOptions, call: F) -> Result<T, RepairShoprError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, RepairShoprError>>,
+{
+    let mut delay = options.retry_delay;
+    let mut attempt = 0;
+    loop {
+        match call().await {
+            Err(RepairShoprError::Status { status: 429, .. }) if attempt < options.max_retries => {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Current value of the field an action changes, for the report
+fn current_value(ticket: &Ticket, action: &PreparedAction, map: &StatusMap) -> Option<String> {
+    match action {
+        PreparedAction::Status { .. } => Some(map.to_display(&ticket.status)),
+        PreparedAction::Assign { .. } => Some(
+            ticket
+                .user
+                .as_ref()
+                .and_then(|u| u.full_name.clone())
+                .or_else(|| ticket.user_id.map(|id| id.to_string()))
+                .unwrap_or_else(|| "Unassigned".to_string()),
+        ),
+        PreparedAction::Comment(_) => None,
+    }
+}
+
+/// New value an action sets, for the report
+fn target_value(action: &PreparedAction) -> Option<String> {
+    match action {
+        PreparedAction::Status { display, .. } => Some(display.clone()),
+        PreparedAction::Assign { user_id } => Some(user_id.to_string()),
+        PreparedAction::Comment(_) => None,
+    }
+}
+
+/// Apply (or, for a dry run, preview) the action on one ticket
+async fn apply_to_ticket(
+    rs_client: &RepairShoprClient,
+    ticket_id: i64,
+    action: &PreparedAction,
+    dry_run: bool,
+    options: &BulkOptions,
+    map: &StatusMap,
+    events: &EventBus,
+) -> BulkTicketResult {
+    let mut result = BulkTicketResult {
+        ticket_id,
+        number: None,
+        ok: false,
+        from: None,
+        to: target_value(action),
+        error: None,
+    };
+
+    // Read first so the report shows what changes, and so a dry run catches missing tickets
+    let ticket = match with_rate_limit_retry(options, || rs_client.get_ticket(ticket_id)).await {
+        Ok(ticket) => ticket,
+        Err(e) => {
+            result.error = Some(e.to_string());
+            return result;
+        }
+    };
+    result.number = Some(ticket.number);
+    result.from = current_value(&ticket, action, map);
+
+    if dry_run {
+        result.ok = true;
+        return result;
+    }
+
+    let outcome = match action {
+        PreparedAction::Status { repairshopr_status, .. } => {
+            let update = TicketUpdate {
+                status: Some(repairshopr_status.clone()),
+                ..Default::default()
+            };
+            match with_rate_limit_retry(options, || rs_client.update_ticket(ticket_id, &update)).await {
+                Ok(updated) => {
+                    let event = TicketEvent::StatusChanged {
+                        ticket: updated,
+                        previous_status: Some(ticket.status.clone()),
+                    };
+                    events.publish(&event).await;
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            }
+        }
+        PreparedAction::Assign { user_id } => {
+            let update = TicketUpdate {
+                user_id: Some(*user_id),
+                ..Default::default()
+            };
+            with_rate_limit_retry(options, || rs_client.update_ticket(ticket_id, &update))
+                .await
+                .map(|_| ())
+        }
+        PreparedAction::Comment(comment) => with_rate_limit_retry(options, || rs_client.add_comment(ticket_id, comment))
+            .await
+            .map(|_| ()),
+    };
+
+    match outcome {
+        Ok(()) => {
+            result.ok = true;
+            if !matches!(action, PreparedAction::Status { .. }) {
+                events.publish(&TicketEvent::Changed { ticket_id }).await;
+            }
+        }
+        Err(e) => result.error = Some(e.to_string()),
+    }
+    result
+}
+
+/// Apply a bulk request, at most `options.concurrency` tickets at a time.
+///
+/// Results come back in the order the ids were given; duplicate ids are processed once.
+pub async fn run_bulk(
+    rs_client: &RepairShoprClient,
+    request: BulkRequest,
+    author: &UserIdentity,
+    can_message_customers: bool,
+    options: &BulkOptions,
+    events: &EventBus,
+) -> Result<BulkReport, ApiError> {
+    let mut ticket_ids: Vec<i64> = vec![];
+    for id in request.ticket_ids {
+        if !ticket_ids.contains(&id) {
+            ticket_ids.push(id);
+        }
+    }
+    if ticket_ids.is_empty() || ticket_ids.len() > MAX_BULK_TICKETS {
+        return Err(ApiError::new(
+            400,
+            "INVALID_BULK_REQUEST",
+            "Provide between 1 and 200 ticket ids",
+            format!("{} ticket ids were provided", ticket_ids.len()),
+        ));
+    }
+
+    let map = status_map();
+    let action = match request.action {
+        BulkAction::Status { status } => {
+            let status = status.trim();
+            let known = map.display_statuses().contains(&status) || map.to_display(status) != status;
+            if !known {
+                return Err(ApiError::new(
+                    400,
+                    "UNKNOWN_STATUS",
+                    "That status does not exist",
+                    format!("'{}' is not a display or RepairShopr status", status),
+                ));
+            }
+            let repairshopr_status = map.to_repairshopr(status);
+            PreparedAction::Status {
+                display: map.to_display(&repairshopr_status),
+                repairshopr_status,
+            }
+        }
+        BulkAction::Assign { user_id } => PreparedAction::Assign { user_id },
+        BulkAction::Comment(comment) => {
+            PreparedAction::Comment(build_comment(comment, author, can_message_customers, comment_templates())?)
+        }
+    };
+
+    let mut results: Vec<BulkTicketResult> = stream::iter(ticket_ids.iter().copied())
+        .map(|id| apply_to_ticket(rs_client, id, &action, request.dry_run, options, map, events))
+        .buffer_unordered(options.concurrency.max(1))
+        .collect()
+        .await;
+    results.sort_by_key(|r| ticket_ids.iter().position(|id| *id == r.ticket_id));
+
+    if !request.dry_run && !matches!(action, PreparedAction::Comment(_)) {
+        invalidate_board_cache();
+    }
+
+    let succeeded = results.iter().filter(|r| r.ok).count();
+    Ok(BulkReport {
+        dry_run: request.dry_run,
+        succeeded,
+        failed: results.len() - succeeded,
+        results,
+    })
+}
+
+/// Handle `POST /tickets/bulk`
+pub async fn handle_bulk_tickets(
+    event: &Request,
+    rs_client: &RepairShoprClient,
+    author: &UserIdentity,
+    can_message_customers: bool,
+    events: &EventBus,
+) -> Result<Response<Body>, ApiError> {
+    let request = parse_json_body::<BulkRequest>(event)?;
+    let report = run_bulk(rs_client, request, author, can_message_customers, &BulkOptions::from_env(), events).await?;
+    let status = if report.failed == 0 { 200 } else { 207 };
+    Ok(success_response(status, json!(report).to_string()))
+}
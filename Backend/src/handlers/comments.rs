@@ -0,0 +1,280 @@
+//! Ticket comments posted on behalf of the signed-in user, with server-side templates
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use lambda_http::{Body, Request, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::tickets::parse_ticket_id;
+use crate::auth::UserIdentity;
+use crate::errors::ApiError;
+use crate::http::{parse_json_body, success_response};
+use crate::repairshopr::{NewComment, RepairShoprClient};
+
+/// Templates available out of the box
+const DEFAULT_TEMPLATES: [(&str, &str); 4] = [
+    ("waiting_for_parts", "Waiting for parts, ETA {date}"),
+    ("ready_for_pickup", "Your device is repaired and ready for pickup."),
+    ("estimate", "The repair will cost {price}. Please let us know if you would like us to go ahead."),
+    ("diagnosing", "We are diagnosing your device and will be in touch by {date}."),
+];
+
+/// A named comment template
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CommentTemplate {
+    pub name: String,
+    pub text: String,
+    /// Placeholders the caller has to fill in
+    pub variables: Vec<String>,
+}
+
+/// The set of named templates
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommentTemplates {
+    templates: Vec<(String, String)>,
+}
+
+impl Default for CommentTemplates {
+    fn default() -> Self {
+        Self {
+            templates: DEFAULT_TEMPLATES
+                .iter()
+                .map(|(name, text)| (name.to_string(), text.to_string()))
+                .collect(),
+        }
+    }
+}
+
+/// Placeholder names in a template, in order of first use
+fn placeholders(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else { break };
+        let name = &after[..end];
+        if !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !names.iter().any(|n| n == name)
+        {
+            names.push(name.to_string());
+        }
+        rest = &after[end + 1..];
+    }
+    names
+}
+
+impl CommentTemplates {
+    /// Parse COMMENT_TEMPLATES (a JSON object of name to text) on top of the defaults;
+    /// a template with the same name replaces the default one.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let extra: HashMap<String, String> =
+            serde_json::from_str(json).map_err(|e| format!("Invalid COMMENT_TEMPLATES: {}", e))?;
+        let mut templates = Self::default();
+        let mut names: Vec<&String> = extra.keys().collect();
+        names.sort();
+        for name in names {
+            let text = extra[name].clone();
+            match templates.templates.iter_mut().find(|(n, _)| n == name) {
+                Some(existing) => existing.1 = text,
+                None => templates.templates.push((name.clone(), text)),
+            }
+        }
+        Ok(templates)
+    }
+
+    /// Load templates from COMMENT_TEMPLATES, falling back to the defaults
+    pub fn from_env() -> Self {
+        match std::env::var("COMMENT_TEMPLATES") {
+            Ok(json) => Self::from_json(&json).unwrap_or_else(|e| {
+                eprintln!("Warning: {}; using default comment templates", e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// All templates with their placeholders
+    pub fn list(&self) -> Vec<CommentTemplate> {
+        self.templates
+            .iter()
+            .map(|(name, text)| CommentTemplate {
+                name: name.clone(),
+                text: text.clone(),
+                variables: placeholders(text),
+            })
+            .collect()
+    }
+
+    /// Fill in a template's placeholders.
+    ///
+    /// Fails with the names of any placeholders `variables` doesn't provide.
+    pub fn render(&self, name: &str, variables: &HashMap<String, String>) -> Result<String, TemplateError> {
+        let text = self
+            .templates
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, text)| text)
+            .ok_or_else(|| TemplateError::Unknown(name.to_string()))?;
+
+        let missing: Vec<String> = placeholders(text)
+            .into_iter()
+            .filter(|p| variables.get(p).is_none_or(|v| v.trim().is_empty()))
+            .collect();
+        if !missing.is_empty() {
+            return Err(TemplateError::MissingVariables(missing));
+        }
+
+        // One pass over the template, so a value containing `{name}` stays as typed
+        let mut rendered = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+        while let Some(start) = rest.find('{') {
+            rendered.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            match after.find('}').and_then(|end| Some((end, variables.get(&after[..end])?))) {
+                Some((end, value)) => {
+                    rendered.push_str(value.trim());
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    rendered.push('{');
+                    rest = after;
+                }
+            }
+        }
+        rendered.push_str(rest);
+        Ok(rendered)
+    }
+}
+
+/// Why a template could not be rendered
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateError {
+    Unknown(String),
+    MissingVariables(Vec<String>),
+}
+
+impl From<TemplateError> for ApiError {
+    fn from(error: TemplateError) -> Self {
+        match error {
+            TemplateError::Unknown(name) => ApiError::new(
+                400,
+                "UNKNOWN_TEMPLATE",
+                "No comment template with that name",
+                format!("Template '{}' does not exist", name),
+            ),
+            TemplateError::MissingVariables(missing) => ApiError::new(
+                400,
+                "MISSING_TEMPLATE_VARIABLES",
+                "The comment template needs more values",
+                format!("Missing values for: {}", missing.join(", ")),
+            ),
+        }
+    }
+}
+
+/// Process-wide comment templates, loaded once per cold start
+pub fn comment_templates() -> &'static CommentTemplates {
+    static TEMPLATES: OnceLock<CommentTemplates> = OnceLock::new();
+    TEMPLATES.get_or_init(CommentTemplates::from_env)
+}
+
+/// Comment as submitted: either a `body` or a `template` with its `variables`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CommentRequest {
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+    /// Internal note (default) or visible to the customer
+    #[serde(default)]
+    pub hidden: Option<bool>,
+}
+
+/// Turn a comment request into the RepairShopr payload, attributed to `author`.
+///
+/// Comments are internal unless `hidden: false` is sent, which needs
+/// `can_message_customers`. The `{tech}` placeholder is always available.
+pub fn build_comment(
+    request: CommentRequest,
+    author: &UserIdentity,
+    can_message_customers: bool,
+    templates: &CommentTemplates,
+) -> Result<NewComment, ApiError> {
+    let body = match (&request.template, request.body.as_deref().map(str::trim)) {
+        (Some(_), Some(body)) if !body.is_empty() => {
+            return Err(ApiError::new(
+                400,
+                "INVALID_COMMENT",
+                "Send either a comment body or a template, not both",
+                "Both 'body' and 'template' were provided".to_string(),
+            ));
+        }
+        (Some(template), _) => {
+            let mut variables = request.variables.clone();
+            variables
+                .entry("tech".to_string())
+                .or_insert_with(|| author.given_name.clone().unwrap_or_else(|| author.display_name()));
+            templates.render(template, &variables)?
+        }
+        (None, Some(body)) if !body.is_empty() => body.to_string(),
+        (None, _) => {
+            return Err(ApiError::new(
+                400,
+                "INVALID_COMMENT",
+                "A comment needs a body or a template",
+                "Neither 'body' nor 'template' was provided".to_string(),
+            ));
+        }
+    };
+
+    let hidden = request.hidden.unwrap_or(true);
+    if !hidden && !can_message_customers {
+        return Err(ApiError::new(
+            403,
+            "FORBIDDEN",
+            "You do not have permission to post customer-visible comments",
+            format!("{} tried to post a customer-visible comment", author.actor()),
+        ));
+    }
+
+    Ok(NewComment {
+        subject: request
+            .subject
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "Update".to_string()),
+        body,
+        tech: Some(author.display_name()),
+        hidden,
+        do_not_email: hidden,
+        sms_body: None,
+    })
+}
+
+/// Handle posting a comment to a ticket as the signed-in user
+pub async fn handle_add_comment(
+    event: &Request,
+    ticket_id: &str,
+    rs_client: &RepairShoprClient,
+    author: &UserIdentity,
+    can_message_customers: bool,
+) -> Result<Response<Body>, ApiError> {
+    let ticket_id = parse_ticket_id(ticket_id)?;
+    let request = parse_json_body::<CommentRequest>(event)?;
+    let comment = build_comment(request, author, can_message_customers, comment_templates())?;
+
+    let created = rs_client.add_comment(ticket_id, &comment).await?;
+    Ok(success_response(201, json!({ "comment": created }).to_string()))
+}
+
+/// Handle listing the comment templates
+pub fn handle_list_comment_templates() -> Response<Body> {
+    success_response(200, json!({ "templates": comment_templates().list() }).to_string())
+}
@@ -0,0 +1,248 @@
+//! Typed customer endpoints with server-side validation and normalization
+
+use lambda_http::{Body, Request, RequestExt, Response};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::contact::{
+    default_phone_region, looks_like_email, national_phone_digits, normalize_email,
+    normalize_name, normalize_phone,
+};
+use crate::errors::ApiError;
+use crate::http::{parse_json_body, success_response};
+use crate::repairshopr::{Customer, CustomerInput, CustomerQuery, RepairShoprClient, RepairShoprError};
+
+/// A validation problem with one field
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+/// Read the request body: either `{"customer": {...}}` (as the frontend sends) or the bare
+/// fields. A wrapped customer that doesn't parse is an error rather than an empty customer.
+fn parse_customer_body(event: &Request) -> Result<CustomerInput, ApiError> {
+    let mut body = parse_json_body::<Value>(event)?;
+    let fields = match body.get_mut("customer") {
+        Some(customer) => customer.take(),
+        None => body,
+    };
+    serde_json::from_value(fields).map_err(|e| {
+        ApiError::new(400, "INVALID_JSON", "The request body is not valid JSON for this endpoint", e.to_string())
+    })
+}
+
+/// Trim a field, treating blank values as absent
+fn present(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+/// Validate and normalize customer fields.
+///
+/// Names are trimmed and case-fixed, emails lowercased, phones converted to E.164 using
+/// `region` for numbers without a country code. New customers need a name and a way to
+/// contact them.
+pub fn normalize_customer(input: CustomerInput, region: &str, is_create: bool) -> Result<CustomerInput, Vec<FieldError>> {
+    let mut errors = vec![];
+
+    let mut phone_field = |field: &'static str, value: Option<String>| {
+        present(value).and_then(|raw| match normalize_phone(&raw, region) {
+            Ok(phone) => Some(phone),
+            Err(message) => {
+                errors.push(FieldError { field, message });
+                None
+            }
+        })
+    };
+    let phone = phone_field("phone", input.phone);
+    let mobile = phone_field("mobile", input.mobile);
+
+    let email = present(input.email).map(|e| normalize_email(&e));
+    if let Some(email) = &email
+        && !looks_like_email(email)
+    {
+        errors.push(FieldError {
+            field: "email",
+            message: "Email address is not valid".to_string(),
+        });
+    }
+
+    let customer = CustomerInput {
+        firstname: present(input.firstname).map(|n| normalize_name(&n)),
+        lastname: present(input.lastname).map(|n| normalize_name(&n)),
+        business_name: present(input.business_name).map(|n| n.split_whitespace().collect::<Vec<_>>().join(" ")),
+        email,
+        phone,
+        mobile,
+        notes: input.notes,
+        get_sms: input.get_sms,
+    };
+
+    if is_create {
+        if customer.firstname.is_none() && customer.lastname.is_none() && customer.business_name.is_none() {
+            errors.push(FieldError {
+                field: "firstname",
+                message: "A first name, last name or business name is required".to_string(),
+            });
+        }
+        if customer.phone.is_none() && customer.mobile.is_none() && customer.email.is_none() && errors.is_empty() {
+            errors.push(FieldError {
+                field: "phone",
+                message: "A phone number or email is required".to_string(),
+            });
+        }
+    }
+
+    if errors.is_empty() { Ok(customer) } else { Err(errors) }
+}
+
+/// An existing customer that shares contact details with the one being created
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PossibleDuplicate {
+    pub customer: Customer,
+    pub matched_on: Vec<&'static str>,
+}
+
+/// Which contact details of `existing` match the normalized `candidate`
+pub fn duplicate_reasons(candidate: &CustomerInput, existing: &Customer) -> Vec<&'static str> {
+    let mut reasons = vec![];
+
+    let candidate_phones: Vec<String> = [&candidate.phone, &candidate.mobile]
+        .into_iter()
+        .flatten()
+        .map(|p| national_phone_digits(p))
+        .collect();
+    let existing_phones: Vec<String> = [&existing.phone, &existing.mobile]
+        .into_iter()
+        .flatten()
+        .map(|p| national_phone_digits(p))
+        .filter(|p| !p.is_empty())
+        .collect();
+    if candidate_phones.iter().any(|p| existing_phones.contains(p)) {
+        reasons.push("phone");
+    }
+
+    if let (Some(candidate_email), Some(existing_email)) = (&candidate.email, &existing.email)
+        && normalize_email(existing_email) == *candidate_email
+    {
+        reasons.push("email");
+    }
+
+    reasons
+}
+
+/// Search RepairShopr for customers with the same phone or email as `candidate`
+pub async fn find_possible_duplicates(
+    rs_client: &RepairShoprClient,
+    candidate: &CustomerInput,
+) -> Result<Vec<PossibleDuplicate>, RepairShoprError> {
+    let phone_digits: Vec<String> = [&candidate.phone, &candidate.mobile]
+        .into_iter()
+        .flatten()
+        .map(|p| national_phone_digits(p))
+        .collect();
+
+    let by_phone = async {
+        let mut found = vec![];
+        for digits in &phone_digits {
+            found.extend(rs_client.search_customers(digits).await?);
+        }
+        Ok::<_, RepairShoprError>(found)
+    };
+    let by_email = async {
+        match &candidate.email {
+            Some(email) => {
+                let query = CustomerQuery {
+                    email: Some(email.clone()),
+                    ..Default::default()
+                };
+                rs_client.list_customers(&query, 1).await.map(|page| page.items)
+            }
+            None => Ok(vec![]),
+        }
+    };
+    let (by_phone, by_email) = tokio::join!(by_phone, by_email);
+
+    let mut duplicates: Vec<PossibleDuplicate> = vec![];
+    for customer in by_phone?.into_iter().chain(by_email?) {
+        let matched_on = duplicate_reasons(candidate, &customer);
+        if matched_on.is_empty() {
+            continue;
+        }
+        // The same customer can come back from both lookups; merge what matched
+        match duplicates.iter_mut().find(|d| d.customer.id == customer.id) {
+            Some(existing) => {
+                for reason in matched_on {
+                    if !existing.matched_on.contains(&reason) {
+                        existing.matched_on.push(reason);
+                    }
+                }
+            }
+            None => duplicates.push(PossibleDuplicate { customer, matched_on }),
+        }
+    }
+    Ok(duplicates)
+}
+
+/// Response listing validation failures
+pub fn validation_failed(errors: &[FieldError]) -> Response<Body> {
+    let body = json!({
+        "error": "Validation failed",
+        "code": "VALIDATION_FAILED",
+        "fields": errors,
+    });
+    success_response(422, body.to_string())
+}
+
+/// Handle creating a customer (`?force=true` skips the duplicate check)
+pub async fn handle_create_customer(event: &Request, rs_client: &RepairShoprClient) -> Result<Response<Body>, ApiError> {
+    let input = parse_customer_body(event)?;
+    let customer = match normalize_customer(input, &default_phone_region(), true) {
+        Ok(customer) => customer,
+        Err(errors) => return Ok(validation_failed(&errors)),
+    };
+
+    let force = event
+        .query_string_parameters()
+        .first("force")
+        .is_some_and(|v| v == "true");
+    if !force {
+        let duplicates = find_possible_duplicates(rs_client, &customer).await?;
+        if !duplicates.is_empty() {
+            let body = json!({
+                "error": "Possible duplicate customer",
+                "code": "DUPLICATE_CUSTOMER",
+                "duplicates": duplicates,
+            });
+            return Ok(success_response(409, body.to_string()));
+        }
+    }
+
+    let created = rs_client.create_customer(&customer).await?;
+    Ok(success_response(201, json!({ "customer": created }).to_string()))
+}
+
+/// Handle updating a customer
+pub async fn handle_update_customer(
+    event: &Request,
+    customer_id: &str,
+    rs_client: &RepairShoprClient,
+) -> Result<Response<Body>, ApiError> {
+    let customer_id: i64 = customer_id.parse().map_err(|_| {
+        ApiError::new(
+            400,
+            "INVALID_CUSTOMER_ID",
+            "The customer id must be a number",
+            format!("'{}' is not a valid customer id", customer_id),
+        )
+    })?;
+
+    let input = parse_customer_body(event)?;
+    let customer = match normalize_customer(input, &default_phone_region(), false) {
+        Ok(customer) => customer,
+        Err(errors) => return Ok(validation_failed(&errors)),
+    };
+
+    let updated = rs_client.update_customer(customer_id, &customer).await?;
+    Ok(success_response(200, json!({ "customer": updated }).to_string()))
+}
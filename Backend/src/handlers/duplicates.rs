@@ -0,0 +1,339 @@
+//! Customer duplicate detection and merging (admin only)
+
+use std::collections::{BTreeSet, HashMap};
+
+use lambda_http::{Body, Request, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::audit::{AuditEntry, AuditStore};
+use crate::auth::UserIdentity;
+use crate::contact::{national_phone_digits, normalize_email};
+use crate::errors::ApiError;
+use crate::http::{parse_json_body, success_response};
+use crate::repairshopr::{Customer, CustomerQuery, RepairShoprClient, TicketQuery, TicketUpdate, MAX_PAGES};
+
+/// Group of customers that probably describe the same person
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DuplicateCluster {
+    pub customers: Vec<Customer>,
+    pub matched_on: Vec<&'static str>,
+    /// Oldest record, which keeps the history if the cluster is merged
+    pub suggested_survivor_id: i64,
+}
+
+/// Phone numbers of a customer as national digits
+fn customer_phones(customer: &Customer) -> Vec<String> {
+    [&customer.phone, &customer.mobile]
+        .into_iter()
+        .flatten()
+        .map(|p| national_phone_digits(p))
+        .filter(|p| p.len() >= 7)
+        .collect()
+}
+
+/// Lowercased name words in sorted order, so "Smith, John" and "john smith" compare equal
+fn name_key(customer: &Customer) -> Option<String> {
+    let name = match (&customer.firstname, &customer.lastname) {
+        (None, None) => customer.business_name.clone()?,
+        (first, last) => format!("{} {}", first.as_deref().unwrap_or(""), last.as_deref().unwrap_or("")),
+    };
+    let mut words: Vec<String> = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect();
+    words.sort();
+    let key = words.join(" ");
+    (key.chars().count() >= 4).then_some(key)
+}
+
+/// Edit distance between two strings
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Names close enough to be typos of each other
+fn names_match(a: &str, b: &str) -> bool {
+    let allowed = (a.chars().count().max(b.chars().count()) / 8).max(1);
+    levenshtein(a, b) <= allowed
+}
+
+/// A customer's contact details and name, normalized once for comparison
+struct MatchKeys {
+    phones: Vec<String>,
+    email: Option<String>,
+    name: Option<String>,
+}
+
+impl MatchKeys {
+    fn new(customer: &Customer) -> Self {
+        Self {
+            phones: customer_phones(customer),
+            email: customer.email.as_deref().map(normalize_email).filter(|e| !e.is_empty()),
+            name: name_key(customer),
+        }
+    }
+}
+
+/// Why two customers look like duplicates (empty when they don't)
+fn match_reasons(a: &MatchKeys, b: &MatchKeys) -> Vec<&'static str> {
+    let mut reasons = vec![];
+    if a.phones.iter().any(|p| b.phones.contains(p)) {
+        reasons.push("phone");
+    }
+    if a.email.is_some() && a.email == b.email {
+        reasons.push("email");
+    }
+    if let (Some(a_name), Some(b_name)) = (&a.name, &b.name)
+        && names_match(a_name, b_name)
+    {
+        reasons.push("name");
+    }
+    reasons
+}
+
+fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Cluster customers that share a phone number or email; a near-identical name is
+/// reported alongside those but never links two records by itself.
+///
+/// Matching is transitive, so a customer sharing a phone with one record and an email
+/// with another puts all three in the same cluster. Only customers sharing a phone or
+/// email are compared, so the scan stays fast on large customer lists. Clusters come back
+/// largest first.
+pub fn cluster_customers(customers: &[Customer]) -> Vec<DuplicateCluster> {
+    let keys: Vec<MatchKeys> = customers.iter().map(MatchKeys::new).collect();
+    // Common names collide, and name-only links would chain strangers together, so only
+    // a shared phone or email brings two customers together
+    let mut buckets: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, key) in keys.iter().enumerate() {
+        let contacts = key.phones.iter().map(|p| format!("phone:{}", p));
+        for contact in contacts.chain(key.email.iter().map(|e| format!("email:{}", e))) {
+            let bucket = buckets.entry(contact).or_default();
+            if !bucket.contains(&i) {
+                bucket.push(i);
+            }
+        }
+    }
+    let mut pairs: BTreeSet<(usize, usize)> = BTreeSet::new();
+    for bucket in buckets.values() {
+        for (n, &i) in bucket.iter().enumerate() {
+            pairs.extend(bucket[n + 1..].iter().map(|&j| (i.min(j), i.max(j))));
+        }
+    }
+
+    let mut parents: Vec<usize> = (0..customers.len()).collect();
+    let mut reasons: Vec<Vec<&'static str>> = vec![vec![]; customers.len()];
+    for (i, j) in pairs {
+        let matched = match_reasons(&keys[i], &keys[j]);
+        let (root_i, root_j) = (find_root(&mut parents, i), find_root(&mut parents, j));
+        let mut merged = std::mem::take(&mut reasons[root_i]);
+        if root_i != root_j {
+            merged.extend(std::mem::take(&mut reasons[root_j]));
+            parents[root_j] = root_i;
+        }
+        merged.extend(matched);
+        reasons[root_i] = merged;
+    }
+
+    let mut clusters: Vec<DuplicateCluster> = vec![];
+    let mut cluster_roots: Vec<usize> = vec![];
+    for (i, customer) in customers.iter().enumerate() {
+        let root = find_root(&mut parents, i);
+        match cluster_roots.iter().position(|r| *r == root) {
+            Some(index) => clusters[index].customers.push(customer.clone()),
+            None => {
+                cluster_roots.push(root);
+                clusters.push(DuplicateCluster {
+                    customers: vec![customer.clone()],
+                    matched_on: vec![],
+                    suggested_survivor_id: customer.id,
+                });
+            }
+        }
+    }
+
+    for (cluster, root) in clusters.iter_mut().zip(&cluster_roots) {
+        for reason in ["phone", "email", "name"] {
+            if reasons[*root].contains(&reason) {
+                cluster.matched_on.push(reason);
+            }
+        }
+        cluster.customers.sort_by_key(|c| c.id);
+        cluster.suggested_survivor_id = cluster.customers.first().map_or(0, |c| c.id);
+    }
+
+    clusters.retain(|c| c.customers.len() > 1);
+    clusters.sort_by(|a, b| {
+        b.customers
+            .len()
+            .cmp(&a.customers.len())
+            .then(a.suggested_survivor_id.cmp(&b.suggested_survivor_id))
+    });
+    clusters
+}
+
+/// Handle scanning all customers for probable duplicates; `truncated` means only the first
+/// pages of customers were scanned
+pub async fn handle_find_duplicates(rs_client: &RepairShoprClient) -> Result<Response<Body>, ApiError> {
+    let listing = rs_client.list_all_customers(&CustomerQuery::default(), MAX_PAGES).await?;
+    let clusters = cluster_customers(&listing.items);
+    let body = json!({
+        "scanned": listing.items.len(),
+        "truncated": listing.truncated,
+        "clusters": clusters,
+    });
+    Ok(success_response(200, body.to_string()))
+}
+
+/// Request to fold one customer into another
+#[derive(Debug, Deserialize)]
+pub struct MergeRequest {
+    pub survivor_id: i64,
+    pub duplicate_id: i64,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Outcome of moving one ticket
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReassignedTicket {
+    pub ticket_id: i64,
+    pub number: i64,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of a merge
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MergeReport {
+    pub survivor_id: i64,
+    pub duplicate_id: i64,
+    pub dry_run: bool,
+    pub tickets: Vec<ReassignedTicket>,
+}
+
+/// Reassign every ticket of `duplicate_id` to `survivor_id`, auditing each change.
+///
+/// The duplicate customer record itself is left in place so nothing is lost if the merge
+/// was a mistake; once its tickets are moved it can be removed from RepairShopr by hand.
+pub async fn merge_customers(
+    rs_client: &RepairShoprClient,
+    audit: &dyn AuditStore,
+    actor: &UserIdentity,
+    request: &MergeRequest,
+) -> Result<MergeReport, ApiError> {
+    if request.survivor_id == request.duplicate_id {
+        return Err(ApiError::new(
+            400,
+            "INVALID_MERGE",
+            "A customer cannot be merged into itself",
+            format!("survivor_id and duplicate_id are both {}", request.survivor_id),
+        ));
+    }
+
+    // Both customers must exist before anything is moved
+    let (survivor, duplicate) = tokio::join!(
+        rs_client.get_customer(request.survivor_id),
+        rs_client.get_customer(request.duplicate_id)
+    );
+    survivor?;
+    duplicate?;
+
+    let query = TicketQuery {
+        customer_id: Some(request.duplicate_id),
+        ..Default::default()
+    };
+    let tickets = rs_client.list_all_tickets(&query, MAX_PAGES).await?.items;
+
+    let mut report = MergeReport {
+        survivor_id: request.survivor_id,
+        duplicate_id: request.duplicate_id,
+        dry_run: request.dry_run,
+        tickets: vec![],
+    };
+
+    for ticket in tickets {
+        if request.dry_run {
+            report.tickets.push(ReassignedTicket {
+                ticket_id: ticket.id,
+                number: ticket.number,
+                ok: true,
+                error: None,
+            });
+            continue;
+        }
+
+        let update = TicketUpdate {
+            customer_id: Some(request.survivor_id),
+            ..Default::default()
+        };
+        let result = rs_client.update_ticket(ticket.id, &update).await;
+        let error = result.err().map(|e| e.to_string());
+
+        if error.is_none() {
+            let entry = AuditEntry::new(
+                &actor.actor(),
+                "ticket.reassign_customer",
+                &format!("ticket:{}", ticket.id),
+                json!({ "from_customer_id": request.duplicate_id, "to_customer_id": request.survivor_id }),
+            );
+            if let Err(e) = audit.record(entry).await {
+                eprintln!("Failed to record audit entry for ticket {}: {}", ticket.id, e);
+            }
+        }
+
+        report.tickets.push(ReassignedTicket {
+            ticket_id: ticket.id,
+            number: ticket.number,
+            ok: error.is_none(),
+            error,
+        });
+    }
+
+    if !request.dry_run {
+        let moved = report.tickets.iter().filter(|t| t.ok).count();
+        let entry = AuditEntry::new(
+            &actor.actor(),
+            "customer.merge",
+            &format!("customer:{}", request.duplicate_id),
+            json!({ "survivor_id": request.survivor_id, "tickets_moved": moved, "tickets_failed": report.tickets.len() - moved }),
+        );
+        if let Err(e) = audit.record(entry).await {
+            eprintln!("Failed to record audit entry for merge: {}", e);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Handle merging a duplicate customer into a surviving one
+pub async fn handle_merge_customers(
+    event: &Request,
+    rs_client: &RepairShoprClient,
+    audit: &dyn AuditStore,
+    actor: &UserIdentity,
+) -> Result<Response<Body>, ApiError> {
+    let request = parse_json_body::<MergeRequest>(event)?;
+    let report = merge_customers(rs_client, audit, actor, &request).await?;
+    let status = if report.tickets.iter().all(|t| t.ok) { 200 } else { 207 };
+    Ok(success_response(status, json!(report).to_string()))
+}
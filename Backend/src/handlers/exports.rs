@@ -0,0 +1,483 @@
+//! Spreadsheet exports of tickets and customers (CSV or XLSX)
+//!
+//! Small files come back in the response. Files over EXPORT_INLINE_MAX_BYTES (or any file
+//! when `delivery=link`) are written to STATE_BUCKET under `exports/` and returned as a
+//! presigned download link, since Lambda responses are capped at 6 MB. The bucket should
+//! expire `exports/` with a lifecycle rule (see QUICK_START.md), since files outlive their links.
+
+use std::time::Duration;
+
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use chrono::{DateTime, NaiveDate, Utc};
+use lambda_http::{Body, Request, RequestExt, Response};
+use rust_xlsxwriter::{Format, Workbook};
+use serde_json::json;
+
+use super::board::UNKNOWN_DEVICE;
+use crate::config::state_bucket;
+use crate::errors::ApiError;
+use crate::http::{file_response, success_response};
+use crate::repairshopr::{Customer, CustomerQuery, RepairShoprClient, Ticket, TicketQuery, MAX_PAGES};
+use crate::status::{status_map, StatusMap};
+
+/// Ticket columns, in default order
+pub const TICKET_COLUMNS: [&str; 12] = [
+    "number",
+    "id",
+    "subject",
+    "status",
+    "customer_id",
+    "customer",
+    "device",
+    "technician",
+    "problem_type",
+    "created_at",
+    "due_date",
+    "resolved_at",
+];
+
+/// Customer columns, in default order
+pub const CUSTOMER_COLUMNS: [&str; 10] = [
+    "id",
+    "name",
+    "business_name",
+    "email",
+    "phone",
+    "mobile",
+    "sms_opt_in",
+    "no_email",
+    "created_at",
+    "notes",
+];
+
+/// Columns written as numbers in XLSX
+const NUMERIC_COLUMNS: [&str; 3] = ["id", "number", "customer_id"];
+
+/// Largest file returned in the response body (default 4 MB, under Lambda's 6 MB cap once encoded)
+const DEFAULT_INLINE_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+/// How long a download link works (default 15 minutes)
+const DEFAULT_LINK_SECONDS: u64 = 900;
+
+/// What is being exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportKind {
+    Tickets,
+    Customers,
+}
+
+impl ExportKind {
+    /// Parse the `/exports/{kind}` path segment
+    pub fn parse(segment: &str) -> Option<Self> {
+        match segment {
+            "tickets" => Some(Self::Tickets),
+            "customers" => Some(Self::Customers),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Tickets => "tickets",
+            Self::Customers => "customers",
+        }
+    }
+
+    fn columns(self) -> &'static [&'static str] {
+        match self {
+            Self::Tickets => &TICKET_COLUMNS,
+            Self::Customers => &CUSTOMER_COLUMNS,
+        }
+    }
+}
+
+/// Spreadsheet file format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Xlsx,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Xlsx => "xlsx",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        }
+    }
+}
+
+/// A parsed export request
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportRequest {
+    pub kind: ExportKind,
+    pub format: ExportFormat,
+    pub columns: Vec<&'static str>,
+    /// Created on or after this date
+    pub from: Option<NaiveDate>,
+    /// Created on or before this date
+    pub to: Option<NaiveDate>,
+    /// Display status (tickets only)
+    pub status: Option<String>,
+    /// Intake device (tickets only)
+    pub device: Option<String>,
+    /// Always answer with a download link
+    pub link: bool,
+}
+
+fn invalid_export(details: String) -> ApiError {
+    ApiError::new(400, "INVALID_EXPORT", "The export request is not valid", details)
+}
+
+impl ExportRequest {
+    /// Read `format`, `columns`, `from`, `to`, `status`, `device` and `delivery` from the query string
+    pub fn from_event(event: &Request, kind: ExportKind) -> Result<Self, ApiError> {
+        let params = event.query_string_parameters();
+        let param = |key: &str| params.first(key).map(str::trim).filter(|v| !v.is_empty());
+        let date = |key: &str| -> Result<Option<NaiveDate>, ApiError> {
+            param(key)
+                .map(|value| {
+                    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                        .map_err(|_| invalid_export(format!("{}={} is not a YYYY-MM-DD date", key, value)))
+                })
+                .transpose()
+        };
+
+        let format = match param("format") {
+            None | Some("csv") => ExportFormat::Csv,
+            Some("xlsx") => ExportFormat::Xlsx,
+            Some(other) => return Err(invalid_export(format!("format={} must be csv or xlsx", other))),
+        };
+
+        let available = kind.columns();
+        let columns = match param("columns") {
+            None => available.to_vec(),
+            Some(list) => {
+                let mut columns = vec![];
+                for name in list.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+                    let Some(column) = available.iter().find(|c| **c == name) else {
+                        return Err(invalid_export(format!(
+                            "Unknown {} column {}; choose from {}",
+                            kind.name(),
+                            name,
+                            available.join(", ")
+                        )));
+                    };
+                    if !columns.contains(column) {
+                        columns.push(*column);
+                    }
+                }
+                columns
+            }
+        };
+        if columns.is_empty() {
+            return Err(invalid_export("columns must name at least one column".to_string()));
+        }
+
+        let status = param("status").map(str::to_string);
+        let device = param("device").map(str::to_string);
+        if kind == ExportKind::Customers && (status.is_some() || device.is_some()) {
+            return Err(invalid_export("Customers can't be filtered by status or device".to_string()));
+        }
+
+        let (from, to) = (date("from")?, date("to")?);
+        if let (Some(from), Some(to)) = (from, to)
+            && from > to
+        {
+            return Err(invalid_export(format!("from {} is after to {}", from, to)));
+        }
+
+        let link = match param("delivery") {
+            None | Some("inline") => false,
+            Some("link") => true,
+            Some(other) => return Err(invalid_export(format!("delivery={} must be inline or link", other))),
+        };
+
+        Ok(Self {
+            kind,
+            format,
+            columns,
+            from,
+            to,
+            status,
+            device,
+            link,
+        })
+    }
+
+    fn in_range(&self, created_at: Option<&str>) -> bool {
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+        let Some(date) = created_at.and_then(|t| DateTime::parse_from_rfc3339(t).ok()).map(|t| t.date_naive()) else {
+            return false;
+        };
+        self.from.is_none_or(|from| date >= from) && self.to.is_none_or(|to| date <= to)
+    }
+
+    /// File name for the download, e.g. `tickets-2026-03-01-to-2026-03-31.csv`
+    pub fn file_name(&self, today: NaiveDate) -> String {
+        let range = match (self.from, self.to) {
+            (Some(from), Some(to)) => format!("{}-to-{}", from, to),
+            (Some(from), None) => format!("{}-to-{}", from, today),
+            (None, Some(to)) => format!("to-{}", to),
+            (None, None) => today.to_string(),
+        };
+        format!("{}-{}.{}", self.kind.name(), range, self.format.extension())
+    }
+}
+
+/// Header row plus one row of cells per record
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub columns: Vec<&'static str>,
+    pub rows: Vec<Vec<String>>,
+}
+
+fn ticket_device(ticket: &Ticket) -> String {
+    ticket
+        .properties
+        .intake_model()
+        .and_then(|intake| intake.device)
+        .unwrap_or_else(|| UNKNOWN_DEVICE.to_string())
+}
+
+fn ticket_cell(ticket: &Ticket, column: &str, map: &StatusMap) -> String {
+    let text = |value: &Option<String>| value.clone().unwrap_or_default();
+    match column {
+        "number" => ticket.number.to_string(),
+        "id" => ticket.id.to_string(),
+        "subject" => ticket.subject.clone(),
+        "status" => map.to_display(&ticket.status),
+        "customer_id" => ticket.customer_id.map(|id| id.to_string()).unwrap_or_default(),
+        "customer" => text(&ticket.customer_business_then_name),
+        "device" => ticket_device(ticket),
+        "technician" => ticket.user.as_ref().and_then(|u| u.full_name.clone()).unwrap_or_default(),
+        "problem_type" => text(&ticket.problem_type),
+        "created_at" => text(&ticket.created_at),
+        "due_date" => text(&ticket.due_date),
+        "resolved_at" => text(&ticket.resolved_at),
+        _ => String::new(),
+    }
+}
+
+fn customer_cell(customer: &Customer, column: &str) -> String {
+    let text = |value: &Option<String>| value.clone().unwrap_or_default();
+    let flag = |value: Option<bool>| value.map(|v| if v { "yes" } else { "no" }).unwrap_or_default().to_string();
+    match column {
+        "id" => customer.id.to_string(),
+        "name" => customer.fullname.clone().unwrap_or_else(|| {
+            let parts = [customer.firstname.as_deref(), customer.lastname.as_deref()];
+            parts.into_iter().flatten().collect::<Vec<_>>().join(" ")
+        }),
+        "business_name" => text(&customer.business_name),
+        "email" => text(&customer.email),
+        "phone" => text(&customer.phone),
+        "mobile" => text(&customer.mobile),
+        "sms_opt_in" => flag(customer.get_sms),
+        "no_email" => flag(customer.no_email),
+        "created_at" => text(&customer.created_at),
+        "notes" => text(&customer.notes),
+        _ => String::new(),
+    }
+}
+
+/// Filter tickets and lay them out in the requested columns, display statuses mapped
+pub fn ticket_table(tickets: &[Ticket], request: &ExportRequest, map: &StatusMap) -> Table {
+    let rows = tickets
+        .iter()
+        .filter(|ticket| request.in_range(ticket.created_at.as_deref()))
+        .filter(|ticket| {
+            request
+                .status
+                .as_ref()
+                .is_none_or(|status| map.to_display(&ticket.status).eq_ignore_ascii_case(status))
+        })
+        .filter(|ticket| {
+            request
+                .device
+                .as_ref()
+                .is_none_or(|device| ticket_device(ticket).eq_ignore_ascii_case(device))
+        })
+        .map(|ticket| request.columns.iter().map(|c| ticket_cell(ticket, c, map)).collect())
+        .collect();
+    Table {
+        columns: request.columns.clone(),
+        rows,
+    }
+}
+
+/// Filter customers and lay them out in the requested columns
+pub fn customer_table(customers: &[Customer], request: &ExportRequest) -> Table {
+    let rows = customers
+        .iter()
+        .filter(|customer| request.in_range(customer.created_at.as_deref()))
+        .map(|customer| request.columns.iter().map(|c| customer_cell(customer, c)).collect())
+        .collect();
+    Table {
+        columns: request.columns.clone(),
+        rows,
+    }
+}
+
+/// Quote text a spreadsheet would otherwise run as a formula (`=`, `+`, `-`, `@`, tab or
+/// carriage return first) with a leading `'`; numbers such as `-5` or `+15550100` are left alone
+fn csv_cell(value: &str) -> std::borrow::Cow<'_, str> {
+    let formula = value.starts_with(['=', '+', '-', '@', '\t', '\r']);
+    if formula && value.parse::<f64>().is_err() {
+        format!("'{}", value).into()
+    } else {
+        value.into()
+    }
+}
+
+/// Write the table as CSV with a header row; cells that look like formulas are neutralised
+pub fn write_csv(table: &Table) -> Result<Vec<u8>, String> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(&table.columns).map_err(|e| e.to_string())?;
+    for row in &table.rows {
+        writer
+            .write_record(row.iter().map(|value| csv_cell(value).into_owned()))
+            .map_err(|e| e.to_string())?;
+    }
+    writer.into_inner().map_err(|e| e.to_string())
+}
+
+/// Write the table as a single-sheet workbook with a bold, frozen header row. Text cells are
+/// written as strings, which Excel never evaluates, so they need no escaping.
+pub fn write_xlsx(table: &Table, sheet_name: &str) -> Result<Vec<u8>, String> {
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet();
+    sheet.set_name(sheet_name).map_err(|e| e.to_string())?;
+    let bold = Format::new().set_bold();
+
+    for (col, name) in table.columns.iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, *name, &bold).map_err(|e| e.to_string())?;
+    }
+    for (row, cells) in table.rows.iter().enumerate() {
+        let row = row as u32 + 1;
+        for (col, (value, name)) in cells.iter().zip(&table.columns).enumerate() {
+            let col = col as u16;
+            match value.parse::<f64>() {
+                Ok(number) if NUMERIC_COLUMNS.contains(name) => sheet.write_number(row, col, number),
+                _ => sheet.write_string(row, col, value),
+            }
+            .map_err(|e| e.to_string())?;
+        }
+    }
+    sheet.set_freeze_panes(1, 0).map_err(|e| e.to_string())?;
+    sheet.autofit();
+
+    workbook.save_to_buffer().map_err(|e| e.to_string())
+}
+
+fn env_number<T: std::str::FromStr>(var: &str, default: T) -> T {
+    std::env::var(var).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+/// Write the file to S3 and return a presigned link to it
+async fn upload_export(
+    s3_client: &S3Client,
+    file_name: &str,
+    format: ExportFormat,
+    bytes: Vec<u8>,
+) -> Result<(String, u64), ApiError> {
+    let bucket =
+        state_bucket().ok_or_else(|| ApiError::configuration("STATE_BUCKET must be set for exports delivered as links"))?;
+    let key = format!("exports/{}/{}", hex::encode(rand::random::<[u8; 8]>()), file_name);
+
+    s3_client
+        .put_object()
+        .bucket(&bucket)
+        .key(&key)
+        .content_type(format.content_type())
+        .content_disposition(format!("attachment; filename=\"{}\"", file_name))
+        .body(ByteStream::from(bytes))
+        .send()
+        .await
+        .map_err(|e| ApiError::storage(format!("Failed to upload {} to bucket {}: {:?}", key, bucket, e)))?;
+
+    let expires_in = env_number("EXPORT_LINK_SECONDS", DEFAULT_LINK_SECONDS);
+    let presigning = PresigningConfig::expires_in(Duration::from_secs(expires_in))
+        .map_err(|e| ApiError::configuration(format!("Invalid EXPORT_LINK_SECONDS: {}", e)))?;
+    let request = s3_client
+        .get_object()
+        .bucket(&bucket)
+        .key(&key)
+        .presigned(presigning)
+        .await
+        .map_err(|e| ApiError::storage(format!("Failed to sign a link for {}: {:?}", key, e)))?;
+    Ok((request.uri().to_string(), expires_in))
+}
+
+/// Refuse to export a partial listing rather than hand back a file with rows missing
+fn check_complete(truncated: bool, kind: ExportKind) -> Result<(), ApiError> {
+    if !truncated {
+        return Ok(());
+    }
+    Err(ApiError::new(
+        413,
+        "EXPORT_TOO_LARGE",
+        "There are more records than can be exported at once",
+        format!("RepairShopr has more than {} pages of {}", MAX_PAGES, kind.name()),
+    ))
+}
+
+/// Handle `GET /exports/{tickets|customers}`
+pub async fn handle_export(
+    event: &Request,
+    kind: ExportKind,
+    rs_client: &RepairShoprClient,
+    s3_client: &S3Client,
+) -> Result<Response<Body>, ApiError> {
+    let request = ExportRequest::from_event(event, kind)?;
+
+    let table = match kind {
+        ExportKind::Tickets => {
+            // Anything created in the range has been updated since it started
+            let query = TicketQuery {
+                since_updated_at: request.from.map(|from| format!("{}T00:00:00Z", from)),
+                ..Default::default()
+            };
+            let tickets = rs_client.list_all_tickets(&query, MAX_PAGES).await?;
+            check_complete(tickets.truncated, kind)?;
+            ticket_table(&tickets.items, &request, status_map())
+        }
+        ExportKind::Customers => {
+            let customers = rs_client.list_all_customers(&CustomerQuery::default(), MAX_PAGES).await?;
+            check_complete(customers.truncated, kind)?;
+            customer_table(&customers.items, &request)
+        }
+    };
+
+    let bytes = match request.format {
+        ExportFormat::Csv => write_csv(&table),
+        ExportFormat::Xlsx => write_xlsx(&table, kind.name()),
+    }
+    .map_err(|e| ApiError::new(500, "EXPORT_ERROR", "The export file could not be built", e))?;
+
+    let file_name = request.file_name(Utc::now().date_naive());
+    if !request.link && bytes.len() <= env_number("EXPORT_INLINE_MAX_BYTES", DEFAULT_INLINE_MAX_BYTES) {
+        return Ok(file_response(request.format.content_type(), "attachment", &file_name, bytes));
+    }
+
+    let size = bytes.len();
+    let (url, expires_in) = upload_export(s3_client, &file_name, request.format, bytes).await?;
+    let body = json!({
+        "url": url,
+        "file_name": file_name,
+        "rows": table.rows.len(),
+        "bytes": size,
+        "expires_in": expires_in,
+    });
+    Ok(success_response(200, body.to_string()))
+}
@@ -0,0 +1,64 @@
+//! Health check handler that verifies RepairShopr accepts our requests
+
+use lambda_http::{Body, Response};
+use serde_json::json;
+
+use crate::config::UpstreamConfig;
+use crate::http::success_response;
+
+/// Outcome of probing RepairShopr with the configured headers
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeResult {
+    pub user_agent: String,
+    pub upstream_status: Option<u16>,
+    pub accepted: bool,
+}
+
+/// Send a lightweight authenticated request to RepairShopr using the configured headers.
+///
+/// The agent counts as accepted unless RepairShopr (or the firewall in front of it) answers
+/// 403/406 or the request fails outright.
+pub async fn probe_upstream(api_key: &str, target_url: &str) -> ProbeResult {
+    let upstream = UpstreamConfig::from_env(target_url);
+
+    let result = reqwest::Client::new()
+        .get(format!("{}/me", target_url))
+        .header("Authorization", format!("Bearer {}", api_key))
+        .header("Accept", "application/json")
+        .header("User-Agent", &upstream.user_agent)
+        .header("Accept-Language", &upstream.accept_language)
+        .send()
+        .await;
+
+    match result {
+        Ok(response) => {
+            let status = response.status().as_u16();
+            ProbeResult {
+                user_agent: upstream.user_agent,
+                upstream_status: Some(status),
+                accepted: !matches!(status, 403 | 406),
+            }
+        }
+        Err(e) => {
+            eprintln!("RepairShopr health probe failed: {}", e);
+            ProbeResult {
+                user_agent: upstream.user_agent,
+                upstream_status: None,
+                accepted: false,
+            }
+        }
+    }
+}
+
+/// Handle the health check endpoint
+pub async fn handle_health(api_key: &str, target_url: &str) -> Response<Body> {
+    let probe = probe_upstream(api_key, target_url).await;
+
+    let response_body = json!({
+        "status": if probe.accepted { "ok" } else { "degraded" },
+        "user_agent": probe.user_agent,
+        "upstream_status": probe.upstream_status,
+    });
+
+    success_response(if probe.accepted { 200 } else { 503 }, response_body.to_string())
+}
@@ -0,0 +1,272 @@
+//! Structured ticket intake: validates the intake form and creates the RepairShopr ticket
+
+use std::sync::OnceLock;
+
+use chrono::Utc;
+use lambda_http::{Body, Request, Response};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::board::invalidate_board_cache;
+use super::customers::{validation_failed, FieldError};
+use super::tickets::TicketView;
+use crate::errors::ApiError;
+use crate::events::{EventBus, TicketEvent};
+use crate::http::{parse_json_body, success_response};
+use crate::repairshopr::{IntakeModel, NewTicket, RepairShoprClient, TicketProperties, INTAKE_MODEL_PREFIX};
+use crate::status::status_map;
+
+/// Device types offered on the intake form (matches the frontend's `DEVICES`)
+const DEFAULT_DEVICES: [&str; 8] = ["Phone", "Tablet", "Watch", "Console", "Laptop", "Desktop", "All in one", "Other"];
+
+/// Items a customer can leave with the device (matches the frontend's `ITEMS_LEFT`)
+const DEFAULT_ITEMS_LEFT: [&str; 5] = ["Charger", "Case", "Controller", "Bag", "Other"];
+
+/// RepairShopr ticket type whose custom properties hold the intake fields
+const DEFAULT_TICKET_TYPE_ID: i64 = 9818;
+
+/// Longest problem description RepairShopr accepts as a ticket subject
+const MAX_DESCRIPTION_CHARS: usize = 255;
+
+/// Allowed values for the intake form
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntakeVocabulary {
+    pub devices: Vec<String>,
+    pub items_left: Vec<String>,
+    pub ticket_type_id: i64,
+}
+
+impl Default for IntakeVocabulary {
+    fn default() -> Self {
+        Self {
+            devices: DEFAULT_DEVICES.iter().map(|d| d.to_string()).collect(),
+            items_left: DEFAULT_ITEMS_LEFT.iter().map(|i| i.to_string()).collect(),
+            ticket_type_id: DEFAULT_TICKET_TYPE_ID,
+        }
+    }
+}
+
+/// Split a comma-separated list, ignoring blanks
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+impl IntakeVocabulary {
+    /// Build the vocabulary from INTAKE_DEVICES, INTAKE_ITEMS_LEFT and INTAKE_TICKET_TYPE_ID,
+    /// read through `lookup`; anything unset or empty keeps its default.
+    pub fn resolve(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let mut vocabulary = Self::default();
+        if let Some(devices) = lookup("INTAKE_DEVICES").map(|v| parse_list(&v)).filter(|v| !v.is_empty()) {
+            vocabulary.devices = devices;
+        }
+        if let Some(items) = lookup("INTAKE_ITEMS_LEFT").map(|v| parse_list(&v)).filter(|v| !v.is_empty()) {
+            vocabulary.items_left = items;
+        }
+        if let Some(id) = lookup("INTAKE_TICKET_TYPE_ID").and_then(|v| v.trim().parse().ok()) {
+            vocabulary.ticket_type_id = id;
+        }
+        vocabulary
+    }
+
+    /// Load the vocabulary from the environment
+    pub fn from_env() -> Self {
+        Self::resolve(|name| std::env::var(name).ok())
+    }
+}
+
+/// Process-wide intake vocabulary, loaded once per cold start
+pub fn intake_vocabulary() -> &'static IntakeVocabulary {
+    static VOCABULARY: OnceLock<IntakeVocabulary> = OnceLock::new();
+    VOCABULARY.get_or_init(IntakeVocabulary::from_env)
+}
+
+/// Intake form as submitted
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct IntakeRequest {
+    pub customer_id: Option<i64>,
+    pub device: Option<String>,
+    #[serde(default, alias = "itemsLeft")]
+    pub items_left: Vec<String>,
+    /// Device passcode or PIN
+    #[serde(default, alias = "password")]
+    pub passcode: Option<String>,
+    /// Unlock pattern as the sequence of grid points touched (1-9, left to right, top to bottom)
+    #[serde(default)]
+    pub pattern: Option<Vec<u8>>,
+    #[serde(default, alias = "problemDescription", alias = "subject")]
+    pub problem_description: Option<String>,
+    #[serde(default, alias = "estimatedTime")]
+    pub estimated_time: Option<String>,
+    #[serde(default)]
+    pub user_id: Option<i64>,
+}
+
+/// Case-insensitive lookup returning the vocabulary's own spelling
+fn canonical<'a>(value: &str, allowed: &'a [String]) -> Option<&'a String> {
+    allowed.iter().find(|a| a.eq_ignore_ascii_case(value.trim()))
+}
+
+/// Check an unlock pattern: 4 to 9 distinct grid points, each 1-9
+fn validate_pattern(pattern: &[u8]) -> Result<(), String> {
+    if !(4..=9).contains(&pattern.len()) {
+        return Err("An unlock pattern must connect between 4 and 9 points".to_string());
+    }
+    if let Some(point) = pattern.iter().find(|p| !(1..=9).contains(*p)) {
+        return Err(format!("Pattern point {} is outside the 3x3 grid (1-9)", point));
+    }
+    let mut seen = [false; 10];
+    for point in pattern {
+        if seen[usize::from(*point)] {
+            return Err(format!("Pattern point {} is used more than once", point));
+        }
+        seen[usize::from(*point)] = true;
+    }
+    Ok(())
+}
+
+/// Validate an intake form and map it onto a RepairShopr ticket.
+///
+/// Mirrors what the frontend's ticket editor sends: the problem description is the
+/// subject, the lock is stored in `Password` ("n" when there is none, `pattern:1-5-9-6`
+/// for patterns), `AC Charger` records whether a charger was left, and the device and
+/// items left go in `Model` as `vT{json}`.
+pub fn build_intake_ticket(request: IntakeRequest, vocabulary: &IntakeVocabulary) -> Result<NewTicket, Vec<FieldError>> {
+    let mut errors = vec![];
+
+    let customer_id = request.customer_id.filter(|id| *id > 0);
+    if customer_id.is_none() {
+        errors.push(FieldError {
+            field: "customer_id",
+            message: "A customer is required".to_string(),
+        });
+    }
+
+    let device = match request.device.as_deref().map(str::trim).filter(|d| !d.is_empty()) {
+        None => {
+            errors.push(FieldError {
+                field: "device",
+                message: "A device type is required".to_string(),
+            });
+            None
+        }
+        Some(device) => match canonical(device, &vocabulary.devices) {
+            Some(device) => Some(device.clone()),
+            None => {
+                errors.push(FieldError {
+                    field: "device",
+                    message: format!("'{}' is not one of: {}", device, vocabulary.devices.join(", ")),
+                });
+                None
+            }
+        },
+    };
+
+    let mut items_left: Vec<String> = vec![];
+    for item in &request.items_left {
+        match canonical(item, &vocabulary.items_left) {
+            Some(item) if !items_left.contains(item) => items_left.push(item.clone()),
+            Some(_) => {}
+            None => errors.push(FieldError {
+                field: "items_left",
+                message: format!("'{}' is not one of: {}", item.trim(), vocabulary.items_left.join(", ")),
+            }),
+        }
+    }
+
+    let passcode = request.passcode.map(|p| p.trim().to_string()).filter(|p| !p.is_empty());
+    let password = match (passcode, request.pattern) {
+        (Some(_), Some(_)) => {
+            errors.push(FieldError {
+                field: "pattern",
+                message: "Send either a passcode or a pattern, not both".to_string(),
+            });
+            None
+        }
+        (Some(passcode), None) => Some(passcode),
+        (None, Some(pattern)) => match validate_pattern(&pattern) {
+            Ok(()) => {
+                let points: Vec<String> = pattern.iter().map(|p| p.to_string()).collect();
+                Some(format!("pattern:{}", points.join("-")))
+            }
+            Err(message) => {
+                errors.push(FieldError { field: "pattern", message });
+                None
+            }
+        },
+        (None, None) => Some("n".to_string()),
+    };
+
+    let description = request
+        .problem_description
+        .map(|d| d.split_whitespace().collect::<Vec<_>>().join(" "))
+        .unwrap_or_default();
+    if description.is_empty() {
+        errors.push(FieldError {
+            field: "problem_description",
+            message: "Describe the problem with the device".to_string(),
+        });
+    } else if description.chars().count() > MAX_DESCRIPTION_CHARS {
+        errors.push(FieldError {
+            field: "problem_description",
+            message: format!("The problem description must be at most {} characters", MAX_DESCRIPTION_CHARS),
+        });
+    }
+
+    let (Some(customer_id), Some(device), Some(password), true) = (customer_id, device, password, errors.is_empty())
+    else {
+        return Err(errors);
+    };
+
+    let model = IntakeModel {
+        device: Some(device),
+        items_left,
+        estimated_time: request.estimated_time.map(|t| t.trim().to_string()).filter(|t| !t.is_empty()),
+    };
+    Ok(NewTicket {
+        customer_id,
+        subject: description,
+        status: Some("New".to_string()),
+        problem_type: Some("Other".to_string()),
+        ticket_type_id: Some(vocabulary.ticket_type_id),
+        user_id: request.user_id,
+        due_date: Some(Utc::now().to_rfc3339()),
+        properties: TicketProperties {
+            password: Some(password),
+            ac_charger: Some(if model.items_left.iter().any(|i| i == "Charger") { "1" } else { "0" }.to_string()),
+            model: Some(format!(
+                "{}{}",
+                INTAKE_MODEL_PREFIX,
+                serde_json::to_string_pretty(&model).unwrap_or_default()
+            )),
+            ..Default::default()
+        },
+    })
+}
+
+/// Handle creating a ticket from the intake form
+pub async fn handle_ticket_intake(
+    event: &Request,
+    rs_client: &RepairShoprClient,
+    events: &EventBus,
+) -> Result<Response<Body>, ApiError> {
+    let request = parse_json_body::<IntakeRequest>(event)?;
+    let ticket = match build_intake_ticket(request, intake_vocabulary()) {
+        Ok(ticket) => ticket,
+        Err(errors) => return Ok(validation_failed(&errors)),
+    };
+
+    let created = rs_client.create_ticket(&ticket).await?;
+    invalidate_board_cache();
+
+    let body = json!({
+        "number": created.number,
+        "ticket": TicketView::from_ticket(&created, status_map()),
+    });
+    events.publish(&TicketEvent::TicketCreated { ticket: created }).await;
+    Ok(success_response(201, body.to_string()))
+}
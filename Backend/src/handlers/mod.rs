@@ -1,10 +1,48 @@
 //! Handler modules for Lambda function
 
+pub mod approvals;
 pub mod attachments;
+pub mod board;
+pub mod bulk;
+pub mod comments;
+pub mod customers;
+pub mod duplicates;
+pub mod exports;
+pub mod health;
+pub mod intake;
+pub mod notifications;
 pub mod proxy;
+pub mod public_status;
+pub mod push;
+pub mod receipts;
+pub mod reports;
+pub mod search;
+pub mod share_links;
+pub mod statuses;
+pub mod tickets;
 pub mod user_management;
+pub mod webhooks;
 
 // Re-export handler functions for convenience
+pub use approvals::{decide_approval, handle_create_approval, view_approval, ApprovalContext};
 pub use attachments::handle_upload_attachment;
-pub use proxy::handle_repairshopr_proxy;
+pub use board::handle_ticket_board;
+pub use bulk::handle_bulk_tickets;
+pub use comments::{handle_add_comment, handle_list_comment_templates};
+pub use customers::{handle_create_customer, handle_update_customer};
+pub use duplicates::{handle_find_duplicates, handle_merge_customers};
+pub use exports::{handle_export, ExportKind};
+pub use health::{handle_health, probe_upstream};
+pub use intake::handle_ticket_intake;
+pub use notifications::{handle_flush_sms, handle_set_sms_opt_out};
+pub use proxy::{handle_repairshopr_proxy, status_write_target, ticket_write_event};
+pub use public_status::handle_public_status;
+pub use push::{handle_broadcast_event, handle_socket_event, handle_socket_token, is_broadcast_event, is_socket_event};
+pub use receipts::handle_print_ticket;
+pub use reports::handle_reports;
+pub use search::handle_search;
+pub use share_links::{handle_create_share_link, handle_revoke_share_link};
+pub use statuses::handle_list_statuses;
+pub use tickets::handle_ticket_detail;
 pub use user_management::{handle_user_invitation, handle_list_users, handle_update_user_group};
+pub use webhooks::{handle_repairshopr_webhook, WebhookContext};
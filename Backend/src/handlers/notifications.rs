@@ -0,0 +1,60 @@
+//! Staff endpoints for customer notifications: SMS opt-outs and held messages
+
+use chrono::Utc;
+use lambda_http::{Body, Request, Response};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::audit::{AuditEntry, AuditStore};
+use crate::auth::UserIdentity;
+use crate::contact::{default_phone_region, normalize_phone};
+use crate::errors::ApiError;
+use crate::http::{parse_json_body, success_response};
+use crate::notifications::sms::{OptOutStore, SmsNotifier, SmsOutcome};
+
+/// Request body for `POST /notifications/sms/opt-outs`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SmsOptOutRequest {
+    pub phone: String,
+    #[serde(default = "default_opted_out")]
+    pub opted_out: bool,
+}
+
+fn default_opted_out() -> bool {
+    true
+}
+
+/// Handle `POST /notifications/sms/opt-outs`: stop (or resume) texting a number
+pub async fn handle_set_sms_opt_out(
+    event: &Request,
+    opt_outs: &dyn OptOutStore,
+    audit: &dyn AuditStore,
+    actor: &UserIdentity,
+) -> Result<Response<Body>, ApiError> {
+    let body = parse_json_body::<SmsOptOutRequest>(event)?;
+    let phone = normalize_phone(&body.phone, &default_phone_region())
+        .map_err(|e| ApiError::new(400, "INVALID_PHONE", "That phone number is not valid", e))?;
+
+    opt_outs
+        .set_opted_out(&phone, body.opted_out)
+        .await
+        .map_err(|e| ApiError::new(500, "OPT_OUT_STORE_ERROR", "The opt-out could not be saved", e))?;
+
+    let action = if body.opted_out { "sms.opt_out" } else { "sms.opt_in" };
+    let entry = AuditEntry::new(&actor.actor(), action, &format!("phone:{}", phone), json!({}));
+    if let Err(e) = audit.record(entry).await {
+        eprintln!("Failed to record audit entry for SMS opt-out: {}", e);
+    }
+
+    Ok(success_response(200, json!({ "phone": phone, "opted_out": body.opted_out }).to_string()))
+}
+
+/// Handle `POST /notifications/sms/flush`: send texts held for quiet hours, if they are over
+pub async fn handle_flush_sms(notifier: &SmsNotifier) -> Response<Body> {
+    let outcomes = notifier.flush_deferred(Utc::now()).await;
+    let sent = outcomes.iter().filter(|o| matches!(o, SmsOutcome::Sent { .. })).count();
+    success_response(
+        200,
+        json!({ "sent": sent, "not_sent": outcomes.len() - sent }).to_string(),
+    )
+}
@@ -3,36 +3,177 @@
 use lambda_http::{Body, Request, RequestExt, Response};
 use serde_json::Value;
 
-use crate::http::success_response;
+use crate::config::UpstreamConfig;
+use crate::errors::ApiError;
+use crate::events::TicketEvent;
+use crate::http::{error_response, success_response};
+use crate::repairshopr::Ticket;
+use crate::status::status_map;
 
+/// Default cap on forwarded request bodies (1 MiB); override with MAX_PROXY_BODY_BYTES
+const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
 
-/// Handle proxying requests to RepairShopr API
-pub async fn handle_repairshopr_proxy(
+/// Request body prepared for forwarding upstream
+#[derive(Debug, PartialEq)]
+pub struct ProxyBody {
+    pub bytes: Vec<u8>,
+    pub content_type: String,
+}
+
+/// Reasons a proxied request body is refused before reaching RepairShopr
+#[derive(Debug, PartialEq)]
+pub enum BodyRejection {
+    TooLarge { size: usize, limit: usize },
+    InvalidJson,
+}
+
+impl BodyRejection {
+    /// Convert the rejection into a client-facing error response
+    pub fn into_response(self) -> Response<Body> {
+        match self {
+            BodyRejection::TooLarge { size, limit } => error_response(
+                413,
+                "Payload too large",
+                &format!("Request body is {} bytes; the limit is {} bytes", size, limit),
+                None,
+            ),
+            BodyRejection::InvalidJson => error_response(
+                400,
+                "Invalid JSON",
+                "Could not parse request body as JSON",
+                Some("Send a valid JSON body or set an explicit non-JSON Content-Type"),
+            ),
+        }
+    }
+}
+
+/// Ticket id of a proxied `PUT /tickets/{id}` whose body sets a status
+pub fn status_write_target(event: &Request, path: &str) -> Option<i64> {
+    if event.method().as_str() != "PUT" {
+        return None;
+    }
+    let id = path.strip_prefix("/tickets/")?.trim_end_matches('/').parse().ok()?;
+    let json = serde_json::from_slice::<Value>(event.body().as_ref()).ok()?;
+    let target = json.get("ticket").filter(|t| t.is_object()).unwrap_or(&json);
+    target.get("status").is_some_and(|s| s.is_string()).then_some(id)
+}
+
+/// Ticket returned by a successful proxied ticket write, with its RepairShopr status
+pub fn written_ticket(response: &Response<Body>) -> Option<Ticket> {
+    if !response.status().is_success() {
+        return None;
+    }
+    let body = match response.body() {
+        Body::Text(text) => text.as_bytes(),
+        Body::Binary(bytes) => bytes.as_slice(),
+        _ => return None,
+    };
+    let mut ticket = serde_json::from_slice::<Value>(body).ok()?.get("ticket")?.clone();
+    // Display-status responses keep the upstream value alongside
+    if let Some(status) = ticket.get("repairshopr_status").cloned() {
+        ticket["status"] = status;
+    }
+    serde_json::from_value(ticket).ok()
+}
+
+/// Event for a proxied ticket write: new tickets and status saves carry the returned
+/// ticket (`previous_status` is what the ticket had before), other writes under
+/// `/tickets/{id}` just name the ticket
+pub fn ticket_write_event(
     event: &Request,
     path: &str,
-    api_key: &str,
-    target_url: &str,
-) -> Result<Response<Body>, String> {
+    response: &Response<Body>,
+    previous_status: Option<String>,
+) -> Option<TicketEvent> {
     let method = event.method().as_str();
+    if method == "GET" || !response.status().is_success() {
+        return None;
+    }
+    if method == "POST" && path.trim_end_matches('/') == "/tickets" {
+        return written_ticket(response).map(|ticket| TicketEvent::TicketCreated { ticket });
+    }
+    if status_write_target(event, path).is_some()
+        && let Some(ticket) = written_ticket(response)
+    {
+        return Some(TicketEvent::StatusChanged { ticket, previous_status });
+    }
+    let ticket_id = path.strip_prefix("/tickets/")?.split('/').next()?.parse().ok()?;
+    Some(TicketEvent::Changed { ticket_id })
+}
 
-    // Extract If-Modified-Since header if present (used for conditional polling)
-    let if_modified_since = event
+/// Maximum request body size the proxy will forward
+pub fn max_body_bytes() -> usize {
+    std::env::var("MAX_PROXY_BODY_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES)
+}
+
+/// Validate the request body before it is sent to RepairShopr.
+///
+/// Bodies over `max_bytes` are rejected with 413. JSON bodies (no content type, or any
+/// `application/json` variant) must parse, otherwise 400. Anything else is forwarded
+/// byte-for-byte with its original content type.
+pub fn prepare_body(event: &Request, max_bytes: usize) -> Result<Option<ProxyBody>, BodyRejection> {
+    let bytes: &[u8] = match event.body() {
+        Body::Empty => return Ok(None),
+        Body::Text(s) => s.as_bytes(),
+        Body::Binary(b) => b,
+        _ => return Ok(None),
+    };
+
+    if bytes.len() > max_bytes {
+        return Err(BodyRejection::TooLarge {
+            size: bytes.len(),
+            limit: max_bytes,
+        });
+    }
+
+    let content_type = event
         .headers()
-        .get("if-modified-since")
+        .get("content-type")
         .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string());
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "application/json".to_string());
 
-    // Extract request body
-    let body = match event.body() {
-        Body::Empty => None,
-        Body::Text(s) => Some(s.clone()),
-        Body::Binary(b) => Some(String::from_utf8_lossy(b).to_string()),
-        _ => None,
-    };
+    let is_json = content_type
+        .split(';')
+        .next()
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"));
 
-    // Build the full URL with query parameters
+    if is_json && serde_json::from_slice::<Value>(bytes).is_err() {
+        return Err(BodyRejection::InvalidJson);
+    }
+
+    Ok(Some(ProxyBody {
+        bytes: bytes.to_vec(),
+        content_type,
+    }))
+}
+
+/// Whether the caller asked for display statuses with `X-Status-Mapping: display`
+pub fn wants_display_statuses(event: &Request) -> bool {
+    event
+        .headers()
+        .get("x-status-mapping")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("display"))
+}
+
+/// Build the upstream RepairShopr URL for a proxied request.
+///
+/// The raw query string is forwarded untouched when the event carries one, which keeps
+/// parameter order and repeated keys (`status[]=New&status[]=In%20Progress`) intact.
+/// Otherwise the query is rebuilt from the parsed parameters, including every value of
+/// multi-value keys.
+pub fn build_upstream_url(event: &Request, path: &str, target_url: &str) -> String {
     let mut url = format!("{}{}", target_url, path);
-    if let Some(params) = event.query_string_parameters_ref() {
+
+    if let Some(raw_query) = event.uri().query().filter(|q| !q.is_empty()) {
+        url.push('?');
+        url.push_str(raw_query);
+    } else if let Some(params) = event.query_string_parameters_ref() {
         let query_parts: Vec<String> = params
             .iter()
             .map(|(k, v)| {
@@ -50,6 +191,44 @@ pub async fn handle_repairshopr_proxy(
         }
     }
 
+    url
+}
+
+/// Handle proxying requests to RepairShopr API
+pub async fn handle_repairshopr_proxy(
+    event: &Request,
+    path: &str,
+    api_key: &str,
+    target_url: &str,
+) -> Result<Response<Body>, ApiError> {
+    let method = event.method().as_str();
+
+    // Extract If-Modified-Since header if present (used for conditional polling)
+    let if_modified_since = event
+        .headers()
+        .get("if-modified-since")
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string());
+
+    // Extract and validate request body
+    let mut body = match prepare_body(event, max_body_bytes()) {
+        Ok(body) => body,
+        Err(rejection) => return Ok(rejection.into_response()),
+    };
+
+    // Translate display statuses back to RepairShopr statuses on ticket writes
+    let map_statuses = wants_display_statuses(event) && path.starts_with("/tickets");
+    if map_statuses
+        && let Some(body) = body.as_mut()
+        && let Ok(mut json) = serde_json::from_slice::<Value>(&body.bytes)
+    {
+        status_map().translate_request(&mut json);
+        body.bytes = json.to_string().into_bytes();
+    }
+
+    // Build the full URL with query parameters
+    let url = build_upstream_url(event, path, target_url);
+
     // Create HTTP client and build request
     let client = reqwest::Client::new();
 
@@ -59,24 +238,31 @@ pub async fn handle_repairshopr_proxy(
         "PUT" => client.put(&url),
         "DELETE" => client.delete(&url),
         "PATCH" => client.patch(&url),
-        _ => return Err(format!("Unsupported HTTP method: {}", method)),
+        _ => {
+            return Ok(error_response(
+                405,
+                "Method not allowed",
+                &format!("Method '{}' cannot be proxied", method),
+                None,
+            ))
+        }
     };
 
-    // Add standard headers because the API doesn't like it if you don't have them
+    // Add standard headers; the identifying agent and language come from configuration
+    let upstream = UpstreamConfig::from_env(target_url);
     request_builder = request_builder
         .header("Authorization", format!("Bearer {}", api_key))
         .header("Accept", "application/json")
-        .header("Content-Type", "application/json")
-        .header(
-            "User-Agent",
-            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/140.0.0.0 Safari/537.36",
-        )
-        .header("Accept-Language", "en-US,en;q=0.9");
-
-    // Add body if present
-    if let Some(body_content) = body {
-        request_builder = request_builder.body(body_content);
-    }
+        .header("User-Agent", upstream.user_agent)
+        .header("Accept-Language", upstream.accept_language);
+
+    // Add body if present, keeping its original content type
+    request_builder = match body {
+        Some(body) => request_builder
+            .header("Content-Type", body.content_type)
+            .body(body.bytes),
+        None => request_builder.header("Content-Type", "application/json"),
+    };
 
     // Send request
     match request_builder.send().await {
@@ -107,14 +293,17 @@ pub async fn handle_repairshopr_proxy(
                 }
             }
 
+            // Translate RepairShopr statuses to display statuses on ticket reads
+            if map_statuses && let Ok(mut response_json) = serde_json::from_str::<Value>(&response_body) {
+                status_map().translate_response(&mut response_json);
+                return Ok(success_response(status, response_json.to_string()));
+            }
+
             Ok(success_response(status, response_body))
         }
-        Err(e) => {
-            let suggestion = format!(
-                "Failed to send {} request to {}. Error: {}",
-                method, url, e
-            );
-            Err(suggestion)
-        }
+        Err(e) => Err(ApiError::upstream_unavailable(format!(
+            "Failed to send {} request to {}. Error: {}",
+            method, url, e
+        ))),
     }
 }
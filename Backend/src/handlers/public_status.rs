@@ -0,0 +1,348 @@
+//! Public, unauthenticated ticket status lookup for customers
+//!
+//! A customer proves they own a ticket with its number and the last four digits of the
+//! phone on file. The response is deliberately minimal: display status, when the ticket
+//! last changed and whether it is ready to collect. Wrong guesses count against the
+//! caller's IP and lock it out for a while, and every miss returns the same error so
+//! the endpoint can't be used to discover which ticket numbers exist.
+//!
+//! Staff can also text a signed share link (`?token=...`, see [`crate::share`]), which
+//! skips the phone check and may additionally show the approved estimate and the
+//! attachments marked as not private.
+
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use lambda_http::{Body, Request, RequestExt, Response};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::contact::national_phone_digits;
+use crate::errors::ApiError;
+use crate::http::{client_ip, success_response};
+use crate::rate_limit::{RateDecision, RateLimiter};
+use crate::repairshopr::{Attachment, Estimate, RepairShoprClient, RepairShoprError, Ticket, TicketQuery};
+use crate::share::{share_secret, verify_token, RevocationStore, ShareClaims, ShareError, ShareScope};
+use crate::status::{status_map, StatusMap};
+
+/// Display status that means the device can be collected
+pub const READY_DISPLAY_STATUS: &str = "Ready";
+
+/// Lookups allowed per IP each minute, and wrong guesses allowed before a lockout
+pub static PUBLIC_LIMITER: LazyLock<RateLimiter> = LazyLock::new(|| {
+    RateLimiter::new(
+        30,
+        Duration::from_secs(60),
+        5,
+        Duration::from_secs(15 * 60),
+        Duration::from_secs(15 * 60),
+    )
+});
+
+/// What a customer is allowed to see about their ticket
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PublicStatus {
+    pub number: i64,
+    pub status: String,
+    pub last_updated: Option<String>,
+    pub ready_for_pickup: bool,
+}
+
+impl PublicStatus {
+    /// Redact a ticket down to the public fields
+    pub fn from_ticket(ticket: &Ticket, map: &StatusMap) -> Self {
+        let status = map.to_display(&ticket.status);
+        Self {
+            number: ticket.number,
+            ready_for_pickup: status == READY_DISPLAY_STATUS,
+            status,
+            last_updated: ticket.updated_at.clone(),
+        }
+    }
+}
+
+/// Estimate details a share link may show
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SharedEstimate {
+    pub number: Option<String>,
+    pub status: Option<String>,
+    pub total: Option<serde_json::Value>,
+}
+
+/// Attachment a share link may show
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SharedAttachment {
+    pub file_name: Option<String>,
+    pub url: Option<String>,
+    pub created_at: Option<String>,
+}
+
+/// What a share link shows, depending on its scopes
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SharedTicket {
+    pub ticket: PublicStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimate: Option<SharedEstimate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attachments: Option<Vec<SharedAttachment>>,
+}
+
+/// Attachments staff explicitly marked as not private
+pub fn customer_visible_attachments(attachments: &[Attachment]) -> Vec<SharedAttachment> {
+    attachments
+        .iter()
+        .filter(|a| a.private == Some(false))
+        .map(|a| SharedAttachment {
+            file_name: a.file_name.clone(),
+            url: a.file.as_ref().and_then(|f| f.url.clone()),
+            created_at: a.created_at.clone(),
+        })
+        .collect()
+}
+
+/// Most recent approved estimate for the ticket
+pub fn approved_estimate(estimates: &[Estimate], ticket_id: i64) -> Option<SharedEstimate> {
+    estimates
+        .iter()
+        .filter(|e| e.ticket_id == Some(ticket_id) && e.is_approved())
+        .max_by_key(|e| e.id)
+        .map(|e| SharedEstimate {
+            number: e.number.clone(),
+            status: e.status.clone(),
+            total: e.total.clone(),
+        })
+}
+
+/// Find the ticket with `number` whose customer's phone or mobile ends in `last_four`
+pub async fn lookup_ticket(
+    rs_client: &RepairShoprClient,
+    number: i64,
+    last_four: &str,
+) -> Result<Option<Ticket>, RepairShoprError> {
+    let query = TicketQuery {
+        number: Some(number.to_string()),
+        ..Default::default()
+    };
+    let tickets = match rs_client.list_tickets(&query, 1).await {
+        Ok(page) => page.items,
+        Err(RepairShoprError::Status { status: 404, .. }) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let Some(ticket) = tickets.into_iter().find(|t| t.number == number) else {
+        return Ok(None);
+    };
+    let Some(customer_id) = ticket.customer_id else {
+        return Ok(None);
+    };
+
+    let customer = match rs_client.get_customer(customer_id).await {
+        Ok(customer) => customer,
+        Err(RepairShoprError::Status { status: 404, .. }) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let matches = [&customer.phone, &customer.mobile]
+        .into_iter()
+        .flatten()
+        .map(|p| national_phone_digits(p))
+        .any(|digits| digits.len() >= 4 && digits.ends_with(last_four));
+
+    Ok(matches.then_some(ticket))
+}
+
+/// 429 response carrying Retry-After
+pub fn too_many_requests(retry_after: Duration, request_id: &str) -> Response<Body> {
+    let mut response = ApiError::new(
+        429,
+        "TOO_MANY_ATTEMPTS",
+        "Too many attempts, please try again later",
+        format!("Client limited for {}s", retry_after.as_secs()),
+    )
+    .into_response(request_id);
+    if let Ok(value) = retry_after.as_secs().max(1).to_string().parse() {
+        response.headers_mut().insert("Retry-After", value);
+    }
+    response
+}
+
+/// Same answer for unknown tickets and wrong phone digits
+fn no_match() -> ApiError {
+    ApiError::new(
+        404,
+        "NOT_FOUND",
+        "No ticket matches that number and phone",
+        "Public status lookup did not match".to_string(),
+    )
+}
+
+/// JSON response for public data, never cached by browsers or proxies
+pub fn no_store_response(body: String) -> Response<Body> {
+    let mut response = success_response(200, body);
+    if let Ok(value) = "no-store".parse() {
+        response.headers_mut().insert("Cache-Control", value);
+    }
+    response
+}
+
+/// Look up a ticket by `ticket` number and `phone` (last four digits)
+pub async fn lookup_public_status(
+    event: &Request,
+    rs_client: &RepairShoprClient,
+    limiter: &RateLimiter,
+    request_id: &str,
+) -> Result<Response<Body>, ApiError> {
+    let client = client_ip(event);
+    let now = Instant::now();
+    if let RateDecision::Limited { retry_after } = limiter.check(&client, now) {
+        return Ok(too_many_requests(retry_after, request_id));
+    }
+
+    let params = event.query_string_parameters();
+    let number = params
+        .first("ticket")
+        .map(|n| n.trim().trim_start_matches('#'))
+        .and_then(|n| n.parse::<i64>().ok())
+        .filter(|n| *n > 0);
+    let last_four = params
+        .first("phone")
+        .map(|p| p.trim())
+        .filter(|p| p.len() == 4 && p.chars().all(|c| c.is_ascii_digit()));
+    let (Some(number), Some(last_four)) = (number, last_four) else {
+        return Err(ApiError::new(
+            400,
+            "INVALID_LOOKUP",
+            "Enter your ticket number and the last 4 digits of your phone",
+            "Missing or malformed 'ticket' or 'phone' parameter".to_string(),
+        ));
+    };
+
+    match lookup_ticket(rs_client, number, last_four).await? {
+        Some(ticket) => Ok(no_store_response(
+            json!({ "ticket": PublicStatus::from_ticket(&ticket, status_map()) }).to_string(),
+        )),
+        None => {
+            if limiter.record_failure(&client, now) {
+                eprintln!("Public status lookups locked for {}", client);
+            }
+            Err(no_match())
+        }
+    }
+}
+
+/// What is needed to check a share link
+pub struct ShareLinkCheck<'a> {
+    pub secret: &'a [u8],
+    pub revocations: &'a dyn RevocationStore,
+    /// Current time in unix seconds
+    pub now: i64,
+}
+
+/// Why a share link request was turned away
+#[derive(Debug)]
+pub enum LinkRejection {
+    Limited(Duration),
+    Error(ApiError),
+}
+
+impl LinkRejection {
+    pub fn into_response(self, request_id: &str) -> Response<Body> {
+        match self {
+            LinkRejection::Limited(retry_after) => too_many_requests(retry_after, request_id),
+            LinkRejection::Error(error) => error.into_response(request_id),
+        }
+    }
+}
+
+impl From<ApiError> for LinkRejection {
+    fn from(error: ApiError) -> Self {
+        LinkRejection::Error(error)
+    }
+}
+
+/// Rate limit the caller, then verify the token and make sure it hasn't been revoked.
+///
+/// Forged or garbled tokens count as failed guesses against the caller's IP.
+pub async fn authorize_link(
+    event: &Request,
+    token: &str,
+    limiter: &RateLimiter,
+    check: &ShareLinkCheck<'_>,
+) -> Result<ShareClaims, LinkRejection> {
+    let client = client_ip(event);
+    if let RateDecision::Limited { retry_after } = limiter.check(&client, Instant::now()) {
+        return Err(LinkRejection::Limited(retry_after));
+    }
+
+    let claims = match verify_token(token, check.secret, check.now) {
+        Ok(claims) => claims,
+        Err(error) => {
+            if matches!(error, ShareError::Malformed | ShareError::BadSignature) {
+                limiter.record_failure(&client, Instant::now());
+            }
+            return Err(ApiError::from(error).into());
+        }
+    };
+    let revoked = check.revocations.is_revoked(&claims.link_id).await.map_err(|e| {
+        ApiError::new(500, "SHARE_LINK_STORE_ERROR", "The link could not be checked", e)
+    })?;
+    if revoked {
+        return Err(ApiError::from(ShareError::Revoked).into());
+    }
+    Ok(claims)
+}
+
+/// Show the ticket behind a share link token
+pub async fn view_shared_ticket(
+    event: &Request,
+    token: &str,
+    rs_client: &RepairShoprClient,
+    limiter: &RateLimiter,
+    check: &ShareLinkCheck<'_>,
+    request_id: &str,
+) -> Result<Response<Body>, ApiError> {
+    let claims = match authorize_link(event, token, limiter, check).await {
+        Ok(claims) => claims,
+        Err(LinkRejection::Limited(retry_after)) => return Ok(too_many_requests(retry_after, request_id)),
+        Err(LinkRejection::Error(error)) => return Err(error),
+    };
+
+    let ticket = rs_client.get_ticket(claims.ticket_id).await?;
+    let estimate = match (claims.allows(ShareScope::Estimate), ticket.customer_id) {
+        (true, Some(customer_id)) => {
+            let estimates = rs_client.list_customer_estimates(customer_id).await?;
+            approved_estimate(&estimates, ticket.id)
+        }
+        _ => None,
+    };
+    let attachments = claims
+        .allows(ShareScope::Attachments)
+        .then(|| customer_visible_attachments(&ticket.attachments));
+
+    let shared = SharedTicket {
+        ticket: PublicStatus::from_ticket(&ticket, status_map()),
+        estimate,
+        attachments,
+    };
+    Ok(no_store_response(json!(shared).to_string()))
+}
+
+/// Handle `GET /public/status`, by share link `token` or by `ticket` and `phone`
+pub async fn handle_public_status(
+    event: &Request,
+    rs_client: &RepairShoprClient,
+    revocations: &dyn RevocationStore,
+    request_id: &str,
+) -> Result<Response<Body>, ApiError> {
+    let token = event.query_string_parameters().first("token").map(str::to_string);
+    match token {
+        Some(token) => {
+            let secret = share_secret()?;
+            let check = ShareLinkCheck {
+                secret: &secret,
+                revocations,
+                now: chrono::Utc::now().timestamp(),
+            };
+            view_shared_ticket(event, &token, rs_client, &PUBLIC_LIMITER, &check, request_id).await
+        }
+        None => lookup_public_status(event, rs_client, &PUBLIC_LIMITER, request_id).await,
+    }
+}
@@ -0,0 +1,178 @@
+//! WebSocket routes for the push channel: connect, disconnect and subscription messages,
+//! plus `POST /push/token` for the token a browser connects with, and the asynchronous
+//! invocations that carry broadcasts
+
+use lambda_http::request::RequestContext;
+use lambda_http::{Body, Request, RequestExt, Response};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::auth::{get_user_identity_from_event, UserIdentity};
+use crate::http::{error_response, success_response};
+use crate::push::{
+    sign_socket_token, ticket_topic, verify_socket_token, Broadcaster, ConnectionStore, PendingBroadcast, ALL_TICKETS,
+    BROADCAST_EVENT_TYPE, SOCKET_TOKEN_SECONDS, TopicUpdate,
+};
+
+/// Most tickets one connection may follow individually
+const MAX_TICKET_SUBSCRIPTIONS: usize = 100;
+
+/// A message sent by the browser, e.g. `{"action": "subscribe", "tickets": [42]}`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum SocketMessage {
+    Subscribe {
+        #[serde(default)]
+        tickets: Vec<i64>,
+        /// Follow every ticket (the board view)
+        #[serde(default)]
+        all: bool,
+    },
+    Unsubscribe {
+        #[serde(default)]
+        tickets: Vec<i64>,
+        #[serde(default)]
+        all: bool,
+    },
+    Ping,
+}
+
+/// Whether the request is an API Gateway WebSocket event rather than HTTP
+pub fn is_socket_event(event: &Request) -> bool {
+    matches!(event.request_context_ref(), Some(RequestContext::WebSocket(_)))
+}
+
+/// Whether the event is a broadcast handed off by [`Broadcaster`] rather than a browser's
+pub fn is_broadcast_event(event: &Request) -> bool {
+    matches!(
+        event.request_context_ref(),
+        Some(RequestContext::WebSocket(context)) if context.event_type.as_deref() == Some(BROADCAST_EVENT_TYPE)
+    )
+}
+
+/// Handle a broadcast invocation: post the change to the ticket's subscribers
+pub async fn handle_broadcast_event(event: &Request, broadcaster: &Broadcaster) -> Response<Body> {
+    let broadcast = match serde_json::from_slice::<PendingBroadcast>(event.body().as_ref()) {
+        Ok(broadcast) => broadcast,
+        Err(e) => {
+            return error_response(400, "Invalid broadcast", &format!("Could not read the broadcast: {}", e), None);
+        }
+    };
+    match broadcaster.deliver(&broadcast).await {
+        Ok(delivered) => success_response(200, json!({ "delivered": delivered }).to_string()),
+        Err(e) => store_failure(e),
+    }
+}
+
+fn store_failure(e: String) -> Response<Body> {
+    eprintln!("Push connection store failed: {}", e);
+    error_response(500, "Connection store error", "Could not update the connection", None)
+}
+
+/// Who is opening the socket: the WebSocket API's authorizer, if one is attached, or the
+/// user a valid `token` query parameter was issued to
+fn socket_identity(event: &Request, secret: Option<&[u8]>, now: i64) -> Option<String> {
+    let identity = get_user_identity_from_event(event);
+    if identity.email.is_some() || identity.username.is_some() {
+        return Some(identity.actor());
+    }
+    let principal = event
+        .request_context_ref()
+        .and_then(|context| context.authorizer())
+        .and_then(|authorizer| authorizer.fields.get("principalId"))
+        .and_then(|principal| principal.as_str())
+        .map(str::trim)
+        .filter(|principal| !principal.is_empty());
+    if let Some(principal) = principal {
+        return Some(principal.to_string());
+    }
+    let token = event.query_string_parameters().first("token")?.to_string();
+    verify_socket_token(&token, secret?, now)
+}
+
+/// Handle `POST /push/token`: a short-lived token for opening the push channel
+pub fn handle_socket_token(actor: &UserIdentity, secret: &[u8], now: i64) -> Response<Body> {
+    let token = sign_socket_token(&actor.actor(), now + SOCKET_TOKEN_SECONDS, secret);
+    success_response(200, json!({ "token": token, "expires_in": SOCKET_TOKEN_SECONDS }).to_string())
+}
+
+/// Handle a `$connect`, `$disconnect` or message event from the WebSocket API.
+///
+/// `$connect` is refused without an identity (see [`socket_identity`]), and messages are
+/// only accepted on connections registered that way. `secret` checks socket tokens and
+/// `now` is in unix seconds.
+pub async fn handle_socket_event(
+    event: &Request,
+    store: &dyn ConnectionStore,
+    secret: Option<&[u8]>,
+    now: i64,
+) -> Response<Body> {
+    let Some(RequestContext::WebSocket(context)) = event.request_context_ref() else {
+        return error_response(400, "Not a WebSocket event", "Expected an API Gateway WebSocket event", None);
+    };
+    let Some(connection_id) = context.connection_id.as_deref() else {
+        return error_response(400, "Missing connection id", "The WebSocket event has no connection id", None);
+    };
+
+    match context.event_type.as_deref() {
+        Some("CONNECT") => {
+            let Some(user) = socket_identity(event, secret, now) else {
+                return error_response(
+                    401,
+                    "Unauthorized",
+                    "Opening the push channel requires a signed-in user",
+                    Some("Connect with ?token= from POST /push/token"),
+                );
+            };
+            match store.connect(connection_id, &user).await {
+                Ok(()) => success_response(200, json!({ "connected": connection_id }).to_string()),
+                Err(e) => store_failure(e),
+            }
+        }
+        Some("DISCONNECT") => match store.disconnect(connection_id).await {
+            Ok(()) => success_response(200, "{}".to_string()),
+            Err(e) => store_failure(e),
+        },
+        _ => {
+            let message = match serde_json::from_slice::<SocketMessage>(event.body().as_ref()) {
+                Ok(message) => message,
+                Err(e) => {
+                    return error_response(
+                        400,
+                        "Invalid message",
+                        &format!("Could not read the message: {}", e),
+                        Some(r#"Send {"action": "subscribe", "tickets": [42]} or {"action": "subscribe", "all": true}"#),
+                    );
+                }
+            };
+            let (tickets, all, subscribe) = match message {
+                SocketMessage::Subscribe { tickets, all } => (tickets, all, true),
+                SocketMessage::Unsubscribe { tickets, all } => (tickets, all, false),
+                SocketMessage::Ping => return success_response(200, json!({ "type": "pong" }).to_string()),
+            };
+            let mut topics: Vec<String> = tickets.into_iter().map(ticket_topic).collect();
+            if all {
+                topics.push(ALL_TICKETS.to_string());
+            }
+            // The cap covers everything the connection follows, not just this message
+            match store.update_topics(connection_id, &topics, subscribe, MAX_TICKET_SUBSCRIPTIONS).await {
+                Ok(TopicUpdate::Updated(topics)) => {
+                    success_response(200, json!({ "type": "subscriptions", "topics": topics }).to_string())
+                }
+                Ok(TopicUpdate::TooManyTickets) => error_response(
+                    400,
+                    "Too many tickets",
+                    &format!("Follow at most {} tickets, or subscribe to all", MAX_TICKET_SUBSCRIPTIONS),
+                    None,
+                ),
+                Ok(TopicUpdate::NotConnected) => error_response(
+                    403,
+                    "Not connected",
+                    "This connection was not opened by a signed-in user",
+                    None,
+                ),
+                Err(e) => store_failure(e),
+            }
+        }
+    }
+}
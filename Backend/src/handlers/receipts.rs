@@ -0,0 +1,278 @@
+//! Printable intake receipts and device bag labels (PDF)
+
+use chrono::DateTime;
+use lambda_http::{Body, Request, RequestExt, Response};
+
+use super::tickets::parse_ticket_id;
+use crate::errors::ApiError;
+use crate::http::file_response;
+use crate::notifications::shop_name;
+use crate::pdf::{truncate, wrap, PageLayout};
+use crate::repairshopr::{Customer, RepairShoprClient, Ticket};
+
+/// US Letter, in points
+const RECEIPT_SIZE: (f32, f32) = (612.0, 792.0);
+
+/// 2.25" x 1.25", the common thermal label stock
+const LABEL_SIZE: (f32, f32) = (162.0, 90.0);
+
+const MARGIN: f32 = 48.0;
+
+/// Where receipt values start, right of their labels
+const VALUE_X: f32 = 150.0;
+
+/// Height of the signature and date lines under the terms
+const SIGNATURE_SPACE: f32 = 60.0;
+
+/// Height of the terms heading and its first line, kept free while listing items
+const TERMS_MIN_SPACE: f32 = 42.0;
+
+/// Printed when RECEIPT_TERMS isn't set
+const DEFAULT_TERMS: &str = "We are not responsible for data loss; please back up your device before repair. \
+Diagnosis may require opening the device, which can void a manufacturer warranty. \
+Devices not collected within 90 days of completion may be recycled. \
+Repairs carry a 90-day warranty on parts and labor, excluding physical or liquid damage.";
+
+/// Which document to print
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrintLayout {
+    /// Full-page receipt for the customer
+    Receipt,
+    /// Small label for the device bag
+    Label,
+}
+
+impl PrintLayout {
+    /// Read `layout` (`receipt` or `label`) from the query string
+    pub fn from_event(event: &Request) -> Result<Self, ApiError> {
+        match event.query_string_parameters().first("layout").map(str::trim) {
+            None | Some("") | Some("receipt") => Ok(Self::Receipt),
+            Some("label") => Ok(Self::Label),
+            Some(other) => Err(ApiError::new(
+                400,
+                "INVALID_LAYOUT",
+                "The print layout must be receipt or label",
+                format!("layout={} is not a print layout", other),
+            )),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Receipt => "receipt",
+            Self::Label => "label",
+        }
+    }
+}
+
+/// Shop details printed in the receipt header
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShopDetails {
+    pub name: String,
+    pub address: Option<String>,
+    pub phone: Option<String>,
+    pub terms: String,
+}
+
+impl ShopDetails {
+    /// SHOP_NAME, SHOP_ADDRESS (lines separated by `|`), SHOP_PHONE and RECEIPT_TERMS
+    pub fn from_env() -> Self {
+        let var = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        Self {
+            name: shop_name(),
+            address: var("SHOP_ADDRESS"),
+            phone: var("SHOP_PHONE"),
+            terms: var("RECEIPT_TERMS").unwrap_or_else(|| DEFAULT_TERMS.to_string()),
+        }
+    }
+}
+
+fn customer_name(ticket: &Ticket, customer: Option<&Customer>) -> String {
+    customer
+        .and_then(|c| {
+            c.fullname.clone().or_else(|| {
+                let parts: Vec<&str> = [c.firstname.as_deref(), c.lastname.as_deref()].into_iter().flatten().collect();
+                (!parts.is_empty()).then(|| parts.join(" "))
+            })
+        })
+        .or_else(|| ticket.customer_business_then_name.clone())
+        .unwrap_or_else(|| "Walk-in customer".to_string())
+}
+
+/// Date the ticket was opened, e.g. "Mar 2, 2026", in the shop's own offset
+fn received_on(ticket: &Ticket) -> Option<String> {
+    let created = DateTime::parse_from_rfc3339(ticket.created_at.as_deref()?).ok()?;
+    Some(created.format("%b %-d, %Y").to_string())
+}
+
+fn device(ticket: &Ticket) -> Option<String> {
+    ticket.properties.intake_model().and_then(|intake| intake.device)
+}
+
+/// Lay out the full-page intake receipt
+pub fn receipt_layout(ticket: &Ticket, customer: Option<&Customer>, shop: &ShopDetails) -> PageLayout {
+    let (width, height) = RECEIPT_SIZE;
+    let right = width - MARGIN;
+    let mut page = PageLayout::new(width, height);
+
+    // Header: shop on the left, scannable ticket number on the right
+    let qr_size = 96.0;
+    page.qr(right - qr_size, height - MARGIN, qr_size, ticket.number.to_string());
+    page.text(right - qr_size, height - MARGIN - qr_size - 14.0, 11.0, true, format!("Ticket #{}", ticket.number));
+
+    // Shop lines stop short of the QR code
+    let header_width = right - qr_size - MARGIN - 8.0;
+    let mut y = height - MARGIN - 16.0;
+    page.text(MARGIN, y, 20.0, true, truncate(&shop.name, 20.0, true, header_width));
+    y -= 8.0;
+    for line in shop.address.iter().flat_map(|a| a.split('|')).chain(shop.phone.as_deref()) {
+        y -= 13.0;
+        page.text(MARGIN, y, 10.0, false, truncate(line.trim(), 10.0, false, header_width));
+    }
+    y -= 30.0;
+    page.text(MARGIN, y, 16.0, true, "Intake Receipt");
+
+    y = y.min(height - MARGIN - qr_size - 30.0) - 12.0;
+    page.line(MARGIN, y, right, y, 0.75);
+
+    let value_width = right - VALUE_X;
+    let section = |page: &mut PageLayout, y: &mut f32, title: &str| {
+        *y -= 26.0;
+        page.text(MARGIN, *y, 12.0, true, title);
+        *y -= 4.0;
+    };
+    let row = |page: &mut PageLayout, y: &mut f32, label: &str, value: &str| {
+        *y -= 15.0;
+        page.text(MARGIN, *y, 10.0, true, label);
+        page.text(VALUE_X, *y, 10.0, false, truncate(value, 10.0, false, value_width));
+    };
+
+    section(&mut page, &mut y, "Customer");
+    row(&mut page, &mut y, "Name", &customer_name(ticket, customer));
+    if let Some(phone) = customer.and_then(|c| c.mobile.clone().or_else(|| c.phone.clone())) {
+        row(&mut page, &mut y, "Phone", &phone);
+    }
+    if let Some(email) = customer.and_then(|c| c.email.clone()) {
+        row(&mut page, &mut y, "Email", &email);
+    }
+
+    let intake = ticket.properties.intake_model();
+    section(&mut page, &mut y, "Device");
+    row(&mut page, &mut y, "Device", &device(ticket).unwrap_or_else(|| "Not recorded".to_string()));
+    if !ticket.subject.trim().is_empty() {
+        row(&mut page, &mut y, "Problem", ticket.subject.trim());
+    }
+    if let Some(serial) = &ticket.properties.imei_or_serial {
+        row(&mut page, &mut y, "IMEI / Serial", serial);
+    }
+    if let Some(estimate) = intake.as_ref().and_then(|i| i.estimated_time.clone()) {
+        row(&mut page, &mut y, "Estimated time", &estimate);
+    }
+    if let Some(received) = received_on(ticket) {
+        row(&mut page, &mut y, "Received", &received);
+    }
+
+    // Long item lists and terms are cut short so the signature stays above the bottom margin
+    let bottom = MARGIN + SIGNATURE_SPACE;
+    section(&mut page, &mut y, "Items left with the device");
+    let items = intake.map(|i| i.items_left).unwrap_or_default();
+    if items.is_empty() {
+        y -= 15.0;
+        page.text(MARGIN, y, 10.0, false, "None");
+    }
+    let items_floor = bottom + TERMS_MIN_SPACE;
+    for (index, item) in items.iter().enumerate() {
+        let remaining = items.len() - index;
+        y -= 15.0;
+        // The last line that fits says how many items didn't
+        if y < items_floor || (remaining > 1 && y - 15.0 < items_floor) {
+            page.text(MARGIN, y, 10.0, false, format!("... and {} more", remaining));
+            break;
+        }
+        page.text(MARGIN, y, 10.0, false, truncate(&format!("- {}", item), 10.0, false, right - MARGIN));
+    }
+
+    section(&mut page, &mut y, "Terms");
+    let terms = wrap(&shop.terms, 9.0, false, right - MARGIN);
+    let room = ((y - bottom) / 12.0).floor().max(0.0) as usize;
+    for (index, line) in terms.iter().take(room).enumerate() {
+        y -= 12.0;
+        if index + 1 == room && terms.len() > room {
+            page.text(MARGIN, y, 9.0, false, truncate(&format!("{} ...", line), 9.0, false, right - MARGIN));
+        } else {
+            page.text(MARGIN, y, 9.0, false, line.as_str());
+        }
+    }
+
+    y -= 48.0;
+    let signature_end = MARGIN + 280.0;
+    page.line(MARGIN, y, signature_end, y, 0.5);
+    page.text(MARGIN, y - 12.0, 8.0, false, "Customer signature");
+    page.line(signature_end + 40.0, y, right, y, 0.5);
+    page.text(signature_end + 40.0, y - 12.0, 8.0, false, "Date");
+
+    page
+}
+
+/// Lay out the thermal label for the device bag
+pub fn label_layout(ticket: &Ticket, customer: Option<&Customer>, shop: &ShopDetails) -> PageLayout {
+    let (width, height) = LABEL_SIZE;
+    let margin = 6.0;
+    let qr_size = height - 2.0 * margin;
+    let mut page = PageLayout::new(width, height);
+    page.qr(margin, height - margin, qr_size, ticket.number.to_string());
+
+    let x = margin + qr_size + 6.0;
+    let text_width = width - x - margin;
+    page.text(x, height - 22.0, 14.0, true, format!("#{}", ticket.number));
+    page.text(x, height - 36.0, 8.0, false, truncate(&customer_name(ticket, customer), 8.0, false, text_width));
+    if let Some(device) = device(ticket) {
+        page.text(x, height - 47.0, 8.0, false, truncate(&device, 8.0, false, text_width));
+    }
+    if let Some(received) = received_on(ticket) {
+        page.text(x, height - 58.0, 7.0, false, received);
+    }
+    page.text(x, margin + 2.0, 6.0, false, truncate(&shop.name, 6.0, false, text_width));
+    page
+}
+
+/// Handle `GET /tickets/{id}/print?layout=receipt|label`
+pub async fn handle_print_ticket(
+    event: &Request,
+    ticket_id: &str,
+    rs_client: &RepairShoprClient,
+) -> Result<Response<Body>, ApiError> {
+    let ticket_id = parse_ticket_id(ticket_id)?;
+    let layout = PrintLayout::from_event(event)?;
+    let ticket = rs_client.get_ticket(ticket_id).await?;
+
+    // A missing customer still leaves a usable receipt
+    let customer = match ticket.customer_id {
+        Some(id) => rs_client.get_customer(id).await.map_or_else(
+            |e| {
+                eprintln!("Could not load customer for ticket {}: {}", ticket_id, e);
+                None
+            },
+            Some,
+        ),
+        None => None,
+    };
+
+    let shop = ShopDetails::from_env();
+    let page = match layout {
+        PrintLayout::Receipt => receipt_layout(&ticket, customer.as_ref(), &shop),
+        PrintLayout::Label => label_layout(&ticket, customer.as_ref(), &shop),
+    };
+    let title = format!("Ticket #{} {}", ticket.number, layout.name());
+    let pdf = page
+        .to_pdf(&title)
+        .map_err(|e| ApiError::new(500, "PDF_ERROR", "The document could not be built", e))?;
+
+    let file_name = format!("ticket-{}-{}.pdf", ticket.number, layout.name());
+    Ok(file_response("application/pdf", "inline", &file_name, pdf))
+}
@@ -0,0 +1,412 @@
+//! Shop metrics for owners: ticket volume, time in status, devices and tech throughput
+//!
+//! Reports are computed from every ticket updated since the start of the range, paged out
+//! of RepairShopr, so the result for a range is cached per instance. Past MAX_PAGES pages
+//! the report is built from what was read and marked `truncated`.
+//! RepairShopr has no status history endpoint. Time in status is rebuilt from the
+//! "Status changed from A to B" comments it logs on each ticket.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, Utc};
+use lambda_http::{Body, Request, RequestExt, Response};
+use serde::Serialize;
+
+use super::board::UNKNOWN_DEVICE;
+use crate::cache::{ttl_from_env, TtlCache};
+use crate::errors::ApiError;
+use crate::http::success_response;
+use crate::repairshopr::{RepairShoprClient, Ticket, TicketQuery, MAX_PAGES};
+use crate::status::{status_map, StatusMap};
+
+/// Days covered when the request doesn't give a range
+const DEFAULT_RANGE_DAYS: i64 = 30;
+
+/// Longest range one report may cover
+const MAX_RANGE_DAYS: i64 = 366;
+
+/// Status a ticket starts in when its first change comment doesn't say
+const INITIAL_STATUS: &str = "New";
+
+/// Technician shown for tickets nobody is assigned to
+const UNASSIGNED: &str = "Unassigned";
+
+/// Computed reports (REPORT_CACHE_SECONDS, default 600)
+static REPORTS: LazyLock<TtlCache<ReportRange, Report>> =
+    LazyLock::new(|| TtlCache::new(ttl_from_env("REPORT_CACHE_SECONDS", 600)));
+
+/// How opened/closed counts are bucketed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interval {
+    Day,
+    /// Weeks starting on Monday
+    Week,
+}
+
+/// Inclusive date range a report covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReportRange {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub interval: Interval,
+}
+
+fn invalid_range(details: String) -> ApiError {
+    ApiError::new(400, "INVALID_REPORT_RANGE", "The report range is not valid", details)
+}
+
+impl ReportRange {
+    /// Read `from`, `to` (YYYY-MM-DD) and `interval` (`day` or `week`) from the query string.
+    ///
+    /// Defaults to the last 30 days, by day.
+    pub fn from_event(event: &Request, today: NaiveDate) -> Result<Self, ApiError> {
+        let params = event.query_string_parameters();
+        let date = |key: &str| -> Result<Option<NaiveDate>, ApiError> {
+            match params.first(key).map(str::trim).filter(|v| !v.is_empty()) {
+                Some(value) => NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                    .map(Some)
+                    .map_err(|_| invalid_range(format!("{}={} is not a YYYY-MM-DD date", key, value))),
+                None => Ok(None),
+            }
+        };
+
+        let to = date("to")?.unwrap_or(today);
+        let from = date("from")?.unwrap_or(to - Duration::days(DEFAULT_RANGE_DAYS - 1));
+        let interval = match params.first("interval").map(str::trim) {
+            None | Some("") | Some("day") => Interval::Day,
+            Some("week") => Interval::Week,
+            Some(other) => return Err(invalid_range(format!("interval={} must be day or week", other))),
+        };
+
+        if from > to {
+            return Err(invalid_range(format!("from {} is after to {}", from, to)));
+        }
+        if (to - from).num_days() >= MAX_RANGE_DAYS {
+            return Err(invalid_range(format!("A report covers at most {} days", MAX_RANGE_DAYS)));
+        }
+        Ok(Self { from, to, interval })
+    }
+
+    fn contains(&self, date: NaiveDate) -> bool {
+        self.from <= date && date <= self.to
+    }
+
+    /// First day of the bucket `date` falls in
+    fn period_start(&self, date: NaiveDate) -> NaiveDate {
+        match self.interval {
+            Interval::Day => date,
+            Interval::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+        }
+    }
+}
+
+/// Tickets opened and closed in one day or week
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PeriodCount {
+    /// First day of the period, YYYY-MM-DD
+    pub period: String,
+    pub opened: usize,
+    pub closed: usize,
+}
+
+/// Average stay in one display status
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatusTime {
+    pub status: String,
+    /// Stays that ended in the range (stays still going count up to now)
+    pub stays: usize,
+    pub average_hours: f64,
+}
+
+/// Tickets opened for one device type
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceCount {
+    pub device: String,
+    pub tickets: usize,
+}
+
+/// What one technician got through
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TechThroughput {
+    pub technician: String,
+    pub user_id: Option<i64>,
+    /// Tickets opened in the range and assigned to them
+    pub assigned: usize,
+    pub closed: usize,
+    /// Average hours from opening to closing, over the tickets they closed
+    pub average_turnaround_hours: Option<f64>,
+}
+
+/// The whole report
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub from: String,
+    pub to: String,
+    pub interval: Interval,
+    pub generated_at: String,
+    pub tickets_considered: usize,
+    pub volume: Vec<PeriodCount>,
+    pub time_in_status: Vec<StatusTime>,
+    pub devices: Vec<DeviceCount>,
+    pub technicians: Vec<TechThroughput>,
+    /// RepairShopr had more tickets than the report reads, so the figures leave some out
+    pub truncated: bool,
+}
+
+fn parse_time(timestamp: Option<&str>) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(timestamp?).ok()
+}
+
+fn hours(duration: Duration) -> f64 {
+    duration.num_seconds() as f64 / 3600.0
+}
+
+fn round_hours(hours: f64) -> f64 {
+    (hours * 10.0).round() / 10.0
+}
+
+/// Strip the quotes and trailing period RepairShopr puts around status names
+fn clean_status(status: &str) -> String {
+    status
+        .trim()
+        .trim_end_matches('.')
+        .trim_matches(|c| matches!(c, '"' | '\'' | '“' | '”'))
+        .trim()
+        .to_string()
+}
+
+/// Read "Status changed from A to B" (or "... changed to B") out of a comment.
+///
+/// Returns the old status when the text names one, and the new status.
+pub fn parse_status_change(text: &str) -> Option<(Option<String>, String)> {
+    const MARKER: &str = "status changed";
+    // ASCII lowercasing keeps byte offsets valid for slicing the original text
+    let lower = text.to_ascii_lowercase();
+    let start = lower.find(MARKER)? + MARKER.len();
+    let rest = text[start..].trim_start();
+    let rest_lower = rest.to_ascii_lowercase();
+
+    let (from, to) = if let Some(after_from) = rest_lower.strip_prefix("from ") {
+        let offset = rest.len() - after_from.len();
+        let split = after_from.find(" to ")?;
+        (Some(&rest[offset..offset + split]), &rest[offset + split + " to ".len()..])
+    } else if rest_lower.starts_with("to ") {
+        (None, &rest["to ".len()..])
+    } else {
+        return None;
+    };
+
+    let to = clean_status(to.lines().next().unwrap_or_default());
+    if to.is_empty() {
+        return None;
+    }
+    Some((from.map(clean_status).filter(|f| !f.is_empty()), to))
+}
+
+/// A ticket's status changes in time order
+fn status_changes(ticket: &Ticket) -> Vec<(DateTime<FixedOffset>, Option<String>, String)> {
+    let mut changes: Vec<_> = ticket
+        .comments
+        .iter()
+        .filter_map(|comment| {
+            let at = parse_time(comment.created_at.as_deref())?;
+            let (from, to) = [comment.body.as_deref(), comment.subject.as_deref()]
+                .into_iter()
+                .flatten()
+                .find_map(parse_status_change)?;
+            Some((at, from, to))
+        })
+        .collect();
+    changes.sort_by_key(|(at, _, _)| *at);
+    changes
+}
+
+/// The `(RepairShopr status, start, end)` stays of one ticket, ending at resolution or `now`.
+///
+/// Resolved tickets without change comments are skipped, since their history is unknown.
+fn status_stays(ticket: &Ticket, now: DateTime<Utc>) -> Vec<(String, DateTime<Utc>, DateTime<Utc>)> {
+    let Some(created) = parse_time(ticket.created_at.as_deref()) else {
+        return vec![];
+    };
+    let resolved = parse_time(ticket.resolved_at.as_deref()).map(|t| t.with_timezone(&Utc));
+    let changes = status_changes(ticket);
+    if changes.is_empty() && resolved.is_some() {
+        return vec![];
+    }
+
+    let mut status = match changes.first() {
+        Some((_, from, _)) => from.clone().unwrap_or_else(|| INITIAL_STATUS.to_string()),
+        None => ticket.status.clone(),
+    };
+    let mut start = created.with_timezone(&Utc);
+    let mut stays = vec![];
+    for (at, _, to) in changes {
+        let at = at.with_timezone(&Utc);
+        if resolved.is_some_and(|resolved| at >= resolved) {
+            break;
+        }
+        stays.push((status, start, at));
+        status = to;
+        start = at;
+    }
+    stays.push((status, start, resolved.unwrap_or(now)));
+    stays
+}
+
+fn technician(ticket: &Ticket) -> (String, Option<i64>) {
+    let user = ticket.user.as_ref();
+    let user_id = ticket.user_id.or(user.and_then(|u| u.id));
+    let name = user
+        .and_then(|u| u.full_name.clone().or_else(|| u.email.clone()))
+        .or_else(|| user_id.map(|id| format!("User {}", id)))
+        .unwrap_or_else(|| UNASSIGNED.to_string());
+    (name, user_id)
+}
+
+/// Compute the report for `range` from the tickets RepairShopr returned
+pub fn build_report(tickets: &[Ticket], range: &ReportRange, map: &StatusMap, now: DateTime<Utc>) -> Report {
+    // Every period gets an entry, even an empty one, so charts have no gaps
+    let mut volume: BTreeMap<NaiveDate, PeriodCount> = BTreeMap::new();
+    let mut day = range.from;
+    while day <= range.to {
+        let period = range.period_start(day);
+        volume.entry(period).or_insert(PeriodCount {
+            period: period.to_string(),
+            opened: 0,
+            closed: 0,
+        });
+        day += Duration::days(1);
+    }
+
+    let mut stays: HashMap<String, (usize, f64)> = HashMap::new();
+    let mut devices: HashMap<String, usize> = HashMap::new();
+    let mut techs: HashMap<(String, Option<i64>), (usize, usize, f64)> = HashMap::new();
+    let mut considered = 0;
+
+    for ticket in tickets {
+        let created = parse_time(ticket.created_at.as_deref());
+        let resolved = parse_time(ticket.resolved_at.as_deref());
+        let opened_in_range = created.is_some_and(|t| range.contains(t.date_naive()));
+        let closed_in_range = resolved.is_some_and(|t| range.contains(t.date_naive()));
+
+        for (status, start, end) in status_stays(ticket, now) {
+            if !range.contains(end.date_naive()) {
+                continue;
+            }
+            let entry = stays.entry(map.to_display(&status)).or_default();
+            entry.0 += 1;
+            entry.1 += hours(end - start);
+        }
+
+        if !opened_in_range && !closed_in_range {
+            continue;
+        }
+        considered += 1;
+        let tech = techs.entry(technician(ticket)).or_default();
+
+        if let Some(created) = created.filter(|_| opened_in_range) {
+            if let Some(count) = volume.get_mut(&range.period_start(created.date_naive())) {
+                count.opened += 1;
+            }
+            let device = ticket
+                .properties
+                .intake_model()
+                .and_then(|intake| intake.device)
+                .unwrap_or_else(|| UNKNOWN_DEVICE.to_string());
+            *devices.entry(device).or_default() += 1;
+            tech.0 += 1;
+        }
+        if let Some(resolved) = resolved.filter(|_| closed_in_range) {
+            if let Some(count) = volume.get_mut(&range.period_start(resolved.date_naive())) {
+                count.closed += 1;
+            }
+            tech.1 += 1;
+            if let Some(created) = created {
+                tech.2 += hours(resolved - created);
+            }
+        }
+    }
+
+    // Display statuses in board order, then any the mapping doesn't list
+    let mut order: Vec<String> = map.display_statuses().into_iter().map(str::to_string).collect();
+    let mut extra: Vec<String> = stays.keys().filter(|s| !order.contains(s)).cloned().collect();
+    extra.sort();
+    order.extend(extra);
+    let time_in_status = order
+        .into_iter()
+        .filter_map(|status| {
+            let (count, total) = stays.get(&status)?;
+            Some(StatusTime {
+                average_hours: round_hours(total / *count as f64),
+                stays: *count,
+                status,
+            })
+        })
+        .collect();
+
+    let mut devices: Vec<DeviceCount> = devices
+        .into_iter()
+        .map(|(device, tickets)| DeviceCount { device, tickets })
+        .collect();
+    devices.sort_by(|a, b| b.tickets.cmp(&a.tickets).then_with(|| a.device.cmp(&b.device)));
+
+    let mut technicians: Vec<TechThroughput> = techs
+        .into_iter()
+        .map(|((technician, user_id), (assigned, closed, turnaround))| TechThroughput {
+            technician,
+            user_id,
+            assigned,
+            closed,
+            average_turnaround_hours: (closed > 0).then(|| round_hours(turnaround / closed as f64)),
+        })
+        .collect();
+    technicians.sort_by(|a, b| {
+        b.closed
+            .cmp(&a.closed)
+            .then_with(|| b.assigned.cmp(&a.assigned))
+            .then_with(|| a.technician.cmp(&b.technician))
+    });
+
+    Report {
+        from: range.from.to_string(),
+        to: range.to.to_string(),
+        interval: range.interval,
+        generated_at: now.to_rfc3339(),
+        tickets_considered: considered,
+        volume: volume.into_values().collect(),
+        time_in_status,
+        devices,
+        technicians,
+        truncated: false,
+    }
+}
+
+/// Handle `GET /reports`
+pub async fn handle_reports(event: &Request, rs_client: &RepairShoprClient) -> Result<Response<Body>, ApiError> {
+    let now = Utc::now();
+    let range = ReportRange::from_event(event, now.date_naive())?;
+
+    let report = match REPORTS.get(&range) {
+        Some(report) => report,
+        None => {
+            // A ticket opened, closed or moved in the range was updated after it started
+            let query = TicketQuery {
+                since_updated_at: Some(format!("{}T00:00:00Z", range.from)),
+                ..Default::default()
+            };
+            let listing = rs_client.list_all_tickets(&query, MAX_PAGES).await?;
+            let report = Report {
+                truncated: listing.truncated,
+                ..build_report(&listing.items, &range, status_map(), now)
+            };
+            REPORTS.insert(range, report.clone());
+            report
+        }
+    };
+
+    let body = serde_json::to_string(&report)
+        .map_err(|e| ApiError::new(500, "SERIALIZATION_ERROR", "The response could not be built", e.to_string()))?;
+    Ok(success_response(200, body))
+}
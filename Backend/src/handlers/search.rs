@@ -0,0 +1,367 @@
+//! Unified search across tickets and customers
+
+use lambda_http::{Body, Request, RequestExt, Response};
+use serde::Serialize;
+
+use crate::contact::{looks_like_email, looks_like_phone, national_phone_digits};
+use crate::errors::ApiError;
+use crate::handlers::tickets::TicketView;
+use crate::http::success_response;
+use crate::repairshopr::{Customer, CustomerQuery, RepairShoprClient, RepairShoprError, Ticket, TicketQuery};
+use crate::status::{status_map, StatusMap};
+
+/// Most results returned by one search (matches the frontend's MAX_SEARCH_RESULTS)
+const MAX_RESULTS: usize = 50;
+
+/// Ticket numbers are short; anything longer is treated as a phone number
+const MAX_TICKET_NUMBER_DIGITS: usize = 6;
+
+/// What a search query looks like
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryKind {
+    TicketNumber,
+    Phone,
+    Email,
+    Name,
+}
+
+/// Work out what kind of query the user typed
+pub fn classify_query(query: &str) -> QueryKind {
+    let trimmed = query.trim().trim_start_matches('#');
+    if !trimmed.is_empty()
+        && trimmed.len() <= MAX_TICKET_NUMBER_DIGITS
+        && trimmed.chars().all(|c| c.is_ascii_digit())
+    {
+        QueryKind::TicketNumber
+    } else if looks_like_email(trimmed) {
+        QueryKind::Email
+    } else if looks_like_phone(trimmed) {
+        QueryKind::Phone
+    } else {
+        QueryKind::Name
+    }
+}
+
+/// The field that matched and where, in characters, the match sits
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchMatch {
+    pub field: String,
+    pub value: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// One ranked search result
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SearchHit {
+    Customer {
+        score: u32,
+        customer: Customer,
+        #[serde(rename = "match")]
+        matched: Option<SearchMatch>,
+    },
+    Ticket {
+        score: u32,
+        ticket: TicketView,
+        #[serde(rename = "match")]
+        matched: Option<SearchMatch>,
+    },
+}
+
+impl SearchHit {
+    fn score(&self) -> u32 {
+        match self {
+            SearchHit::Customer { score, .. } | SearchHit::Ticket { score, .. } => *score,
+        }
+    }
+
+    fn sort_key(&self) -> (u8, i64) {
+        match self {
+            SearchHit::Customer { customer, .. } => (0, customer.id),
+            SearchHit::Ticket { ticket, .. } => (1, ticket.id),
+        }
+    }
+}
+
+/// Search response
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchResults {
+    pub query: String,
+    pub kind: QueryKind,
+    pub results: Vec<SearchHit>,
+}
+
+/// Score a text field against the query (case-insensitive)
+fn score_text(field: &str, value: &str, query: &str) -> Option<(u32, SearchMatch)> {
+    let haystack = value.to_lowercase();
+    let needle = query.trim().to_lowercase();
+    if needle.is_empty() {
+        return None;
+    }
+    let byte_start = haystack.find(&needle)?;
+
+    let score = if haystack == needle {
+        100
+    } else if byte_start == 0 {
+        75
+    } else if haystack[..byte_start].ends_with(' ') {
+        60
+    } else {
+        40
+    };
+    let start = haystack[..byte_start].chars().count();
+    Some((
+        score,
+        SearchMatch {
+            field: field.to_string(),
+            value: value.to_string(),
+            start,
+            end: start + needle.chars().count(),
+        },
+    ))
+}
+
+/// Score a phone field by comparing normalized digits
+fn score_phone(field: &str, value: &str, query_digits: &str) -> Option<(u32, SearchMatch)> {
+    let digits = national_phone_digits(value);
+    if digits.is_empty() || query_digits.is_empty() {
+        return None;
+    }
+    let score = if digits == query_digits {
+        100
+    } else if digits.contains(query_digits) {
+        60
+    } else {
+        return None;
+    };
+    Some((
+        score,
+        SearchMatch {
+            field: field.to_string(),
+            value: value.to_string(),
+            start: 0,
+            end: value.chars().count(),
+        },
+    ))
+}
+
+/// Best match among candidate `(field, value)` pairs
+fn best_match(candidates: Vec<Option<(u32, SearchMatch)>>) -> Option<(u32, SearchMatch)> {
+    candidates.into_iter().flatten().max_by_key(|(score, _)| *score)
+}
+
+/// Rank a customer for the query
+pub fn rank_customer(customer: Customer, query: &str, kind: QueryKind) -> SearchHit {
+    let text = |field: &str, value: &Option<String>| value.as_deref().and_then(|v| score_text(field, v, query));
+    let best = match kind {
+        QueryKind::Phone | QueryKind::TicketNumber => {
+            let digits = national_phone_digits(query);
+            best_match(vec![
+                customer.phone.as_deref().and_then(|v| score_phone("phone", v, &digits)),
+                customer.mobile.as_deref().and_then(|v| score_phone("mobile", v, &digits)),
+            ])
+        }
+        QueryKind::Email => best_match(vec![text("email", &customer.email)]),
+        QueryKind::Name => {
+            let full_name = customer.fullname.clone().or_else(|| {
+                let joined = format!(
+                    "{} {}",
+                    customer.firstname.as_deref().unwrap_or(""),
+                    customer.lastname.as_deref().unwrap_or("")
+                );
+                Some(joined.trim().to_string()).filter(|n| !n.is_empty())
+            });
+            best_match(vec![
+                text("fullname", &full_name),
+                text("business_name", &customer.business_name),
+                text("email", &customer.email),
+            ])
+        }
+    };
+
+    let (score, matched) = match best {
+        Some((score, matched)) => (score, Some(matched)),
+        None => (10, None),
+    };
+    SearchHit::Customer {
+        score,
+        customer,
+        matched,
+    }
+}
+
+/// Rank a ticket for the query
+pub fn rank_ticket(ticket: &Ticket, query: &str, kind: QueryKind, map: &StatusMap) -> SearchHit {
+    let number = ticket.number.to_string();
+    let best = match kind {
+        QueryKind::TicketNumber => {
+            let wanted = query.trim().trim_start_matches('#');
+            if number == wanted {
+                Some((
+                    100,
+                    SearchMatch {
+                        field: "number".to_string(),
+                        value: number.clone(),
+                        start: 0,
+                        end: number.len(),
+                    },
+                ))
+            } else {
+                best_match(vec![score_text("subject", &ticket.subject, wanted)])
+            }
+        }
+        QueryKind::Name | QueryKind::Email => best_match(vec![
+            ticket
+                .customer_business_then_name
+                .as_deref()
+                .and_then(|v| score_text("customer_name", v, query)),
+            score_text("subject", &ticket.subject, query),
+        ]),
+        QueryKind::Phone => None,
+    };
+
+    // Tickets rank just below customers with the same quality of match
+    let (score, matched) = match best {
+        Some((score, matched)) => (score.saturating_sub(5), Some(matched)),
+        None => (5, None),
+    };
+    SearchHit::Ticket {
+        score,
+        ticket: TicketView::from_ticket(ticket, map),
+        matched,
+    }
+}
+
+/// Merge customers and tickets: dedupe by id, rank and truncate
+pub fn merge_results(
+    query: &str,
+    kind: QueryKind,
+    customers: Vec<Customer>,
+    tickets: Vec<Ticket>,
+    map: &StatusMap,
+) -> Vec<SearchHit> {
+    let mut seen_customers = std::collections::HashSet::new();
+    let mut seen_tickets = std::collections::HashSet::new();
+
+    let mut hits: Vec<SearchHit> = customers
+        .into_iter()
+        .filter(|c| seen_customers.insert(c.id))
+        .map(|c| rank_customer(c, query, kind))
+        .collect();
+    hits.extend(
+        tickets
+            .iter()
+            .filter(|t| seen_tickets.insert(t.id))
+            .map(|t| rank_ticket(t, query, kind, map)),
+    );
+
+    hits.sort_by(|a, b| b.score().cmp(&a.score()).then_with(|| a.sort_key().cmp(&b.sort_key())));
+    hits.truncate(MAX_RESULTS);
+    hits
+}
+
+type Lookup<T> = Result<Vec<T>, RepairShoprError>;
+
+/// Collect successful lookups, remembering the first failure
+fn flatten_lookups<T>(lookups: Vec<Lookup<T>>, first_error: &mut Option<RepairShoprError>, any_ok: &mut bool) -> Vec<T> {
+    let mut items = vec![];
+    for lookup in lookups {
+        match lookup {
+            Ok(found) => {
+                *any_ok = true;
+                items.extend(found);
+            }
+            Err(e) => {
+                eprintln!("Search lookup failed: {}", e);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    items
+}
+
+/// Flatten the successful lookups, failing only if every lookup failed
+fn combine_lookups(
+    customer_lookups: Vec<Lookup<Customer>>,
+    ticket_lookups: Vec<Lookup<Ticket>>,
+) -> Result<(Vec<Customer>, Vec<Ticket>), RepairShoprError> {
+    let mut first_error = None;
+    let mut any_ok = false;
+    let customers = flatten_lookups(customer_lookups, &mut first_error, &mut any_ok);
+    let tickets = flatten_lookups(ticket_lookups, &mut first_error, &mut any_ok);
+
+    match first_error {
+        Some(e) if !any_ok => Err(e),
+        _ => Ok((customers, tickets)),
+    }
+}
+
+/// Run the RepairShopr lookups appropriate for the query kind concurrently
+pub async fn search(rs_client: &RepairShoprClient, query: &str) -> Result<SearchResults, RepairShoprError> {
+    let kind = classify_query(query);
+    let trimmed = query.trim();
+
+    let (customer_lookups, ticket_lookups) = match kind {
+        QueryKind::TicketNumber => {
+            let number = trimmed.trim_start_matches('#');
+            let by_number = TicketQuery {
+                number: Some(number.to_string()),
+                ..Default::default()
+            };
+            let (exact, text) = tokio::join!(
+                rs_client.list_tickets(&by_number, 1),
+                rs_client.search_tickets(number)
+            );
+            (vec![], vec![exact.map(|page| page.items), text])
+        }
+        QueryKind::Phone => {
+            let digits = national_phone_digits(trimmed);
+            let (customers, tickets) = tokio::join!(
+                rs_client.search_customers(&digits),
+                rs_client.search_tickets(&digits)
+            );
+            (vec![customers], vec![tickets])
+        }
+        QueryKind::Email => {
+            let by_email = CustomerQuery {
+                email: Some(trimmed.to_string()),
+                ..Default::default()
+            };
+            let (exact, text) = tokio::join!(
+                rs_client.list_customers(&by_email, 1),
+                rs_client.search_customers(trimmed)
+            );
+            (vec![exact.map(|page| page.items), text], vec![])
+        }
+        QueryKind::Name => {
+            let (customers, tickets) = tokio::join!(
+                rs_client.search_customers(trimmed),
+                rs_client.search_tickets(trimmed)
+            );
+            (vec![customers], vec![tickets])
+        }
+    };
+
+    let (customers, tickets) = combine_lookups(customer_lookups, ticket_lookups)?;
+    Ok(SearchResults {
+        query: trimmed.to_string(),
+        kind,
+        results: merge_results(trimmed, kind, customers, tickets, status_map()),
+    })
+}
+
+/// Handle the unified search endpoint
+pub async fn handle_search(event: &Request, rs_client: &RepairShoprClient) -> Result<Response<Body>, ApiError> {
+    let params = event.query_string_parameters();
+    let query = params.first("q").map(str::trim).unwrap_or("");
+    if query.is_empty() {
+        return Err(ApiError::new(400, "MISSING_QUERY", "A search query is required", "q parameter missing"));
+    }
+
+    let results = search(rs_client, query).await?;
+    let body = serde_json::to_string(&results)
+        .map_err(|e| ApiError::new(500, "SERIALIZATION_ERROR", "The response could not be built", e.to_string()))?;
+    Ok(success_response(200, body))
+}
@@ -0,0 +1,132 @@
+//! Staff endpoints for creating and revoking customer share links
+
+use chrono::{DateTime, Utc};
+use lambda_http::{Body, Request, Response};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::tickets::parse_ticket_id;
+use crate::audit::{AuditEntry, AuditStore};
+use crate::auth::UserIdentity;
+use crate::errors::ApiError;
+use crate::http::{parse_json_body, success_response};
+use crate::repairshopr::RepairShoprClient;
+use crate::share::{new_link_id, sign_token, RevocationStore, ShareClaims, ShareScope};
+
+/// Default and longest lifetime of a share link
+const DEFAULT_EXPIRY_HOURS: i64 = 72;
+const MAX_EXPIRY_HOURS: i64 = 30 * 24;
+
+/// Request body for creating a link
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CreateShareLink {
+    #[serde(default)]
+    pub scopes: Vec<ShareScope>,
+    #[serde(default)]
+    pub expires_in_hours: Option<i64>,
+}
+
+/// Link a customer can open: SHARE_LINK_BASE_URL (the frontend's status page) plus the
+/// token, or the API's public route when no base URL is configured
+pub fn share_url(base_url: Option<&str>, token: &str) -> String {
+    let base = base_url.map(|b| b.trim()).filter(|b| !b.is_empty()).unwrap_or("/public/status");
+    let separator = if base.contains('?') { '&' } else { '?' };
+    format!("{}{}token={}", base, separator, token)
+}
+
+/// Build and sign the claims for a new link (`now` in unix seconds)
+pub fn new_share_link(ticket_id: i64, request: &CreateShareLink, secret: &[u8], now: i64) -> Result<(ShareClaims, String), ApiError> {
+    let hours = request.expires_in_hours.unwrap_or(DEFAULT_EXPIRY_HOURS);
+    if !(1..=MAX_EXPIRY_HOURS).contains(&hours) {
+        return Err(ApiError::new(
+            400,
+            "INVALID_EXPIRY",
+            "Links can last between 1 hour and 30 days",
+            format!("expires_in_hours was {}", hours),
+        ));
+    }
+
+    let mut scopes = vec![ShareScope::Status];
+    for scope in &request.scopes {
+        if !scopes.contains(scope) {
+            scopes.push(*scope);
+        }
+    }
+
+    let claims = ShareClaims {
+        link_id: new_link_id(),
+        ticket_id,
+        expires_at: now + hours * 3600,
+        scopes,
+    };
+    let token = sign_token(&claims, secret);
+    Ok((claims, token))
+}
+
+/// Handle creating a share link for a ticket
+pub async fn handle_create_share_link(
+    event: &Request,
+    ticket_id: &str,
+    rs_client: &RepairShoprClient,
+    secret: &[u8],
+    audit: &dyn AuditStore,
+    actor: &UserIdentity,
+) -> Result<Response<Body>, ApiError> {
+    let ticket_id = parse_ticket_id(ticket_id)?;
+    let request = parse_json_body::<CreateShareLink>(event)?;
+    // Make sure the ticket exists before handing out a link to it
+    let ticket = rs_client.get_ticket(ticket_id).await?;
+
+    let (claims, token) = new_share_link(ticket.id, &request, secret, Utc::now().timestamp())?;
+    let expires_at = DateTime::<Utc>::from_timestamp(claims.expires_at, 0).map(|t| t.to_rfc3339());
+
+    let entry = AuditEntry::new(
+        &actor.actor(),
+        "share_link.create",
+        &format!("ticket:{}", ticket.id),
+        json!({ "link_id": claims.link_id, "scopes": claims.scopes, "expires_at": expires_at }),
+    );
+    if let Err(e) = audit.record(entry).await {
+        eprintln!("Failed to record audit entry for share link: {}", e);
+    }
+
+    let base_url = std::env::var("SHARE_LINK_BASE_URL").ok();
+    let body = json!({
+        "link_id": claims.link_id,
+        "token": token,
+        "url": share_url(base_url.as_deref(), &token),
+        "ticket_id": ticket.id,
+        "scopes": claims.scopes,
+        "expires_at": expires_at,
+    });
+    Ok(success_response(201, body.to_string()))
+}
+
+/// Handle revoking a share link before it expires
+pub async fn handle_revoke_share_link(
+    link_id: &str,
+    revocations: &dyn RevocationStore,
+    audit: &dyn AuditStore,
+    actor: &UserIdentity,
+) -> Result<Response<Body>, ApiError> {
+    if link_id.len() != 24 || !link_id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ApiError::new(
+            400,
+            "INVALID_LINK_ID",
+            "That is not a share link id",
+            format!("'{}' is not a valid link id", link_id),
+        ));
+    }
+
+    revocations
+        .revoke(link_id, None)
+        .await
+        .map_err(|e| ApiError::new(500, "SHARE_LINK_STORE_ERROR", "The link could not be revoked", e))?;
+
+    let entry = AuditEntry::new(&actor.actor(), "share_link.revoke", &format!("share_link:{}", link_id), json!({}));
+    if let Err(e) = audit.record(entry).await {
+        eprintln!("Failed to record audit entry for share link revocation: {}", e);
+    }
+
+    Ok(success_response(200, json!({ "revoked": link_id }).to_string()))
+}
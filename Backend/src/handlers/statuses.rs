@@ -0,0 +1,26 @@
+//! Status mapping handler
+
+use lambda_http::{Body, Response};
+use serde_json::json;
+
+use crate::http::success_response;
+use crate::status::status_map;
+
+/// Handle listing the display statuses and the RepairShopr statuses behind each
+pub fn handle_list_statuses() -> Response<Body> {
+    let map = status_map();
+
+    let statuses: Vec<_> = map
+        .display_statuses()
+        .into_iter()
+        .map(|display| {
+            json!({
+                "display": display,
+                "repairshopr": map.repairshopr_statuses_for(display),
+                "canonical": map.to_repairshopr(display),
+            })
+        })
+        .collect();
+
+    success_response(200, json!({ "statuses": statuses }).to_string())
+}
@@ -0,0 +1,147 @@
+//! Typed ticket endpoints built on the RepairShopr client
+
+use lambda_http::{Body, Request, Response};
+use serde::Serialize;
+
+use crate::errors::ApiError;
+use crate::http::etag_response;
+use crate::repairshopr::{
+    Attachment, Comment, Customer, RepairShoprClient, RepairShoprError, Ticket, TicketProperties,
+    TicketQuery,
+};
+use crate::status::{status_map, StatusMap};
+
+/// A ticket with its display status applied
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TicketView {
+    pub id: i64,
+    pub number: i64,
+    pub subject: String,
+    pub status: String,
+    pub repairshopr_status: String,
+    pub customer_id: Option<i64>,
+    pub customer_name: Option<String>,
+    pub problem_type: Option<String>,
+    pub user_id: Option<i64>,
+    pub assigned_to: Option<String>,
+    pub due_date: Option<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub resolved_at: Option<String>,
+    pub properties: TicketProperties,
+}
+
+impl TicketView {
+    /// Normalize a RepairShopr ticket using `map` for the display status
+    pub fn from_ticket(ticket: &Ticket, map: &StatusMap) -> Self {
+        Self {
+            id: ticket.id,
+            number: ticket.number,
+            subject: ticket.subject.clone(),
+            status: map.to_display(&ticket.status),
+            repairshopr_status: ticket.status.clone(),
+            customer_id: ticket.customer_id,
+            customer_name: ticket.customer_business_then_name.clone(),
+            problem_type: ticket.problem_type.clone(),
+            user_id: ticket.user_id,
+            assigned_to: ticket.user.as_ref().and_then(|u| u.full_name.clone()),
+            due_date: ticket.due_date.clone(),
+            created_at: ticket.created_at.clone(),
+            updated_at: ticket.updated_at.clone(),
+            resolved_at: ticket.resolved_at.clone(),
+            properties: ticket.properties.clone(),
+        }
+    }
+}
+
+/// Everything the ticket screen needs, in one document
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TicketDetail {
+    pub ticket: TicketView,
+    pub customer: Option<Customer>,
+    pub comments: Vec<Comment>,
+    pub attachments: Vec<Attachment>,
+    /// The customer's other tickets (most recent page)
+    pub customer_tickets: Vec<TicketView>,
+}
+
+/// Parse a ticket id path segment
+pub fn parse_ticket_id(segment: &str) -> Result<i64, ApiError> {
+    segment.parse::<i64>().map_err(|_| {
+        ApiError::new(
+            400,
+            "INVALID_TICKET_ID",
+            "The ticket id must be a number",
+            format!("'{}' is not a valid ticket id", segment),
+        )
+    })
+}
+
+/// Fetch a ticket and, concurrently, its customer and the customer's other tickets
+pub async fn fetch_ticket_detail(rs_client: &RepairShoprClient, ticket_id: i64) -> Result<TicketDetail, RepairShoprError> {
+    let ticket = rs_client.get_ticket(ticket_id).await?;
+    let map = status_map();
+
+    let customer_id = ticket.customer_id;
+    let customer = async {
+        match customer_id {
+            Some(id) => rs_client.get_customer(id).await.map(Some),
+            None => Ok(None),
+        }
+    };
+    let history = async {
+        match customer_id {
+            Some(id) => {
+                let query = TicketQuery {
+                    customer_id: Some(id),
+                    ..Default::default()
+                };
+                rs_client.list_tickets(&query, 1).await.map(|page| page.items)
+            }
+            None => Ok(vec![]),
+        }
+    };
+    let (customer, history) = tokio::join!(customer, history);
+
+    // A missing customer or history shouldn't hide the ticket itself
+    let customer = customer.unwrap_or_else(|e| {
+        eprintln!("Could not load customer for ticket {}: {}", ticket_id, e);
+        None
+    });
+    let history = history.unwrap_or_else(|e| {
+        eprintln!("Could not load customer tickets for ticket {}: {}", ticket_id, e);
+        vec![]
+    });
+
+    Ok(TicketDetail {
+        ticket: TicketView::from_ticket(&ticket, map),
+        customer,
+        comments: ticket.comments.clone(),
+        attachments: ticket.attachments.clone(),
+        customer_tickets: history
+            .iter()
+            .filter(|t| t.id != ticket.id)
+            .map(|t| TicketView::from_ticket(t, map))
+            .collect(),
+    })
+}
+
+/// Handle fetching the aggregated ticket detail document
+pub async fn handle_ticket_detail(
+    event: &Request,
+    ticket_id: &str,
+    rs_client: &RepairShoprClient,
+) -> Result<Response<Body>, ApiError> {
+    let ticket_id = parse_ticket_id(ticket_id)?;
+
+    let detail = fetch_ticket_detail(rs_client, ticket_id).await?;
+    let body = serde_json::to_string(&detail)
+        .map_err(|e| ApiError::new(500, "SERIALIZATION_ERROR", "The response could not be built", e.to_string()))?;
+
+    let if_none_match = event
+        .headers()
+        .get("if-none-match")
+        .and_then(|v| v.to_str().ok());
+
+    Ok(etag_response(body, if_none_match))
+}
@@ -6,6 +6,7 @@ use aws_sdk_cognitoidentityprovider::types::AttributeType;
 use serde_json::json;
 
 use crate::auth::{get_user_groups_from_event, can_manage_users, generate_temp_password};
+use crate::errors::ApiError;
 use crate::http::{error_response, success_response};
 
 /// Handle user invitation
@@ -13,18 +14,9 @@ pub async fn handle_user_invitation(
     email: &str,
     first_name: &str,
     cognito_client: &CognitoClient,
-) -> Response<Body> {
-    let user_pool_id = match std::env::var("USER_POOL_ID") {
-        Ok(id) => id,
-        Err(_) => {
-            return error_response(
-                500,
-                "Configuration error",
-                "USER_POOL_ID environment variable not set",
-                None,
-            )
-        }
-    };
+) -> Result<Response<Body>, ApiError> {
+    let user_pool_id = std::env::var("USER_POOL_ID")
+        .map_err(|_| ApiError::configuration("USER_POOL_ID environment variable not set"))?;
 
     // Check if user already exists
     match cognito_client
@@ -38,16 +30,12 @@ pub async fn handle_user_invitation(
             eprintln!("User {} already exists with status: {:?}", email, user.user_status());
         }
         Err(e) => {
-            if !e.to_string().contains("UserNotFoundException") {
-                if e.to_string().contains("AccessDeniedException") {
-                    return error_response(
-                        500,
-                        "Access denied",
-                        "The Lambda's execution role is missing permissions to call Cognito admin APIs",
-                        Some("Attach an IAM policy granting the cognito-idp:Admin* actions to the Lambda role"),
-                    );
-                }
-                eprintln!("Error checking if user exists: {}", e);
+            let error = ApiError::from_cognito("admin_get_user", &e);
+            if error.code == "IDENTITY_ACCESS_DENIED" {
+                return Err(error);
+            }
+            if error.code != "USER_NOT_FOUND" {
+                eprintln!("Error checking if user exists: {}", error.detail());
             }
         }
     }
@@ -99,20 +87,7 @@ pub async fn handle_user_invitation(
                 .send()
                 .await
             {
-                if e.to_string().contains("AccessDeniedException") {
-                    return error_response(
-                        500,
-                        "Access denied",
-                        "The Lambda's execution role is not authorized to set user password",
-                        Some("Attach an IAM policy allowing cognito-idp:AdminSetUserPassword to the Lambda role"),
-                    );
-                }
-                return error_response(
-                    500,
-                    "Password error",
-                    &format!("Could not set user password: {}", e),
-                    None,
-                );
+                return Err(ApiError::from_cognito("admin_set_user_password", &e));
             }
 
             // Add user to default employee group
@@ -124,7 +99,7 @@ pub async fn handle_user_invitation(
                 .send()
                 .await
             {
-                eprintln!("Warning: Could not add user to group: {}", e);
+                eprintln!("Warning: Could not add user to group: {:?}", e);
             }
 
             let user = response.user().expect("Couldn't collect user info after successfully inviting them");
@@ -139,52 +114,27 @@ pub async fn handle_user_invitation(
                 "user": user_info,
             });
 
-            success_response(200, response_body.to_string())
-        }
-        Err(e) => {
-            let error_code = e.to_string();
-            if error_code.contains("AccessDeniedException") {
-                return error_response(
-                    500,
-                    "Access denied",
-                    &format!("Could not invite user: {}", e),
-                    Some("Attach an IAM policy allowing the required Cognito admin actions to the Lambda role"),
-                );
-            }
-            error_response(
-                400,
-                "Could not invite user",
-                &e.to_string(),
-                None,
-            )
+            Ok(success_response(200, response_body.to_string()))
         }
+        Err(e) => Err(ApiError::from_cognito("admin_create_user", &e)),
     }
 }
 
 /// Handle listing all users
-pub async fn handle_list_users(event: &Request, cognito_client: &CognitoClient) -> Response<Body> {
+pub async fn handle_list_users(event: &Request, cognito_client: &CognitoClient) -> Result<Response<Body>, ApiError> {
     // Check user permissions
     let user_groups = get_user_groups_from_event(event);
     if !can_manage_users(&user_groups) {
-        return error_response(
+        return Ok(error_response(
             403,
             "Insufficient permissions",
             "You do not have permission to view users",
             Some("Only ApplicationAdmin and Owner can view users"),
-        );
+        ));
     }
 
-    let user_pool_id = match std::env::var("USER_POOL_ID") {
-        Ok(id) => id,
-        Err(_) => {
-            return error_response(
-                500,
-                "Configuration error",
-                "USER_POOL_ID environment variable not set",
-                None,
-            )
-        }
-    };
+    let user_pool_id = std::env::var("USER_POOL_ID")
+        .map_err(|_| ApiError::configuration("USER_POOL_ID environment variable not set"))?;
 
     match cognito_client
         .list_users()
@@ -244,16 +194,9 @@ pub async fn handle_list_users(event: &Request, cognito_client: &CognitoClient)
                 "users": users,
             });
 
-            success_response(200, response_body.to_string())
-        }
-        Err(e) => {
-            error_response(
-                500,
-                "Failed to list users",
-                &e.to_string(),
-                None,
-            )
+            Ok(success_response(200, response_body.to_string()))
         }
+        Err(e) => Err(ApiError::from_cognito("list_users", &e)),
     }
 }
 
@@ -262,19 +205,10 @@ pub async fn handle_update_user_group(
     username: &str,
     new_group: &str,
     cognito_client: &CognitoClient,
-) -> Response<Body> {
+) -> Result<Response<Body>, ApiError> {
 
-    let user_pool_id = match std::env::var("USER_POOL_ID") {
-        Ok(id) => id,
-        Err(_) => {
-            return error_response(
-                500,
-                "Configuration error",
-                "USER_POOL_ID environment variable not set",
-                None,
-            )
-        }
-    };
+    let user_pool_id = std::env::var("USER_POOL_ID")
+        .map_err(|_| ApiError::configuration("USER_POOL_ID environment variable not set"))?;
 
     // Check if the new group is "delete" - if so, delete the user
     if new_group.to_lowercase() == "delete" {
@@ -311,16 +245,9 @@ pub async fn handle_update_user_group(
                 let response_body = json!({
                     "message": format!("User {} deleted successfully", username),
                 });
-                success_response(200, response_body.to_string())
-            }
-            Err(e) => {
-                error_response(
-                    500,
-                    "Failed to delete user",
-                    &e.to_string(),
-                    None,
-                )
+                Ok(success_response(200, response_body.to_string()))
             }
+            Err(e) => Err(ApiError::from_cognito("admin_delete_user", &e)),
         }
     } else {
         // Get current user groups
@@ -358,26 +285,12 @@ pub async fn handle_update_user_group(
                         let response_body = json!({
                             "message": format!("User {} moved to group {}", username, new_group),
                         });
-                        success_response(200, response_body.to_string())
-                    }
-                    Err(e) => {
-                        error_response(
-                            500,
-                            "Failed to add user to group",
-                            &e.to_string(),
-                            None,
-                        )
+                        Ok(success_response(200, response_body.to_string()))
                     }
+                    Err(e) => Err(ApiError::from_cognito("admin_add_user_to_group", &e)),
                 }
             }
-            Err(e) => {
-                error_response(
-                    500,
-                    "Failed to get user groups",
-                    &e.to_string(),
-                    None,
-                )
-            }
+            Err(e) => Err(ApiError::from_cognito("admin_list_groups_for_user", &e)),
         }
     }
 }
@@ -0,0 +1,137 @@
+//! RepairShopr webhook receiver: verifies deliveries and fans them out to the board
+//! cache, customer notifications and the audit log
+
+use lambda_http::{Body, Request, RequestExt, Response};
+use serde_json::json;
+
+use super::board::invalidate_board_cache;
+use crate::audit::{AuditEntry, AuditStore};
+use crate::errors::ApiError;
+use crate::events::{EventBus, TicketEvent};
+use crate::http::success_response;
+use crate::webhooks::{delivery_id, parse_webhook, verify_webhook, DeliveryStore, WebhookAction, WebhookEvent};
+
+/// Where a verified delivery goes
+pub struct WebhookContext<'a> {
+    pub deliveries: &'a dyn DeliveryStore,
+    pub events: &'a EventBus,
+    pub audit: &'a dyn AuditStore,
+}
+
+/// The ticket event to publish, if any: creations and status changes notify customers,
+/// other ticket and comment changes only refresh open views
+fn ticket_event(event: &WebhookEvent) -> Option<TicketEvent> {
+    let (action, ticket, previous_status) = match event {
+        WebhookEvent::Ticket {
+            action,
+            ticket,
+            previous_status,
+        } => (action, ticket, previous_status),
+        WebhookEvent::Comment { comment, .. } => {
+            return comment.ticket_id.map(|ticket_id| TicketEvent::Changed { ticket_id });
+        }
+        WebhookEvent::Customer { .. } => return None,
+    };
+    match action {
+        WebhookAction::Created => Some(TicketEvent::TicketCreated {
+            ticket: ticket.as_ref().clone(),
+        }),
+        WebhookAction::StatusChanged => Some(TicketEvent::StatusChanged {
+            ticket: ticket.as_ref().clone(),
+            previous_status: previous_status.clone(),
+        }),
+        WebhookAction::Updated | WebhookAction::Deleted => Some(TicketEvent::Changed { ticket_id: ticket.id }),
+    }
+}
+
+/// Apply a verified, first-time delivery; fails if any event consumer did, so the
+/// delivery can be retried
+pub async fn dispatch_webhook(event: &WebhookEvent, delivery: &str, context: &WebhookContext<'_>) -> Result<(), String> {
+    // Any ticket, customer or comment change can alter what the board shows
+    invalidate_board_cache();
+
+    if let Some(ticket_event) = ticket_event(event) {
+        // A retry after a partial failure reruns only the consumers that didn't finish, so
+        // customers aren't notified twice
+        let mut done = vec![];
+        for name in context.events.handler_names() {
+            if context.deliveries.consumer_done(delivery, name).await? {
+                done.push(name);
+            }
+        }
+        let mut failures = vec![];
+        for (name, result) in context.events.publish_each(&ticket_event, &done).await {
+            match result {
+                Ok(()) => {
+                    if let Err(e) = context.deliveries.mark_consumer_done(delivery, name).await {
+                        eprintln!("Failed to record webhook delivery {} progress: {}", delivery, e);
+                    }
+                }
+                Err(e) => failures.push(format!("{}: {}", name, e)),
+            }
+        }
+        if !failures.is_empty() {
+            return Err(failures.join("; "));
+        }
+    }
+
+    let details = match event {
+        WebhookEvent::Ticket { ticket, .. } => json!({ "delivery_id": delivery, "status": ticket.status }),
+        WebhookEvent::Comment { comment, .. } => json!({ "delivery_id": delivery, "ticket_id": comment.ticket_id }),
+        WebhookEvent::Customer { .. } => json!({ "delivery_id": delivery }),
+    };
+    let entry = AuditEntry::new(
+        "repairshopr",
+        &format!("webhook.{}", event.name()),
+        &event.target(),
+        details,
+    );
+    if let Err(e) = context.audit.record(entry).await {
+        eprintln!("Failed to record audit entry for webhook: {}", e);
+    }
+    Ok(())
+}
+
+/// Handle `POST /webhooks/repairshopr`
+pub async fn handle_repairshopr_webhook(
+    event: &Request,
+    secret: &[u8],
+    context: &WebhookContext<'_>,
+) -> Result<Response<Body>, ApiError> {
+    let body = event.body().as_ref();
+    let header = |name: &str| event.headers().get(name).and_then(|v| v.to_str().ok());
+    let query = event.query_string_parameters();
+    let signature = header("x-signature").or_else(|| header("x-hub-signature-256"));
+    let provided_secret = header("x-webhook-secret").or_else(|| query.first("secret"));
+    verify_webhook(secret, body, signature, provided_secret)?;
+
+    let Some(webhook) = parse_webhook(body, query.first("event"))? else {
+        // Acknowledge so RepairShopr doesn't keep retrying events we don't use
+        return Ok(success_response(200, json!({ "ignored": true }).to_string()));
+    };
+
+    let delivery = delivery_id(header("x-delivery-id"), body);
+    let first = context.deliveries.first_delivery(&delivery).await.map_err(|e| {
+        ApiError::new(500, "WEBHOOK_STORE_ERROR", "The webhook could not be recorded", e)
+    })?;
+    if !first {
+        let body = json!({ "duplicate": true, "delivery_id": delivery });
+        return Ok(success_response(200, body.to_string()));
+    }
+
+    // The claim only sticks once the delivery went through; otherwise RepairShopr's retry
+    // (prompted by the error) is processed again rather than acknowledged as a duplicate
+    if let Err(e) = dispatch_webhook(&webhook, &delivery, context).await {
+        if let Err(forget) = context.deliveries.forget_delivery(&delivery).await {
+            eprintln!("Failed to release webhook delivery {}: {}", delivery, forget);
+        }
+        return Err(ApiError::new(
+            502,
+            "WEBHOOK_DISPATCH_FAILED",
+            "The webhook could not be processed, retry later",
+            e,
+        ));
+    }
+    let body = json!({ "event": webhook.name(), "delivery_id": delivery });
+    Ok(success_response(200, body.to_string()))
+}
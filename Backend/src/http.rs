@@ -1,8 +1,41 @@
 //! HTTP utilities for request/response handling and CORS
 
-use lambda_http::{Body, Response};
+use lambda_http::{Body, Request, RequestExt, Response};
+use serde::de::DeserializeOwned;
 use serde_json::{json};
 
+use crate::errors::ApiError;
+
+/// HTTP methods accepted by the Lambda (advertised to browsers during preflight)
+pub const ALLOWED_METHODS: &str = "GET,POST,PUT,PATCH,DELETE,OPTIONS";
+
+/// Check whether a request method is one we accept
+pub fn is_allowed_method(method: &str) -> bool {
+    ALLOWED_METHODS.split(',').any(|m| m == method)
+}
+
+/// Best-effort client IP: the source IP API Gateway saw, else the first X-Forwarded-For hop
+pub fn client_ip(event: &Request) -> String {
+    use lambda_http::request::RequestContext;
+
+    let from_context = match event.request_context_ref() {
+        Some(RequestContext::ApiGatewayV1(context)) => context.identity.source_ip.clone(),
+        Some(RequestContext::ApiGatewayV2(context)) => context.http.source_ip.clone(),
+        _ => None,
+    };
+    from_context
+        .or_else(|| {
+            event
+                .headers()
+                .get("X-Forwarded-For")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .map(|ip| ip.trim().to_string())
+        })
+        .filter(|ip| !ip.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
 /// CORS origin header for all responses
 pub fn get_cors_origin_header() -> (&'static str, &'static str) {
     ("Access-Control-Allow-Origin", "*")
@@ -14,9 +47,9 @@ pub fn get_cors_preflight_headers() -> Vec<(&'static str, &'static str)> {
         ("Access-Control-Allow-Origin", "*"),
         (
             "Access-Control-Allow-Headers",
-            "Content-Type,X-Amz-Date,Authorization,X-Api-Key,X-Amz-Security-Token,If-Modified-Since",
+            "Content-Type,X-Amz-Date,Authorization,X-Api-Key,X-Amz-Security-Token,If-Modified-Since,If-None-Match,X-Status-Mapping",
         ),
-        ("Access-Control-Allow-Methods", "GET,POST,PUT,DELETE,OPTIONS"),
+        ("Access-Control-Allow-Methods", ALLOWED_METHODS),
         ("Access-Control-Max-Age", "86400"),
     ]
 }
@@ -57,6 +90,56 @@ pub fn success_response(status: u16, body: String) -> Response<Body> {
         .expect("Couldn't create success response")
 }
 
+/// Build a file response with CORS headers; `disposition` is `attachment` or `inline`
+pub fn file_response(content_type: &str, disposition: &str, file_name: &str, bytes: Vec<u8>) -> Response<Body> {
+    let (key, value) = get_cors_origin_header();
+    Response::builder()
+        .status(200)
+        .header(key, value)
+        .header("Content-Type", content_type)
+        .header("Content-Disposition", format!("{}; filename=\"{}\"", disposition, file_name))
+        .body(Body::Binary(bytes))
+        .expect("Couldn't create file response")
+}
+
+/// Compute a strong ETag for a response body
+pub fn compute_etag(body: &str) -> String {
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::digest(body.as_bytes());
+    format!("\"{}\"", hex::encode(&digest[..16]))
+}
+
+/// Build a JSON response carrying an ETag, or a bodiless 304 when the client's
+/// If-None-Match already matches it
+pub fn etag_response(body: String, if_none_match: Option<&str>) -> Response<Body> {
+    let etag = compute_etag(&body);
+    let matches = if_none_match.is_some_and(|header| {
+        header
+            .split(',')
+            .any(|tag| tag.trim() == "*" || tag.trim().trim_start_matches("W/") == etag)
+    });
+
+    let (key, value) = get_cors_origin_header();
+    let builder = Response::builder()
+        .header(key, value)
+        .header("Access-Control-Expose-Headers", "ETag")
+        .header("ETag", &etag)
+        .header("Cache-Control", "no-cache");
+
+    if matches {
+        builder
+            .status(304)
+            .body(Body::Empty)
+            .expect("Couldn't create not-modified response")
+    } else {
+        builder
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(body.into())
+            .expect("Couldn't create success response")
+    }
+}
+
 /// Handle CORS preflight requests
 pub fn handle_options() -> Response<Body> {
     let mut response = Response::builder().status(200);
@@ -70,3 +153,17 @@ pub fn handle_options() -> Response<Body> {
         .body(Body::Empty)
         .expect("Couldn't handle CORS request")
 }
+
+/// Parse a JSON request body into `T`, treating an empty body as `{}`
+pub fn parse_json_body<T: DeserializeOwned>(event: &Request) -> Result<T, ApiError> {
+    let bytes: &[u8] = match event.body() {
+        Body::Text(s) => s.as_bytes(),
+        Body::Binary(b) => b,
+        _ => b"{}",
+    };
+    let bytes = if bytes.iter().all(u8::is_ascii_whitespace) { b"{}" } else { bytes };
+
+    serde_json::from_slice(bytes).map_err(|e| {
+        ApiError::new(400, "INVALID_JSON", "The request body is not valid JSON for this endpoint", e.to_string())
+    })
+}
@@ -1,6 +1,21 @@
+mod approvals;
+mod audit;
 mod auth;
+mod cache;
+mod config;
+mod contact;
+mod errors;
+mod events;
 mod handlers;
 mod http;
+mod notifications;
+mod pdf;
+mod push;
+mod rate_limit;
+mod repairshopr;
+mod share;
+mod status;
+mod webhooks;
 
 use lambda_http::{run, service_fn, Body, Request, Response};
 use serde_json::Value;
@@ -8,24 +23,46 @@ use aws_config::BehaviorVersion;
 use aws_sdk_cognitoidentityprovider::Client as CognitoClient;
 use aws_sdk_s3::Client as S3Client;
 
-use auth::{can_invite_users, can_manage_users, get_user_groups_from_event};
-use handlers::{handle_list_users, handle_repairshopr_proxy, handle_update_user_group, handle_upload_attachment, handle_user_invitation};
-use http::{error_response, handle_options};
+use approvals::approval_store_from_env;
+use audit::audit_store_from_env;
+use auth::{can_invite_users, can_manage_users, can_message_customers, can_modify_records, can_use_admin_tools, get_user_groups_from_event, get_user_identity_from_event, is_staff};
+use handlers::{decide_approval, handle_create_approval, view_approval, ApprovalContext, handle_add_comment, handle_bulk_tickets, handle_list_comment_templates, handle_create_customer, handle_update_customer, handle_find_duplicates, handle_merge_customers, handle_export, ExportKind, handle_health, handle_search, handle_ticket_intake, handle_flush_sms, handle_set_sms_opt_out, handle_ticket_board, handle_list_statuses, handle_list_users, handle_public_status, handle_broadcast_event, handle_socket_event, handle_socket_token, is_broadcast_event, is_socket_event, handle_print_ticket, handle_reports, handle_create_share_link, handle_revoke_share_link, handle_ticket_detail, handle_repairshopr_proxy, status_write_target, ticket_write_event, probe_upstream, handle_update_user_group, handle_upload_attachment, handle_user_invitation, handle_repairshopr_webhook, WebhookContext};
+use errors::{request_id, ApiError};
+use http::{error_response, handle_options, is_allowed_method};
+use handlers::board::invalidate_board_cache;
+use handlers::public_status::{ShareLinkCheck, PUBLIC_LIMITER};
+use events::{event_bus_from_env, TicketEvent};
+use push::{connection_store_from_env, Broadcaster};
+use notifications::sms::{opt_out_store_from_env, SmsNotifier};
+use repairshopr::RepairShoprClient;
+use share::{revocation_store_from_env, share_secret};
+use webhooks::{delivery_store_from_env, webhook_secret};
 
 const TARGET_URL: &str = "https://Cacell.repairshopr.com/api/v1";
 
 /// Handle the Lambda event
 async fn handle_lambda_event(event: Request, cognito_client: &CognitoClient, s3_client: &S3Client) -> Response<Body> {
+    let request_id = request_id(&event);
+
+    // WebSocket events for the push channel have no HTTP route
+    if is_socket_event(&event) {
+        if is_broadcast_event(&event) {
+            return match Broadcaster::from_env(s3_client) {
+                Some(broadcaster) => handle_broadcast_event(&event, &broadcaster).await,
+                None => ApiError::configuration("WEBSOCKET_ENDPOINT environment variable not set").into_response(&request_id),
+            };
+        }
+        let store = connection_store_from_env(s3_client);
+        let secret = share_secret().ok();
+        return handle_socket_event(&event, store.as_ref(), secret.as_deref(), chrono::Utc::now().timestamp()).await;
+    }
+
     // Get API key from environment
     let api_key = match std::env::var("REPAIRSHOPR_API_KEY") {
         Ok(v) => v,
         Err(_) => {
-            return error_response(
-                500,
-                "Configuration error",
-                "REPAIRSHOPR_API_KEY environment variable not set",
-                None,
-            )
+            return ApiError::configuration("REPAIRSHOPR_API_KEY environment variable not set")
+                .into_response(&request_id)
         }
     };
 
@@ -45,7 +82,7 @@ async fn handle_lambda_event(event: Request, cognito_client: &CognitoClient, s3_
     }
 
     // Validate HTTP method
-    if !matches!(method, "GET" | "POST" | "PUT") {
+    if !is_allowed_method(method) {
         return error_response(
             400,
             "Invalid HTTP method",
@@ -54,9 +91,10 @@ async fn handle_lambda_event(event: Request, cognito_client: &CognitoClient, s3_
         );
     }
 
-    // Route based on path and method
-    match (path, method) {
-        ("/invite-user", "POST") => {
+    // Route based on path segments and method
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (segments.as_slice(), method) {
+        (["invite-user"], "POST") => {
             // Extract and validate invitation data from request
             let body_str = match event.body() {
                 Body::Empty => "{}",
@@ -114,12 +152,352 @@ async fn handle_lambda_event(event: Request, cognito_client: &CognitoClient, s3_
                 );
             }
 
-            handle_user_invitation(email, first_name, cognito_client).await
+            handle_user_invitation(email, first_name, cognito_client)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["admin", "customers", "duplicates"], "GET") => {
+            let user_groups = get_user_groups_from_event(&event);
+            if !can_use_admin_tools(&user_groups) {
+                return error_response(
+                    403,
+                    "Insufficient permissions",
+                    "You do not have permission to use admin tools",
+                    Some("Only ApplicationAdmin and Owner can find duplicate customers"),
+                );
+            }
+
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            handle_find_duplicates(&rs_client)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["admin", "customers", "merge"], "POST") => {
+            let user_groups = get_user_groups_from_event(&event);
+            if !can_use_admin_tools(&user_groups) {
+                return error_response(
+                    403,
+                    "Insufficient permissions",
+                    "You do not have permission to use admin tools",
+                    Some("Only ApplicationAdmin and Owner can merge customers"),
+                );
+            }
+
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            let audit = audit_store_from_env(s3_client);
+            let actor = get_user_identity_from_event(&event);
+            handle_merge_customers(&event, &rs_client, audit.as_ref(), &actor)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["exports", kind], "GET") => {
+            let Some(kind) = ExportKind::parse(kind) else {
+                return error_response(404, "Not found", "Exports are available for tickets and customers", None);
+            };
+            let user_groups = get_user_groups_from_event(&event);
+            if !can_use_admin_tools(&user_groups) {
+                return error_response(
+                    403,
+                    "Insufficient permissions",
+                    "You do not have permission to export tickets or customers",
+                    Some("Only ApplicationAdmin and Owner can run exports"),
+                );
+            }
+
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            handle_export(&event, kind, &rs_client, s3_client)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["reports"], "GET") => {
+            let user_groups = get_user_groups_from_event(&event);
+            if !can_use_admin_tools(&user_groups) {
+                return error_response(
+                    403,
+                    "Insufficient permissions",
+                    "You do not have permission to view reports",
+                    Some("Only ApplicationAdmin and Owner can view reports"),
+                );
+            }
+
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            handle_reports(&event, &rs_client)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["health"], "GET") => {
+            handle_health(&api_key, TARGET_URL).await
+        }
+        (["comment-templates"], "GET") => handle_list_comment_templates(),
+        (["customers"], "POST") => {
+            let user_groups = get_user_groups_from_event(&event);
+            if !is_staff(&user_groups) {
+                return error_response(
+                    403,
+                    "Insufficient permissions",
+                    "You do not have permission to create customers",
+                    None,
+                );
+            }
+
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            handle_create_customer(&event, &rs_client)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["customers", customer_id], "PUT") => {
+            let user_groups = get_user_groups_from_event(&event);
+            if !is_staff(&user_groups) {
+                return error_response(
+                    403,
+                    "Insufficient permissions",
+                    "You do not have permission to change customers",
+                    None,
+                );
+            }
+
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            handle_update_customer(&event, customer_id, &rs_client)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        // Public routes: API Gateway serves /public/* without the Cognito authorizer
+        (["public", "status"], "GET") => {
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            let revocations = revocation_store_from_env(s3_client);
+            handle_public_status(&event, &rs_client, revocations.as_ref(), &request_id)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["notifications", "sms", "flush"], "POST") => {
+            let user_groups = get_user_groups_from_event(&event);
+            if !can_modify_records(&user_groups) {
+                return error_response(
+                    403,
+                    "Insufficient permissions",
+                    "You do not have permission to send notifications",
+                    None,
+                );
+            }
+
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            handle_flush_sms(&SmsNotifier::from_env(rs_client, s3_client)).await
+        }
+        (["notifications", "sms", "opt-outs"], "POST") => {
+            let user_groups = get_user_groups_from_event(&event);
+            if !can_message_customers(&user_groups) {
+                return error_response(
+                    403,
+                    "Insufficient permissions",
+                    "You do not have permission to change customer messaging preferences",
+                    None,
+                );
+            }
+
+            let opt_outs = opt_out_store_from_env(s3_client);
+            let audit = audit_store_from_env(s3_client);
+            let actor = get_user_identity_from_event(&event);
+            handle_set_sms_opt_out(&event, opt_outs.as_ref(), audit.as_ref(), &actor)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["public", "approvals"], "GET" | "POST") => {
+            let secret = match share_secret() {
+                Ok(secret) => secret,
+                Err(e) => return e.into_response(&request_id),
+            };
+            let revocations = revocation_store_from_env(s3_client);
+            let approvals = approval_store_from_env(s3_client);
+            let check = ShareLinkCheck {
+                secret: &secret,
+                revocations: revocations.as_ref(),
+                now: chrono::Utc::now().timestamp(),
+            };
+
+            if method == "GET" {
+                view_approval(&event, &PUBLIC_LIMITER, &check, approvals.as_ref(), &request_id).await
+            } else {
+                let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+                let audit = audit_store_from_env(s3_client);
+                let events = event_bus_from_env(&rs_client, s3_client);
+                let context = ApprovalContext {
+                    approvals: approvals.as_ref(),
+                    audit: audit.as_ref(),
+                    events: &events,
+                };
+                decide_approval(&event, &rs_client, &PUBLIC_LIMITER, &check, &context, &request_id)
+                    .await
+                    .unwrap_or_else(|e| e.into_response(&request_id))
+            }
+        }
+        (["push", "token"], "POST") => {
+            let user_groups = get_user_groups_from_event(&event);
+            if !is_staff(&user_groups) {
+                return error_response(
+                    403,
+                    "Insufficient permissions",
+                    "You do not have permission to open the push channel",
+                    None,
+                );
+            }
+
+            match share_secret() {
+                Ok(secret) => {
+                    let actor = get_user_identity_from_event(&event);
+                    handle_socket_token(&actor, &secret, chrono::Utc::now().timestamp())
+                }
+                Err(e) => e.into_response(&request_id),
+            }
+        }
+        (["search"], "GET") => {
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            handle_search(&event, &rs_client)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["share-links", link_id], "DELETE") => {
+            let user_groups = get_user_groups_from_event(&event);
+            if !is_staff(&user_groups) {
+                return error_response(
+                    403,
+                    "Insufficient permissions",
+                    "You do not have permission to revoke share links",
+                    None,
+                );
+            }
+
+            let revocations = revocation_store_from_env(s3_client);
+            let audit = audit_store_from_env(s3_client);
+            let actor = get_user_identity_from_event(&event);
+            handle_revoke_share_link(link_id, revocations.as_ref(), audit.as_ref(), &actor)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["statuses"], "GET") => handle_list_statuses(),
+        (["tickets", "board"], "GET") => {
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            handle_ticket_board(&event, &rs_client)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["tickets", "bulk"], "POST") => {
+            let user_groups = get_user_groups_from_event(&event);
+            if !can_modify_records(&user_groups) {
+                return error_response(
+                    403,
+                    "Insufficient permissions",
+                    "You do not have permission to change tickets",
+                    None,
+                );
+            }
+
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            let author = get_user_identity_from_event(&event);
+            let events = event_bus_from_env(&rs_client, s3_client);
+            handle_bulk_tickets(&event, &rs_client, &author, can_message_customers(&user_groups), &events)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["tickets", "intake"], "POST") => {
+            let user_groups = get_user_groups_from_event(&event);
+            if !is_staff(&user_groups) {
+                return error_response(
+                    403,
+                    "Insufficient permissions",
+                    "You do not have permission to create tickets",
+                    None,
+                );
+            }
+
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            let events = event_bus_from_env(&rs_client, s3_client);
+            handle_ticket_intake(&event, &rs_client, &events)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["tickets", ticket_id, "comment"], "POST") => {
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            let author = get_user_identity_from_event(&event);
+            let user_groups = get_user_groups_from_event(&event);
+            let response = handle_add_comment(&event, ticket_id, &rs_client, &author, can_message_customers(&user_groups))
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id));
+            if response.status().is_success()
+                && let Ok(ticket_id) = ticket_id.parse()
+            {
+                event_bus_from_env(&rs_client, s3_client).publish(&TicketEvent::Changed { ticket_id }).await;
+            }
+            response
+        }
+        (["tickets", ticket_id, "approvals"], "POST") => {
+            let user_groups = get_user_groups_from_event(&event);
+            if !is_staff(&user_groups) {
+                return error_response(
+                    403,
+                    "Insufficient permissions",
+                    "You do not have permission to change tickets",
+                    None,
+                );
+            }
+
+            let secret = match share_secret() {
+                Ok(secret) => secret,
+                Err(e) => return e.into_response(&request_id),
+            };
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            let approvals = approval_store_from_env(s3_client);
+            let audit = audit_store_from_env(s3_client);
+            let events = event_bus_from_env(&rs_client, s3_client);
+            let context = ApprovalContext {
+                approvals: approvals.as_ref(),
+                audit: audit.as_ref(),
+                events: &events,
+            };
+            let actor = get_user_identity_from_event(&event);
+            handle_create_approval(&event, ticket_id, &rs_client, &context, &actor, &secret)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["tickets", ticket_id, "share-links"], "POST") => {
+            let user_groups = get_user_groups_from_event(&event);
+            if !is_staff(&user_groups) {
+                return error_response(
+                    403,
+                    "Insufficient permissions",
+                    "You do not have permission to share tickets",
+                    None,
+                );
+            }
+
+            let secret = match share_secret() {
+                Ok(secret) => secret,
+                Err(e) => return e.into_response(&request_id),
+            };
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            let audit = audit_store_from_env(s3_client);
+            let actor = get_user_identity_from_event(&event);
+            handle_create_share_link(&event, ticket_id, &rs_client, &secret, audit.as_ref(), &actor)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["tickets", ticket_id, "print"], "GET") => {
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            handle_print_ticket(&event, ticket_id, &rs_client)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
         }
-        ("/users", "GET") => {
-            handle_list_users(&event, cognito_client).await
+        (["tickets", ticket_id, "full"], "GET") => {
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            handle_ticket_detail(&event, ticket_id, &rs_client)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
         }
-        ("/update-user-group", "POST") => {
+        (["users"], "GET") => {
+            handle_list_users(&event, cognito_client)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["update-user-group"], "POST") => {
             // Extract and validate user group update data from request
             let body_str = match event.body() {
                 Body::Empty => "{}",
@@ -187,9 +565,11 @@ async fn handle_lambda_event(event: Request, cognito_client: &CognitoClient, s3_
                 );
             }
 
-            handle_update_user_group(username, new_group, cognito_client).await
+            handle_update_user_group(username, new_group, cognito_client)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
         }
-        ("/upload-attachment", "POST") => {
+        (["upload-attachment"], "POST") => {
             // Extract and validate attachment data from request
             let body_str = match event.body() {
                 Body::Empty => "{}",
@@ -269,20 +649,69 @@ async fn handle_lambda_event(event: Request, cognito_client: &CognitoClient, s3_
                 image_data
             };
 
-            handle_upload_attachment(ticket_id, base64_data, file_name, &api_key, s3_client, TARGET_URL).await
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            let response = handle_upload_attachment(ticket_id, base64_data, file_name, s3_client, &rs_client)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id));
+            if response.status().is_success() {
+                event_bus_from_env(&rs_client, s3_client).publish(&TicketEvent::Changed { ticket_id }).await;
+            }
+            response
         }
-        (p, _) if p.starts_with("/api") => {
+        (["webhooks", "repairshopr"], "POST") => {
+            let secret = match webhook_secret() {
+                Ok(secret) => secret,
+                Err(e) => return e.into_response(&request_id),
+            };
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            let deliveries = delivery_store_from_env(s3_client);
+            let events = event_bus_from_env(&rs_client, s3_client);
+            let audit = audit_store_from_env(s3_client);
+            let context = WebhookContext {
+                deliveries: deliveries.as_ref(),
+                events: &events,
+                audit: audit.as_ref(),
+            };
+            handle_repairshopr_webhook(&event, &secret, &context)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id))
+        }
+        (["api", ..], _) => {
             // Route to RepairShopr proxy for /api/* paths
+            if matches!(method, "DELETE" | "PATCH") {
+                let user_groups = get_user_groups_from_event(&event);
+                if !can_modify_records(&user_groups) {
+                    return error_response(
+                        403,
+                        "Insufficient permissions",
+                        &format!("You do not have permission to send {} requests", method),
+                        Some("Only managers, owners and administrators can delete or patch RepairShopr records"),
+                    );
+                }
+            }
+
             let modified_path = path.strip_prefix("/api").unwrap_or("");
-            match handle_repairshopr_proxy(&event, modified_path, &api_key, TARGET_URL).await {
-                Ok(response) => response,
-                Err(e) => error_response(
-                    502,
-                    "Bad Gateway (rs)",
-                    &e,
-                    Some("A network error occurred when trying to reach RepairShopr."),
-                ),
+
+            // Writes to tickets make this instance's cached board stale
+            if method != "GET" && modified_path.starts_with("/tickets") {
+                invalidate_board_cache();
+            }
+
+            // Ticket writes are published; status saves also note the status being replaced
+            let rs_client = RepairShoprClient::new(TARGET_URL, &api_key);
+            let status_write = status_write_target(&event, modified_path);
+            let previous_status = match status_write {
+                Some(ticket_id) => rs_client.get_ticket(ticket_id).await.ok().map(|t| t.status),
+                None => None,
+            };
+
+            let response = handle_repairshopr_proxy(&event, modified_path, &api_key, TARGET_URL)
+                .await
+                .unwrap_or_else(|e| e.into_response(&request_id));
+            if let Some(ticket_event) = ticket_write_event(&event, modified_path, &response, previous_status) {
+                event_bus_from_env(&rs_client, s3_client).publish(&ticket_event).await;
             }
+            response
         }
         _ => {
             // Method not allowed for other paths
@@ -309,14 +738,120 @@ async fn function_handler(event: Request) -> Result<Response<Body>, lambda_http:
 #[tokio::main]
 async fn main() -> Result<(), lambda_http::Error> {
     lambda_http::tracing::init_default_subscriber();
+
+    // Internal state must never land in the public attachments bucket
+    config::check_state_bucket(|key| std::env::var(key).ok())?;
+
+    // Optionally confirm at cold start that RepairShopr accepts the configured User-Agent
+    if std::env::var("REPAIRSHOPR_PROBE_ON_STARTUP").is_ok_and(|v| v == "true")
+        && let Ok(api_key) = std::env::var("REPAIRSHOPR_API_KEY")
+    {
+        let probe = probe_upstream(&api_key, TARGET_URL).await;
+        if !probe.accepted {
+            eprintln!(
+                "Warning: RepairShopr rejected User-Agent '{}' (status {:?})",
+                probe.user_agent, probe.upstream_status
+            );
+        }
+    }
+
     run(service_fn(function_handler)).await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::http::{get_cors_preflight_headers, success_response};
-    use crate::auth::{can_invite_users, can_manage_users, generate_temp_password};
+    use crate::http::{get_cors_preflight_headers, success_response, ALLOWED_METHODS};
+    use crate::auth::{can_invite_users, can_manage_users, can_modify_records, generate_temp_password, is_staff};
+    use crate::handlers::proxy::{build_upstream_url, prepare_body, BodyRejection};
+    use lambda_http::{http, RequestExt};
+    use crate::config::{check_state_bucket, default_user_agent, tenant_from_target_url, UpstreamConfig};
+    use crate::repairshopr::{
+        AttachmentUrl, Comment, Customer, CustomerInput, NewComment, RepairShoprError, Ticket, TicketProperties,
+        TicketQuery, MAX_PAGES,
+    };
+    use crate::repairshopr::models::TicketUser;
+    use crate::contact::{normalize_email, normalize_name, normalize_phone};
+    use crate::handlers::board::{build_board, BoardFilters};
+    use crate::handlers::exports::{customer_table, ticket_table, write_csv, write_xlsx, ExportFormat, ExportRequest, Table};
+    use crate::handlers::receipts::{label_layout, receipt_layout, PrintLayout, ShopDetails};
+    use crate::pdf::{text_width, truncate, wrap};
+    use crate::handlers::reports::{build_report, parse_status_change, Interval, ReportRange};
+    use crate::handlers::duplicates::{cluster_customers, merge_customers, MergeRequest};
+    use crate::audit::InMemoryAuditStore;
+    use crate::approvals::{format_amount, ApprovalRequest, ApprovalStore, InMemoryApprovalStore};
+    use crate::handlers::approvals::{validate_approval, CreateApproval};
+    use serde_json::json;
+    use crate::handlers::public_status::{lookup_public_status, view_shared_ticket, ShareLinkCheck};
+    use crate::handlers::share_links::{new_share_link, share_url, CreateShareLink};
+    use crate::share::{sign_token, verify_token, InMemoryRevocationStore, ShareClaims, ShareError, ShareScope};
+    use crate::rate_limit::{RateDecision, RateLimiter};
+    use crate::handlers::bulk::{run_bulk, BulkAction, BulkOptions, BulkRequest};
+    use crate::handlers::comments::{build_comment, CommentRequest, CommentTemplates, TemplateError};
+    use crate::handlers::intake::{build_intake_ticket, IntakeRequest, IntakeVocabulary};
+    use crate::auth::UserIdentity;
+    use crate::events::{EventBus, TicketEvent};
+    use crate::push::{
+        broadcast_event, sign_socket_token, ticket_topic, verify_socket_token, Broadcaster, ConnectionStore, InMemoryConnectionStore, PendingBroadcast,
+        S3ConnectionStore,
+        MockConnectionSender, ALL_TICKETS,
+    };
+    use lambda_http::aws_lambda_events::apigw::ApiGatewayWebsocketProxyRequestContext;
+    use lambda_http::request::RequestContext;
+    use crate::webhooks::{
+        parse_webhook, sign_webhook, verify_webhook, InMemoryDeliveryStore, WebhookAction, WebhookError, WebhookEvent,
+    };
+    use crate::notifications::dedupe::{DedupeStore, InMemoryDedupeStore, S3DedupeStore};
+    use crate::notifications::email::{redact_email, EmailAttachment, EmailMessage, EmailNotifier, EmailOutcome, EmailSender, MockEmailSender, SmtpSender};
+    use crate::notifications::sms::{
+        deferred_queue_from_env, opt_out_store_from_env, parse_utc_offset, redact_phone, InMemoryOptOutStore, MockSmsSender, OptOutStore,
+        DeferredQueue, PendingSms, QuietHours, S3DeferredQueue, SmsError, SmsNotifier, SmsOutcome, SmsSender, TwilioSender,
+    };
+    use crate::handlers::customers::normalize_customer;
+    use crate::handlers::search::{classify_query, merge_results, search, QueryKind, SearchHit};
+    use crate::handlers::tickets::fetch_ticket_detail;
+    use crate::status::StatusMap;
+    use wiremock::matchers::{body_json, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Read a response body as text for assertions
+    fn body_text(response: &Response<Body>) -> String {
+        match response.body() {
+            Body::Text(s) => s.clone(),
+            Body::Binary(b) => String::from_utf8_lossy(b).to_string(),
+            _ => String::new(),
+        }
+    }
+
+    /// S3 client that is never reached; the `*_from_env` stores fall back to memory without STATE_BUCKET
+    fn offline_s3_client() -> S3Client {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(aws_sdk_s3::config::Region::new("us-east-1"))
+            .build();
+        S3Client::from_conf(config)
+    }
+
+    /// S3 client talking to a wiremock server, with buckets in the path (`/state/<key>`)
+    fn mock_s3_client(server: &MockServer) -> S3Client {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(aws_sdk_s3::config::Region::new("us-east-1"))
+            .credentials_provider(aws_sdk_s3::config::Credentials::new("test", "test", None, None, "test"))
+            .endpoint_url(server.uri())
+            .force_path_style(true)
+            .build();
+        S3Client::from_conf(config)
+    }
+
+    /// ListObjectsV2 response naming `keys`
+    fn s3_listing(keys: &[String]) -> ResponseTemplate {
+        let contents: String = keys.iter().map(|k| format!("<Contents><Key>{}</Key></Contents>", k)).collect();
+        ResponseTemplate::new(200).set_body_string(format!(
+            "<ListBucketResult><Name>state</Name><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+            contents
+        ))
+    }
 
     #[test]
     fn test_cors_headers() {
@@ -376,6 +911,3352 @@ mod tests {
         assert!(!can_manage_users(&manager_groups));
     }
 
+    #[test]
+    fn test_allowed_methods_match_cors_header() {
+        let headers = get_cors_preflight_headers();
+        let (_, methods) = headers
+            .iter()
+            .find(|(k, _)| *k == "Access-Control-Allow-Methods")
+            .expect("missing Allow-Methods header");
+        assert_eq!(*methods, ALLOWED_METHODS);
+
+        for verb in ["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"] {
+            assert!(is_allowed_method(verb), "{} should be allowed", verb);
+        }
+        assert!(!is_allowed_method("TRACE"));
+    }
+
+    #[test]
+    fn test_can_modify_records() {
+        let manager_groups = vec!["TrueTickets-Cacell-Manager".to_string()];
+        assert!(can_modify_records(&manager_groups));
+
+        let employee_groups = vec!["TrueTickets-Cacell-Employee".to_string()];
+        assert!(!can_modify_records(&employee_groups));
+
+        let other_groups = vec!["SomeOtherApp-Admin".to_string()];
+        assert!(!can_modify_records(&other_groups));
+        assert!(!can_modify_records(&[]));
+    }
+
+    #[test]
+    fn test_is_staff() {
+        let employee_groups = vec!["TrueTickets-Cacell-Employee".to_string()];
+        assert!(is_staff(&employee_groups));
+
+        let other_groups = vec!["SomeOtherApp-Admin".to_string()];
+        assert!(!is_staff(&other_groups));
+        assert!(!is_staff(&[]));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_forwards_each_verb() {
+        let server = MockServer::start().await;
+        for verb in ["GET", "POST", "PUT", "PATCH", "DELETE"] {
+            Mock::given(method(verb))
+                .and(path("/tickets/1"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(format!("{{\"verb\":\"{}\"}}", verb)))
+                .mount(&server)
+                .await;
+        }
+
+        for verb in ["GET", "POST", "PUT", "PATCH", "DELETE"] {
+            let request = http::Request::builder()
+                .method(verb)
+                .uri("/api/tickets/1")
+                .body(Body::Empty)
+                .expect("failed to build request");
+            let response = handle_repairshopr_proxy(&request, "/tickets/1", "test-key", &server.uri())
+                .await
+                .expect("proxy request failed");
+            assert_eq!(response.status(), 200);
+            assert_eq!(body_text(&response), format!("{{\"verb\":\"{}\"}}", verb));
+        }
+    }
+
+    #[test]
+    fn test_upstream_url_keeps_raw_query() {
+        let request = http::Request::builder()
+            .uri("/api/tickets?status%5B%5D=New&status%5B%5D=In%20Progress&query=&email=a%2Bb%40c.com&page=2")
+            .body(Body::Empty)
+            .expect("failed to build request");
+        let url = build_upstream_url(&request, "/tickets", "https://example.com/api/v1");
+        assert_eq!(
+            url,
+            "https://example.com/api/v1/tickets?status%5B%5D=New&status%5B%5D=In%20Progress&query=&email=a%2Bb%40c.com&page=2"
+        );
+    }
+
+    #[test]
+    fn test_upstream_url_rebuilds_multi_value_params() {
+        let mut params = std::collections::HashMap::new();
+        params.insert(
+            "ticket_ids[]".to_string(),
+            vec!["1".to_string(), "2".to_string(), "3".to_string()],
+        );
+        let request = http::Request::builder()
+            .uri("/api/tickets")
+            .body(Body::Empty)
+            .expect("failed to build request")
+            .with_query_string_parameters(params);
+        let url = build_upstream_url(&request, "/tickets", "https://example.com/api/v1");
+        assert_eq!(
+            url,
+            "https://example.com/api/v1/tickets?ticket_ids%5B%5D=1&ticket_ids%5B%5D=2&ticket_ids%5B%5D=3"
+        );
+
+        let mut params = std::collections::HashMap::new();
+        params.insert("query".to_string(), vec!["a&b=c d/é".to_string()]);
+        let request = http::Request::builder()
+            .uri("/api/customers")
+            .body(Body::Empty)
+            .expect("failed to build request")
+            .with_query_string_parameters(params);
+        let url = build_upstream_url(&request, "/customers", "https://example.com/api/v1");
+        assert_eq!(url, "https://example.com/api/v1/customers?query=a%26b%3Dc%20d%2F%C3%A9");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_forwards_repeated_query_params() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/tickets"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .mount(&server)
+            .await;
+
+        let request = http::Request::builder()
+            .method("GET")
+            .uri("/api/tickets?status%5B%5D=New&status%5B%5D=In%20Progress&q=")
+            .body(Body::Empty)
+            .expect("failed to build request");
+        handle_repairshopr_proxy(&request, "/tickets", "test-key", &server.uri())
+            .await
+            .expect("proxy request failed");
+
+        let received = server.received_requests().await.unwrap_or_default();
+        assert_eq!(received.len(), 1);
+        assert_eq!(
+            received[0].url.query(),
+            Some("status%5B%5D=New&status%5B%5D=In%20Progress&q=")
+        );
+        let statuses: Vec<String> = received[0]
+            .url
+            .query_pairs()
+            .filter(|(k, _)| k == "status[]")
+            .map(|(_, v)| v.to_string())
+            .collect();
+        assert_eq!(statuses, vec!["New", "In Progress"]);
+    }
+
+    #[test]
+    fn test_prepare_body_limits_and_validates() {
+        let request = http::Request::builder()
+            .method("POST")
+            .body(Body::Text(r#"{"customer":{"firstname":"Ann"}}"#.to_string()))
+            .expect("failed to build request");
+        let body = prepare_body(&request, 1024)
+            .expect("valid JSON should pass")
+            .expect("body should be present");
+        assert_eq!(body.content_type, "application/json");
+
+        assert_eq!(
+            prepare_body(&request, 8).err(),
+            Some(BodyRejection::TooLarge { size: 32, limit: 8 })
+        );
+        assert_eq!(BodyRejection::TooLarge { size: 32, limit: 8 }.into_response().status(), 413);
+
+        let request = http::Request::builder()
+            .method("POST")
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(Body::Text("{not json".to_string()))
+            .expect("failed to build request");
+        assert_eq!(prepare_body(&request, 1024).err(), Some(BodyRejection::InvalidJson));
+
+        let request = http::Request::builder()
+            .method("PUT")
+            .body(Body::Empty)
+            .expect("failed to build request");
+        assert_eq!(prepare_body(&request, 1024).ok(), Some(None));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_forwards_binary_body_unchanged() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/tickets/1/attach"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .mount(&server)
+            .await;
+
+        let payload = vec![0x89, 0x50, 0x4e, 0x47, 0xff, 0x00, 0xfe];
+        let request = http::Request::builder()
+            .method("POST")
+            .header("Content-Type", "image/png")
+            .body(Body::Binary(payload.clone()))
+            .expect("failed to build request");
+        let response = handle_repairshopr_proxy(&request, "/tickets/1/attach", "test-key", &server.uri())
+            .await
+            .expect("proxy request failed");
+        assert_eq!(response.status(), 200);
+
+        let received = server.received_requests().await.unwrap_or_default();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].body, payload);
+        assert_eq!(
+            received[0].headers.get("content-type").and_then(|v| v.to_str().ok()),
+            Some("image/png")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rejects_invalid_json_without_calling_upstream() {
+        let server = MockServer::start().await;
+        let request = http::Request::builder()
+            .method("POST")
+            .body(Body::Text("{\"ticket\":".to_string()))
+            .expect("failed to build request");
+        let response = handle_repairshopr_proxy(&request, "/tickets", "test-key", &server.uri())
+            .await
+            .expect("validation errors are returned as responses");
+        assert_eq!(response.status(), 400);
+        assert!(server.received_requests().await.unwrap_or_default().is_empty());
+    }
+
+    #[test]
+    fn test_upstream_config_defaults_to_identifying_agent() {
+        let config = UpstreamConfig::resolve(TARGET_URL, |_| None);
+        assert_eq!(config.user_agent, format!("TrueTickets/{}", env!("CARGO_PKG_VERSION")));
+        assert_eq!(config.accept_language, "en-US,en;q=0.9");
+    }
+
+    #[test]
+    fn test_upstream_config_overrides() {
+        let env = |key: &str| match key {
+            "REPAIRSHOPR_USER_AGENT" => Some("Global/1.0".to_string()),
+            "REPAIRSHOPR_USER_AGENT_CACELL" => Some("Cacell/2.0".to_string()),
+            "REPAIRSHOPR_ACCEPT_LANGUAGE" => Some("es-MX".to_string()),
+            _ => None,
+        };
+        let config = UpstreamConfig::resolve(TARGET_URL, env);
+        assert_eq!(config.user_agent, "Cacell/2.0");
+        assert_eq!(config.accept_language, "es-MX");
+
+        let config = UpstreamConfig::resolve("https://othershop.repairshopr.com/api/v1", env);
+        assert_eq!(config.user_agent, "Global/1.0");
+
+        assert_eq!(tenant_from_target_url(TARGET_URL).as_deref(), Some("cacell"));
+        assert_eq!(tenant_from_target_url("http://127.0.0.1:8080"), None);
+    }
+
+    #[test]
+    fn test_state_bucket_required_on_lambda() {
+        let env = |pairs: &'static [(&'static str, &'static str)]| {
+            move |key: &str| pairs.iter().find(|(k, _)| *k == key).map(|(_, v)| v.to_string())
+        };
+        assert!(check_state_bucket(env(&[])).is_ok());
+        assert!(check_state_bucket(env(&[("AWS_LAMBDA_FUNCTION_NAME", "tickets")])).is_err());
+        assert!(check_state_bucket(env(&[("AWS_LAMBDA_FUNCTION_NAME", "tickets"), ("STATE_BUCKET", "state")])).is_ok());
+
+        let shared = env(&[("STATE_BUCKET", "files"), ("S3_BUCKET_NAME", "files")]);
+        assert!(check_state_bucket(shared).is_err());
+        let public_audit = env(&[("STATE_BUCKET", "state"), ("AUDIT_BUCKET", "files"), ("S3_BUCKET_NAME", "files")]);
+        assert!(check_state_bucket(public_audit).is_err());
+        assert!(check_state_bucket(env(&[("STATE_BUCKET", "state"), ("AUDIT_BUCKET", "audit"), ("S3_BUCKET_NAME", "files")])).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_probe_reports_rejected_agent() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/me"))
+            .and(header("user-agent", default_user_agent().as_str()))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .mount(&server)
+            .await;
+
+        let probe = probe_upstream("test-key", &server.uri()).await;
+        assert!(probe.accepted);
+        assert_eq!(probe.upstream_status, Some(200));
+
+        server.reset().await;
+        Mock::given(method("GET"))
+            .and(path("/me"))
+            .respond_with(ResponseTemplate::new(403))
+            .mount(&server)
+            .await;
+
+        let probe = probe_upstream("test-key", &server.uri()).await;
+        assert!(!probe.accepted);
+        assert_eq!(probe.upstream_status, Some(403));
+    }
+
+    #[test]
+    fn test_cognito_errors_are_sanitized() {
+        let raw = "ServiceError { source: UsernameExistsException { message: \"User account already exists\" }, \
+                   raw: user_pool_id=us-east-1_AbC123xyz }";
+        let error = ApiError::from_cognito("admin_create_user", &raw);
+        assert_eq!(error.status, 409);
+        assert_eq!(error.code, "USER_EXISTS");
+
+        let response = error.into_response("req-1");
+        let body = body_text(&response);
+        assert!(!body.contains("us-east-1_AbC123xyz"));
+        assert!(!body.contains("UsernameExistsException"));
+        assert!(body.contains("\"code\":\"USER_EXISTS\""));
+        assert!(body.contains("\"request_id\":\"req-1\""));
+        assert_eq!(
+            response.headers().get("X-Request-Id").and_then(|v| v.to_str().ok()),
+            Some("req-1")
+        );
+
+        let unknown = ApiError::from_cognito("list_users", &"dispatch failure: us-east-1_AbC123xyz");
+        assert_eq!(unknown.status, 502);
+        assert!(!body_text(&unknown.into_response("req-2")).contains("us-east-1_AbC123xyz"));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_transport_failure_hides_url_and_key() {
+        let api_key = "rs-secret-api-key-1234";
+        let request = http::Request::builder()
+            .method("GET")
+            .uri("/api/customers?api_key=rs-secret-api-key-1234&query=smith")
+            .body(Body::Empty)
+            .expect("failed to build request");
+
+        // Nothing listens on port 9 locally, so the transport fails
+        let error = handle_repairshopr_proxy(&request, "/customers", api_key, "http://127.0.0.1:9/api/v1")
+            .await
+            .expect_err("unreachable upstream should fail");
+        assert!(error.detail().contains("127.0.0.1:9"));
+
+        let response = error.into_response("req-3");
+        assert_eq!(response.status(), 502);
+        let body = body_text(&response);
+        assert!(!body.contains(api_key));
+        assert!(!body.contains("127.0.0.1"));
+        assert!(!body.contains("query=smith"));
+        assert!(body.contains("UPSTREAM_UNAVAILABLE"));
+    }
+
+    #[test]
+    fn test_configuration_errors_hide_variable_names() {
+        let response = ApiError::configuration("USER_POOL_ID environment variable not set").into_response("req-4");
+        assert_eq!(response.status(), 500);
+        assert!(!body_text(&response).contains("USER_POOL_ID"));
+    }
+
+    #[tokio::test]
+    async fn test_repairshopr_client_get_ticket() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/tickets/42"))
+            .and(header("authorization", "Bearer test-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"ticket":{"id":42,"number":1042,"subject":"Cracked screen","status":"New",
+                    "customer_id":7,"properties":{"Password":"1234","Model":"vT{}","Custom":"x"},
+                    "comments":[{"id":1,"body":"Dropped","hidden":true}],
+                    "attachments":[{"id":5,"file_name":"front.png","file":{"url":"https://f/front.png"}}]}}"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let ticket = client.get_ticket(42).await.expect("ticket should decode");
+        assert_eq!(ticket.number, 1042);
+        assert_eq!(ticket.status, "New");
+        assert_eq!(ticket.customer_id, Some(7));
+        assert_eq!(ticket.properties.password.as_deref(), Some("1234"));
+        assert_eq!(ticket.properties.extra.get("Custom"), Some(&serde_json::json!("x")));
+        assert_eq!(ticket.comments.len(), 1);
+        assert_eq!(ticket.attachments[0].file_name.as_deref(), Some("front.png"));
+    }
+
+    #[tokio::test]
+    async fn test_repairshopr_client_walks_pages() {
+        let server = MockServer::start().await;
+        for page in 1..=3 {
+            Mock::given(method("GET"))
+                .and(path("/tickets"))
+                .and(query_param("page", page.to_string()))
+                .and(query_param("status", "New"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                    r#"{{"tickets":[{{"id":{},"number":{}}}],"meta":{{"total_pages":3,"page":{}}}}}"#,
+                    page,
+                    1000 + page,
+                    page
+                )))
+                .mount(&server)
+                .await;
+        }
+
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let query = TicketQuery {
+            status: Some("New".to_string()),
+            ..Default::default()
+        };
+        let tickets = client.list_all_tickets(&query, MAX_PAGES).await.expect("pages should decode");
+        assert_eq!(tickets.items.iter().map(|t| t.id).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert!(!tickets.truncated);
+
+        let capped = client.list_all_tickets(&query, 2).await.expect("pages should decode");
+        assert_eq!(capped.items.len(), 2);
+        assert!(capped.truncated);
+
+        let exact = client.list_all_tickets(&query, 3).await.expect("pages should decode");
+        assert!(!exact.truncated);
+    }
+
+    #[tokio::test]
+    async fn test_repairshopr_client_maps_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/customers/9"))
+            .respond_with(ResponseTemplate::new(404).set_body_string(r#"{"error":"not found"}"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/customers/10"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("not json"))
+            .mount(&server)
+            .await;
+
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let error = client.get_customer(9).await.expect_err("404 should fail");
+        assert!(matches!(error, RepairShoprError::Status { status: 404, .. }));
+        assert_eq!(ApiError::from(error).status, 404);
+
+        let error = client
+            .update_customer(10, &CustomerInput::default())
+            .await
+            .expect_err("bad body should fail");
+        assert!(matches!(error, RepairShoprError::Decode(_)));
+        assert_eq!(ApiError::from(error).code, "UPSTREAM_INVALID_RESPONSE");
+    }
+
+    #[tokio::test]
+    async fn test_repairshopr_client_sends_typed_payloads() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/tickets/42/comment"))
+            .and(body_json(serde_json::json!({
+                "subject": "Update",
+                "body": "Screen ordered",
+                "hidden": true,
+                "do_not_email": true
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"comment":{"id":3,"body":"Screen ordered"}}"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/tickets/42/attach_file_url"))
+            .and(body_json(serde_json::json!({
+                "files": [{"url": "https://bucket/a.png", "filename": "a.png"}]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"success":true}"#))
+            .mount(&server)
+            .await;
+
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let comment = NewComment {
+            subject: "Update".to_string(),
+            body: "Screen ordered".to_string(),
+            hidden: true,
+            do_not_email: true,
+            ..Default::default()
+        };
+        assert_eq!(client.add_comment(42, &comment).await.expect("comment should post").id, 3);
+
+        let files = [AttachmentUrl {
+            url: "https://bucket/a.png".to_string(),
+            filename: "a.png".to_string(),
+        }];
+        client.attach_file_url(42, &files).await.expect("attach should succeed");
+    }
+
+    #[test]
+    fn test_status_map_defaults() {
+        let map = StatusMap::default();
+        assert_eq!(map.to_display("New"), "Diagnosing");
+        assert_eq!(map.to_display("Call Customer"), "Approval Needed");
+        assert_eq!(map.to_display("Customer Reply"), "Ready");
+        assert_eq!(map.to_display("Ready!"), "Ready");
+        assert_eq!(map.to_display("Invoiced"), "Invoiced");
+
+        assert_eq!(map.to_repairshopr("Diagnosing"), "New");
+        assert_eq!(map.to_repairshopr("Waiting (Other)"), "Waiting on Customer");
+        assert_eq!(map.to_repairshopr("In Progress"), "In Progress");
+        assert_eq!(map.to_repairshopr("Scheduled"), "Scheduled");
+
+        assert_eq!(
+            map.display_statuses(),
+            vec![
+                "Diagnosing",
+                "Finding Price",
+                "Approval Needed",
+                "Waiting for Parts",
+                "Waiting (Other)",
+                "In Progress",
+                "Ready",
+                "Resolved",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_status_map_reverse_is_deterministic() {
+        let map = StatusMap::default();
+        assert_eq!(map.repairshopr_statuses_for("Ready"), vec!["Customer Reply", "Ready!"]);
+        for _ in 0..10 {
+            assert_eq!(map.to_repairshopr("Ready"), "Customer Reply");
+        }
+
+        // Every display status round-trips through its canonical RepairShopr status
+        for display in map.display_statuses() {
+            assert_eq!(map.to_display(&map.to_repairshopr(display)), display);
+        }
+
+        let reordered = StatusMap::from_json(r#"[["Ready!","Ready"],["Customer Reply","Ready"]]"#)
+            .expect("valid mapping");
+        assert_eq!(reordered.to_repairshopr("Ready"), "Ready!");
+        assert!(StatusMap::from_json("[]").is_err());
+        assert!(StatusMap::from_json(r#"{"New":"Diagnosing"}"#).is_err());
+    }
+
+    #[test]
+    fn test_status_map_translates_ticket_json() {
+        let map = StatusMap::default();
+        let mut body = serde_json::json!({
+            "tickets": [{"id": 1, "status": "Ready!"}, {"id": 2, "status": "Scheduled"}],
+            "meta": {"page": 1}
+        });
+        map.translate_response(&mut body);
+        assert_eq!(body["tickets"][0]["status"], "Ready");
+        assert_eq!(body["tickets"][0]["repairshopr_status"], "Ready!");
+        assert_eq!(body["tickets"][1]["status"], "Finding Price");
+
+        let mut body = serde_json::json!({"ticket": {"status": "Approval Needed", "subject": "x"}});
+        map.translate_request(&mut body);
+        assert_eq!(body["ticket"]["status"], "Call Customer");
+
+        let mut body = serde_json::json!({"status": "Ready"});
+        map.translate_request(&mut body);
+        assert_eq!(body["status"], "Customer Reply");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_maps_statuses_when_requested() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/tickets/1"))
+            .and(body_json(serde_json::json!({"status": "Call Customer"})))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"ticket":{"id":1,"status":"Call Customer"}}"#))
+            .mount(&server)
+            .await;
+
+        let request = http::Request::builder()
+            .method("PUT")
+            .header("X-Status-Mapping", "display")
+            .body(Body::Text(r#"{"status":"Approval Needed"}"#.to_string()))
+            .expect("failed to build request");
+        let response = handle_repairshopr_proxy(&request, "/tickets/1", "test-key", &server.uri())
+            .await
+            .expect("proxy request failed");
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_str(&body_text(&response)).expect("JSON body");
+        assert_eq!(body["ticket"]["status"], "Approval Needed");
+        assert_eq!(body["ticket"]["repairshopr_status"], "Call Customer");
+    }
+
+    /// Mount a ticket, its customer and the customer's ticket list on a mock RepairShopr
+    async fn mount_ticket_fixture(server: &MockServer) {
+        Mock::given(method("GET"))
+            .and(path("/tickets/42"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"ticket":{"id":42,"number":1042,"subject":"Cracked screen","status":"Ready!",
+                    "customer_id":7,"updated_at":"2026-01-02T10:00:00-06:00",
+                    "comments":[{"id":1,"body":"Screen replaced","hidden":false}],
+                    "attachments":[{"id":5,"file_name":"front.png"}]}}"#,
+            ))
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/customers/7"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"customer":{"id":7,"firstname":"Ann","lastname":"Lee","phone":"5551234567"}}"#,
+            ))
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/tickets"))
+            .and(query_param("customer_id", "7"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"tickets":[{"id":42,"number":1042,"status":"Ready!"},{"id":30,"number":1030,"status":"Resolved"}],
+                    "meta":{"total_pages":1}}"#,
+            ))
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_ticket_detail_aggregates_related_data() {
+        let server = MockServer::start().await;
+        mount_ticket_fixture(&server).await;
+
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let detail = fetch_ticket_detail(&client, 42).await.expect("detail should load");
+        assert_eq!(detail.ticket.status, "Ready");
+        assert_eq!(detail.ticket.repairshopr_status, "Ready!");
+        assert_eq!(detail.customer.as_ref().and_then(|c| c.firstname.as_deref()), Some("Ann"));
+        assert_eq!(detail.comments.len(), 1);
+        assert_eq!(detail.attachments.len(), 1);
+        assert_eq!(detail.customer_tickets.len(), 1);
+        assert_eq!(detail.customer_tickets[0].number, 1030);
+    }
+
+    #[tokio::test]
+    async fn test_ticket_detail_supports_etag() {
+        let server = MockServer::start().await;
+        mount_ticket_fixture(&server).await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+
+        let request = http::Request::builder()
+            .uri("/tickets/42/full")
+            .body(Body::Empty)
+            .expect("failed to build request");
+        let response = handle_ticket_detail(&request, "42", &client).await.expect("detail should load");
+        assert_eq!(response.status(), 200);
+        let etag = response
+            .headers()
+            .get("ETag")
+            .and_then(|v| v.to_str().ok())
+            .expect("ETag header")
+            .to_string();
+
+        let request = http::Request::builder()
+            .uri("/tickets/42/full")
+            .header("If-None-Match", &etag)
+            .body(Body::Empty)
+            .expect("failed to build request");
+        let response = handle_ticket_detail(&request, "42", &client).await.expect("detail should load");
+        assert_eq!(response.status(), 304);
+        assert_eq!(body_text(&response), "");
+
+        let error = handle_ticket_detail(&request, "abc", &client).await.expect_err("bad id should fail");
+        assert_eq!(error.status, 400);
+    }
+
+    /// Build a ticket for board tests
+    fn board_ticket(id: i64, status: &str, device: &str, user_id: i64, created_at: &str) -> Ticket {
+        Ticket {
+            id,
+            number: 1000 + id,
+            status: status.to_string(),
+            user_id: Some(user_id),
+            created_at: Some(created_at.to_string()),
+            properties: TicketProperties {
+                model: Some(format!(r#"vT{{"device":"{}","itemsLeft":["Charger"]}}"#, device)),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_build_board_groups_by_display_status() {
+        let now = chrono::DateTime::parse_from_rfc3339("2026-03-10T12:00:00Z")
+            .expect("valid timestamp")
+            .with_timezone(&chrono::Utc);
+        let tickets = vec![
+            board_ticket(1, "New", "Phone", 10, "2026-03-09T09:00:00-06:00"),
+            board_ticket(2, "Ready!", "Laptop", 11, "2026-03-01T09:00:00-06:00"),
+            board_ticket(3, "Customer Reply", "Phone", 10, "2026-02-20T09:00:00-06:00"),
+            board_ticket(4, "Invoiced", "Tablet", 12, "2026-03-10T09:00:00-06:00"),
+        ];
+
+        let board = build_board(&tickets, &BoardFilters::default(), &StatusMap::default(), now);
+        assert_eq!(board.total, 4);
+        let ready = board.columns.iter().find(|c| c.status == "Ready").expect("Ready column");
+        assert_eq!(ready.count, 2);
+        // Oldest ticket first
+        assert_eq!(ready.tickets[0].ticket.id, 3);
+        assert_eq!(ready.tickets[0].age_days, Some(17));
+        assert_eq!(board.columns.first().map(|c| c.status.as_str()), Some("Diagnosing"));
+        assert_eq!(board.columns.last().map(|c| c.status.as_str()), Some("Invoiced"));
+
+        let filters = BoardFilters {
+            device: Some("phone".to_string()),
+            ..Default::default()
+        };
+        let board = build_board(&tickets, &filters, &StatusMap::default(), now);
+        assert_eq!(board.total, 2);
+
+        let filters = BoardFilters {
+            user_id: Some(10),
+            min_age_days: Some(7),
+            ..Default::default()
+        };
+        let board = build_board(&tickets, &filters, &StatusMap::default(), now);
+        assert_eq!(board.total, 1);
+
+        let filters = BoardFilters {
+            max_age_days: Some(1),
+            ..Default::default()
+        };
+        let board = build_board(&tickets, &filters, &StatusMap::default(), now);
+        assert_eq!(board.total, 2);
+    }
+
+    #[test]
+    fn test_board_filters_from_query() {
+        let request = http::Request::builder()
+            .uri("/tickets/board")
+            .body(Body::Empty)
+            .expect("failed to build request")
+            .with_query_string_parameters(std::collections::HashMap::from([
+                ("device".to_string(), "Laptop".to_string()),
+                ("max_age_days".to_string(), "3".to_string()),
+            ]));
+        let filters = BoardFilters::from_event(&request).expect("valid filters");
+        assert_eq!(filters.device.as_deref(), Some("Laptop"));
+        assert_eq!(filters.max_age_days, Some(3));
+
+        let request = http::Request::builder()
+            .uri("/tickets/board")
+            .body(Body::Empty)
+            .expect("failed to build request")
+            .with_query_string_parameters(std::collections::HashMap::from([(
+                "user_id".to_string(),
+                "bob".to_string(),
+            )]));
+        assert_eq!(BoardFilters::from_event(&request).expect_err("invalid filter").status, 400);
+    }
+
+    #[test]
+    fn test_classify_search_query() {
+        assert_eq!(classify_query("1042"), QueryKind::TicketNumber);
+        assert_eq!(classify_query("#1042"), QueryKind::TicketNumber);
+        assert_eq!(classify_query("(555) 123-4567"), QueryKind::Phone);
+        assert_eq!(classify_query("555.123.4567"), QueryKind::Phone);
+        assert_eq!(classify_query("+1 555 123 4567"), QueryKind::Phone);
+        assert_eq!(classify_query("ann.lee@example.com"), QueryKind::Email);
+        assert_eq!(classify_query("Ann Lee"), QueryKind::Name);
+        assert_eq!(classify_query("iPhone 12"), QueryKind::Name);
+    }
+
+    #[test]
+    fn test_search_ranks_and_dedupes() {
+        let customer = |id: i64, first: &str, phone: &str| Customer {
+            id,
+            firstname: Some(first.to_string()),
+            lastname: Some("Lee".to_string()),
+            phone: Some(phone.to_string()),
+            ..Default::default()
+        };
+        let customers = vec![
+            customer(1, "Annabel", "555-999-0000"),
+            customer(2, "Ann", "(555) 123-4567"),
+            customer(2, "Ann", "(555) 123-4567"),
+        ];
+
+        let hits = merge_results("5551234567", QueryKind::Phone, customers.clone(), vec![], &StatusMap::default());
+        assert_eq!(hits.len(), 2);
+        let SearchHit::Customer { customer, matched, score } = &hits[0] else {
+            unreachable!("customers only");
+        };
+        assert_eq!(customer.id, 2);
+        assert_eq!(*score, 100);
+        assert_eq!(matched.as_ref().map(|m| m.field.as_str()), Some("phone"));
+
+        let tickets = vec![Ticket {
+            id: 9,
+            number: 1009,
+            subject: "Ann's iPad".to_string(),
+            status: "New".to_string(),
+            customer_business_then_name: Some("Ann Lee".to_string()),
+            ..Default::default()
+        }];
+        let hits = merge_results("ann lee", QueryKind::Name, customers, tickets, &StatusMap::default());
+        let first_match = match &hits[0] {
+            SearchHit::Customer { matched, .. } | SearchHit::Ticket { matched, .. } => matched.clone(),
+        };
+        let first_match = first_match.expect("best hit has a match");
+        assert_eq!((first_match.start, first_match.end), (0, 7));
+        assert!(matches!(hits[0], SearchHit::Customer { ref customer, .. } if customer.id == 2));
+        assert!(matches!(hits[1], SearchHit::Ticket { ref ticket, .. } if ticket.status == "Diagnosing"));
+    }
+
+    #[tokio::test]
+    async fn test_search_fans_out_for_ticket_numbers() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/tickets"))
+            .and(query_param("number", "1042"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"tickets":[{"id":42,"number":1042,"subject":"Screen","status":"New"}]}"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/tickets"))
+            .and(query_param("query", "1042"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"tickets":[{"id":42,"number":1042,"status":"New"},{"id":7,"number":77,"subject":"Order 1042","status":"New"}]}"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let results = search(&client, "#1042").await.expect("search should succeed");
+        assert_eq!(results.kind, QueryKind::TicketNumber);
+        assert_eq!(results.results.len(), 2);
+        assert!(matches!(results.results[0], SearchHit::Ticket { ref ticket, score: 95, .. } if ticket.id == 42));
+    }
+
+    #[test]
+    fn test_normalize_phone_to_e164() {
+        assert_eq!(normalize_phone("(555) 234-5678", "US").as_deref(), Ok("+15552345678"));
+        assert_eq!(normalize_phone("1-555-234-5678", "US").as_deref(), Ok("+15552345678"));
+        assert_eq!(normalize_phone("+44 20 7946 0958", "US").as_deref(), Ok("+442079460958"));
+        assert_eq!(normalize_phone("020 7946 0958", "GB").as_deref(), Ok("+442079460958"));
+        assert_eq!(normalize_phone("0044 20 7946 0958", "US").as_deref(), Ok("+442079460958"));
+        assert!(normalize_phone("555-1234", "US").is_err());
+        assert!(normalize_phone("(055) 234-5678", "US").is_err());
+        assert!(normalize_phone("call me", "US").is_err());
+        assert!(normalize_phone("5552345678", "ZZ").is_err());
+    }
+
+    #[test]
+    fn test_normalize_names_and_emails() {
+        assert_eq!(normalize_name("  john   SMITH "), "John Smith");
+        assert_eq!(normalize_name("MARY-JANE O'NEIL"), "Mary-Jane O'Neil");
+        assert_eq!(normalize_name("McDonald"), "McDonald");
+        assert_eq!(normalize_email("  Ann.Lee@Example.COM "), "ann.lee@example.com");
+    }
+
+    #[test]
+    fn test_normalize_customer_validates_fields() {
+        let input = CustomerInput {
+            firstname: Some(" ann ".to_string()),
+            lastname: Some("LEE".to_string()),
+            email: Some(" Ann@Example.com".to_string()),
+            phone: Some("555.234.5678".to_string()),
+            mobile: Some("   ".to_string()),
+            ..Default::default()
+        };
+        let customer = normalize_customer(input, "US", true).expect("valid customer");
+        assert_eq!(customer.firstname.as_deref(), Some("Ann"));
+        assert_eq!(customer.lastname.as_deref(), Some("Lee"));
+        assert_eq!(customer.email.as_deref(), Some("ann@example.com"));
+        assert_eq!(customer.phone.as_deref(), Some("+15552345678"));
+        assert_eq!(customer.mobile, None);
+
+        let input = CustomerInput {
+            email: Some("not-an-email".to_string()),
+            phone: Some("12".to_string()),
+            ..Default::default()
+        };
+        let errors = normalize_customer(input, "US", true).expect_err("invalid customer");
+        let fields: Vec<&str> = errors.iter().map(|e| e.field).collect();
+        assert_eq!(fields, vec!["phone", "email", "firstname"]);
+
+        // Updates may touch a single field
+        let input = CustomerInput {
+            notes: Some("Prefers texts".to_string()),
+            ..Default::default()
+        };
+        assert!(normalize_customer(input, "US", false).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_create_customer_reports_duplicates() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/customers"))
+            .and(query_param("query", "5552345678"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"customers":[{"id":7,"firstname":"John","lastname":"Smith","phone":"555-234-5678"},
+                                 {"id":8,"firstname":"Jon","phone":"555-234-5679"}]}"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/customers"))
+            .and(query_param("email", "john@example.com"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"customers":[{"id":7,"email":"John@Example.com","phone":"555-234-5678"}]}"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/customers"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"customer":{"id":99,"firstname":"John"}}"#))
+            .mount(&server)
+            .await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+
+        let body = r#"{"customer":{"firstname":"john","lastname":"smith","phone":"(555) 234-5678","email":"JOHN@example.com"}}"#;
+        let request = http::Request::builder()
+            .method("POST")
+            .body(Body::Text(body.to_string()))
+            .expect("failed to build request");
+        let response = handle_create_customer(&request, &client).await.expect("create should respond");
+        assert_eq!(response.status(), 409);
+        let json: serde_json::Value = serde_json::from_str(&body_text(&response)).expect("JSON body");
+        assert_eq!(json["duplicates"].as_array().map(Vec::len), Some(1));
+        assert_eq!(json["duplicates"][0]["customer"]["id"], 7);
+        assert_eq!(json["duplicates"][0]["matched_on"], serde_json::json!(["phone", "email"]));
+
+        let request = http::Request::builder()
+            .method("POST")
+            .body(Body::Text(body.to_string()))
+            .expect("failed to build request")
+            .with_query_string_parameters(std::collections::HashMap::from([(
+                "force".to_string(),
+                "true".to_string(),
+            )]));
+        let response = handle_create_customer(&request, &client).await.expect("create should respond");
+        assert_eq!(response.status(), 201);
+
+        let received = server.received_requests().await.unwrap_or_default();
+        let created = received
+            .iter()
+            .find(|r| r.method.as_str() == "POST")
+            .expect("customer was created");
+        let sent: serde_json::Value = serde_json::from_slice(&created.body).expect("JSON payload");
+        assert_eq!(sent["phone"], "+15552345678");
+        assert_eq!(sent["email"], "john@example.com");
+        assert_eq!(sent["firstname"], "John");
+
+        // A wrapped customer with a bad field is refused, not read as an empty bare body
+        let request = http::Request::builder()
+            .method("POST")
+            .body(Body::Text(r#"{"customer":{"firstname":"John","phone":5552345678}}"#.to_string()))
+            .expect("failed to build request");
+        let error = handle_create_customer(&request, &client).await.expect_err("body is refused");
+        assert_eq!(error.status, 400);
+        assert_eq!(error.code, "INVALID_JSON");
+    }
+
+    /// Build a request carrying Cognito authorizer claims
+    fn request_with_claims(claims: serde_json::Value) -> Request {
+        use lambda_http::aws_lambda_events::apigw::ApiGatewayProxyRequestContext;
+        use lambda_http::request::RequestContext;
+
+        let mut context = ApiGatewayProxyRequestContext::default();
+        context.authorizer.fields.insert("claims".to_string(), claims);
+        http::Request::builder()
+            .method("POST")
+            .body(Body::Empty)
+            .expect("failed to build request")
+            .with_request_context(RequestContext::ApiGatewayV1(context))
+    }
+
+    #[test]
+    fn test_user_identity_from_claims() {
+        let request = request_with_claims(serde_json::json!({
+            "cognito:username": "abc-123",
+            "email": "tech@example.com",
+            "custom:given_name": "Sam",
+        }));
+        let identity = get_user_identity_from_event(&request);
+        assert_eq!(identity.given_name.as_deref(), Some("Sam"));
+        assert_eq!(identity.actor(), "tech@example.com");
+
+        let anonymous = get_user_identity_from_event(&http::Request::new(Body::Empty));
+        assert_eq!(anonymous.actor(), "unknown");
+
+        assert!(can_use_admin_tools(&["TrueTickets-Cacell-Owner".to_string()]));
+        assert!(!can_use_admin_tools(&["TrueTickets-Cacell-Manager".to_string()]));
+    }
+
+    #[test]
+    fn test_cluster_duplicate_customers() {
+        let customer = |id: i64, first: &str, last: &str, phone: Option<&str>, email: Option<&str>| Customer {
+            id,
+            firstname: Some(first.to_string()),
+            lastname: Some(last.to_string()),
+            phone: phone.map(str::to_string),
+            email: email.map(str::to_string),
+            ..Default::default()
+        };
+        let customers = vec![
+            customer(12, "John", "Smith", Some("(555) 234-5678"), None),
+            customer(4, "Johnny", "Appleseed", Some("+1 555 234 5678"), Some("j@example.com")),
+            customer(30, "Jay", "Apple", None, Some("J@Example.com ")),
+            customer(7, "Katherine", "Johnson", None, None),
+            customer(9, "Katharine", "Johnson", None, Some("kj@example.com")),
+            customer(15, "Katharine", "Johnson", Some("5551112222"), Some("KJ@example.com")),
+            customer(5, "Ann", "Lee", Some("5559990000"), None),
+            // A similar name alone links nothing, so Anne doesn't pull in Ann's phone twin
+            customer(6, "Anne", "Lee", Some("5558887777"), None),
+            customer(8, "Bob", "Stone", Some("5558887777"), None),
+        ];
+
+        let clusters = cluster_customers(&customers);
+        assert_eq!(clusters.len(), 3);
+
+        let ids: Vec<i64> = clusters[0].customers.iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![4, 12, 30]);
+        assert_eq!(clusters[0].matched_on, vec!["phone", "email"]);
+        assert_eq!(clusters[0].suggested_survivor_id, 4);
+
+        let ids: Vec<i64> = clusters[1].customers.iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![6, 8]);
+        assert_eq!(clusters[1].matched_on, vec!["phone"]);
+
+        // Katherine (7) only shares a name with the others, so she stays out
+        let ids: Vec<i64> = clusters[2].customers.iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![9, 15]);
+        assert_eq!(clusters[2].matched_on, vec!["email", "name"]);
+    }
+
+    #[tokio::test]
+    async fn test_merge_customers_reassigns_tickets_and_audits() {
+        let server = MockServer::start().await;
+        for id in [4, 12] {
+            Mock::given(method("GET"))
+                .and(path(format!("/customers/{}", id)))
+                .respond_with(ResponseTemplate::new(200).set_body_string(format!(r#"{{"customer":{{"id":{}}}}}"#, id)))
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(path("/tickets"))
+            .and(query_param("customer_id", "12"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"tickets":[{"id":100,"number":2001},{"id":101,"number":2002}],"meta":{"total_pages":1,"page":1}}"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/tickets/100"))
+            .and(body_json(serde_json::json!({"customer_id": 4})))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"ticket":{"id":100,"number":2001}}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/tickets/101"))
+            .respond_with(ResponseTemplate::new(422).set_body_string(r#"{"message":"locked"}"#))
+            .mount(&server)
+            .await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let audit = InMemoryAuditStore::default();
+        let actor = UserIdentity {
+            email: Some("owner@example.com".to_string()),
+            ..Default::default()
+        };
+
+        let dry_run = MergeRequest { survivor_id: 4, duplicate_id: 12, dry_run: true };
+        let report = merge_customers(&client, &audit, &actor, &dry_run).await.expect("dry run succeeds");
+        assert_eq!(report.tickets.len(), 2);
+        assert!(audit.entries().is_empty());
+
+        let request = MergeRequest { survivor_id: 4, duplicate_id: 12, dry_run: false };
+        let report = merge_customers(&client, &audit, &actor, &request).await.expect("merge succeeds");
+        assert!(report.tickets[0].ok);
+        assert!(!report.tickets[1].ok);
+
+        let entries = audit.entries();
+        let actions: Vec<&str> = entries.iter().map(|e| e.action.as_str()).collect();
+        assert_eq!(actions, vec!["ticket.reassign_customer", "customer.merge"]);
+        assert_eq!(entries[0].target, "ticket:100");
+        assert_eq!(entries[0].actor, "owner@example.com");
+        assert_eq!(entries[1].details["tickets_failed"], 1);
+
+        let same = MergeRequest { survivor_id: 4, duplicate_id: 4, dry_run: false };
+        let error = merge_customers(&client, &audit, &actor, &same).await.expect_err("self-merge is rejected");
+        assert_eq!(error.status, 400);
+    }
+
+    #[test]
+    fn test_intake_vocabulary_from_lookup() {
+        let vocabulary = IntakeVocabulary::resolve(|name| match name {
+            "INTAKE_DEVICES" => Some("Phone, Drone ,".to_string()),
+            "INTAKE_TICKET_TYPE_ID" => Some("not-a-number".to_string()),
+            _ => None,
+        });
+        assert_eq!(vocabulary.devices, vec!["Phone", "Drone"]);
+        assert_eq!(vocabulary.items_left, IntakeVocabulary::default().items_left);
+        assert_eq!(vocabulary.ticket_type_id, 9818);
+    }
+
+    #[test]
+    fn test_build_intake_ticket_validates_fields() {
+        let vocabulary = IntakeVocabulary::default();
+
+        let request = IntakeRequest {
+            customer_id: Some(7),
+            device: Some("laptop".to_string()),
+            items_left: vec!["charger".to_string(), "Bag".to_string(), "Charger".to_string()],
+            pattern: Some(vec![1, 5, 9, 6]),
+            problem_description: Some("  Cracked   screen ".to_string()),
+            estimated_time: Some("2 days".to_string()),
+            ..Default::default()
+        };
+        let ticket = build_intake_ticket(request, &vocabulary).expect("valid intake");
+        assert_eq!(ticket.subject, "Cracked screen");
+        assert_eq!(ticket.ticket_type_id, Some(9818));
+        assert_eq!(ticket.properties.password.as_deref(), Some("pattern:1-5-9-6"));
+        assert_eq!(ticket.properties.ac_charger.as_deref(), Some("1"));
+        let model = ticket.properties.intake_model().expect("model is encoded");
+        assert_eq!(model.device.as_deref(), Some("Laptop"));
+        assert_eq!(model.items_left, vec!["Charger", "Bag"]);
+        assert_eq!(model.estimated_time.as_deref(), Some("2 days"));
+
+        let request = IntakeRequest {
+            customer_id: Some(7),
+            device: Some("Phone".to_string()),
+            problem_description: Some("Won't charge".to_string()),
+            ..Default::default()
+        };
+        let ticket = build_intake_ticket(request, &vocabulary).expect("valid intake");
+        assert_eq!(ticket.properties.password.as_deref(), Some("n"));
+        assert_eq!(ticket.properties.ac_charger.as_deref(), Some("0"));
+
+        let request = IntakeRequest {
+            device: Some("Toaster".to_string()),
+            items_left: vec!["Sticker".to_string()],
+            passcode: Some("1234".to_string()),
+            pattern: Some(vec![1, 1, 2, 3]),
+            ..Default::default()
+        };
+        let errors = build_intake_ticket(request, &vocabulary).expect_err("invalid intake");
+        let fields: Vec<&str> = errors.iter().map(|e| e.field).collect();
+        assert_eq!(fields, vec!["customer_id", "device", "items_left", "pattern", "problem_description"]);
+    }
+
+    #[tokio::test]
+    async fn test_ticket_intake_creates_ticket() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/tickets"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"ticket":{"id":555,"number":3001,"subject":"No power","status":"New","customer_id":7}}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+
+        let body = r#"{"customer_id":7,"device":"Console","itemsLeft":["Controller"],"password":"0000","problem_description":"No power"}"#;
+        let request = http::Request::builder()
+            .method("POST")
+            .body(Body::Text(body.to_string()))
+            .expect("failed to build request");
+        let response = handle_ticket_intake(&request, &client, &EventBus::default()).await.expect("intake should respond");
+        assert_eq!(response.status(), 201);
+        let json: serde_json::Value = serde_json::from_str(&body_text(&response)).expect("JSON body");
+        assert_eq!(json["number"], 3001);
+        assert_eq!(json["ticket"]["status"], "Diagnosing");
+
+        let received = server.received_requests().await.unwrap_or_default();
+        let sent: serde_json::Value = serde_json::from_slice(&received[0].body).expect("JSON payload");
+        assert_eq!(sent["problem_type"], "Other");
+        assert_eq!(sent["status"], "New");
+        assert_eq!(sent["properties"]["Password"], "0000");
+        assert_eq!(sent["properties"]["AC Charger"], "0");
+
+        let request = http::Request::builder()
+            .method("POST")
+            .body(Body::Text(r#"{"customer_id":7}"#.to_string()))
+            .expect("failed to build request");
+        let response = handle_ticket_intake(&request, &client, &EventBus::default()).await.expect("intake should respond");
+        assert_eq!(response.status(), 422);
+    }
+
+    #[test]
+    fn test_comment_templates_render() {
+        let templates = CommentTemplates::from_json(r#"{"waiting_for_parts":"Part ordered, ETA {date} ({supplier})","thanks":"Thanks from {tech}!"}"#)
+            .expect("valid templates");
+        let variables = std::collections::HashMap::from([
+            ("date".to_string(), "Friday".to_string()),
+            ("supplier".to_string(), " Acme ".to_string()),
+        ]);
+        assert_eq!(
+            templates.render("waiting_for_parts", &variables),
+            Ok("Part ordered, ETA Friday (Acme)".to_string())
+        );
+        assert_eq!(
+            templates.render("estimate", &variables),
+            Err(TemplateError::MissingVariables(vec!["price".to_string()]))
+        );
+        assert_eq!(templates.render("nope", &variables), Err(TemplateError::Unknown("nope".to_string())));
+        // Values are inserted as typed, even when they look like placeholders
+        let variables = std::collections::HashMap::from([
+            ("date".to_string(), "{supplier}".to_string()),
+            ("supplier".to_string(), "{date}".to_string()),
+        ]);
+        assert_eq!(
+            templates.render("waiting_for_parts", &variables),
+            Ok("Part ordered, ETA {supplier} ({date})".to_string())
+        );
+
+        let listed = templates.list();
+        assert_eq!(listed.len(), 5);
+        assert_eq!(listed[0].variables, vec!["date", "supplier"]);
+        assert!(CommentTemplates::from_json("[]").is_err());
+    }
+
+    #[test]
+    fn test_build_comment_attribution_and_visibility() {
+        let author = UserIdentity {
+            username: Some("abc-123".to_string()),
+            email: Some("sam@example.com".to_string()),
+            given_name: Some("Sam".to_string()),
+        };
+        let templates = CommentTemplates::from_json(r#"{"thanks":"Thanks from {tech}!"}"#).expect("valid templates");
+
+        let request = CommentRequest {
+            template: Some("thanks".to_string()),
+            ..Default::default()
+        };
+        let comment = build_comment(request, &author, false, &templates).expect("hidden comment is allowed");
+        assert_eq!(comment.body, "Thanks from Sam!");
+        assert_eq!(comment.tech.as_deref(), Some("Sam (sam@example.com)"));
+        assert!(comment.hidden);
+        assert!(comment.do_not_email);
+
+        let request = CommentRequest {
+            body: Some("Ready when you are".to_string()),
+            hidden: Some(false),
+            ..Default::default()
+        };
+        let error = build_comment(request.clone(), &author, false, &templates).expect_err("needs permission");
+        assert_eq!(error.status, 403);
+        let comment = build_comment(request, &author, true, &templates).expect("managers can message customers");
+        assert!(!comment.hidden);
+
+        let error = build_comment(CommentRequest::default(), &author, true, &templates).expect_err("empty comment");
+        assert_eq!(error.code, "INVALID_COMMENT");
+    }
+
+    #[tokio::test]
+    async fn test_add_comment_posts_as_caller() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/tickets/42/comment"))
+            .and(body_json(serde_json::json!({
+                "subject": "Update",
+                "body": "Screen replaced",
+                "tech": "tech@example.com",
+                "hidden": true,
+                "do_not_email": true,
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"comment":{"id":9,"body":"Screen replaced"}}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let author = UserIdentity {
+            email: Some("tech@example.com".to_string()),
+            ..Default::default()
+        };
+
+        let request = http::Request::builder()
+            .method("POST")
+            .body(Body::Text(r#"{"body":"Screen replaced"}"#.to_string()))
+            .expect("failed to build request");
+        let response = handle_add_comment(&request, "42", &client, &author, false)
+            .await
+            .expect("comment should post");
+        assert_eq!(response.status(), 201);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_status_change_reports_per_ticket() {
+        let server = MockServer::start().await;
+        for id in [1, 2] {
+            Mock::given(method("GET"))
+                .and(path(format!("/tickets/{}", id)))
+                .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                    r#"{{"ticket":{{"id":{},"number":{},"status":"In Progress"}}}}"#,
+                    id,
+                    1000 + id
+                )))
+                .mount(&server)
+                .await;
+        }
+        Mock::given(method("GET"))
+            .and(path("/tickets/3"))
+            .respond_with(ResponseTemplate::new(404).set_body_string(r#"{"error":"not found"}"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/tickets/1"))
+            .and(body_json(serde_json::json!({"status": "Customer Reply"})))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"ticket":{"id":1,"number":1001}}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // Rate limited once, then accepted
+        Mock::given(method("PUT"))
+            .and(path("/tickets/2"))
+            .respond_with(ResponseTemplate::new(429))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/tickets/2"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"ticket":{"id":2,"number":1002}}"#))
+            .mount(&server)
+            .await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let options = BulkOptions {
+            concurrency: 2,
+            max_retries: 2,
+            retry_delay: std::time::Duration::from_millis(1),
+        };
+        let author = UserIdentity::default();
+        let request = |dry_run: bool| BulkRequest {
+            ticket_ids: vec![3, 1, 2, 1],
+            action: BulkAction::Status { status: "Ready".to_string() },
+            dry_run,
+        };
+
+        let report = run_bulk(&client, request(true), &author, false, &options, &EventBus::default()).await.expect("dry run");
+        assert_eq!((report.succeeded, report.failed), (2, 1));
+        let received = server.received_requests().await.unwrap_or_default();
+        assert!(received.iter().all(|r| r.method.as_str() == "GET"));
+
+        let report = run_bulk(&client, request(false), &author, false, &options, &EventBus::default()).await.expect("bulk run");
+        let ids: Vec<i64> = report.results.iter().map(|r| r.ticket_id).collect();
+        assert_eq!(ids, vec![3, 1, 2]);
+        assert!(!report.results[0].ok);
+        assert_eq!(report.results[1].from.as_deref(), Some("In Progress"));
+        assert_eq!(report.results[1].to.as_deref(), Some("Ready"));
+        assert!(report.results[2].ok);
+
+        let unknown = BulkRequest {
+            ticket_ids: vec![1],
+            action: BulkAction::Status { status: "Teleported".to_string() },
+            dry_run: false,
+        };
+        let error = run_bulk(&client, unknown, &author, false, &options, &EventBus::default()).await.expect_err("unknown status");
+        assert_eq!(error.code, "UNKNOWN_STATUS");
+    }
+
+    #[test]
+    fn test_rate_limiter_window_and_lockout() {
+        use std::time::{Duration, Instant};
+
+        let limiter = RateLimiter::new(3, Duration::from_secs(60), 2, Duration::from_secs(600), Duration::from_secs(900));
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.check("1.2.3.4", start), RateDecision::Allowed);
+        }
+        assert!(matches!(limiter.check("1.2.3.4", start), RateDecision::Limited { .. }));
+        assert_eq!(limiter.check("5.6.7.8", start), RateDecision::Allowed);
+        assert_eq!(limiter.check("1.2.3.4", start + Duration::from_secs(61)), RateDecision::Allowed);
+
+        let later = start + Duration::from_secs(61);
+        assert!(!limiter.record_failure("5.6.7.8", later));
+        assert!(limiter.record_failure("5.6.7.8", later));
+        assert_eq!(
+            limiter.check("5.6.7.8", later + Duration::from_secs(60)),
+            RateDecision::Limited { retry_after: Duration::from_secs(840) }
+        );
+        assert_eq!(limiter.check("5.6.7.8", later + Duration::from_secs(901)), RateDecision::Allowed);
+    }
+
+    #[tokio::test]
+    async fn test_public_status_lookup_is_redacted_and_locks_out() {
+        use std::time::Duration;
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/tickets"))
+            .and(query_param("number", "1042"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"tickets":[{"id":5,"number":1042,"subject":"Cracked screen","status":"Customer Reply",
+                    "customer_id":7,"updated_at":"2026-10-01T10:00:00Z",
+                    "properties":{"Password":"1234","Tech Notes":"internal"}}]}"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/customers/7"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"customer":{"id":7,"mobile":"(555) 234-5678"}}"#))
+            .mount(&server)
+            .await;
+        let client = RepairShoprClient::new(&server.uri(), "rs-secret-api-key");
+        let limiter = RateLimiter::new(30, Duration::from_secs(60), 2, Duration::from_secs(600), Duration::from_secs(900));
+        let lookup = |ticket: &str, phone: &str| {
+            http::Request::builder()
+                .header("X-Forwarded-For", "203.0.113.9, 10.0.0.1")
+                .body(Body::Empty)
+                .expect("failed to build request")
+                .with_query_string_parameters(std::collections::HashMap::from([
+                    ("ticket".to_string(), ticket.to_string()),
+                    ("phone".to_string(), phone.to_string()),
+                ]))
+        };
+
+        let response = lookup_public_status(&lookup("#1042", "5678"), &client, &limiter, "req-1")
+            .await
+            .expect("lookup should respond");
+        assert_eq!(response.status(), 200);
+        let body = body_text(&response);
+        let json: serde_json::Value = serde_json::from_str(&body).expect("JSON body");
+        assert_eq!(
+            json,
+            serde_json::json!({"ticket": {
+                "number": 1042,
+                "status": "Ready",
+                "last_updated": "2026-10-01T10:00:00Z",
+                "ready_for_pickup": true,
+            }})
+        );
+        assert!(!body.contains("1234") && !body.contains("internal") && !body.contains("rs-secret"));
+
+        let error = lookup_public_status(&lookup("1042", "0000"), &client, &limiter, "req-2")
+            .await
+            .expect_err("wrong digits");
+        assert_eq!(error.status, 404);
+        // Looking up a known ticket between guesses doesn't wipe the failure count
+        let response = lookup_public_status(&lookup("1042", "5678"), &client, &limiter, "req-2b")
+            .await
+            .expect("lookup should respond");
+        assert_eq!(response.status(), 200);
+        let error = lookup_public_status(&lookup("9999", "5678"), &client, &limiter, "req-3")
+            .await
+            .expect_err("unknown ticket");
+        assert_eq!((error.status, error.code), (404, "NOT_FOUND"));
+
+        // Locked out now, even with the right answer
+        let response = lookup_public_status(&lookup("1042", "5678"), &client, &limiter, "req-4")
+            .await
+            .expect("lookup should respond");
+        assert_eq!(response.status(), 429);
+        assert!(response.headers().contains_key("Retry-After"));
+
+        let fresh = RateLimiter::new(30, Duration::from_secs(60), 2, Duration::from_secs(600), Duration::from_secs(900));
+        let error = lookup_public_status(&lookup("1042", "56"), &client, &fresh, "req-5")
+            .await
+            .expect_err("malformed phone");
+        assert_eq!(error.status, 400);
+    }
+
+    const TEST_SHARE_SECRET: &[u8] = b"test-share-secret-0123456789abcdef";
+
+    #[test]
+    fn test_share_tokens_sign_and_verify() {
+        let request = CreateShareLink {
+            scopes: vec![ShareScope::Attachments, ShareScope::Attachments],
+            expires_in_hours: Some(2),
+        };
+        let (claims, token) = new_share_link(42, &request, TEST_SHARE_SECRET, 1_000).expect("valid link");
+        assert_eq!(claims.scopes, vec![ShareScope::Status, ShareScope::Attachments]);
+        assert_eq!(claims.expires_at, 1_000 + 7_200);
+
+        assert_eq!(verify_token(&token, TEST_SHARE_SECRET, 2_000), Ok(claims.clone()));
+        assert_eq!(verify_token(&token, TEST_SHARE_SECRET, 8_200), Err(ShareError::Expired));
+        assert_eq!(verify_token(&token, b"some-other-secret-0123456789abcdef", 2_000), Err(ShareError::BadSignature));
+        assert_eq!(verify_token("not-a-token", TEST_SHARE_SECRET, 2_000), Err(ShareError::Malformed));
+
+        // Changing the ticket id invalidates the signature
+        let forged = ShareClaims { ticket_id: 43, ..claims };
+        let forged_payload = sign_token(&forged, TEST_SHARE_SECRET);
+        let (payload, _) = forged_payload.split_once('.').expect("payload");
+        let (_, signature) = token.split_once('.').expect("signature");
+        let tampered = format!("{}.{}", payload, signature);
+        assert_eq!(verify_token(&tampered, TEST_SHARE_SECRET, 2_000), Err(ShareError::BadSignature));
+
+        let too_long = CreateShareLink { expires_in_hours: Some(24 * 31), ..Default::default() };
+        assert!(new_share_link(42, &too_long, TEST_SHARE_SECRET, 1_000).is_err());
+
+        assert_eq!(share_url(None, "abc"), "/public/status?token=abc");
+        assert_eq!(share_url(Some("https://shop.example/status?lang=en"), "abc"), "https://shop.example/status?lang=en&token=abc");
+    }
+
+    #[tokio::test]
+    async fn test_shared_ticket_view_respects_scopes_and_revocation() {
+        use std::time::Duration;
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/tickets/42"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"ticket":{"id":42,"number":1042,"status":"Call Customer","customer_id":7,
+                    "comments":[{"id":1,"body":"internal note","hidden":true}],
+                    "attachments":[{"id":1,"file_name":"before.jpg","private":false,"file":{"url":"https://files/before.jpg"}},
+                                   {"id":2,"file_name":"invoice-cost.pdf","private":true}]}}"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/estimates"))
+            .and(query_param("customer_id", "7"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"estimates":[{"id":3,"number":"E-3","status":"Approved","ticket_id":42,"total":"149.00"},
+                                 {"id":4,"number":"E-4","status":"Draft","ticket_id":42,"total":"99.00"}]}"#,
+            ))
+            .mount(&server)
+            .await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let limiter = RateLimiter::new(30, Duration::from_secs(60), 5, Duration::from_secs(600), Duration::from_secs(900));
+        let revocations = InMemoryRevocationStore::default();
+        let check = ShareLinkCheck {
+            secret: TEST_SHARE_SECRET,
+            revocations: &revocations,
+            now: 1_000,
+        };
+        let request = http::Request::new(Body::Empty);
+
+        let (_, status_only) = new_share_link(42, &CreateShareLink::default(), TEST_SHARE_SECRET, 900).expect("link");
+        let response = view_shared_ticket(&request, &status_only, &client, &limiter, &check, "req-1")
+            .await
+            .expect("shared view");
+        let json: serde_json::Value = serde_json::from_str(&body_text(&response)).expect("JSON body");
+        assert_eq!(json["ticket"]["status"], "Approval Needed");
+        assert!(json.get("estimate").is_none() && json.get("attachments").is_none());
+
+        let everything = CreateShareLink {
+            scopes: vec![ShareScope::Estimate, ShareScope::Attachments],
+            expires_in_hours: None,
+        };
+        let (claims, token) = new_share_link(42, &everything, TEST_SHARE_SECRET, 900).expect("link");
+        let response = view_shared_ticket(&request, &token, &client, &limiter, &check, "req-2")
+            .await
+            .expect("shared view");
+        let body = body_text(&response);
+        let json: serde_json::Value = serde_json::from_str(&body).expect("JSON body");
+        assert_eq!(json["estimate"]["number"], "E-3");
+        assert_eq!(json["estimate"]["total"], "149.00");
+        assert_eq!(json["attachments"].as_array().map(Vec::len), Some(1));
+        assert_eq!(json["attachments"][0]["url"], "https://files/before.jpg");
+        assert!(!body.contains("internal note") && !body.contains("invoice-cost"));
+
+        let audit = InMemoryAuditStore::default();
+        let response = handle_revoke_share_link(&claims.link_id, &revocations, &audit, &UserIdentity::default())
+            .await
+            .expect("revoke");
+        assert_eq!(response.status(), 200);
+        assert_eq!(audit.entries()[0].action, "share_link.revoke");
+        let error = view_shared_ticket(&request, &token, &client, &limiter, &check, "req-3")
+            .await
+            .expect_err("revoked link");
+        assert_eq!((error.status, error.code), (410, "LINK_REVOKED"));
+    }
+
+    #[tokio::test]
+    async fn test_revocations_outlive_the_request() {
+        let s3_client = offline_s3_client();
+        revocation_store_from_env(&s3_client)
+            .revoke("link-outlives-request", None)
+            .await
+            .expect("revoke");
+        let later = revocation_store_from_env(&s3_client);
+        assert!(later.is_revoked("link-outlives-request").await.expect("check"));
+    }
+
+    #[test]
+    fn test_validate_approval_request() {
+        assert_eq!(format_amount(14_999), "$149.99");
+        assert_eq!(format_amount(5), "$0.05");
+
+        let request = CreateApproval {
+            price: Some(149.999),
+            description: Some("  New screen ".to_string()),
+            expires_in_hours: None,
+        };
+        assert_eq!(validate_approval(&request).expect("valid"), (15_000, "New screen".to_string()));
+
+        for price in [None, Some(0.0), Some(-5.0), Some(f64::NAN)] {
+            let request = CreateApproval {
+                price,
+                description: Some("New screen".to_string()),
+                expires_in_hours: None,
+            };
+            assert!(validate_approval(&request).is_err());
+        }
+        let request = CreateApproval {
+            price: Some(10.0),
+            description: Some("   ".to_string()),
+            expires_in_hours: None,
+        };
+        assert!(validate_approval(&request).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_approvals_outlive_the_request() {
+        let s3_client = offline_s3_client();
+        let approval = ApprovalRequest {
+            id: "approval-outlives-request".to_string(),
+            ticket_id: 42,
+            ticket_number: 1042,
+            amount_cents: 14_900,
+            description: "Replace battery".to_string(),
+            requested_by: "sam@example.com".to_string(),
+            created_at: "2026-03-02T10:00:00Z".to_string(),
+            expires_at: 2_000_000_000,
+            decision: None,
+            decided_at: None,
+            customer_note: None,
+        };
+        approval_store_from_env(&s3_client).save(&approval).await.expect("save");
+        let later = approval_store_from_env(&s3_client);
+        assert_eq!(later.get(&approval.id).await.expect("get"), Some(approval));
+    }
+
+    /// Keeps every published event for assertions
+    #[derive(Default, Clone)]
+    struct RecordingHandler {
+        events: std::sync::Arc<std::sync::Mutex<Vec<TicketEvent>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::events::EventHandler for RecordingHandler {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        async fn handle(&self, event: &TicketEvent) -> Result<(), String> {
+            self.events.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_approval_flow_moves_ticket() {
+        use std::time::Duration;
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/tickets/42"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"ticket":{"id":42,"number":1042,"status":"Scheduled","customer_id":7}}"#,
+            ))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/tickets/42"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"ticket":{"id":42,"number":1042,"status":"Call Customer","customer_id":7}}"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/tickets/43"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"ticket":{"id":43,"number":1043,"status":"In Progress","customer_id":7}}"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/tickets/42/comment"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"comment":{"id":1}}"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/tickets/42"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"ticket":{"id":42,"number":1042}}"#))
+            .mount(&server)
+            .await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let approvals = InMemoryApprovalStore::default();
+        let audit = InMemoryAuditStore::default();
+        let revocations = InMemoryRevocationStore::default();
+        let limiter = RateLimiter::new(30, Duration::from_secs(60), 5, Duration::from_secs(600), Duration::from_secs(900));
+        let recorder = RecordingHandler::default();
+        let events = EventBus::default().with(recorder.clone());
+        let context = ApprovalContext {
+            approvals: &approvals,
+            audit: &audit,
+            events: &events,
+        };
+        let actor = UserIdentity {
+            email: Some("sam@example.com".to_string()),
+            ..Default::default()
+        };
+        let post = |body: String| {
+            http::Request::builder()
+                .method("POST")
+                .body(Body::Text(body))
+                .expect("failed to build request")
+        };
+
+        let request = post(r#"{"price":149.5,"description":"Replace battery"}"#.to_string());
+        let response = handle_create_approval(&request, "42", &client, &context, &actor, TEST_SHARE_SECRET)
+            .await
+            .expect("approval created");
+        assert_eq!(response.status(), 201);
+        let created: serde_json::Value = serde_json::from_str(&body_text(&response)).expect("JSON body");
+        let token = created["token"].as_str().expect("token").to_string();
+        assert_eq!(created["approval"]["amount_cents"], 14_950);
+
+        let check = ShareLinkCheck {
+            secret: TEST_SHARE_SECRET,
+            revocations: &revocations,
+            now: chrono::Utc::now().timestamp(),
+        };
+        let view = http::Request::new(Body::Empty).with_query_string_parameters(std::collections::HashMap::from([(
+            "token".to_string(),
+            token.clone(),
+        )]));
+        let response = view_approval(&view, &limiter, &check, &approvals, "req-1").await;
+        let json: serde_json::Value = serde_json::from_str(&body_text(&response)).expect("JSON body");
+        assert_eq!(json["approval"]["amount"], "$149.50");
+        assert_eq!(json["approval"]["decision"], serde_json::Value::Null);
+
+        let decision = post(json!({"token": token, "decision": "approve", "note": "Go ahead"}).to_string());
+        let response = decide_approval(&decision, &client, &limiter, &check, &context, "req-2")
+            .await
+            .expect("decision recorded");
+        assert_eq!(response.status(), 200);
+        let response = decide_approval(&decision, &client, &limiter, &check, &context, "req-3")
+            .await
+            .expect("second decision responds");
+        assert_eq!(response.status(), 409);
+
+        let received = server.received_requests().await.unwrap_or_default();
+        let statuses: Vec<String> = received
+            .iter()
+            .filter(|r| r.method.as_str() == "PUT")
+            .filter_map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).ok())
+            .filter_map(|v| v["status"].as_str().map(str::to_string))
+            .collect();
+        assert_eq!(statuses, vec!["Call Customer", "In Progress"]);
+        let comments: Vec<serde_json::Value> = received
+            .iter()
+            .filter(|r| r.url.path().ends_with("/comment"))
+            .filter_map(|r| serde_json::from_slice(&r.body).ok())
+            .collect();
+        assert_eq!(comments.len(), 2);
+        assert!(comments[1]["body"].as_str().is_some_and(|b| b.contains("approved") && b.contains("Go ahead")));
+
+        let actions: Vec<String> = audit.entries().into_iter().map(|e| e.action).collect();
+        assert_eq!(actions, vec!["approval.create", "approval.approved"]);
+        let previous: Vec<Option<String>> = recorder
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|e| match e {
+                TicketEvent::StatusChanged { previous_status, .. } => Some(previous_status.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(previous, vec![Some("Scheduled".to_string()), Some("Call Customer".to_string())]);
+
+        // Staff moved this ticket on before the customer answered
+        let claims = ShareClaims {
+            link_id: "approval-moved-on".to_string(),
+            ticket_id: 43,
+            expires_at: check.now + 3600,
+            scopes: vec![ShareScope::Approval],
+        };
+        let stale = ApprovalRequest {
+            id: claims.link_id.clone(),
+            ticket_id: 43,
+            ticket_number: 1043,
+            amount_cents: 9_900,
+            description: "Replace screen".to_string(),
+            requested_by: "sam@example.com".to_string(),
+            created_at: "2026-03-02T10:00:00Z".to_string(),
+            expires_at: claims.expires_at,
+            decision: None,
+            decided_at: None,
+            customer_note: None,
+        };
+        approvals.save(&stale).await.expect("save");
+        let decision = post(json!({"token": sign_token(&claims, TEST_SHARE_SECRET), "decision": "approve"}).to_string());
+        let response = decide_approval(&decision, &client, &limiter, &check, &context, "req-5")
+            .await
+            .expect("decision responds");
+        assert_eq!(response.status(), 409);
+        assert!(body_text(&response).contains("NOT_AWAITING_APPROVAL"));
+        assert_eq!(approvals.get("approval-moved-on").await.expect("get").and_then(|a| a.decision), None);
+
+        // A status-only share link can't be used to answer
+        let (_, status_link) = new_share_link(42, &CreateShareLink::default(), TEST_SHARE_SECRET, check.now).expect("link");
+        let decision = post(json!({"token": status_link, "decision": "decline"}).to_string());
+        let response = decide_approval(&decision, &client, &limiter, &check, &context, "req-4")
+            .await
+            .expect("decision responds");
+        assert_eq!(response.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn test_approval_reopened_when_ticket_update_fails() {
+        use std::time::Duration;
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/tickets/44"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"ticket":{"id":44,"number":1044,"status":"Call Customer","customer_id":7}}"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/tickets/44/comment"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"comment":{"id":1}}"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/tickets/44"))
+            .respond_with(ResponseTemplate::new(503).set_body_string("unavailable"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/tickets/44"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"ticket":{"id":44,"number":1044}}"#))
+            .mount(&server)
+            .await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let approvals = InMemoryApprovalStore::default();
+        let audit = InMemoryAuditStore::default();
+        let revocations = InMemoryRevocationStore::default();
+        let limiter = RateLimiter::new(30, Duration::from_secs(60), 5, Duration::from_secs(600), Duration::from_secs(900));
+        let events = EventBus::default();
+        let context = ApprovalContext {
+            approvals: &approvals,
+            audit: &audit,
+            events: &events,
+        };
+        let check = ShareLinkCheck {
+            secret: TEST_SHARE_SECRET,
+            revocations: &revocations,
+            now: chrono::Utc::now().timestamp(),
+        };
+        let claims = ShareClaims {
+            link_id: "approval-retry".to_string(),
+            ticket_id: 44,
+            expires_at: check.now + 3600,
+            scopes: vec![ShareScope::Approval],
+        };
+        approvals
+            .save(&ApprovalRequest {
+                id: claims.link_id.clone(),
+                ticket_id: 44,
+                ticket_number: 1044,
+                amount_cents: 9_900,
+                description: "Replace screen".to_string(),
+                requested_by: "sam@example.com".to_string(),
+                created_at: "2026-03-02T10:00:00Z".to_string(),
+                expires_at: claims.expires_at,
+                decision: None,
+                decided_at: None,
+                customer_note: None,
+            })
+            .await
+            .expect("save");
+        let decision = || {
+            http::Request::builder()
+                .method("POST")
+                .body(Body::Text(json!({"token": sign_token(&claims, TEST_SHARE_SECRET), "decision": "approve"}).to_string()))
+                .expect("failed to build request")
+        };
+
+        let error = decide_approval(&decision(), &client, &limiter, &check, &context, "req-1")
+            .await
+            .expect_err("ticket update failed");
+        assert_eq!(error.status, 502);
+        assert_eq!(approvals.get("approval-retry").await.expect("get").and_then(|a| a.decision), None);
+        assert!(audit.entries().is_empty());
+
+        // The customer's retry goes through instead of being told it was already answered
+        let response = decide_approval(&decision(), &client, &limiter, &check, &context, "req-2")
+            .await
+            .expect("retry recorded");
+        assert_eq!(response.status(), 200);
+        assert!(approvals.get("approval-retry").await.expect("get").and_then(|a| a.decision).is_some());
+    }
+
+    #[test]
+    fn test_sms_quiet_hours_wrap_midnight_in_shop_time() {
+        use chrono::TimeZone;
+
+        let offset = parse_utc_offset("-05:00").expect("offset");
+        assert_eq!(parse_utc_offset("+0530").map(|o| o.local_minus_utc()), Some(5 * 3600 + 30 * 60));
+        assert!(parse_utc_offset("5").is_none());
+
+        let quiet = QuietHours::parse("21:00-08:00", offset).expect("window");
+        let at = |hour| chrono::Utc.with_ymd_and_hms(2026, 3, 2, hour, 30, 0).single().expect("valid time");
+        assert!(quiet.contains(at(3))); // 22:30 local
+        assert!(quiet.contains(at(12))); // 07:30 local
+        assert!(!quiet.contains(at(13))); // 08:30 local
+        assert!(!quiet.contains(at(20))); // 15:30 local
+        assert!(QuietHours::parse("08:00-08:00", offset).is_none());
+        assert!(QuietHours::parse("25:00-08:00", offset).is_none());
+    }
+
+    #[test]
+    fn test_fill_inserts_values_as_typed() {
+        let values = std::collections::HashMap::from([
+            ("description", "Swap {url} cable".to_string()),
+            ("url", "https://example.com/a".to_string()),
+        ]);
+        assert_eq!(
+            crate::notifications::fill("{description} at {url} {unknown} {", &values),
+            "Swap {url} cable at https://example.com/a {unknown} {"
+        );
+    }
+
+    #[test]
+    fn test_redact_contact_details_for_logs() {
+        assert_eq!(redact_phone("+15552013344"), "***3344");
+        assert_eq!(redact_phone("(555) 201-3344"), "***3344");
+        assert_eq!(redact_phone("12"), "***12");
+        assert_eq!(redact_email("john@example.com"), "j***@example.com");
+        assert_eq!(redact_email("not-an-address"), "***");
+    }
+
+    /// RepairShopr stub for a customer with a mobile number
+    async fn sms_customer_server() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/customers/7"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"customer":{"id":7,"firstname":"Dana","mobile":"(555) 201-3344","phone":"555-000-1111"}}"#,
+            ))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn ready_event(ticket_id: i64, previous_status: Option<&str>) -> TicketEvent {
+        let mut ticket = Ticket {
+            id: ticket_id,
+            number: 1000 + ticket_id,
+            status: "Ready!".to_string(),
+            customer_id: Some(7),
+            ..Default::default()
+        };
+        ticket.properties.model = Some(r#"vT{"device":"Phone","itemsLeft":[]}"#.to_string());
+        TicketEvent::StatusChanged {
+            ticket,
+            previous_status: previous_status.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sms_notifier_texts_once_and_honours_opt_outs() {
+        let server = sms_customer_server().await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let sender = std::sync::Arc::new(MockSmsSender::default());
+        let opt_outs = std::sync::Arc::new(InMemoryOptOutStore::default());
+        let notifier = SmsNotifier::new(client, sender.clone(), opt_outs.clone());
+        let now = chrono::Utc::now();
+
+        let outcome = notifier.notify(&ready_event(42, Some("In Progress")), now).await;
+        assert!(matches!(&outcome, SmsOutcome::Sent { to, .. } if to == "+15552013344"), "{:?}", outcome);
+        let sent = sender.sent();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].1.starts_with("Hi Dana, your phone (ticket #1042) is ready for pickup at Cacell."));
+
+        // Saving the same ticket again doesn't text twice
+        assert_eq!(notifier.notify(&ready_event(42, None), now).await, SmsOutcome::Duplicate);
+        // Neither does a save that leaves the status alone
+        assert_eq!(notifier.notify(&ready_event(43, Some("Ready!")), now).await, SmsOutcome::NoTemplate);
+
+        opt_outs.set_opted_out("+15552013344", true).await.expect("opt out");
+        assert_eq!(notifier.notify(&ready_event(44, None), now).await, SmsOutcome::OptedOut);
+        assert_eq!(sender.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sms_notifiers_sharing_dedupe_text_once() {
+        let server = sms_customer_server().await;
+        let sender = std::sync::Arc::new(MockSmsSender::default());
+        let sent: std::sync::Arc<dyn DedupeStore> =
+            std::sync::Arc::new(InMemoryDedupeStore::new(std::time::Duration::from_secs(60)));
+        // Two instances handling the proxy save and the webhook for the same change
+        let notifiers = [0, 1].map(|_| {
+            SmsNotifier::new(
+                RepairShoprClient::new(&server.uri(), "test-key"),
+                sender.clone(),
+                std::sync::Arc::new(InMemoryOptOutStore::default()),
+            )
+            .with_dedupe(sent.clone())
+        });
+        let now = chrono::Utc::now();
+
+        let outcome = notifiers[0].notify(&ready_event(42, None), now).await;
+        assert!(matches!(outcome, SmsOutcome::Sent { .. }), "{:?}", outcome);
+        assert_eq!(notifiers[1].notify(&ready_event(42, None), now).await, SmsOutcome::Duplicate);
+        assert_eq!(sender.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sms_failed_send_can_be_retried() {
+        let server = sms_customer_server().await;
+        Mock::given(method("POST"))
+            .and(path("/Accounts/AC123/Messages.json"))
+            .respond_with(ResponseTemplate::new(503).set_body_string("unavailable"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/Accounts/AC123/Messages.json"))
+            .respond_with(ResponseTemplate::new(201).set_body_string(r#"{"sid":"SM2"}"#))
+            .mount(&server)
+            .await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let sender = std::sync::Arc::new(TwilioSender::new(&server.uri(), "AC123", "secret", "+15550000000"));
+        let notifier = SmsNotifier::new(client, sender, std::sync::Arc::new(InMemoryOptOutStore::default()));
+        let now = chrono::Utc::now();
+
+        let outcome = notifier.notify(&ready_event(42, None), now).await;
+        assert!(matches!(outcome, SmsOutcome::Failed(_)), "{:?}", outcome);
+        let outcome = notifier.notify(&ready_event(42, None), now).await;
+        assert!(matches!(&outcome, SmsOutcome::Sent { message_id, .. } if message_id == "SM2"), "{:?}", outcome);
+        assert_eq!(notifier.notify(&ready_event(42, None), now).await, SmsOutcome::Duplicate);
+    }
+
+    #[tokio::test]
+    async fn test_sms_held_during_quiet_hours_until_flushed() {
+        use chrono::TimeZone;
+
+        let server = sms_customer_server().await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let sender = std::sync::Arc::new(MockSmsSender::default());
+        let utc = parse_utc_offset("+00:00").expect("offset");
+        let notifier = SmsNotifier::new(client, sender.clone(), std::sync::Arc::new(InMemoryOptOutStore::default()))
+            .with_quiet_hours(QuietHours::parse("21:00-08:00", utc));
+        let night = chrono::Utc.with_ymd_and_hms(2026, 3, 2, 23, 0, 0).single().expect("valid time");
+        let morning = chrono::Utc.with_ymd_and_hms(2026, 3, 3, 9, 0, 0).single().expect("valid time");
+
+        let outcome = notifier.notify(&ready_event(42, None), night).await;
+        assert_eq!(outcome, SmsOutcome::Deferred { to: "+15552013344".to_string() });
+        assert!(notifier.flush_deferred(night).await.is_empty());
+        assert!(sender.sent().is_empty());
+
+        let flushed = notifier.flush_deferred(morning).await;
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(sender.sent().len(), 1);
+        assert!(notifier.flush_deferred(morning).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_held_sms_that_fails_is_requeued() {
+        use chrono::TimeZone;
+
+        let server = sms_customer_server().await;
+        Mock::given(method("POST"))
+            .and(path("/Accounts/AC123/Messages.json"))
+            .respond_with(ResponseTemplate::new(503).set_body_string("unavailable"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/Accounts/AC123/Messages.json"))
+            .respond_with(ResponseTemplate::new(201).set_body_string(r#"{"sid":"SM3"}"#))
+            .mount(&server)
+            .await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let sender = std::sync::Arc::new(TwilioSender::new(&server.uri(), "AC123", "secret", "+15550000000"));
+        let utc = parse_utc_offset("+00:00").expect("offset");
+        let notifier = SmsNotifier::new(client, sender, std::sync::Arc::new(InMemoryOptOutStore::default()))
+            .with_quiet_hours(QuietHours::parse("21:00-08:00", utc));
+        let night = chrono::Utc.with_ymd_and_hms(2026, 3, 2, 23, 0, 0).single().expect("valid time");
+        let morning = chrono::Utc.with_ymd_and_hms(2026, 3, 3, 9, 0, 0).single().expect("valid time");
+
+        notifier.notify(&ready_event(42, None), night).await;
+        let flushed = notifier.flush_deferred(morning).await;
+        assert!(matches!(flushed.as_slice(), [SmsOutcome::Failed(_)]), "{:?}", flushed);
+        let flushed = notifier.flush_deferred(morning).await;
+        assert!(matches!(flushed.as_slice(), [SmsOutcome::Sent { message_id, .. }] if message_id == "SM3"), "{:?}", flushed);
+        assert!(notifier.flush_deferred(morning).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_s3_drain_skips_texts_claimed_by_another_flush() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(query_param("prefix", "sms-deferred/"))
+            .respond_with(s3_listing(&["sms-deferred/42-aa.json".to_string(), "sms-deferred/43-bb.json".to_string()]))
+            .mount(&server)
+            .await;
+        // Another flush holds the claim on 42; 43 is free
+        Mock::given(method("PUT"))
+            .and(path("/state/sms-deferred-claims/42-aa.json"))
+            .respond_with(ResponseTemplate::new(412).set_body_string(
+                "<Error><Code>PreconditionFailed</Code><Message>At least one of the pre-conditions you specified did not hold</Message></Error>",
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/state/sms-deferred/42-aa.json"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/state/sms-deferred-claims/43-bb.json"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/state/sms-deferred/43-bb.json"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(r#"{"ticket_id":43,"to":"+15552013344","body":"Ready"}"#),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let queue = S3DeferredQueue::new(mock_s3_client(&server), "state".to_string());
+        let drained = queue.drain().await.expect("drain");
+        assert_eq!(
+            drained,
+            vec![PendingSms {
+                ticket_id: 43,
+                to: "+15552013344".to_string(),
+                body: "Ready".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_s3_dedupe_claims_each_key_once_per_window() {
+        let server = MockServer::start().await;
+        let taken = || {
+            ResponseTemplate::new(412).set_body_string(
+                "<Error><Code>PreconditionFailed</Code><Message>At least one of the pre-conditions you specified did not hold</Message></Error>",
+            )
+        };
+        // 42 is new, 43 was sent by another instance an hour ago, 44 two days ago
+        Mock::given(method("PUT"))
+            .and(path("/state/notifications/sent/sms/34323a5265616479"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/state/notifications/sent/sms/34333a5265616479"))
+            .respond_with(taken())
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/state/notifications/sent/sms/34333a5265616479"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", "\"recent\"")
+                    .set_body_string((chrono::Utc::now() - chrono::Duration::hours(1)).to_rfc3339()),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/state/notifications/sent/sms/34343a5265616479"))
+            .and(header("if-match", "\"stale\""))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/state/notifications/sent/sms/34343a5265616479"))
+            .respond_with(taken())
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/state/notifications/sent/sms/34343a5265616479"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", "\"stale\"")
+                    .set_body_string((chrono::Utc::now() - chrono::Duration::days(2)).to_rfc3339()),
+            )
+            .mount(&server)
+            .await;
+
+        let store = S3DedupeStore::new(
+            mock_s3_client(&server),
+            "state".to_string(),
+            "sms",
+            std::time::Duration::from_secs(24 * 60 * 60),
+        );
+        assert!(store.claim("42:Ready").await.expect("claim"));
+        assert!(!store.claim("43:Ready").await.expect("claim"));
+        assert!(store.claim("44:Ready").await.expect("claim"));
+    }
+
+    #[tokio::test]
+    async fn test_opt_outs_outlive_the_request() {
+        let s3_client = offline_s3_client();
+        opt_out_store_from_env(&s3_client).set_opted_out("+15559870001", true).await.expect("opt out");
+        let later = opt_out_store_from_env(&s3_client);
+        assert!(later.is_opted_out("+15559870001").await.expect("check"));
+    }
+
+    #[tokio::test]
+    async fn test_held_texts_outlive_the_request() {
+        let s3_client = offline_s3_client();
+        let held = PendingSms {
+            ticket_id: 42,
+            to: "+15552013344".to_string(),
+            body: "Ready for pickup".to_string(),
+        };
+        deferred_queue_from_env(&s3_client).push(&held).await.expect("hold");
+        let later = deferred_queue_from_env(&s3_client);
+        assert_eq!(later.drain().await.expect("drain"), vec![held]);
+        assert!(later.drain().await.expect("drain").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_twilio_sender_posts_form_and_detects_unsubscribed() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/Accounts/AC123/Messages.json"))
+            .and(header("authorization", "Basic QUMxMjM6c2VjcmV0"))
+            .and(wiremock::matchers::body_string_contains("To=%2B15552013344"))
+            .respond_with(ResponseTemplate::new(201).set_body_string(r#"{"sid":"SM1","status":"queued"}"#))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/Accounts/AC123/Messages.json"))
+            .respond_with(
+                ResponseTemplate::new(400)
+                    .set_body_string(r#"{"code":21610,"message":"Attempt to send to unsubscribed recipient"}"#),
+            )
+            .mount(&server)
+            .await;
+
+        let sender = TwilioSender::new(&server.uri(), "AC123", "secret", "+15550000000");
+        assert_eq!(sender.send("+15552013344", "Hello").await, Ok("SM1".to_string()));
+        assert_eq!(sender.send("+15552013344", "Hello").await, Err(SmsError::Unsubscribed));
+    }
+
+    /// RepairShopr stub for a customer with an email address
+    async fn email_customer_server(no_email: bool) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/customers/7"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                json!({
+                    "customer": {
+                        "id": 7,
+                        "firstname": "Dana <3",
+                        "fullname": "Dana Smith",
+                        "email": "dana@example.com",
+                        "no_email": no_email
+                    }
+                })
+                .to_string(),
+            ))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn test_email_notifier_sends_each_kind_once() {
+        let server = email_customer_server(false).await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let sender = std::sync::Arc::new(MockEmailSender::default());
+        let notifier = EmailNotifier::new(client, sender.clone());
+        let ticket = ready_event(42, None).ticket().cloned().expect("event carries the ticket");
+
+        let created = TicketEvent::TicketCreated { ticket: Ticket { status: "New".to_string(), ..ticket.clone() } };
+        assert!(matches!(notifier.notify(&created).await, EmailOutcome::Sent { to, .. } if to == "dana@example.com"));
+        assert_eq!(notifier.notify(&created).await, EmailOutcome::Duplicate);
+
+        let in_progress = TicketEvent::StatusChanged {
+            ticket: Ticket { status: "In Progress".to_string(), ..ticket.clone() },
+            previous_status: None,
+        };
+        assert_eq!(notifier.notify(&in_progress).await, EmailOutcome::NotApplicable);
+        assert_eq!(notifier.notify(&ready_event(42, Some("Ready!"))).await, EmailOutcome::NotApplicable);
+        assert!(matches!(notifier.notify(&ready_event(42, Some("In Progress"))).await, EmailOutcome::Sent { .. }));
+
+        let estimate = TicketEvent::EstimateReady {
+            ticket,
+            amount_cents: 14_950,
+            description: "Replace battery".to_string(),
+            url: "https://tickets.example.com/approve?token=abc".to_string(),
+        };
+        assert!(matches!(notifier.notify(&estimate).await, EmailOutcome::Sent { .. }));
+
+        let sent = sender.sent();
+        let subjects: Vec<&str> = sent.iter().map(|m| m.subject.as_str()).collect();
+        assert_eq!(
+            subjects,
+            vec![
+                "We've got your phone (ticket #1042)",
+                "Your phone is ready for pickup (ticket #1042)",
+                "Your estimate for ticket #1042 is ready",
+            ]
+        );
+        // Values are escaped in HTML only
+        assert!(sent[0].text.starts_with("Hi Dana <3,"));
+        assert!(sent[0].html.starts_with("<p>Hi Dana &lt;3,</p>"));
+        assert!(sent[2].text.contains("Replace battery, for $149.50"));
+        assert!(sent[2].html.contains(r#"<a href="https://tickets.example.com/approve?token=abc">"#));
+
+        let summary = String::from_utf8(sent[1].attachments[0].content.clone()).expect("UTF-8 summary");
+        assert_eq!(sent[1].attachments[0].filename, "ticket-1042-summary.txt");
+        assert!(summary.starts_with("Ticket #1042\nCustomer: Dana Smith\nDevice: Phone\n"));
+        assert!(summary.contains("Status: Ready\nItems left: None\n"));
+    }
+
+    #[tokio::test]
+    async fn test_email_notifiers_sharing_dedupe_email_once() {
+        let server = email_customer_server(false).await;
+        let sender = std::sync::Arc::new(MockEmailSender::default());
+        let sent: std::sync::Arc<dyn DedupeStore> =
+            std::sync::Arc::new(InMemoryDedupeStore::new(std::time::Duration::from_secs(60)));
+        let notifiers = [0, 1].map(|_| {
+            EmailNotifier::new(RepairShoprClient::new(&server.uri(), "test-key"), sender.clone()).with_dedupe(sent.clone())
+        });
+
+        let ready = ready_event(42, Some("In Progress"));
+        assert!(matches!(notifiers[0].notify(&ready).await, EmailOutcome::Sent { .. }));
+        assert_eq!(notifiers[1].notify(&ready).await, EmailOutcome::Duplicate);
+        assert_eq!(sender.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_email_notifier_respects_no_email_flag() {
+        let server = email_customer_server(true).await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let sender = std::sync::Arc::new(MockEmailSender::default());
+        let notifier = EmailNotifier::new(client, sender.clone());
+
+        assert_eq!(notifier.notify(&ready_event(42, None)).await, EmailOutcome::OptedOut);
+        assert!(sender.sent().is_empty());
+    }
+
+    /// Email sender whose first send fails
+    #[derive(Default)]
+    struct FlakyEmailSender {
+        attempts: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl EmailSender for FlakyEmailSender {
+        async fn send(&self, _message: &EmailMessage) -> Result<String, String> {
+            match self.attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => Err("connection reset".to_string()),
+                n => Ok(format!("flaky-{}", n)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_email_failed_send_can_be_retried() {
+        let server = email_customer_server(false).await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let notifier = EmailNotifier::new(client, std::sync::Arc::new(FlakyEmailSender::default()));
+
+        let ready = ready_event(42, Some("In Progress"));
+        assert_eq!(notifier.notify(&ready).await, EmailOutcome::Failed("connection reset".to_string()));
+        assert!(matches!(notifier.notify(&ready).await, EmailOutcome::Sent { .. }));
+        assert_eq!(notifier.notify(&ready).await, EmailOutcome::Duplicate);
+    }
+
+    #[tokio::test]
+    async fn test_smtp_sender_delivers_multipart_message_to_local_server() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        // Just enough of an SMTP server to accept one message
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let port = listener.local_addr().expect("address").port();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.expect("accept");
+            let (reader, mut writer) = socket.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut data = String::new();
+            let mut in_data = false;
+            writer.write_all(b"220 localhost ESMTP\r\n").await.expect("greeting");
+            while let Ok(Some(line)) = lines.next_line().await {
+                if in_data {
+                    if line == "." {
+                        in_data = false;
+                        writer.write_all(b"250 2.0.0 queued as TEST1\r\n").await.expect("reply");
+                    } else {
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    continue;
+                }
+                let reply: &[u8] = match line.split(' ').next().unwrap_or_default().to_uppercase().as_str() {
+                    "EHLO" | "HELO" => b"250 localhost\r\n",
+                    "DATA" => {
+                        in_data = true;
+                        b"354 end with .\r\n"
+                    }
+                    "QUIT" => {
+                        writer.write_all(b"221 bye\r\n").await.expect("reply");
+                        break;
+                    }
+                    _ => b"250 OK\r\n",
+                };
+                writer.write_all(reply).await.expect("reply");
+            }
+            data
+        });
+
+        let sender = SmtpSender::unencrypted("127.0.0.1", port, "Cacell <repairs@example.com>");
+        let message = EmailMessage {
+            to: "dana@example.com".to_string(),
+            subject: "Ready for pickup".to_string(),
+            text: "Your phone is ready".to_string(),
+            html: "<p>Your phone is ready</p>".to_string(),
+            attachments: vec![EmailAttachment {
+                filename: "ticket-1042-summary.txt".to_string(),
+                content_type: "text/plain; charset=utf-8".to_string(),
+                content: b"Ticket #1042\n".to_vec(),
+            }],
+        };
+        let response = sender.send(&message).await.expect("sent");
+        assert!(response.contains("queued as TEST1"));
+
+        let data = server.await.expect("server finished");
+        assert!(data.contains("To: dana@example.com"));
+        assert!(data.contains("Subject: Ready for pickup"));
+        assert!(data.contains("multipart/alternative"));
+        assert!(data.contains("<p>Your phone is ready</p>"));
+        assert!(data.contains("filename=\"ticket-1042-summary.txt\""));
+    }
+
+    #[test]
+    fn test_webhook_verification_accepts_signature_or_secret() {
+        let secret = b"webhook-secret-123";
+        let body = br#"{"event":"ticket.updated"}"#;
+        let signature = sign_webhook(secret, body);
+
+        assert_eq!(verify_webhook(secret, body, Some(&signature), None), Ok(()));
+        assert_eq!(verify_webhook(secret, body, Some(&format!("sha256={}", signature)), None), Ok(()));
+        assert_eq!(verify_webhook(secret, b"{}", Some(&signature), None), Err(WebhookError::Unauthorized));
+        assert_eq!(verify_webhook(secret, body, Some("not-hex"), None), Err(WebhookError::Unauthorized));
+        // A bad signature isn't rescued by the secret
+        assert_eq!(
+            verify_webhook(secret, body, Some("00"), Some("webhook-secret-123")),
+            Err(WebhookError::Unauthorized)
+        );
+        assert_eq!(verify_webhook(secret, body, None, Some("webhook-secret-123")), Ok(()));
+        assert_eq!(verify_webhook(secret, body, None, Some("webhook-secret-12")), Err(WebhookError::Unauthorized));
+        assert_eq!(verify_webhook(secret, body, None, None), Err(WebhookError::Unauthorized));
+    }
+
+    #[test]
+    fn test_parse_webhook_into_typed_events() {
+        let ticket = parse_webhook(
+            br#"{"event":"ticket.status_changed","previous_status":"In Progress","attributes":{"id":42,"number":1042,"status":"Ready!"}}"#,
+            None,
+        )
+        .expect("valid")
+        .expect("handled");
+        assert_eq!(ticket.name(), "ticket.status_changed");
+        assert_eq!(ticket.target(), "ticket:42");
+        assert!(matches!(&ticket, WebhookEvent::Ticket { previous_status: Some(p), .. } if p == "In Progress"));
+
+        let customer = parse_webhook(br#"{"type":"customer.updated","customer":{"id":7,"firstname":"Dana"}}"#, None)
+            .expect("valid")
+            .expect("handled");
+        assert!(matches!(customer, WebhookEvent::Customer { action: WebhookAction::Updated, customer } if customer.id == 7));
+
+        // Event named in the URL, record at the body root
+        let comment = parse_webhook(br#"{"id":5,"ticket_id":42,"body":"Hi"}"#, Some("comment.created"))
+            .expect("valid")
+            .expect("handled");
+        assert_eq!(comment.name(), "comment.created");
+
+        assert_eq!(parse_webhook(br#"{"event":"invoice.created","attributes":{}}"#, None), Ok(None));
+        assert_eq!(parse_webhook(br#"{"event":"customer.status_changed","attributes":{"id":1}}"#, None), Ok(None));
+        assert!(matches!(parse_webhook(b"nope", None), Err(WebhookError::Malformed(_))));
+        assert!(matches!(parse_webhook(br#"{"attributes":{}}"#, None), Err(WebhookError::Malformed(_))));
+        assert!(matches!(
+            parse_webhook(br#"{"event":"ticket.updated","attributes":{"id":"x"}}"#, None),
+            Err(WebhookError::Malformed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_webhook_dedupes_redeliveries_and_fans_out() {
+        let server = sms_customer_server().await;
+        let client = RepairShoprClient::new(&server.uri(), "test-key");
+        let sender = std::sync::Arc::new(MockSmsSender::default());
+        let notifier = SmsNotifier::new(client, sender.clone(), std::sync::Arc::new(InMemoryOptOutStore::default()));
+        let events = EventBus::default().with(notifier);
+        let deliveries = InMemoryDeliveryStore::default();
+        let audit = InMemoryAuditStore::default();
+        let context = WebhookContext {
+            deliveries: &deliveries,
+            events: &events,
+            audit: &audit,
+        };
+        let secret = b"webhook-secret-123";
+        let body = json!({
+            "event": "ticket.status_changed",
+            "attributes": {"id": 42, "number": 1042, "status": "Ready!", "customer_id": 7}
+        })
+        .to_string();
+        let delivery = |signature: &str| {
+            http::Request::builder()
+                .method("POST")
+                .header("x-signature", signature)
+                .header("x-delivery-id", "delivery-1")
+                .body(Body::Text(body.clone()))
+                .expect("failed to build request")
+        };
+        let signature = sign_webhook(secret, body.as_bytes());
+
+        let response = handle_repairshopr_webhook(&delivery(&signature), secret, &context)
+            .await
+            .expect("accepted");
+        assert_eq!(response.status(), 200);
+        let json: serde_json::Value = serde_json::from_str(&body_text(&response)).expect("JSON body");
+        assert_eq!(json["event"], "ticket.status_changed");
+
+        let response = handle_repairshopr_webhook(&delivery(&signature), secret, &context)
+            .await
+            .expect("redelivery acknowledged");
+        let json: serde_json::Value = serde_json::from_str(&body_text(&response)).expect("JSON body");
+        assert_eq!(json["duplicate"], true);
+
+        let error = handle_repairshopr_webhook(&delivery("deadbeef"), secret, &context)
+            .await
+            .expect_err("bad signature");
+        assert_eq!(error.status, 401);
+
+        assert_eq!(sender.sent().len(), 1);
+        let actions: Vec<String> = audit.entries().into_iter().map(|e| e.action).collect();
+        assert_eq!(actions, vec!["webhook.ticket.status_changed"]);
+    }
+
+    /// Fails the first event it sees, then records the rest
+    #[derive(Default, Clone)]
+    struct FailOnceHandler {
+        calls: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::events::EventHandler for FailOnceHandler {
+        fn name(&self) -> &'static str {
+            "fail-once"
+        }
+
+        async fn handle(&self, _event: &TicketEvent) -> Result<(), String> {
+            match self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => Err("downstream unavailable".to_string()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    /// Counts the events it sees
+    #[derive(Default, Clone)]
+    struct CountingHandler {
+        calls: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::events::EventHandler for CountingHandler {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        async fn handle(&self, _event: &TicketEvent) -> Result<(), String> {
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_webhook_failed_dispatch_is_retried() {
+        let handler = FailOnceHandler::default();
+        let counting = CountingHandler::default();
+        let events = EventBus::default().with(counting.clone()).with(handler.clone());
+        let deliveries = InMemoryDeliveryStore::default();
+        let audit = InMemoryAuditStore::default();
+        let context = WebhookContext {
+            deliveries: &deliveries,
+            events: &events,
+            audit: &audit,
+        };
+        let secret = b"webhook-secret-123";
+        let body = json!({ "event": "ticket.updated", "attributes": {"id": 42, "number": 1042} }).to_string();
+        let delivery = || {
+            http::Request::builder()
+                .method("POST")
+                .header("x-signature", sign_webhook(secret, body.as_bytes()))
+                .header("x-delivery-id", "delivery-2")
+                .body(Body::Text(body.clone()))
+                .expect("failed to build request")
+        };
+
+        let error = handle_repairshopr_webhook(&delivery(), secret, &context)
+            .await
+            .expect_err("dispatch failed");
+        assert_eq!(error.status, 502);
+        assert_eq!(error.code, "WEBHOOK_DISPATCH_FAILED");
+        assert!(audit.entries().is_empty());
+
+        // RepairShopr's retry is processed, not acknowledged as a duplicate
+        let response = handle_repairshopr_webhook(&delivery(), secret, &context)
+            .await
+            .expect("retry accepted");
+        let json: serde_json::Value = serde_json::from_str(&body_text(&response)).expect("JSON body");
+        assert_eq!(json["event"], "ticket.updated");
+        assert_eq!(handler.calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+        // The consumer that succeeded the first time isn't run again
+        assert_eq!(counting.calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(audit.entries().len(), 1);
+
+        let response = handle_repairshopr_webhook(&delivery(), secret, &context)
+            .await
+            .expect("redelivery acknowledged");
+        let json: serde_json::Value = serde_json::from_str(&body_text(&response)).expect("JSON body");
+        assert_eq!(json["duplicate"], true);
+    }
+
+    fn socket_event(connection_id: &str, event_type: &str, body: &str) -> Request {
+        let mut context = ApiGatewayWebsocketProxyRequestContext::default();
+        context.connection_id = Some(connection_id.to_string());
+        context.event_type = Some(event_type.to_string());
+        Request::new(Body::from(body.to_string())).with_request_context(RequestContext::WebSocket(context))
+    }
+
+    /// A `$connect` event carrying `?token=`
+    fn socket_connect_with_token(connection_id: &str, token: &str) -> Request {
+        socket_event(connection_id, "CONNECT", "").with_query_string_parameters(std::collections::HashMap::from([(
+            "token".to_string(),
+            token.to_string(),
+        )]))
+    }
+
+    const SOCKET_NOW: i64 = 1_800_000_000;
+
+    async fn socket_json(event: &Request, store: &InMemoryConnectionStore) -> (u16, serde_json::Value) {
+        let response = handle_socket_event(event, store, Some(TEST_SHARE_SECRET), SOCKET_NOW).await;
+        let status = response.status().as_u16();
+        (status, serde_json::from_slice(response.body().as_ref()).expect("json body"))
+    }
+
+    #[tokio::test]
+    async fn test_socket_connect_requires_identity() {
+        let store = InMemoryConnectionStore::default();
+        let (status, _) = socket_json(&socket_event("anon=", "CONNECT", ""), &store).await;
+        assert_eq!(status, 401);
+        let (status, _) = socket_json(&socket_connect_with_token("anon=", "not-a-token"), &store).await;
+        assert_eq!(status, 401);
+        let expired = sign_socket_token("sam@example.com", SOCKET_NOW, TEST_SHARE_SECRET);
+        let (status, _) = socket_json(&socket_connect_with_token("anon=", &expired), &store).await;
+        assert_eq!(status, 401);
+        let forged = sign_socket_token("sam@example.com", SOCKET_NOW + 60, b"some-other-secret-0123456789abcdef");
+        let (status, _) = socket_json(&socket_connect_with_token("anon=", &forged), &store).await;
+        assert_eq!(status, 401);
+
+        // A share link token can't open the channel either
+        let (_, share_token) = new_share_link(42, &CreateShareLink::default(), TEST_SHARE_SECRET, SOCKET_NOW).expect("link");
+        let (status, _) = socket_json(&socket_connect_with_token("anon=", &share_token), &store).await;
+        assert_eq!(status, 401);
+
+        // Nor can a connection that never authenticated subscribe
+        let subscribe = r#"{"action": "subscribe", "all": true}"#;
+        let (status, _) = socket_json(&socket_event("anon=", "MESSAGE", subscribe), &store).await;
+        assert_eq!(status, 403);
+        assert!(store.subscribers(&[ALL_TICKETS.to_string()]).await.expect("subscribers").is_empty());
+
+        // An authorizer on the WebSocket API identifies the user instead of a token
+        let mut context = ApiGatewayWebsocketProxyRequestContext::default();
+        context.connection_id = Some("authorized=".to_string());
+        context.event_type = Some("CONNECT".to_string());
+        context.authorizer.fields.insert("principalId".to_string(), json!("user-123"));
+        let authorized = Request::default().with_request_context(RequestContext::WebSocket(context));
+        let (status, _) = socket_json(&authorized, &store).await;
+        assert_eq!(status, 200);
+    }
+
+    #[tokio::test]
+    async fn test_socket_token_reaches_connect_through_api_gateway_event() {
+        let response = handle_socket_token(
+            &UserIdentity {
+                email: Some("sam@example.com".to_string()),
+                ..Default::default()
+            },
+            TEST_SHARE_SECRET,
+            SOCKET_NOW,
+        );
+        let body: serde_json::Value = serde_json::from_str(&body_text(&response)).expect("JSON body");
+        assert_eq!(body["expires_in"], 300);
+        let token = body["token"].as_str().expect("token");
+        assert_eq!(verify_socket_token(token, TEST_SHARE_SECRET, SOCKET_NOW).as_deref(), Some("sam@example.com"));
+
+        let raw = json!({
+            "requestContext": {
+                "routeKey": "$connect",
+                "eventType": "CONNECT",
+                "connectionId": "raw=",
+                "stage": "prod",
+                "apiId": "abc123",
+                "requestId": "req",
+                "domainName": "abc123.execute-api.us-east-1.amazonaws.com",
+                "messageDirection": "IN",
+                "connectedAt": 1_800_000_000_000_i64,
+                "requestTimeEpoch": 1_800_000_000_000_i64,
+                "identity": { "sourceIp": "203.0.113.9" }
+            },
+            "queryStringParameters": { "token": token },
+            "headers": {},
+            "isBase64Encoded": false
+        });
+        let event = lambda_http::request::from_str(&raw.to_string()).expect("WebSocket event");
+        assert!(is_socket_event(&event));
+        let store = InMemoryConnectionStore::default();
+        let (status, _) = socket_json(&event, &store).await;
+        assert_eq!(status, 200);
+    }
+
+    #[tokio::test]
+    async fn test_socket_connect_subscribe_and_disconnect() {
+        let store = InMemoryConnectionStore::default();
+        assert!(is_socket_event(&socket_event("abc=", "CONNECT", "")));
+        assert!(!is_socket_event(&Request::default()));
+
+        let token = sign_socket_token("sam@example.com", SOCKET_NOW + 60, TEST_SHARE_SECRET);
+        let (status, _) = socket_json(&socket_connect_with_token("abc=", &token), &store).await;
+        assert_eq!(status, 200);
+
+        let subscribe = r#"{"action": "subscribe", "tickets": [42, 7]}"#;
+        let (status, body) = socket_json(&socket_event("abc=", "MESSAGE", subscribe), &store).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["type"], "subscriptions");
+        assert_eq!(body["topics"], json!(["ticket:42", "ticket:7"]));
+
+        let unsubscribe = r#"{"action": "unsubscribe", "tickets": [7]}"#;
+        let (_, body) = socket_json(&socket_event("abc=", "MESSAGE", unsubscribe), &store).await;
+        assert_eq!(body["topics"], json!(["ticket:42"]));
+
+        let (_, body) = socket_json(&socket_event("abc=", "MESSAGE", r#"{"action": "ping"}"#), &store).await;
+        assert_eq!(body["type"], "pong");
+
+        let (status, _) = socket_json(&socket_event("abc=", "MESSAGE", r#"{"action": "shout"}"#), &store).await;
+        assert_eq!(status, 400);
+        let too_many = json!({ "action": "subscribe", "tickets": (0..101).collect::<Vec<i64>>() }).to_string();
+        let (status, _) = socket_json(&socket_event("abc=", "MESSAGE", &too_many), &store).await;
+        assert_eq!(status, 400);
+        // The cap counts tickets already followed, so it can't be reached in small steps
+        let more = json!({ "action": "subscribe", "tickets": (100..199).collect::<Vec<i64>>() }).to_string();
+        let (status, body) = socket_json(&socket_event("abc=", "MESSAGE", &more), &store).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["topics"].as_array().map(Vec::len), Some(100));
+        let one_more = r#"{"action": "subscribe", "tickets": [500]}"#;
+        let (status, _) = socket_json(&socket_event("abc=", "MESSAGE", one_more), &store).await;
+        assert_eq!(status, 400);
+        let all = r#"{"action": "subscribe", "all": true}"#;
+        let (status, _) = socket_json(&socket_event("abc=", "MESSAGE", all), &store).await;
+        assert_eq!(status, 200);
+
+        let topics = [ticket_topic(42)];
+        assert_eq!(store.subscribers(&topics).await.expect("subscribers"), vec!["abc=".to_string()]);
+        socket_json(&socket_event("abc=", "DISCONNECT", ""), &store).await;
+        assert!(store.subscribers(&topics).await.expect("subscribers").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_reaches_subscribers_and_drops_closed_connections() {
+        let store = std::sync::Arc::new(InMemoryConnectionStore::default());
+        for id in ["board", "detail", "other", "closed"] {
+            store.connect(id, "sam@example.com").await.expect("connect");
+        }
+        store.update_topics("board", &[ALL_TICKETS.to_string()], true, 100).await.expect("subscribe");
+        store.update_topics("detail", &[ticket_topic(42)], true, 100).await.expect("subscribe");
+        store.update_topics("other", &[ticket_topic(43)], true, 100).await.expect("subscribe");
+        store.update_topics("closed", &[ticket_topic(42)], true, 100).await.expect("subscribe");
+
+        let sender = std::sync::Arc::new(MockConnectionSender {
+            gone: vec!["closed".to_string()],
+            ..Default::default()
+        });
+        let broadcaster = Broadcaster::new(store.clone(), sender.clone());
+
+        let delivered = broadcaster
+            .deliver(&PendingBroadcast::from(&TicketEvent::Changed { ticket_id: 42 }))
+            .await.expect("broadcast");
+        assert_eq!(delivered, 2);
+        let sent = sender.sent.lock().expect("lock").clone();
+        let mut recipients: Vec<&str> = sent.iter().map(|(id, _)| id.as_str()).collect();
+        recipients.sort();
+        assert_eq!(recipients, vec!["board", "detail"]);
+        let message: serde_json::Value = serde_json::from_str(&sent[0].1).expect("json");
+        assert_eq!(message["type"], "ticket.changed");
+        assert_eq!(message["ticket_id"], 42);
+        assert_eq!(message["reason"], "updated");
+
+        // The closed connection was forgotten
+        let topics = [ticket_topic(42)];
+        assert_eq!(store.subscribers(&topics).await.expect("subscribers"), vec!["detail".to_string()]);
+
+        let delivered = broadcaster.deliver(&PendingBroadcast::from(&ready_event(43, None))).await.expect("broadcast");
+        assert_eq!(delivered, 2);
+        let sent = sender.sent.lock().expect("lock").clone();
+        let last: serde_json::Value = serde_json::from_str(&sent[sent.len() - 1].1).expect("json");
+        assert_eq!(last["reason"], "status_changed");
+        assert_eq!(last["number"], 1043);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_hand_off_is_delivered_by_its_invocation() {
+        let store = std::sync::Arc::new(InMemoryConnectionStore::default());
+        store.connect("board", "sam@example.com").await.expect("connect");
+        store.update_topics("board", &[ALL_TICKETS.to_string()], true, 100).await.expect("subscribe");
+        let sender = std::sync::Arc::new(MockConnectionSender::default());
+        let broadcaster = Broadcaster::new(store.clone(), sender.clone());
+
+        // The hand-off parses as a WebSocket event that only the broadcast route accepts
+        let pending = PendingBroadcast::from(&TicketEvent::Changed { ticket_id: 42 });
+        let raw = broadcast_event(&pending, SOCKET_NOW * 1000).to_string();
+        let event = lambda_http::request::from_str(&raw).expect("broadcast event");
+        assert!(is_socket_event(&event));
+        assert!(is_broadcast_event(&event));
+        assert!(!is_broadcast_event(&socket_event("abc=", "MESSAGE", "{}")));
+
+        let response = handle_broadcast_event(&event, &broadcaster).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_str(&body_text(&response)).expect("JSON body");
+        assert_eq!(body["delivered"], 1);
+        let sent = sender.sent.lock().expect("lock").clone();
+        assert_eq!(sent.len(), 1);
+        let message: serde_json::Value = serde_json::from_str(&sent[0].1).expect("json");
+        assert_eq!(message, pending.message);
+    }
+
+    #[tokio::test]
+    async fn test_s3_subscribers_come_from_the_topic_index() {
+        let server = MockServer::start().await;
+        let listing = s3_listing;
+        Mock::given(method("GET"))
+            .and(query_param("prefix", "push/topics/tickets/"))
+            .respond_with(listing(&[format!("push/topics/tickets/{}", hex::encode("board="))]))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(query_param("prefix", "push/topics/ticket:42/"))
+            .respond_with(listing(&[
+                format!("push/topics/ticket:42/{}", hex::encode("board=")),
+                format!("push/topics/ticket:42/{}", hex::encode("detail/1")),
+            ]))
+            .mount(&server)
+            .await;
+        // Connection records are never read to find subscribers
+        Mock::given(method("GET"))
+            .and(wiremock::matchers::path_regex("^/state/push/connections/"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let store = S3ConnectionStore::new(mock_s3_client(&server), "state".to_string());
+        let subscribers = store
+            .subscribers(&[ALL_TICKETS.to_string(), ticket_topic(42)])
+            .await
+            .expect("subscribers");
+        assert_eq!(subscribers, vec!["board=".to_string(), "detail/1".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_status_change_comments() {
+        assert_eq!(
+            parse_status_change("Status changed from New to In Progress"),
+            Some((Some("New".to_string()), "In Progress".to_string()))
+        );
+        assert_eq!(
+            parse_status_change("Ticket status changed to \"Waiting for Parts\"."),
+            Some((None, "Waiting for Parts".to_string()))
+        );
+        assert_eq!(
+            parse_status_change("STATUS CHANGED FROM 'Ready!' TO 'Resolved'\nBy Alex"),
+            Some((Some("Ready!".to_string()), "Resolved".to_string()))
+        );
+        assert_eq!(parse_status_change("Called customer, no answer"), None);
+        assert_eq!(parse_status_change("Status changed"), None);
+    }
+
+    fn status_comment(at: &str, text: &str) -> Comment {
+        Comment {
+            subject: Some("Status".to_string()),
+            body: Some(text.to_string()),
+            created_at: Some(at.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn report_tickets() -> Vec<Ticket> {
+        let mut repaired = Ticket {
+            id: 1,
+            number: 1001,
+            status: "Resolved".to_string(),
+            user_id: Some(5),
+            user: Some(TicketUser {
+                id: Some(5),
+                full_name: Some("Alex Tech".to_string()),
+                ..Default::default()
+            }),
+            created_at: Some("2026-03-02T09:00:00-05:00".to_string()),
+            resolved_at: Some("2026-03-04T09:00:00-05:00".to_string()),
+            comments: vec![
+                status_comment("2026-03-03T15:00:00-05:00", "Status changed from In Progress to Ready!"),
+                status_comment("2026-03-02T15:00:00-05:00", "Status changed from New to In Progress"),
+                status_comment("2026-03-02T16:00:00-05:00", "Screen ordered"),
+            ],
+            ..Default::default()
+        };
+        repaired.properties.model = Some(r#"vT{"device":"Phone","itemsLeft":[]}"#.to_string());
+        let waiting = Ticket {
+            id: 2,
+            number: 1002,
+            status: "New".to_string(),
+            created_at: Some("2026-03-03T10:00:00Z".to_string()),
+            ..Default::default()
+        };
+        let old = Ticket {
+            id: 3,
+            number: 900,
+            status: "Resolved".to_string(),
+            created_at: Some("2026-02-01T10:00:00Z".to_string()),
+            resolved_at: Some("2026-02-02T10:00:00Z".to_string()),
+            ..Default::default()
+        };
+        vec![repaired, waiting, old]
+    }
+
+    #[test]
+    fn test_build_report_metrics() {
+        let date = |d: &str| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").expect("date");
+        let now = chrono::DateTime::parse_from_rfc3339("2026-03-05T10:00:00Z")
+            .expect("now")
+            .with_timezone(&chrono::Utc);
+        let range = ReportRange {
+            from: date("2026-03-02"),
+            to: date("2026-03-05"),
+            interval: Interval::Day,
+        };
+        let report = build_report(&report_tickets(), &range, &StatusMap::default(), now);
+        let value = serde_json::to_value(&report).expect("json");
+
+        assert_eq!(report.tickets_considered, 2);
+        assert_eq!(
+            value["volume"],
+            json!([
+                { "period": "2026-03-02", "opened": 1, "closed": 0 },
+                { "period": "2026-03-03", "opened": 1, "closed": 0 },
+                { "period": "2026-03-04", "opened": 0, "closed": 1 },
+                { "period": "2026-03-05", "opened": 0, "closed": 0 },
+            ])
+        );
+        // Diagnosing: 6h for the repair plus 48h (so far) for the waiting ticket
+        assert_eq!(
+            value["time_in_status"],
+            json!([
+                { "status": "Diagnosing", "stays": 2, "average_hours": 27.0 },
+                { "status": "In Progress", "stays": 1, "average_hours": 24.0 },
+                { "status": "Ready", "stays": 1, "average_hours": 18.0 },
+            ])
+        );
+        assert_eq!(
+            value["devices"],
+            json!([{ "device": "Other", "tickets": 1 }, { "device": "Phone", "tickets": 1 }])
+        );
+        assert_eq!(
+            value["technicians"],
+            json!([
+                { "technician": "Alex Tech", "user_id": 5, "assigned": 1, "closed": 1, "average_turnaround_hours": 48.0 },
+                { "technician": "Unassigned", "user_id": null, "assigned": 1, "closed": 0, "average_turnaround_hours": null },
+            ])
+        );
+
+        let weekly = ReportRange {
+            from: date("2026-03-01"),
+            to: date("2026-03-08"),
+            interval: Interval::Week,
+        };
+        let report = build_report(&report_tickets(), &weekly, &StatusMap::default(), now);
+        let periods: Vec<(&str, usize, usize)> = report
+            .volume
+            .iter()
+            .map(|p| (p.period.as_str(), p.opened, p.closed))
+            .collect();
+        assert_eq!(periods, vec![("2026-02-23", 0, 0), ("2026-03-02", 2, 1)]);
+    }
+
+    #[test]
+    fn test_report_range_from_query() {
+        let today = chrono::NaiveDate::parse_from_str("2026-03-31", "%Y-%m-%d").expect("date");
+        let range = ReportRange::from_event(&Request::default(), today).expect("default range");
+        assert_eq!((range.from.to_string(), range.to.to_string()), ("2026-03-02".to_string(), "2026-03-31".to_string()));
+        assert_eq!(range.interval, Interval::Day);
+
+        let with_query = |query: &[(&str, &str)]| {
+            let params: std::collections::HashMap<String, String> = query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            ReportRange::from_event(&Request::default().with_query_string_parameters(params), today)
+        };
+        let range = with_query(&[("from", "2026-01-01"), ("to", "2026-01-31"), ("interval", "week")]).expect("range");
+        assert_eq!(range.interval, Interval::Week);
+        assert_eq!(with_query(&[("from", "2026-02-01"), ("to", "2026-01-01")]).expect_err("reversed").status, 400);
+        assert_eq!(with_query(&[("from", "2024-01-01")]).expect_err("too long").status, 400);
+        assert_eq!(with_query(&[("from", "yesterday")]).expect_err("bad date").status, 400);
+        assert_eq!(with_query(&[("interval", "month")]).expect_err("bad interval").status, 400);
+    }
+
+    fn export_request(kind: ExportKind, query: &[(&str, &str)]) -> Result<ExportRequest, ApiError> {
+        let params: std::collections::HashMap<String, String> =
+            query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        ExportRequest::from_event(&Request::default().with_query_string_parameters(params), kind)
+    }
+
+    #[test]
+    fn test_export_request_from_query() {
+        let request = export_request(ExportKind::Tickets, &[]).expect("defaults");
+        assert_eq!(request.format, ExportFormat::Csv);
+        assert_eq!(request.columns.len(), 12);
+        assert!(!request.link);
+
+        let request = export_request(
+            ExportKind::Tickets,
+            &[("format", "xlsx"), ("columns", "number, status,number"), ("from", "2026-03-01"), ("delivery", "link")],
+        )
+        .expect("request");
+        assert_eq!(request.format, ExportFormat::Xlsx);
+        assert_eq!(request.columns, vec!["number", "status"]);
+        assert!(request.link);
+        let today = chrono::NaiveDate::parse_from_str("2026-03-31", "%Y-%m-%d").expect("date");
+        assert_eq!(request.file_name(today), "tickets-2026-03-01-to-2026-03-31.xlsx");
+
+        for bad in [
+            vec![("format", "pdf")],
+            vec![("columns", "number,password")],
+            vec![("from", "2026-03-02"), ("to", "2026-03-01")],
+            vec![("delivery", "email")],
+        ] {
+            assert_eq!(export_request(ExportKind::Tickets, &bad).expect_err("invalid").status, 400);
+        }
+        assert_eq!(
+            export_request(ExportKind::Customers, &[("status", "Ready")]).expect_err("status filter").status,
+            400
+        );
+    }
+
+    #[test]
+    fn test_ticket_export_filters_and_maps_statuses() {
+        let mut phone = Ticket {
+            id: 1,
+            number: 1001,
+            subject: "Cracked screen, \"urgent\"".to_string(),
+            status: "Ready!".to_string(),
+            customer_business_then_name: Some("Jane Doe".to_string()),
+            created_at: Some("2026-03-02T09:00:00-05:00".to_string()),
+            ..Default::default()
+        };
+        phone.properties.model = Some(r#"vT{"device":"Phone","itemsLeft":[]}"#.to_string());
+        let laptop = Ticket {
+            id: 2,
+            number: 1002,
+            subject: "Battery".to_string(),
+            status: "Customer Reply".to_string(),
+            created_at: Some("2026-03-03T10:00:00Z".to_string()),
+            ..Default::default()
+        };
+        let old = Ticket {
+            id: 3,
+            number: 900,
+            status: "Ready!".to_string(),
+            created_at: Some("2026-02-01T10:00:00Z".to_string()),
+            ..Default::default()
+        };
+        let tickets = vec![phone, laptop, old];
+        let map = StatusMap::default();
+
+        let request = export_request(
+            ExportKind::Tickets,
+            &[("columns", "number,subject,status,device"), ("from", "2026-03-01"), ("status", "ready")],
+        )
+        .expect("request");
+        let table = ticket_table(&tickets, &request, &map);
+        assert_eq!(
+            table.rows,
+            vec![
+                vec!["1001", "Cracked screen, \"urgent\"", "Ready", "Phone"],
+                vec!["1002", "Battery", "Ready", "Other"],
+            ]
+        );
+        let csv = String::from_utf8(write_csv(&table).expect("csv")).expect("utf8");
+        assert_eq!(
+            csv,
+            "number,subject,status,device\n1001,\"Cracked screen, \"\"urgent\"\"\",Ready,Phone\n1002,Battery,Ready,Other\n"
+        );
+
+        // Text that would run as a formula in a spreadsheet is quoted; numbers are not
+        let formulas = Table {
+            columns: vec!["subject", "customer"],
+            rows: vec![
+                vec!["=HYPERLINK(\"http://evil\")".to_string(), "+15550102000".to_string()],
+                vec!["@SUM(A1)".to_string(), "-2+3".to_string()],
+                vec!["\tcmd".to_string(), "-5".to_string()],
+            ],
+        };
+        let csv = String::from_utf8(write_csv(&formulas).expect("csv")).expect("utf8");
+        assert_eq!(
+            csv,
+            "subject,customer\n\"'=HYPERLINK(\"\"http://evil\"\")\",+15550102000\n'@SUM(A1),'-2+3\n'\tcmd,-5\n"
+        );
+
+        let request = export_request(ExportKind::Tickets, &[("columns", "number"), ("device", "phone")]).expect("request");
+        assert_eq!(ticket_table(&tickets, &request, &map).rows, vec![vec!["1001"]]);
+
+        let xlsx = write_xlsx(&table, "tickets").expect("xlsx");
+        assert!(xlsx.starts_with(b"PK"), "XLSX files are zip archives");
+    }
+
+    #[tokio::test]
+    async fn test_export_refuses_a_truncated_listing() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/customers"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"customers":[{"id":7,"fullname":"Jane Doe"}],"meta":{"total_pages":80}}"#),
+            )
+            .expect(u64::from(MAX_PAGES))
+            .mount(&server)
+            .await;
+
+        let rs_client = RepairShoprClient::new(&server.uri(), "test-key");
+        let error = handle_export(&Request::default(), ExportKind::Customers, &rs_client, &offline_s3_client())
+            .await
+            .expect_err("partial export");
+        assert_eq!(error.status, 413);
+        assert_eq!(error.code, "EXPORT_TOO_LARGE");
+    }
+
+    #[test]
+    fn test_customer_export_columns() {
+        let customers = vec![
+            Customer {
+                id: 7,
+                firstname: Some("Jane".to_string()),
+                lastname: Some("Doe".to_string()),
+                email: Some("jane@example.com".to_string()),
+                get_sms: Some(true),
+                created_at: Some("2026-03-02T09:00:00Z".to_string()),
+                ..Default::default()
+            },
+            Customer {
+                id: 8,
+                fullname: Some("Acme Repairs".to_string()),
+                created_at: Some("2025-12-01T09:00:00Z".to_string()),
+                ..Default::default()
+            },
+        ];
+        let request = export_request(ExportKind::Customers, &[("columns", "id,name,email,sms_opt_in")]).expect("request");
+        let table = customer_table(&customers, &request);
+        assert_eq!(
+            table.rows,
+            vec![vec!["7", "Jane Doe", "jane@example.com", "yes"], vec!["8", "Acme Repairs", "", ""]]
+        );
+
+        let request = export_request(ExportKind::Customers, &[("columns", "id"), ("from", "2026-01-01")]).expect("request");
+        assert_eq!(customer_table(&customers, &request).rows, vec![vec!["7"]]);
+    }
+
+    fn receipt_fixture() -> (Ticket, Customer, ShopDetails) {
+        let mut ticket = Ticket {
+            id: 42,
+            number: 1001,
+            subject: "Cracked screen".to_string(),
+            status: "New".to_string(),
+            customer_id: Some(7),
+            created_at: Some("2026-03-02T21:30:00-05:00".to_string()),
+            ..Default::default()
+        };
+        ticket.properties.model =
+            Some(r#"vT{"device":"Phone","itemsLeft":["Charger","Case"],"estimatedTime":"2 days"}"#.to_string());
+        ticket.properties.imei_or_serial = Some("356938035643809".to_string());
+        ticket.properties.password = Some("1234".to_string());
+        let customer = Customer {
+            id: 7,
+            firstname: Some("Jane".to_string()),
+            lastname: Some("Doe".to_string()),
+            mobile: Some("(555) 201-3344".to_string()),
+            email: Some("jane@example.com".to_string()),
+            ..Default::default()
+        };
+        let shop = ShopDetails {
+            name: "Cacell".to_string(),
+            address: Some("12 Main St|Springfield, IL 62701".to_string()),
+            phone: Some("(555) 010-2000".to_string()),
+            terms: "Devices not collected within 90 days may be recycled.".to_string(),
+        };
+        (ticket, customer, shop)
+    }
+
+    #[test]
+    fn test_receipt_layout_snapshot() {
+        let (ticket, customer, shop) = receipt_fixture();
+        let page = receipt_layout(&ticket, Some(&customer), &shop);
+        assert_eq!((page.width, page.height), (612.0, 792.0));
+        assert_eq!(
+            page.outline(),
+            vec![
+                "qr 468,744 96 1001",
+                "text 468,634 11 bold Ticket #1001",
+                "text 48,728 20 bold Cacell",
+                "text 48,707 10 12 Main St",
+                "text 48,694 10 Springfield, IL 62701",
+                "text 48,681 10 (555) 010-2000",
+                "text 48,651 16 bold Intake Receipt",
+                "line 48,606 564,606",
+                "text 48,580 12 bold Customer",
+                "text 48,561 10 bold Name",
+                "text 150,561 10 Jane Doe",
+                "text 48,546 10 bold Phone",
+                "text 150,546 10 (555) 201-3344",
+                "text 48,531 10 bold Email",
+                "text 150,531 10 jane@example.com",
+                "text 48,505 12 bold Device",
+                "text 48,486 10 bold Device",
+                "text 150,486 10 Phone",
+                "text 48,471 10 bold Problem",
+                "text 150,471 10 Cracked screen",
+                "text 48,456 10 bold IMEI / Serial",
+                "text 150,456 10 356938035643809",
+                "text 48,441 10 bold Estimated time",
+                "text 150,441 10 2 days",
+                "text 48,426 10 bold Received",
+                "text 150,426 10 Mar 2, 2026",
+                "text 48,400 12 bold Items left with the device",
+                "text 48,381 10 - Charger",
+                "text 48,366 10 - Case",
+                "text 48,340 12 bold Terms",
+                "text 48,324 9 Devices not collected within 90 days may be recycled.",
+                "line 48,276 328,276",
+                "text 48,264 8 Customer signature",
+                "line 368,276 564,276",
+                "text 368,264 8 Date",
+            ]
+        );
+        // The device passcode never goes on paper
+        assert!(!page.outline().iter().any(|line| line.contains("1234")));
+
+        // Without a customer record the ticket's own name is used and contact rows are dropped
+        let mut walk_in = ticket.clone();
+        walk_in.customer_business_then_name = Some("Acme Repairs".to_string());
+        walk_in.properties.model = None;
+        let outline = receipt_layout(&walk_in, None, &shop).outline();
+        assert!(outline.contains(&"text 150,561 10 Acme Repairs".to_string()));
+        assert!(outline.contains(&"text 150,516 10 Not recorded".to_string()));
+        assert!(!outline.iter().any(|line| line.contains("bold Phone")));
+        assert!(outline.iter().any(|line| line.ends_with(" 10 None")));
+    }
+
+    #[test]
+    fn test_receipt_layout_overflow_snapshot() {
+        let (mut ticket, customer, mut shop) = receipt_fixture();
+        let items: Vec<String> = (1..=40).map(|i| format!("Item {}", i)).collect();
+        ticket.properties.model = Some(format!(
+            r#"vT{{"device":"Phone","itemsLeft":{},"estimatedTime":"2 days"}}"#,
+            json!(items)
+        ));
+        shop.terms = "Devices not collected within 90 days may be recycled. ".repeat(12);
+        shop.name = "Cacell Phone, Tablet and Laptop Repair Center".to_string();
+        shop.address = Some(format!("{}|Springfield, IL 62701", "Unit 4, Riverside Shopping Plaza, 1200 North Main Street, Building C, Second Floor, Rear Entrance"));
+
+        let outline = receipt_layout(&ticket, Some(&customer), &shop).outline();
+        // The shop header stops short of the QR code on the right
+        let header_width = 612.0 - 48.0 - 96.0 - 48.0 - 8.0;
+        let name = outline[2].strip_prefix("text 48,728 20 bold ").expect("shop name");
+        assert!(name.ends_with("...") && text_width(name, 20.0, true) <= header_width, "{}", name);
+        let street = outline[3].strip_prefix("text 48,707 10 ").expect("street");
+        assert!(street.ends_with("...") && text_width(street, 10.0, false) <= header_width, "{}", street);
+        assert_eq!(outline[4], "text 48,694 10 Springfield, IL 62701");
+
+        let start = outline
+            .iter()
+            .position(|line| line == "text 48,381 10 - Item 1")
+            .expect("first item");
+        assert_eq!(outline[start + 14], "text 48,171 10 - Item 15");
+        assert_eq!(
+            outline[start + 15..].to_vec(),
+            vec![
+                "text 48,156 10 ... and 25 more",
+                "text 48,130 12 bold Terms",
+                concat!(
+                    "text 48,114 9 Devices not collected within 90 days may be recycled. ",
+                    "Devices not collected within 90 days may be recycled. Devices not ..."
+                ),
+                "line 48,66 328,66",
+                "text 48,54 8 Customer signature",
+                "line 368,66 564,66",
+                "text 368,54 8 Date",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_label_layout_snapshot() {
+        let (ticket, customer, shop) = receipt_fixture();
+        let page = label_layout(&ticket, Some(&customer), &shop);
+        assert_eq!((page.width, page.height), (162.0, 90.0));
+        assert_eq!(
+            page.outline(),
+            vec![
+                "qr 6,84 78 1001",
+                "text 90,68 14 bold #1001",
+                "text 90,54 8 Jane Doe",
+                "text 90,43 8 Phone",
+                "text 90,32 7 Mar 2, 2026",
+                "text 90,8 6 Cacell",
+            ]
+        );
+
+        let mut long = customer.clone();
+        long.firstname = Some("Bartholomew-Alexander".to_string());
+        let outline = label_layout(&ticket, Some(&long), &shop).outline();
+        assert_eq!(outline[2], "text 90,54 8 Bartholomew-Al...");
+    }
+
+    #[test]
+    fn test_pdf_document_structure() {
+        let (ticket, customer, shop) = receipt_fixture();
+        let pdf = receipt_layout(&ticket, Some(&customer), &shop)
+            .to_pdf("Ticket #1001 receipt")
+            .expect("pdf");
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.starts_with("%PDF-1.7"));
+        assert!(text.trim_end().ends_with("%%EOF"));
+        assert!(text.contains("/Type /Page\n"));
+        assert!(text.contains("/MediaBox [0 0 612 792]"));
+        assert!(text.contains("/BaseFont /Helvetica-Bold"));
+        assert!(text.contains("/Encoding /WinAnsiEncoding"));
+        assert!(text.contains("/Title (Ticket #1001 receipt)"));
+        assert!(text.contains("(Intake Receipt) Tj"));
+        assert!(text.contains("(Jane Doe) Tj"));
+
+        let label = label_layout(&ticket, Some(&customer), &shop).to_pdf("Ticket #1001 label").expect("pdf");
+        assert!(String::from_utf8_lossy(&label).contains("/MediaBox [0 0 162 90]"));
+        // Same input, same bytes
+        assert_eq!(label, label_layout(&ticket, Some(&customer), &shop).to_pdf("Ticket #1001 label").expect("pdf"));
+    }
+
+    #[test]
+    fn test_pdf_text_fitting() {
+        assert_eq!(text_width("Hi", 10.0, false), 9.44);
+        assert!(text_width("Hi", 10.0, true) > text_width("Hi", 10.0, false));
+        assert_eq!(wrap("one two three", 10.0, false, 40.0), vec!["one two", "three"]);
+        assert_eq!(wrap("first\nsecond", 10.0, false, 500.0), vec!["first", "second"]);
+        assert_eq!(truncate("short", 10.0, false, 100.0), "short");
+        assert!(text_width(&truncate("a much longer line of text", 10.0, false, 60.0), 10.0, false) <= 60.0);
+
+        let layout = |query: &str| {
+            let params = std::collections::HashMap::from([("layout".to_string(), query.to_string())]);
+            PrintLayout::from_event(&Request::default().with_query_string_parameters(params))
+        };
+        assert_eq!(PrintLayout::from_event(&Request::default()).expect("default"), PrintLayout::Receipt);
+        assert_eq!(layout("label").expect("label"), PrintLayout::Label);
+        assert_eq!(layout("poster").expect_err("unknown").status, 400);
+    }
+
     #[test]
     fn test_generate_temp_password() {
         let password = generate_temp_password();
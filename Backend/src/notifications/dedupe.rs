@@ -0,0 +1,182 @@
+//! Which customer notifications already went out, so each goes out once per window
+//!
+//! A status change saved through the proxy and the RepairShopr webhook for the same change
+//! both publish an event, often on different instances. The memory therefore lives in
+//! STATE_BUCKET (`notifications/sent/<channel>/<hex key>`, claimed with a conditional write)
+//! when configured, and in process memory otherwise.
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use chrono::{DateTime, Utc};
+
+use crate::cache::TtlCache;
+use crate::config::state_bucket;
+
+/// Remembers sent notifications for a window
+#[async_trait]
+pub trait DedupeStore: Send + Sync {
+    /// Claim `key`, returning false if it was already claimed within the window
+    async fn claim(&self, key: &str) -> Result<bool, String>;
+    /// Forget a claim whose send failed, so the next event tries again
+    async fn release(&self, key: &str) -> Result<(), String>;
+}
+
+/// Claims kept in process memory
+#[derive(Debug, Clone)]
+pub struct InMemoryDedupeStore {
+    seen: Arc<TtlCache<String, ()>>,
+}
+
+impl InMemoryDedupeStore {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            seen: Arc::new(TtlCache::new(window)),
+        }
+    }
+
+    /// Store for `channel` shared by every request this instance handles
+    pub fn shared(channel: &'static str, window: Duration) -> Self {
+        static SHARED: LazyLock<Mutex<HashMap<&'static str, InMemoryDedupeStore>>> = LazyLock::new(Mutex::default);
+        SHARED
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(channel)
+            .or_insert_with(|| Self::new(window))
+            .clone()
+    }
+}
+
+#[async_trait]
+impl DedupeStore for InMemoryDedupeStore {
+    async fn claim(&self, key: &str) -> Result<bool, String> {
+        let key = key.to_string();
+        if self.seen.get(&key).is_some() {
+            return Ok(false);
+        }
+        self.seen.insert(key, ());
+        Ok(true)
+    }
+
+    async fn release(&self, key: &str) -> Result<(), String> {
+        self.seen.remove(&key.to_string());
+        Ok(())
+    }
+}
+
+/// Claims stored as S3 objects holding the time they were made
+#[derive(Debug, Clone)]
+pub struct S3DedupeStore {
+    client: S3Client,
+    bucket: String,
+    channel: &'static str,
+    window: Duration,
+}
+
+fn is_precondition_failure(code: Option<&str>) -> bool {
+    matches!(code, Some("PreconditionFailed" | "ConditionalRequestConflict"))
+}
+
+impl S3DedupeStore {
+    pub fn new(client: S3Client, bucket: String, channel: &'static str, window: Duration) -> Self {
+        Self {
+            client,
+            bucket,
+            channel,
+            window,
+        }
+    }
+
+    /// Keys may hold URLs, so objects are named by their hex form
+    fn object_key(&self, key: &str) -> String {
+        format!("notifications/sent/{}/{}", self.channel, hex::encode(key))
+    }
+
+    /// Write the claim if the object is absent (`e_tag` None) or still the version read
+    async fn put_claim(&self, object_key: &str, e_tag: Option<String>) -> Result<bool, String> {
+        let request = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(object_key)
+            .body(ByteStream::from(Utc::now().to_rfc3339().into_bytes()));
+        let request = match e_tag {
+            Some(e_tag) => request.if_match(e_tag),
+            None => request.if_none_match("*"),
+        };
+        match request.send().await {
+            Ok(_) => Ok(true),
+            Err(e) if is_precondition_failure(e.code()) => Ok(false),
+            Err(e) => Err(format!("Failed to claim {}: {:?}", object_key, e)),
+        }
+    }
+
+    /// When the existing claim was made, with its ETag; `None` if it is gone
+    async fn existing_claim(&self, object_key: &str) -> Result<Option<(Option<DateTime<Utc>>, Option<String>)>, String> {
+        let object = match self.client.get_object().bucket(&self.bucket).key(object_key).send().await {
+            Ok(object) => object,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+            Err(e) => return Err(format!("Failed to read {}: {:?}", object_key, e)),
+        };
+        let e_tag = object.e_tag.clone();
+        let bytes = object
+            .body
+            .collect()
+            .await
+            .map_err(|e| format!("Failed to read {}: {:?}", object_key, e))?
+            .into_bytes();
+        let claimed_at = std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|text| DateTime::parse_from_rfc3339(text.trim()).ok())
+            .map(|time| time.with_timezone(&Utc));
+        Ok(Some((claimed_at, e_tag)))
+    }
+}
+
+#[async_trait]
+impl DedupeStore for S3DedupeStore {
+    async fn claim(&self, key: &str) -> Result<bool, String> {
+        let object_key = self.object_key(key);
+        if self.put_claim(&object_key, None).await? {
+            return Ok(true);
+        }
+        let Some((claimed_at, e_tag)) = self.existing_claim(&object_key).await? else {
+            // Released since the first attempt
+            return self.put_claim(&object_key, None).await;
+        };
+        let window = chrono::Duration::from_std(self.window).unwrap_or(chrono::Duration::MAX);
+        if claimed_at.is_some_and(|at| Utc::now() - at < window) {
+            return Ok(false);
+        }
+        // An expired claim is taken over only if nobody else replaced it first
+        match e_tag {
+            Some(e_tag) => self.put_claim(&object_key, Some(e_tag)).await,
+            None => Ok(false),
+        }
+    }
+
+    async fn release(&self, key: &str) -> Result<(), String> {
+        let object_key = self.object_key(key);
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(&object_key)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to release {}: {:?}", object_key, e))
+    }
+}
+
+/// Pick the dedupe store for `channel` in this deployment
+pub fn dedupe_store_from_env(s3_client: &S3Client, channel: &'static str, window: Duration) -> Arc<dyn DedupeStore> {
+    match state_bucket() {
+        Some(bucket) => Arc::new(S3DedupeStore::new(s3_client.clone(), bucket, channel, window)),
+        _ => Arc::new(InMemoryDedupeStore::shared(channel, window)),
+    }
+}
@@ -0,0 +1,532 @@
+//! Email notifications for new tickets, estimates and pickups
+//!
+//! Customers with an email address (and without RepairShopr's "no email" flag) get a
+//! multipart message, HTML plus plain text, with the ticket summary attached as a text
+//! file. Messages are deduplicated per ticket and kind through [`super::dedupe`], like the
+//! SMS notifier.
+//!
+//! Configuration:
+//! - EMAIL_FROM: sender address, e.g. `Cacell <repairs@example.com>` (required to send)
+//! - EMAIL_TRANSPORT=ses to send through Amazon SES, otherwise SMTP_HOST (with optional
+//!   SMTP_PORT, SMTP_USERNAME and SMTP_PASSWORD; credentials switch on STARTTLS). Without
+//!   either, messages are only logged
+//! - EMAIL_TEMPLATES: JSON object of kind (`ticket_created`, `estimate_ready`,
+//!   `ready_for_pickup`) to `{subject, text, html}`, overriding the defaults per kind
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_sesv2::primitives::Blob;
+use aws_sdk_sesv2::types::{Destination, EmailContent, RawMessage};
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Deserialize;
+
+use super::dedupe::{dedupe_store_from_env, DedupeStore, InMemoryDedupeStore};
+use super::{device_label, fill, first_name, shop_name};
+use crate::approvals::format_amount;
+use crate::events::{EventHandler, TicketEvent};
+use crate::handlers::public_status::READY_DISPLAY_STATUS;
+use crate::repairshopr::{Customer, RepairShoprClient, Ticket};
+use crate::status::status_map;
+
+/// How long a (ticket, kind) email is remembered for deduplication
+const DEDUPE_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// An email ready to send
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailMessage {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+    pub attachments: Vec<EmailAttachment>,
+}
+
+/// A file attached to an email
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailAttachment {
+    pub filename: String,
+    pub content_type: String,
+    pub content: Vec<u8>,
+}
+
+/// Build the MIME message: text and HTML alternatives, followed by the attachments
+pub fn to_mime(from: &str, message: &EmailMessage) -> Result<Message, String> {
+    let from: Mailbox = from.parse().map_err(|e| format!("Invalid sender '{}': {}", from, e))?;
+    let to: Mailbox = message
+        .to
+        .parse()
+        .map_err(|e| format!("Invalid recipient '{}': {}", message.to, e))?;
+
+    let mut body = MultiPart::mixed().multipart(MultiPart::alternative_plain_html(
+        message.text.clone(),
+        message.html.clone(),
+    ));
+    for attachment in &message.attachments {
+        let content_type = ContentType::parse(&attachment.content_type)
+            .map_err(|e| format!("Invalid content type '{}': {}", attachment.content_type, e))?;
+        body = body.singlepart(Attachment::new(attachment.filename.clone()).body(attachment.content.clone(), content_type));
+    }
+
+    Message::builder()
+        .from(from)
+        .to(to)
+        .subject(message.subject.clone())
+        .multipart(body)
+        .map_err(|e| format!("Failed to build email: {}", e))
+}
+
+/// Sends an email
+#[async_trait]
+pub trait EmailSender: Send + Sync {
+    /// Send `message`, returning the provider's message id or response
+    async fn send(&self, message: &EmailMessage) -> Result<String, String>;
+}
+
+/// Sender for any SMTP server (a local catcher such as MailHog in development)
+pub struct SmtpSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: String,
+}
+
+impl SmtpSender {
+    /// Plain SMTP without TLS, for local servers
+    pub fn unencrypted(host: &str, port: u16, from: &str) -> Self {
+        Self {
+            transport: AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host).port(port).build(),
+            from: from.to_string(),
+        }
+    }
+
+    /// SMTP with STARTTLS and a username/password login
+    pub fn authenticated(host: &str, port: u16, username: &str, password: &str, from: &str) -> Result<Self, String> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+            .map_err(|e| format!("Invalid SMTP host '{}': {}", host, e))?
+            .port(port)
+            .credentials(Credentials::new(username.to_string(), password.to_string()))
+            .build();
+        Ok(Self {
+            transport,
+            from: from.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl EmailSender for SmtpSender {
+    async fn send(&self, message: &EmailMessage) -> Result<String, String> {
+        let mime = to_mime(&self.from, message)?;
+        let response = self
+            .transport
+            .send(mime)
+            .await
+            .map_err(|e| format!("SMTP send failed: {}", e))?;
+        Ok(response.message().collect::<Vec<_>>().join(" "))
+    }
+}
+
+/// Sender for Amazon SES, using the Lambda's AWS credentials
+pub struct SesSender {
+    from: String,
+}
+
+impl SesSender {
+    pub fn new(from: &str) -> Self {
+        Self { from: from.to_string() }
+    }
+}
+
+#[async_trait]
+impl EmailSender for SesSender {
+    async fn send(&self, message: &EmailMessage) -> Result<String, String> {
+        let raw = RawMessage::builder()
+            .data(Blob::new(to_mime(&self.from, message)?.formatted()))
+            .build()
+            .map_err(|e| format!("Failed to build SES message: {}", e))?;
+        let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+        let output = aws_sdk_sesv2::Client::new(&config)
+            .send_email()
+            .from_email_address(&self.from)
+            .destination(Destination::builder().to_addresses(&message.to).build())
+            .content(EmailContent::builder().raw(raw).build())
+            .send()
+            .await
+            .map_err(|e| format!("SES send failed: {:?}", e))?;
+        Ok(output.message_id().unwrap_or_default().to_string())
+    }
+}
+
+/// Email address reduced to its first letter and domain for logs, e.g. `j***@example.com`
+pub fn redact_email(email: &str) -> String {
+    let email = email.trim();
+    match email.rsplit_once('@') {
+        Some((local, domain)) => format!("{}***@{}", local.chars().next().unwrap_or('*'), domain),
+        None => "***".to_string(),
+    }
+}
+
+/// Sender that records messages instead of sending them (local runs and tests)
+#[derive(Debug, Default)]
+pub struct MockEmailSender {
+    sent: Mutex<Vec<EmailMessage>>,
+}
+
+impl MockEmailSender {
+    /// Messages "sent" so far
+    #[cfg(test)]
+    pub fn sent(&self) -> Vec<EmailMessage> {
+        self.sent.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+#[async_trait]
+impl EmailSender for MockEmailSender {
+    async fn send(&self, message: &EmailMessage) -> Result<String, String> {
+        eprintln!("[email] to {}: {}", redact_email(&message.to), message.subject);
+        let mut sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        sent.push(message.clone());
+        Ok(format!("mock-{}", sent.len()))
+    }
+}
+
+/// Pick the email sender for this deployment
+pub fn email_sender_from_env() -> Arc<dyn EmailSender> {
+    let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+    let Some(from) = var("EMAIL_FROM") else {
+        return Arc::new(MockEmailSender::default());
+    };
+
+    if var("EMAIL_TRANSPORT").is_some_and(|t| t.eq_ignore_ascii_case("ses")) {
+        return Arc::new(SesSender::new(&from));
+    }
+    let Some(host) = var("SMTP_HOST") else {
+        return Arc::new(MockEmailSender::default());
+    };
+    match (var("SMTP_USERNAME"), var("SMTP_PASSWORD")) {
+        (Some(username), Some(password)) => {
+            let port = var("SMTP_PORT").and_then(|p| p.parse().ok()).unwrap_or(587);
+            match SmtpSender::authenticated(&host, port, &username, &password, &from) {
+                Ok(sender) => Arc::new(sender),
+                Err(e) => {
+                    eprintln!("Warning: {}; emails will only be logged", e);
+                    Arc::new(MockEmailSender::default())
+                }
+            }
+        }
+        _ => {
+            let port = var("SMTP_PORT").and_then(|p| p.parse().ok()).unwrap_or(25);
+            Arc::new(SmtpSender::unencrypted(&host, port, &from))
+        }
+    }
+}
+
+/// The emails customers can receive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EmailKind {
+    TicketCreated,
+    EstimateReady,
+    ReadyForPickup,
+}
+
+impl EmailKind {
+    /// Name used in EMAIL_TEMPLATES
+    pub fn key(self) -> &'static str {
+        match self {
+            EmailKind::TicketCreated => "ticket_created",
+            EmailKind::EstimateReady => "estimate_ready",
+            EmailKind::ReadyForPickup => "ready_for_pickup",
+        }
+    }
+}
+
+/// Subject and bodies for one kind of email; values are HTML-escaped in `html`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EmailTemplate {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+impl EmailTemplate {
+    fn new(subject: &str, text: &str, html: &str) -> Self {
+        Self {
+            subject: subject.to_string(),
+            text: text.to_string(),
+            html: html.to_string(),
+        }
+    }
+}
+
+/// Built-in templates for every kind
+pub fn default_email_templates() -> HashMap<EmailKind, EmailTemplate> {
+    HashMap::from([
+        (
+            EmailKind::TicketCreated,
+            EmailTemplate::new(
+                "We've got your {device} (ticket #{number})",
+                "Hi {first_name},\n\nThanks for dropping off your {device} at {shop_name}. Your ticket number is #{number}; we'll let you know as soon as we've had a look.\n\nThe ticket summary is attached.\n\n{shop_name}",
+                "<p>Hi {first_name},</p><p>Thanks for dropping off your {device} at {shop_name}. Your ticket number is <strong>#{number}</strong>; we'll let you know as soon as we've had a look.</p><p>The ticket summary is attached.</p><p>{shop_name}</p>",
+            ),
+        ),
+        (
+            EmailKind::EstimateReady,
+            EmailTemplate::new(
+                "Your estimate for ticket #{number} is ready",
+                "Hi {first_name},\n\nWe've looked at your {device}: {description}, for {amount}.\n\nApprove or decline the estimate here: {url}\n\n{shop_name}",
+                "<p>Hi {first_name},</p><p>We've looked at your {device}: {description}, for <strong>{amount}</strong>.</p><p><a href=\"{url}\">Approve or decline the estimate</a></p><p>{shop_name}</p>",
+            ),
+        ),
+        (
+            EmailKind::ReadyForPickup,
+            EmailTemplate::new(
+                "Your {device} is ready for pickup (ticket #{number})",
+                "Hi {first_name},\n\nGood news: your {device} is ready for pickup at {shop_name}. Please bring your ticket number, #{number}.\n\n{shop_name}",
+                "<p>Hi {first_name},</p><p>Good news: your {device} is ready for pickup at {shop_name}. Please bring your ticket number, <strong>#{number}</strong>.</p><p>{shop_name}</p>",
+            ),
+        ),
+    ])
+}
+
+/// Escape text for inclusion in HTML
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Plain-text summary of a ticket, attached to every email
+pub fn ticket_summary(ticket: &Ticket, customer: &Customer) -> String {
+    let intake = ticket.properties.intake_model().unwrap_or_default();
+    let customer_name = customer
+        .fullname
+        .clone()
+        .or_else(|| ticket.customer_business_then_name.clone())
+        .unwrap_or_default();
+    let items_left = if intake.items_left.is_empty() {
+        "None".to_string()
+    } else {
+        intake.items_left.join(", ")
+    };
+
+    let mut lines = vec![
+        format!("Ticket #{}", ticket.number),
+        format!("Customer: {}", customer_name),
+        format!("Device: {}", intake.device.unwrap_or_else(|| "Not recorded".to_string())),
+        format!("Issue: {}", ticket.subject),
+        format!("Status: {}", status_map().to_display(&ticket.status)),
+        format!("Items left: {}", items_left),
+    ];
+    if let Some(estimated) = intake.estimated_time {
+        lines.push(format!("Estimated time: {}", estimated));
+    }
+    if let Some(created_at) = &ticket.created_at {
+        lines.push(format!("Opened: {}", created_at));
+    }
+    lines.join("\n") + "\n"
+}
+
+/// What happened to an event
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmailOutcome {
+    Sent { to: String, message_id: String },
+    /// The event isn't one customers are emailed about
+    NotApplicable,
+    Duplicate,
+    NoEmail,
+    OptedOut,
+    Failed(String),
+}
+
+/// Emails customers about new tickets, estimates and finished repairs
+pub struct EmailNotifier {
+    rs_client: RepairShoprClient,
+    sender: Arc<dyn EmailSender>,
+    templates: HashMap<EmailKind, EmailTemplate>,
+    shop_name: String,
+    sent: Arc<dyn DedupeStore>,
+}
+
+impl EmailNotifier {
+    /// Notifier with default templates and its own dedupe memory
+    pub fn new(rs_client: RepairShoprClient, sender: Arc<dyn EmailSender>) -> Self {
+        Self {
+            rs_client,
+            sender,
+            templates: default_email_templates(),
+            shop_name: "Cacell".to_string(),
+            sent: Arc::new(InMemoryDedupeStore::new(DEDUPE_WINDOW)),
+        }
+    }
+
+    pub fn with_dedupe(mut self, sent: Arc<dyn DedupeStore>) -> Self {
+        self.sent = sent;
+        self
+    }
+
+    /// Notifier configured from the environment, sharing the deployment's dedupe memory
+    pub fn from_env(rs_client: RepairShoprClient, s3_client: &S3Client) -> Self {
+        let mut notifier = Self::new(rs_client, email_sender_from_env())
+            .with_dedupe(dedupe_store_from_env(s3_client, "email", DEDUPE_WINDOW));
+        if let Ok(json) = std::env::var("EMAIL_TEMPLATES") {
+            match serde_json::from_str::<HashMap<String, EmailTemplate>>(&json) {
+                Ok(overrides) => {
+                    for kind in [EmailKind::TicketCreated, EmailKind::EstimateReady, EmailKind::ReadyForPickup] {
+                        if let Some(template) = overrides.get(kind.key()) {
+                            notifier.templates.insert(kind, template.clone());
+                        }
+                    }
+                }
+                Err(e) => eprintln!("Warning: Invalid EMAIL_TEMPLATES: {}; using defaults", e),
+            }
+        }
+        notifier.shop_name = shop_name();
+        notifier
+    }
+
+    /// Which email an event calls for, with its extra template values and dedupe key
+    fn classify(event: &TicketEvent) -> Option<(EmailKind, HashMap<&'static str, String>, String)> {
+        match event {
+            TicketEvent::TicketCreated { .. } => {
+                Some((EmailKind::TicketCreated, HashMap::new(), EmailKind::TicketCreated.key().to_string()))
+            }
+            TicketEvent::EstimateReady {
+                amount_cents,
+                description,
+                url,
+                ..
+            } => {
+                let values = HashMap::from([
+                    ("amount", format_amount(*amount_cents)),
+                    ("description", description.clone()),
+                    ("url", url.clone()),
+                ]);
+                Some((EmailKind::EstimateReady, values, format!("{}:{}", EmailKind::EstimateReady.key(), url)))
+            }
+            TicketEvent::StatusChanged { ticket, previous_status } => {
+                let map = status_map();
+                let ready = map.to_display(&ticket.status) == READY_DISPLAY_STATUS;
+                let was_ready = previous_status
+                    .as_deref()
+                    .is_some_and(|p| map.to_display(p) == READY_DISPLAY_STATUS);
+                (ready && !was_ready).then(|| {
+                    (
+                        EmailKind::ReadyForPickup,
+                        HashMap::new(),
+                        EmailKind::ReadyForPickup.key().to_string(),
+                    )
+                })
+            }
+            TicketEvent::Changed { .. } => None,
+        }
+    }
+
+    /// Render the email of `kind` for `ticket`
+    pub fn render(
+        &self,
+        kind: EmailKind,
+        ticket: &Ticket,
+        customer: &Customer,
+        to: &str,
+        extra: &HashMap<&str, String>,
+    ) -> Option<EmailMessage> {
+        let template = self.templates.get(&kind)?;
+        let mut values = HashMap::from([
+            ("first_name", first_name(customer)),
+            ("number", ticket.number.to_string()),
+            ("status", status_map().to_display(&ticket.status)),
+            ("device", device_label(ticket)),
+            ("shop_name", self.shop_name.clone()),
+        ]);
+        values.extend(extra.iter().map(|(k, v)| (*k, v.clone())));
+        let escaped: HashMap<&str, String> = values.iter().map(|(k, v)| (*k, escape_html(v))).collect();
+
+        Some(EmailMessage {
+            to: to.to_string(),
+            subject: fill(&template.subject, &values),
+            text: fill(&template.text, &values),
+            html: fill(&template.html, &escaped),
+            attachments: vec![EmailAttachment {
+                filename: format!("ticket-{}-summary.txt", ticket.number),
+                content_type: "text/plain; charset=utf-8".to_string(),
+                content: ticket_summary(ticket, customer).into_bytes(),
+            }],
+        })
+    }
+
+    /// Decide whether and what to email the customer about `event`
+    pub async fn notify(&self, event: &TicketEvent) -> EmailOutcome {
+        let Some((kind, extra, dedupe)) = Self::classify(event) else {
+            return EmailOutcome::NotApplicable;
+        };
+        let Some(ticket) = event.ticket() else {
+            return EmailOutcome::NotApplicable;
+        };
+        let Some(customer_id) = ticket.customer_id else {
+            return EmailOutcome::NoEmail;
+        };
+        let customer = match self.rs_client.get_customer(customer_id).await {
+            Ok(customer) => customer,
+            Err(e) => return EmailOutcome::Failed(e.to_string()),
+        };
+        let Some(to) = customer.email.as_deref().map(str::trim).filter(|e| e.contains('@')) else {
+            return EmailOutcome::NoEmail;
+        };
+        if customer.no_email == Some(true) {
+            return EmailOutcome::OptedOut;
+        }
+
+        let Some(message) = self.render(kind, ticket, &customer, to, &extra) else {
+            return EmailOutcome::NotApplicable;
+        };
+        // Claim before sending so a concurrent save, here or on another instance, can't email twice
+        let key = format!("{}:{}", ticket.id, dedupe);
+        match self.sent.claim(&key).await {
+            Ok(true) => {}
+            Ok(false) => return EmailOutcome::Duplicate,
+            Err(e) => return EmailOutcome::Failed(e),
+        }
+        match self.sender.send(&message).await {
+            Ok(message_id) => EmailOutcome::Sent {
+                to: to.to_string(),
+                message_id,
+            },
+            Err(e) => {
+                // Let the next event of this kind try again
+                if let Err(e) = self.sent.release(&key).await {
+                    eprintln!("Failed to forget email {}: {}", key, e);
+                }
+                EmailOutcome::Failed(e)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for EmailNotifier {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    async fn handle(&self, event: &TicketEvent) -> Result<(), String> {
+        match self.notify(event).await {
+            EmailOutcome::Failed(e) => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
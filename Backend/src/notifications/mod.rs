@@ -0,0 +1,69 @@
+//! Customer notifications triggered by ticket events
+//!
+//! Each channel is an [`EventHandler`](crate::events::EventHandler) on the same
+//! [`EventBus`](crate::events::EventBus): texts for status changes ([`sms`]) and emails for new tickets, estimates
+//! and pickups ([`email`]).
+//!
+//! SHOP_NAME (default "Cacell") is used in every channel's templates as `{shop_name}`.
+
+pub mod dedupe;
+pub mod email;
+pub mod sms;
+
+use std::collections::HashMap;
+
+use crate::repairshopr::{Customer, Ticket};
+
+/// Shop name shown to customers
+pub fn shop_name() -> String {
+    std::env::var("SHOP_NAME")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "Cacell".to_string())
+}
+
+/// Fill `{name}` placeholders in one pass; unknown placeholders are left as they are, and
+/// values are inserted as they are even if they contain braces
+pub fn fill(template: &str, values: &HashMap<&str, String>) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('}').and_then(|end| Some((end, values.get(&after[..end])?))) {
+            Some((end, value)) => {
+                text.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = after;
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+/// How to greet the customer
+pub fn first_name(customer: &Customer) -> String {
+    customer
+        .firstname
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or("there")
+        .to_string()
+}
+
+/// Lower-case device type from the intake details, e.g. "phone"
+pub fn device_label(ticket: &Ticket) -> String {
+    ticket
+        .properties
+        .intake_model()
+        .and_then(|m| m.device)
+        .filter(|d| d != "Other")
+        .map(|d| d.to_lowercase())
+        .unwrap_or_else(|| "device".to_string())
+}
@@ -0,0 +1,734 @@
+//! SMS notifications on status changes
+//!
+//! When a ticket reaches a display status that has a template, the customer gets a
+//! text. Messages are skipped for customers who opted out (in RepairShopr or in our
+//! own opt-out store), deduplicated per ticket and status so a ticket saved twice
+//! doesn't text twice (across instances when STATE_BUCKET is set, see [`super::dedupe`]), and held during quiet hours until the window ends. Held texts are
+//! kept in STATE_BUCKET (`sms-deferred/`) so any instance can send them later; a flush
+//! claims each one before sending it and puts back any it fails to send.
+//!
+//! Configuration:
+//! - TWILIO_ACCOUNT_SID, TWILIO_AUTH_TOKEN, TWILIO_FROM_NUMBER: send through Twilio (or
+//!   any Twilio-compatible API at TWILIO_API_URL); without them messages are only logged
+//! - SMS_TEMPLATES: JSON object of display status to template, replacing the defaults
+//! - SMS_QUIET_HOURS (e.g. `21:00-08:00`) in the shop's SHOP_UTC_OFFSET (e.g. `-05:00`)
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use chrono::{DateTime, FixedOffset, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::dedupe::{dedupe_store_from_env, DedupeStore, InMemoryDedupeStore};
+use super::{device_label, fill, first_name, shop_name};
+use crate::config::state_bucket;
+use crate::contact::{default_phone_region, normalize_phone};
+use crate::events::{EventHandler, TicketEvent};
+use crate::repairshopr::{Customer, RepairShoprClient, Ticket};
+use crate::status::status_map;
+
+/// Default texts per display status; statuses without a template don't text
+const DEFAULT_TEMPLATES: [(&str, &str); 3] = [
+    (
+        "Ready",
+        "Hi {first_name}, your {device} (ticket #{number}) is ready for pickup at {shop_name}. Reply STOP to opt out.",
+    ),
+    (
+        "Approval Needed",
+        "Hi {first_name}, we have an estimate for your {device} (ticket #{number}). Please call {shop_name} or use the link we send you.",
+    ),
+    (
+        "Waiting for Parts",
+        "Hi {first_name}, we're waiting on parts for your {device} (ticket #{number}). We'll text you when it's ready.",
+    ),
+];
+
+/// How long a (ticket, status) text is remembered for deduplication
+const DEDUPE_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Twilio error code for a recipient who replied STOP
+const TWILIO_UNSUBSCRIBED: &str = "21610";
+
+/// Sends a text message
+#[async_trait]
+pub trait SmsSender: Send + Sync {
+    /// Send `body` to `to` (E.164), returning the provider's message id
+    async fn send(&self, to: &str, body: &str) -> Result<String, SmsError>;
+}
+
+/// Why a text wasn't sent
+#[derive(Debug, Clone, PartialEq)]
+pub enum SmsError {
+    /// The recipient has unsubscribed at the carrier/provider level
+    Unsubscribed,
+    Failed(String),
+}
+
+/// Twilio (or Twilio-compatible) Messages API sender
+#[derive(Debug, Clone)]
+pub struct TwilioSender {
+    client: reqwest::Client,
+    base_url: String,
+    account_sid: String,
+    auth_token: String,
+    from: String,
+}
+
+impl TwilioSender {
+    pub fn new(base_url: &str, account_sid: &str, auth_token: &str, from: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            account_sid: account_sid.to_string(),
+            auth_token: auth_token.to_string(),
+            from: from.to_string(),
+        }
+    }
+
+    /// Build a sender from the TWILIO_* variables, if they are all set
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let base_url = var("TWILIO_API_URL").unwrap_or_else(|| "https://api.twilio.com/2010-04-01".to_string());
+        Some(Self::new(
+            &base_url,
+            &var("TWILIO_ACCOUNT_SID")?,
+            &var("TWILIO_AUTH_TOKEN")?,
+            &var("TWILIO_FROM_NUMBER")?,
+        ))
+    }
+}
+
+#[derive(Deserialize)]
+struct TwilioMessage {
+    sid: String,
+}
+
+#[async_trait]
+impl SmsSender for TwilioSender {
+    async fn send(&self, to: &str, body: &str) -> Result<String, SmsError> {
+        let url = format!("{}/Accounts/{}/Messages.json", self.base_url, self.account_sid);
+        let response = self
+            .client
+            .post(&url)
+            .basic_auth(&self.account_sid, Some(&self.auth_token))
+            .form(&[("To", to), ("From", self.from.as_str()), ("Body", body)])
+            .send()
+            .await
+            .map_err(|e| SmsError::Failed(format!("Twilio request failed: {}", e)))?;
+
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            if text.contains(TWILIO_UNSUBSCRIBED) {
+                return Err(SmsError::Unsubscribed);
+            }
+            return Err(SmsError::Failed(format!("Twilio returned {}: {}", status.as_u16(), text)));
+        }
+        serde_json::from_str::<TwilioMessage>(&text)
+            .map(|m| m.sid)
+            .map_err(|e| SmsError::Failed(format!("Unexpected Twilio response: {}", e)))
+    }
+}
+
+/// Phone number reduced to its last four digits for logs, e.g. `***3344`
+pub fn redact_phone(phone: &str) -> String {
+    let digits: Vec<char> = phone.chars().filter(char::is_ascii_digit).collect();
+    let last_four: String = digits[digits.len().saturating_sub(4)..].iter().collect();
+    format!("***{}", last_four)
+}
+
+/// Sender that records messages instead of sending them (local runs and tests)
+#[derive(Debug, Default)]
+pub struct MockSmsSender {
+    sent: Mutex<Vec<(String, String)>>,
+}
+
+impl MockSmsSender {
+    /// Messages "sent" so far as (to, body)
+    #[cfg(test)]
+    pub fn sent(&self) -> Vec<(String, String)> {
+        self.sent.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+#[async_trait]
+impl SmsSender for MockSmsSender {
+    async fn send(&self, to: &str, body: &str) -> Result<String, SmsError> {
+        eprintln!("[sms] to {} ({} characters)", redact_phone(to), body.chars().count());
+        let mut sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        sent.push((to.to_string(), body.to_string()));
+        Ok(format!("mock-{}", sent.len()))
+    }
+}
+
+/// Phone numbers that asked not to be texted
+#[async_trait]
+pub trait OptOutStore: Send + Sync {
+    async fn is_opted_out(&self, phone: &str) -> Result<bool, String>;
+    async fn set_opted_out(&self, phone: &str, opted_out: bool) -> Result<(), String>;
+}
+
+/// Opt-outs kept in process memory
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryOptOutStore {
+    phones: Arc<Mutex<Vec<String>>>,
+}
+
+impl InMemoryOptOutStore {
+    /// Store shared by every request this instance handles
+    pub fn shared() -> Self {
+        static SHARED: LazyLock<InMemoryOptOutStore> = LazyLock::new(InMemoryOptOutStore::default);
+        SHARED.clone()
+    }
+}
+
+#[async_trait]
+impl OptOutStore for InMemoryOptOutStore {
+    async fn is_opted_out(&self, phone: &str) -> Result<bool, String> {
+        Ok(self.phones.lock().unwrap_or_else(|e| e.into_inner()).iter().any(|p| p == phone))
+    }
+
+    async fn set_opted_out(&self, phone: &str, opted_out: bool) -> Result<(), String> {
+        let mut phones = self.phones.lock().unwrap_or_else(|e| e.into_inner());
+        phones.retain(|p| p != phone);
+        if opted_out {
+            phones.push(phone.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Opt-outs stored as one S3 object per phone number
+#[derive(Debug, Clone)]
+pub struct S3OptOutStore {
+    client: S3Client,
+    bucket: String,
+}
+
+impl S3OptOutStore {
+    pub fn new(client: S3Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+
+    fn key(phone: &str) -> String {
+        format!("sms-opt-outs/{}", phone)
+    }
+}
+
+#[async_trait]
+impl OptOutStore for S3OptOutStore {
+    async fn is_opted_out(&self, phone: &str) -> Result<bool, String> {
+        match self.client.head_object().bucket(&self.bucket).key(Self::key(phone)).send().await {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(false),
+            Err(e) => Err(format!("Failed to check opt-out for {}: {:?}", phone, e)),
+        }
+    }
+
+    async fn set_opted_out(&self, phone: &str, opted_out: bool) -> Result<(), String> {
+        let result = if opted_out {
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(Self::key(phone))
+                .body(ByteStream::from(Utc::now().to_rfc3339().into_bytes()))
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(|e| format!("{:?}", e))
+        } else {
+            self.client
+                .delete_object()
+                .bucket(&self.bucket)
+                .key(Self::key(phone))
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(|e| format!("{:?}", e))
+        };
+        result.map_err(|e| format!("Failed to update opt-out for {}: {}", phone, e))
+    }
+}
+
+/// Pick the opt-out store for this deployment
+pub fn opt_out_store_from_env(s3_client: &S3Client) -> Arc<dyn OptOutStore> {
+    match state_bucket() {
+        Some(bucket) => Arc::new(S3OptOutStore::new(s3_client.clone(), bucket)),
+        _ => Arc::new(InMemoryOptOutStore::shared()),
+    }
+}
+
+/// Daily window, in the shop's local time, when texts are held back
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuietHours {
+    /// Minutes after local midnight
+    start: u32,
+    end: u32,
+    offset: FixedOffset,
+}
+
+/// Parse `HH:MM` into minutes after midnight
+fn parse_clock(value: &str) -> Option<u32> {
+    let (hours, minutes) = value.trim().split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+/// Parse a UTC offset such as `-05:00` or `+0530`
+pub fn parse_utc_offset(value: &str) -> Option<FixedOffset> {
+    let value = value.trim();
+    let (sign, rest) = match value.chars().next()? {
+        '+' => (1, &value[1..]),
+        '-' => (-1, &value[1..]),
+        _ => (1, value),
+    };
+    let digits: String = rest.chars().filter(|c| *c != ':').collect();
+    if digits.len() != 4 {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+impl QuietHours {
+    /// Parse `HH:MM-HH:MM` (the window may wrap past midnight)
+    pub fn parse(window: &str, offset: FixedOffset) -> Option<Self> {
+        let (start, end) = window.split_once('-')?;
+        let (start, end) = (parse_clock(start)?, parse_clock(end)?);
+        (start != end).then_some(Self { start, end, offset })
+    }
+
+    /// SMS_QUIET_HOURS in SHOP_UTC_OFFSET (UTC when unset)
+    pub fn from_env() -> Option<Self> {
+        let offset = std::env::var("SHOP_UTC_OFFSET")
+            .ok()
+            .and_then(|v| parse_utc_offset(&v))
+            .or_else(|| FixedOffset::east_opt(0))?;
+        Self::parse(&std::env::var("SMS_QUIET_HOURS").ok()?, offset)
+    }
+
+    /// Whether `now` falls inside the window
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let local = now.with_timezone(&self.offset);
+        let minute = local.hour() * 60 + local.minute();
+        if self.start < self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+/// Text held back during quiet hours
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingSms {
+    pub ticket_id: i64,
+    pub to: String,
+    pub body: String,
+}
+
+/// Where texts held for quiet hours wait until the window ends
+#[async_trait]
+pub trait DeferredQueue: Send + Sync {
+    async fn push(&self, sms: &PendingSms) -> Result<(), String>;
+    /// Remove and return everything queued; concurrent drains never return the same text
+    async fn drain(&self) -> Result<Vec<PendingSms>, String>;
+}
+
+/// Queue kept in process memory
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryDeferredQueue {
+    pending: Arc<Mutex<Vec<PendingSms>>>,
+}
+
+impl InMemoryDeferredQueue {
+    /// Queue shared by every request this instance handles
+    pub fn shared() -> Self {
+        static SHARED: LazyLock<InMemoryDeferredQueue> = LazyLock::new(InMemoryDeferredQueue::default);
+        SHARED.clone()
+    }
+}
+
+#[async_trait]
+impl DeferredQueue for InMemoryDeferredQueue {
+    async fn push(&self, sms: &PendingSms) -> Result<(), String> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).push(sms.clone());
+        Ok(())
+    }
+
+    async fn drain(&self) -> Result<Vec<PendingSms>, String> {
+        Ok(std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner())))
+    }
+}
+
+/// Queue stored as one S3 object per held text, so it survives instance recycling and any
+/// instance can flush it
+#[derive(Debug, Clone)]
+pub struct S3DeferredQueue {
+    client: S3Client,
+    bucket: String,
+}
+
+const DEFERRED_PREFIX: &str = "sms-deferred/";
+
+/// Marker a drain creates (only if absent) before taking a held text
+const CLAIMS_PREFIX: &str = "sms-deferred-claims/";
+
+impl S3DeferredQueue {
+    pub fn new(client: S3Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+
+    /// The held text, or `None` if another drain already took it
+    async fn load(&self, key: &str) -> Result<Option<PendingSms>, String> {
+        let object = match self.client.get_object().bucket(&self.bucket).key(key).send().await {
+            Ok(object) => object,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+            Err(e) => return Err(format!("Failed to load held SMS {}: {:?}", key, e)),
+        };
+        let bytes = object
+            .body
+            .collect()
+            .await
+            .map_err(|e| format!("Failed to read held SMS {}: {:?}", key, e))?
+            .into_bytes();
+        serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| format!("Invalid held SMS {}: {}", key, e))
+    }
+
+    /// Create the claim marker for `key`, returning false if another drain holds it
+    async fn claim(&self, claim_key: &str) -> Result<bool, String> {
+        let result = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(claim_key)
+            .if_none_match("*")
+            .body(ByteStream::from(chrono::Utc::now().to_rfc3339().into_bytes()))
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if matches!(e.code(), Some("PreconditionFailed" | "ConditionalRequestConflict")) => Ok(false),
+            Err(e) => Err(format!("Failed to claim held SMS {}: {:?}", claim_key, e)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to delete {}: {:?}", key, e))
+    }
+
+    /// Take one held text: claim it, read it and remove it, then drop the claim. A drain
+    /// that listed the key too either loses the claim or finds the text already gone.
+    async fn take(&self, key: &str) -> Result<Option<PendingSms>, String> {
+        let claim_key = format!("{}{}", CLAIMS_PREFIX, key.strip_prefix(DEFERRED_PREFIX).unwrap_or(key));
+        if !self.claim(&claim_key).await? {
+            return Ok(None);
+        }
+        let taken = match self.load(key).await {
+            Ok(Some(sms)) => self.delete(key).await.map(|()| Some(sms)),
+            other => other,
+        };
+        if let Err(e) = self.delete(&claim_key).await {
+            eprintln!("{}", e);
+        }
+        taken
+    }
+}
+
+#[async_trait]
+impl DeferredQueue for S3DeferredQueue {
+    async fn push(&self, sms: &PendingSms) -> Result<(), String> {
+        use rand::Rng;
+        let key = format!("{}{}-{}.json", DEFERRED_PREFIX, sms.ticket_id, hex::encode(rand::rng().random::<[u8; 8]>()));
+        let body = serde_json::to_vec(sms).map_err(|e| e.to_string())?;
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .content_type("application/json")
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to hold SMS for ticket {}: {:?}", sms.ticket_id, e))
+    }
+
+    async fn drain(&self) -> Result<Vec<PendingSms>, String> {
+        let mut keys = vec![];
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(DEFERRED_PREFIX)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| format!("Failed to list held SMS: {:?}", e))?;
+            keys.extend(page.contents().iter().filter_map(|o| o.key()).map(str::to_string));
+        }
+
+        // A text that can't be taken now stays queued for the next flush
+        let mut drained = vec![];
+        for key in keys {
+            match self.take(&key).await {
+                Ok(Some(sms)) => drained.push(sms),
+                Ok(None) => {}
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        Ok(drained)
+    }
+}
+
+/// Pick the quiet-hours queue for this deployment
+pub fn deferred_queue_from_env(s3_client: &S3Client) -> Arc<dyn DeferredQueue> {
+    match state_bucket() {
+        Some(bucket) => Arc::new(S3DeferredQueue::new(s3_client.clone(), bucket)),
+        _ => Arc::new(InMemoryDeferredQueue::shared()),
+    }
+}
+
+/// What happened to a status change
+#[derive(Debug, Clone, PartialEq)]
+pub enum SmsOutcome {
+    Sent { to: String, message_id: String },
+    /// Held until quiet hours end
+    Deferred { to: String },
+    /// Nothing to send: not a status change, status didn't change, or no template
+    NoTemplate,
+    Duplicate,
+    NoPhone,
+    OptedOut,
+    Failed(String),
+}
+
+/// The number to text: mobile first, then phone, in E.164
+fn sms_number(customer: &Customer, region: &str) -> Option<String> {
+    [&customer.mobile, &customer.phone]
+        .into_iter()
+        .flatten()
+        .find_map(|p| normalize_phone(p, region).ok())
+}
+
+/// Texts customers when their ticket reaches a status with a template
+pub struct SmsNotifier {
+    rs_client: RepairShoprClient,
+    sender: Arc<dyn SmsSender>,
+    opt_outs: Arc<dyn OptOutStore>,
+    templates: HashMap<String, String>,
+    quiet_hours: Option<QuietHours>,
+    shop_name: String,
+    sent: Arc<dyn DedupeStore>,
+    deferred: Arc<dyn DeferredQueue>,
+}
+
+impl SmsNotifier {
+    /// Notifier with default templates, no quiet hours and its own dedupe memory and queue
+    pub fn new(rs_client: RepairShoprClient, sender: Arc<dyn SmsSender>, opt_outs: Arc<dyn OptOutStore>) -> Self {
+        Self {
+            rs_client,
+            sender,
+            opt_outs,
+            templates: DEFAULT_TEMPLATES
+                .iter()
+                .map(|(status, text)| (status.to_string(), text.to_string()))
+                .collect(),
+            quiet_hours: None,
+            shop_name: "Cacell".to_string(),
+            sent: Arc::new(InMemoryDedupeStore::new(DEDUPE_WINDOW)),
+            deferred: Arc::new(InMemoryDeferredQueue::default()),
+        }
+    }
+
+    pub fn with_quiet_hours(mut self, quiet_hours: Option<QuietHours>) -> Self {
+        self.quiet_hours = quiet_hours;
+        self
+    }
+
+    pub fn with_templates(mut self, templates: HashMap<String, String>) -> Self {
+        self.templates = templates;
+        self
+    }
+
+    pub fn with_dedupe(mut self, sent: Arc<dyn DedupeStore>) -> Self {
+        self.sent = sent;
+        self
+    }
+
+    /// Notifier configured from the environment, sharing the deployment's dedupe memory and
+    /// quiet-hours queue
+    pub fn from_env(rs_client: RepairShoprClient, s3_client: &S3Client) -> Self {
+        let sender: Arc<dyn SmsSender> = match TwilioSender::from_env() {
+            Some(twilio) => Arc::new(twilio),
+            None => Arc::new(MockSmsSender::default()),
+        };
+        let mut notifier = Self::new(rs_client, sender, opt_out_store_from_env(s3_client))
+            .with_quiet_hours(QuietHours::from_env())
+            .with_dedupe(dedupe_store_from_env(s3_client, "sms", DEDUPE_WINDOW));
+        if let Ok(json) = std::env::var("SMS_TEMPLATES") {
+            match serde_json::from_str::<HashMap<String, String>>(&json) {
+                Ok(templates) => notifier = notifier.with_templates(templates),
+                Err(e) => eprintln!("Warning: Invalid SMS_TEMPLATES: {}; using defaults", e),
+            }
+        }
+        notifier.shop_name = shop_name();
+        notifier.deferred = deferred_queue_from_env(s3_client);
+        notifier
+    }
+
+    /// Render the text for `ticket` in `display_status`, if that status has a template
+    pub fn render(&self, display_status: &str, ticket: &Ticket, customer: &Customer) -> Option<String> {
+        let template = self.templates.get(display_status)?;
+        let values = HashMap::from([
+            ("first_name", first_name(customer)),
+            ("number", ticket.number.to_string()),
+            ("status", display_status.to_string()),
+            ("device", device_label(ticket)),
+            ("shop_name", self.shop_name.clone()),
+        ]);
+        Some(fill(template, &values))
+    }
+
+    /// Send any texts held during quiet hours, unless it is still quiet
+    pub async fn flush_deferred(&self, now: DateTime<Utc>) -> Vec<SmsOutcome> {
+        if self.quiet_hours.is_some_and(|q| q.contains(now)) {
+            return vec![];
+        }
+        let pending = match self.deferred.drain().await {
+            Ok(pending) => pending,
+            Err(e) => return vec![SmsOutcome::Failed(e)],
+        };
+        let mut outcomes = vec![];
+        for sms in pending {
+            let outcome = self.deliver(&sms.to, &sms.body).await;
+            // Put a text that failed back, so the next flush tries it again
+            if matches!(outcome, SmsOutcome::Failed(_))
+                && let Err(e) = self.deferred.push(&sms).await
+            {
+                eprintln!("Failed to requeue held SMS for ticket {}: {}", sms.ticket_id, e);
+            }
+            outcomes.push(outcome);
+        }
+        outcomes
+    }
+
+    async fn deliver(&self, to: &str, body: &str) -> SmsOutcome {
+        match self.sender.send(to, body).await {
+            Ok(message_id) => SmsOutcome::Sent {
+                to: to.to_string(),
+                message_id,
+            },
+            Err(SmsError::Unsubscribed) => {
+                if let Err(e) = self.opt_outs.set_opted_out(to, true).await {
+                    eprintln!("Failed to record opt-out: {}", e);
+                }
+                SmsOutcome::OptedOut
+            }
+            Err(SmsError::Failed(e)) => SmsOutcome::Failed(e),
+        }
+    }
+
+    async fn release(&self, key: &str) {
+        if let Err(e) = self.sent.release(key).await {
+            eprintln!("Failed to forget SMS {}: {}", key, e);
+        }
+    }
+
+    /// Decide whether and how to text the customer about `event`
+    pub async fn notify(&self, event: &TicketEvent, now: DateTime<Utc>) -> SmsOutcome {
+        let TicketEvent::StatusChanged { ticket, previous_status } = event else {
+            return SmsOutcome::NoTemplate;
+        };
+        let map = status_map();
+        let display_status = map.to_display(&ticket.status);
+        if previous_status.as_deref().is_some_and(|p| map.to_display(p) == display_status)
+            || !self.templates.contains_key(&display_status)
+        {
+            return SmsOutcome::NoTemplate;
+        }
+
+        let Some(customer_id) = ticket.customer_id else {
+            return SmsOutcome::NoPhone;
+        };
+        let customer = match self.rs_client.get_customer(customer_id).await {
+            Ok(customer) => customer,
+            Err(e) => return SmsOutcome::Failed(e.to_string()),
+        };
+        let Some(to) = sms_number(&customer, &default_phone_region()) else {
+            return SmsOutcome::NoPhone;
+        };
+        if customer.opt_out == Some(true) || customer.get_sms == Some(false) {
+            return SmsOutcome::OptedOut;
+        }
+        match self.opt_outs.is_opted_out(&to).await {
+            Ok(true) => return SmsOutcome::OptedOut,
+            Ok(false) => {}
+            Err(e) => return SmsOutcome::Failed(e),
+        }
+
+        let Some(body) = self.render(&display_status, ticket, &customer) else {
+            return SmsOutcome::NoTemplate;
+        };
+        // Claim before sending so a concurrent save, here or on another instance, can't text twice
+        let key = format!("{}:{}", ticket.id, display_status);
+        match self.sent.claim(&key).await {
+            Ok(true) => {}
+            Ok(false) => return SmsOutcome::Duplicate,
+            Err(e) => return SmsOutcome::Failed(e),
+        }
+
+        if self.quiet_hours.is_some_and(|q| q.contains(now)) {
+            let pending = PendingSms {
+                ticket_id: ticket.id,
+                to: to.clone(),
+                body,
+            };
+            if let Err(e) = self.deferred.push(&pending).await {
+                self.release(&key).await;
+                return SmsOutcome::Failed(e);
+            }
+            return SmsOutcome::Deferred { to };
+        }
+
+        for outcome in self.flush_deferred(now).await {
+            if let SmsOutcome::Failed(e) = outcome {
+                eprintln!("Deferred SMS failed: {}", e);
+            }
+        }
+        let outcome = self.deliver(&to, &body).await;
+        if matches!(outcome, SmsOutcome::Failed(_)) {
+            // Let the next save of this status try again
+            self.release(&key).await;
+        }
+        outcome
+    }
+}
+
+#[async_trait]
+impl EventHandler for SmsNotifier {
+    fn name(&self) -> &'static str {
+        "sms"
+    }
+
+    async fn handle(&self, event: &TicketEvent) -> Result<(), String> {
+        match self.notify(event, Utc::now()).await {
+            SmsOutcome::Failed(e) => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Minimal single-page PDF layout: positioned text, rules and QR codes
+//!
+//! Pages are laid out as a list of [`Element`]s in PDF points (origin bottom-left) and
+//! written with the standard Helvetica fonts, so no font files are embedded. The layout is
+//! plain data, which lets tests compare its [`PageLayout::outline`] instead of PDF bytes.
+
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str, TextStr};
+use qrcode::{Color, QrCode};
+
+/// Helvetica advance widths for ASCII 32..=126, in thousandths of an em
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, // space to /
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, // 0 to ?
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778, // @ to O
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556, // P to _
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, // ` to o
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584, // p to ~
+];
+
+/// Helvetica-Bold runs a little wider; close enough for wrapping and truncation
+const BOLD_FACTOR: f32 = 1.06;
+
+/// Something drawn on the page
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    /// Text with its baseline starting at `(x, y)`
+    Text {
+        x: f32,
+        y: f32,
+        size: f32,
+        bold: bool,
+        text: String,
+    },
+    /// A straight rule
+    Line { x1: f32, y1: f32, x2: f32, y2: f32, width: f32 },
+    /// A QR code whose top-left corner is at `(x, y)`, `size` points square
+    Qr { x: f32, y: f32, size: f32, data: String },
+}
+
+/// Width of `text` in points when set in Helvetica at `size`
+pub fn text_width(text: &str, size: f32, bold: bool) -> f32 {
+    let units: u32 = text
+        .chars()
+        .map(|c| match c as u32 {
+            code @ 32..=126 => HELVETICA_WIDTHS[(code - 32) as usize] as u32,
+            _ => 556,
+        })
+        .sum();
+    let width = units as f32 * size / 1000.0;
+    if bold { width * BOLD_FACTOR } else { width }
+}
+
+/// Break `text` into lines no wider than `max_width`, splitting on spaces
+pub fn wrap(text: &str, size: f32, bold: bool, max_width: f32) -> Vec<String> {
+    let mut lines = vec![];
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", line, word)
+            };
+            if !line.is_empty() && text_width(&candidate, size, bold) > max_width {
+                lines.push(std::mem::replace(&mut line, word.to_string()));
+            } else {
+                line = candidate;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Shorten `text` with "..." so it fits in `max_width`
+pub fn truncate(text: &str, size: f32, bold: bool, max_width: f32) -> String {
+    if text_width(text, size, bold) <= max_width {
+        return text.to_string();
+    }
+    let mut shortened: String = text.to_string();
+    while !shortened.is_empty() && text_width(&format!("{}...", shortened.trim_end()), size, bold) > max_width {
+        shortened.pop();
+    }
+    format!("{}...", shortened.trim_end())
+}
+
+/// Encode text for a WinAnsi-encoded standard font; characters it lacks become `?`
+fn win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c as u32 {
+            code @ (32..=126 | 160..=255) => code as u8,
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// One page of elements
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageLayout {
+    pub width: f32,
+    pub height: f32,
+    pub elements: Vec<Element>,
+}
+
+impl PageLayout {
+    pub fn new(width: f32, height: f32) -> Self {
+        Self {
+            width,
+            height,
+            elements: vec![],
+        }
+    }
+
+    pub fn text(&mut self, x: f32, y: f32, size: f32, bold: bool, text: impl Into<String>) {
+        self.elements.push(Element::Text {
+            x,
+            y,
+            size,
+            bold,
+            text: text.into(),
+        });
+    }
+
+    pub fn line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, width: f32) {
+        self.elements.push(Element::Line { x1, y1, x2, y2, width });
+    }
+
+    pub fn qr(&mut self, x: f32, y: f32, size: f32, data: impl Into<String>) {
+        self.elements.push(Element::Qr {
+            x,
+            y,
+            size,
+            data: data.into(),
+        });
+    }
+
+    /// One line per element with rounded positions, for snapshot tests
+    #[cfg(test)]
+    pub fn outline(&self) -> Vec<String> {
+        self.elements
+            .iter()
+            .map(|element| match element {
+                Element::Text { x, y, size, bold, text } => {
+                    let weight = if *bold { " bold" } else { "" };
+                    format!("text {:.0},{:.0} {}{} {}", x, y, size, weight, text)
+                }
+                Element::Line { x1, y1, x2, y2, .. } => format!("line {:.0},{:.0} {:.0},{:.0}", x1, y1, x2, y2),
+                Element::Qr { x, y, size, data } => format!("qr {:.0},{:.0} {:.0} {}", x, y, size, data),
+            })
+            .collect()
+    }
+
+    fn content(&self) -> Result<Vec<u8>, String> {
+        let mut content = Content::new();
+        for element in &self.elements {
+            match element {
+                Element::Text { x, y, size, bold, text } => {
+                    let font = if *bold { Name(b"F2") } else { Name(b"F1") };
+                    content.begin_text();
+                    content.set_font(font, *size);
+                    content.next_line(*x, *y);
+                    content.show(Str(&win_ansi(text)));
+                    content.end_text();
+                }
+                Element::Line { x1, y1, x2, y2, width } => {
+                    content.set_line_width(*width);
+                    content.move_to(*x1, *y1);
+                    content.line_to(*x2, *y2);
+                    content.stroke();
+                }
+                Element::Qr { x, y, size, data } => {
+                    let code = QrCode::new(data.as_bytes()).map_err(|e| format!("QR code for {}: {}", data, e))?;
+                    let modules = code.width();
+                    let module = size / modules as f32;
+                    content.set_fill_gray(0.0);
+                    for (index, color) in code.to_colors().into_iter().enumerate() {
+                        if color == Color::Dark {
+                            let (row, col) = (index / modules, index % modules);
+                            content.rect(x + col as f32 * module, y - (row + 1) as f32 * module, module, module);
+                        }
+                    }
+                    content.fill_nonzero();
+                }
+            }
+        }
+        Ok(content.finish())
+    }
+
+    /// Write the page as a complete PDF document
+    pub fn to_pdf(&self, title: &str) -> Result<Vec<u8>, String> {
+        let catalog_id = Ref::new(1);
+        let page_tree_id = Ref::new(2);
+        let page_id = Ref::new(3);
+        let content_id = Ref::new(4);
+        let regular_id = Ref::new(5);
+        let bold_id = Ref::new(6);
+        let info_id = Ref::new(7);
+
+        let mut pdf = Pdf::new();
+        pdf.catalog(catalog_id).pages(page_tree_id);
+        pdf.pages(page_tree_id).kids([page_id]).count(1);
+
+        let mut page = pdf.page(page_id);
+        page.parent(page_tree_id)
+            .media_box(Rect::new(0.0, 0.0, self.width, self.height))
+            .contents(content_id);
+        let mut resources = page.resources();
+        let mut fonts = resources.fonts();
+        fonts.pair(Name(b"F1"), regular_id);
+        fonts.pair(Name(b"F2"), bold_id);
+        fonts.finish();
+        resources.finish();
+        page.finish();
+
+        pdf.type1_font(regular_id)
+            .base_font(Name(b"Helvetica"))
+            .encoding_predefined(Name(b"WinAnsiEncoding"));
+        pdf.type1_font(bold_id)
+            .base_font(Name(b"Helvetica-Bold"))
+            .encoding_predefined(Name(b"WinAnsiEncoding"));
+
+        let content = self.content()?;
+        pdf.stream(content_id, &content);
+        pdf.document_info(info_id).title(TextStr(title)).producer(TextStr("TrueTickets"));
+
+        Ok(pdf.finish())
+    }
+}
@@ -0,0 +1,660 @@
+//! Push channel: tells connected browsers when a ticket changes, so they don't have to poll
+//!
+//! Browsers open an API Gateway WebSocket to the same Lambda (`$connect`, `$disconnect`
+//! and a default route for messages) and subscribe to single tickets or to all of them.
+//! `$connect` needs an identity: an authorizer on the WebSocket API, or a short-lived
+//! `token` query parameter from `POST /push/token` (see [`sign_socket_token`]).
+//! Connection ids and their subscriptions live in a [`ConnectionStore`]: S3
+//! (`push/connections/<hex id>.json` in STATE_BUCKET, indexed by topic under
+//! `push/topics/<topic>/<hex id>`) when configured, otherwise process memory. The
+//! [`Broadcaster`] listens on the ticket [`EventBus`](crate::events::EventBus) and posts
+//! `{"type": "ticket.changed", ...}` to every subscribed connection through the API Gateway
+//! management API at WEBSOCKET_ENDPOINT. In Lambda the posting happens in an asynchronous
+//! invocation of this function (see [`broadcast_event`]), so the request that changed the
+//! ticket doesn't wait for it.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+
+use async_trait::async_trait;
+use aws_config::{BehaviorVersion, SdkConfig};
+use aws_sdk_apigatewaymanagement::primitives::Blob;
+use aws_sdk_lambda::types::InvocationType;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use futures::future::try_join_all;
+use futures::stream::{self, StreamExt};
+use serde_json::json;
+use sha2::Sha256;
+use tokio::sync::OnceCell;
+
+use crate::config::state_bucket;
+use crate::events::{EventHandler, TicketEvent};
+use crate::status::status_map;
+
+/// Topic for connections that follow every ticket
+pub const ALL_TICKETS: &str = "tickets";
+
+/// Topic for connections that follow one ticket
+pub fn ticket_topic(ticket_id: i64) -> String {
+    format!("ticket:{}", ticket_id)
+}
+
+/// How long a token from `POST /push/token` can be used to open a connection
+pub const SOCKET_TOKEN_SECONDS: i64 = 300;
+
+/// Socket tokens start with this, so they can never pass as share link tokens
+const SOCKET_TOKEN_PREFIX: &str = "push";
+
+fn socket_mac(secret: &[u8]) -> Hmac<Sha256> {
+    <Hmac<Sha256> as Mac>::new_from_slice(secret).expect("HMAC accepts keys of any length")
+}
+
+/// Sign a token that lets `user` open a connection until `expires_at` (unix seconds)
+pub fn sign_socket_token(user: &str, expires_at: i64, secret: &[u8]) -> String {
+    let payload = format!("{}|{}|{}", SOCKET_TOKEN_PREFIX, expires_at, user);
+    let mut mac = socket_mac(secret);
+    mac.update(payload.as_bytes());
+    format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(payload),
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    )
+}
+
+/// Check a socket token's signature and expiry, returning the user it was issued to
+pub fn verify_socket_token(token: &str, secret: &[u8], now: i64) -> Option<String> {
+    let (payload, signature) = token.trim().split_once('.')?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+    let mut mac = socket_mac(secret);
+    mac.update(&payload);
+    mac.verify_slice(&signature).ok()?;
+
+    let payload = String::from_utf8(payload).ok()?;
+    let mut parts = payload.splitn(3, '|');
+    if parts.next()? != SOCKET_TOKEN_PREFIX {
+        return None;
+    }
+    let expires_at: i64 = parts.next()?.parse().ok()?;
+    let user = parts.next()?;
+    (expires_at > now && !user.is_empty()).then(|| user.to_string())
+}
+
+/// Outcome of changing a connection's subscriptions
+#[derive(Debug, Clone, PartialEq)]
+pub enum TopicUpdate {
+    /// The connection's topics afterwards
+    Updated(Vec<String>),
+    /// The connection was never registered
+    NotConnected,
+    /// The connection would follow too many tickets; nothing was changed
+    TooManyTickets,
+}
+
+/// Where open connections and their subscriptions are kept
+#[async_trait]
+pub trait ConnectionStore: Send + Sync {
+    /// Register a connection opened by `user`
+    async fn connect(&self, connection_id: &str, user: &str) -> Result<(), String>;
+    async fn disconnect(&self, connection_id: &str) -> Result<(), String>;
+    /// Add (or remove) topics. Removing topics from an unknown connection still clears
+    /// anything it left in the store's topic index; subscribing that would leave the
+    /// connection following more than `max_tickets` individual tickets changes nothing.
+    async fn update_topics(
+        &self,
+        connection_id: &str,
+        topics: &[String],
+        subscribe: bool,
+        max_tickets: usize,
+    ) -> Result<TopicUpdate, String>;
+    /// Connections subscribed to any of `topics`
+    async fn subscribers(&self, topics: &[String]) -> Result<Vec<String>, String>;
+}
+
+/// Connections kept in process memory
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryConnectionStore {
+    connections: Arc<Mutex<HashMap<String, StoredConnection>>>,
+}
+
+impl InMemoryConnectionStore {
+    /// Store shared by every request this instance handles
+    pub fn shared() -> Self {
+        static SHARED: LazyLock<InMemoryConnectionStore> = LazyLock::new(InMemoryConnectionStore::default);
+        SHARED.clone()
+    }
+}
+
+#[async_trait]
+impl ConnectionStore for InMemoryConnectionStore {
+    async fn connect(&self, connection_id: &str, user: &str) -> Result<(), String> {
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        connections.insert(connection_id.to_string(), StoredConnection::new(connection_id, user));
+        Ok(())
+    }
+
+    async fn disconnect(&self, connection_id: &str) -> Result<(), String> {
+        self.connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(connection_id);
+        Ok(())
+    }
+
+    async fn update_topics(
+        &self,
+        connection_id: &str,
+        topics: &[String],
+        subscribe: bool,
+        max_tickets: usize,
+    ) -> Result<TopicUpdate, String> {
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(connection) = connections.get_mut(connection_id) else {
+            return Ok(TopicUpdate::NotConnected);
+        };
+        if !connection.apply(topics, subscribe, max_tickets) {
+            return Ok(TopicUpdate::TooManyTickets);
+        }
+        Ok(TopicUpdate::Updated(connection.topics.iter().cloned().collect()))
+    }
+
+    async fn subscribers(&self, topics: &[String]) -> Result<Vec<String>, String> {
+        let connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        let mut ids: Vec<String> = connections
+            .iter()
+            .filter(|(_, connection)| topics.iter().any(|t| connection.topics.contains(t)))
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+}
+
+/// One connection and what it follows
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredConnection {
+    connection_id: String,
+    /// Who opened the connection
+    #[serde(default)]
+    user: String,
+    #[serde(default)]
+    topics: BTreeSet<String>,
+}
+
+impl StoredConnection {
+    fn new(connection_id: &str, user: &str) -> Self {
+        Self {
+            connection_id: connection_id.to_string(),
+            user: user.to_string(),
+            topics: BTreeSet::new(),
+        }
+    }
+
+    /// Apply a subscription change, unless it would follow more than `max_tickets` tickets
+    fn apply(&mut self, topics: &[String], subscribe: bool, max_tickets: usize) -> bool {
+        let mut updated = self.topics.clone();
+        for topic in topics {
+            if subscribe {
+                updated.insert(topic.clone());
+            } else {
+                updated.remove(topic);
+            }
+        }
+        if updated.iter().filter(|t| *t != ALL_TICKETS).count() > max_tickets {
+            return false;
+        }
+        self.topics = updated;
+        true
+    }
+}
+
+/// Connections stored as one S3 object each
+#[derive(Debug, Clone)]
+pub struct S3ConnectionStore {
+    client: S3Client,
+    bucket: String,
+}
+
+const CONNECTIONS_PREFIX: &str = "push/connections/";
+const TOPICS_PREFIX: &str = "push/topics/";
+
+impl S3ConnectionStore {
+    pub fn new(client: S3Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+
+    /// Connection ids may contain `/` and `=`, so keys use their hex form
+    fn key(connection_id: &str) -> String {
+        format!("{}{}.json", CONNECTIONS_PREFIX, hex::encode(connection_id))
+    }
+
+    fn topic_prefix(topic: &str) -> String {
+        format!("{}{}/", TOPICS_PREFIX, topic)
+    }
+
+    /// Empty marker that lists the connection under `topic`
+    fn marker_key(topic: &str, connection_id: &str) -> String {
+        format!("{}{}", Self::topic_prefix(topic), hex::encode(connection_id))
+    }
+
+    /// Add or remove the connection's markers for `topics`
+    async fn set_markers(&self, connection_id: &str, topics: &[String], present: bool) -> Result<(), String> {
+        try_join_all(topics.iter().map(|topic| async move {
+            let key = Self::marker_key(topic, connection_id);
+            let result = if present {
+                self.client
+                    .put_object()
+                    .bucket(&self.bucket)
+                    .key(&key)
+                    .body(ByteStream::from_static(b""))
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| format!("{:?}", e))
+            } else {
+                self.client
+                    .delete_object()
+                    .bucket(&self.bucket)
+                    .key(&key)
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| format!("{:?}", e))
+            };
+            result.map_err(|e| format!("Failed to update topic index {}: {}", key, e))
+        }))
+        .await
+        .map(|_| ())
+    }
+
+    async fn load(&self, key: &str) -> Result<Option<StoredConnection>, String> {
+        match self.client.get_object().bucket(&self.bucket).key(key).send().await {
+            Ok(output) => {
+                let bytes = output
+                    .body
+                    .collect()
+                    .await
+                    .map_err(|e| format!("Failed to read {}: {}", key, e))?
+                    .into_bytes();
+                serde_json::from_slice(&bytes)
+                    .map(Some)
+                    .map_err(|e| format!("Invalid connection record {}: {}", key, e))
+            }
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => Ok(None),
+            Err(e) => Err(format!("Failed to load {}: {:?}", key, e)),
+        }
+    }
+
+    async fn save(&self, connection: &StoredConnection) -> Result<(), String> {
+        let body = serde_json::to_vec(connection).map_err(|e| e.to_string())?;
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(Self::key(&connection.connection_id))
+            .content_type("application/json")
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to save connection {}: {:?}", connection.connection_id, e))
+    }
+}
+
+#[async_trait]
+impl ConnectionStore for S3ConnectionStore {
+    async fn connect(&self, connection_id: &str, user: &str) -> Result<(), String> {
+        self.save(&StoredConnection::new(connection_id, user)).await
+    }
+
+    async fn disconnect(&self, connection_id: &str) -> Result<(), String> {
+        if let Some(connection) = self.load(&Self::key(connection_id)).await? {
+            let topics: Vec<String> = connection.topics.into_iter().collect();
+            self.set_markers(connection_id, &topics, false).await?;
+        }
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(Self::key(connection_id))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to remove connection {}: {:?}", connection_id, e))
+    }
+
+    async fn update_topics(
+        &self,
+        connection_id: &str,
+        topics: &[String],
+        subscribe: bool,
+        max_tickets: usize,
+    ) -> Result<TopicUpdate, String> {
+        let Some(mut connection) = self.load(&Self::key(connection_id)).await? else {
+            if !subscribe {
+                self.set_markers(connection_id, topics, false).await?;
+            }
+            return Ok(TopicUpdate::NotConnected);
+        };
+        if !connection.apply(topics, subscribe, max_tickets) {
+            return Ok(TopicUpdate::TooManyTickets);
+        }
+        self.save(&connection).await?;
+        self.set_markers(connection_id, topics, subscribe).await?;
+        Ok(TopicUpdate::Updated(connection.topics.into_iter().collect()))
+    }
+
+    /// Lists the topic index only; connection records are never read here
+    async fn subscribers(&self, topics: &[String]) -> Result<Vec<String>, String> {
+        let mut ids = BTreeSet::new();
+        for topic in topics {
+            let prefix = Self::topic_prefix(topic);
+            let mut pages = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&prefix)
+                .into_paginator()
+                .send();
+            while let Some(page) = pages.next().await {
+                let page = page.map_err(|e| format!("Failed to list subscribers of {}: {:?}", topic, e))?;
+                let encoded = page.contents().iter().filter_map(|o| o.key()?.strip_prefix(&prefix));
+                ids.extend(
+                    encoded
+                        .filter_map(|hex_id| hex::decode(hex_id).ok())
+                        .filter_map(|id| String::from_utf8(id).ok()),
+                );
+            }
+        }
+        Ok(ids.into_iter().collect())
+    }
+}
+
+/// Pick the connection store for this deployment
+pub fn connection_store_from_env(s3_client: &S3Client) -> Box<dyn ConnectionStore> {
+    match state_bucket() {
+        Some(bucket) => Box::new(S3ConnectionStore::new(s3_client.clone(), bucket)),
+        _ => Box::new(InMemoryConnectionStore::shared()),
+    }
+}
+
+/// Why a message couldn't be delivered
+#[derive(Debug, Clone, PartialEq)]
+pub enum PushError {
+    /// The connection is closed and should be forgotten
+    Gone,
+    Failed(String),
+}
+
+/// Delivers a message to one open connection
+#[async_trait]
+pub trait ConnectionSender: Send + Sync {
+    async fn send(&self, connection_id: &str, payload: &str) -> Result<(), PushError>;
+}
+
+/// AWS config loaded once per instance and shared by the push clients
+async fn aws_config() -> &'static SdkConfig {
+    static CONFIG: OnceCell<SdkConfig> = OnceCell::const_new();
+    CONFIG.get_or_init(|| aws_config::load_defaults(BehaviorVersion::latest())).await
+}
+
+/// Sender through the API Gateway management API
+#[derive(Debug, Clone)]
+pub struct ApiGatewaySender {
+    endpoint: String,
+    /// Built on first use and reused for every send
+    client: Arc<OnceCell<aws_sdk_apigatewaymanagement::Client>>,
+}
+
+impl ApiGatewaySender {
+    /// `endpoint` is the WebSocket stage URL, e.g. `https://abc123.execute-api.us-east-1.amazonaws.com/prod`
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            client: Arc::default(),
+        }
+    }
+
+    /// Sender shared by every request this instance handles, so the client is built once
+    pub fn shared(endpoint: &str) -> Self {
+        static SHARED: OnceLock<ApiGatewaySender> = OnceLock::new();
+        let sender = SHARED.get_or_init(|| Self::new(endpoint));
+        if sender.endpoint == endpoint.trim_end_matches('/') {
+            sender.clone()
+        } else {
+            Self::new(endpoint)
+        }
+    }
+
+    async fn client(&self) -> &aws_sdk_apigatewaymanagement::Client {
+        self.client
+            .get_or_init(|| async {
+                let config = aws_sdk_apigatewaymanagement::config::Builder::from(aws_config().await)
+                    .endpoint_url(&self.endpoint)
+                    .build();
+                aws_sdk_apigatewaymanagement::Client::from_conf(config)
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl ConnectionSender for ApiGatewaySender {
+    async fn send(&self, connection_id: &str, payload: &str) -> Result<(), PushError> {
+        let result = self
+            .client()
+            .await
+            .post_to_connection()
+            .connection_id(connection_id)
+            .data(Blob::new(payload.as_bytes()))
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_gone_exception()) => Err(PushError::Gone),
+            Err(e) => Err(PushError::Failed(format!("{:?}", e))),
+        }
+    }
+}
+
+/// Sender that records messages instead of sending them (tests)
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MockConnectionSender {
+    pub sent: Mutex<Vec<(String, String)>>,
+    /// Connections that report as closed
+    pub gone: Vec<String>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl ConnectionSender for MockConnectionSender {
+    async fn send(&self, connection_id: &str, payload: &str) -> Result<(), PushError> {
+        if self.gone.iter().any(|id| id == connection_id) {
+            return Err(PushError::Gone);
+        }
+        self.sent
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((connection_id.to_string(), payload.to_string()));
+        Ok(())
+    }
+}
+
+/// The message sent for an event
+pub fn change_message(event: &TicketEvent) -> serde_json::Value {
+    let reason = match event {
+        TicketEvent::StatusChanged { .. } => "status_changed",
+        TicketEvent::TicketCreated { .. } => "created",
+        TicketEvent::EstimateReady { .. } => "estimate_ready",
+        TicketEvent::Changed { .. } => "updated",
+    };
+    let mut message = json!({
+        "type": "ticket.changed",
+        "ticket_id": event.ticket_id(),
+        "reason": reason,
+    });
+    if let Some(ticket) = event.ticket() {
+        message["number"] = json!(ticket.number);
+        message["status"] = json!(status_map().to_display(&ticket.status));
+    }
+    message
+}
+
+/// Connections a broadcast posts to at once
+const PUSH_CONCURRENCY: usize = 16;
+
+/// Event type of the invocation that carries a broadcast; API Gateway only ever sends
+/// CONNECT, MESSAGE and DISCONNECT
+pub const BROADCAST_EVENT_TYPE: &str = "BROADCAST";
+
+/// A change waiting to be pushed to the ticket's subscribers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingBroadcast {
+    pub ticket_id: i64,
+    pub message: serde_json::Value,
+}
+
+impl From<&TicketEvent> for PendingBroadcast {
+    fn from(event: &TicketEvent) -> Self {
+        Self {
+            ticket_id: event.ticket_id(),
+            message: change_message(event),
+        }
+    }
+}
+
+/// The WebSocket-shaped event an asynchronous invocation carries `broadcast` in; only
+/// `lambda:InvokeFunction` can produce one
+pub fn broadcast_event(broadcast: &PendingBroadcast, now_millis: i64) -> serde_json::Value {
+    json!({
+        "requestContext": {
+            "routeKey": "$broadcast",
+            "eventType": BROADCAST_EVENT_TYPE,
+            "stage": "internal",
+            "apiId": "internal",
+            "requestId": format!("broadcast-{}-{}", broadcast.ticket_id, now_millis),
+            "domainName": "internal",
+            "messageDirection": "IN",
+            "connectedAt": now_millis,
+            "requestTimeEpoch": now_millis,
+            "identity": { "sourceIp": "127.0.0.1" }
+        },
+        "headers": {},
+        "body": json!({ "ticket_id": broadcast.ticket_id, "message": broadcast.message }).to_string(),
+        "isBase64Encoded": false
+    })
+}
+
+async fn lambda_client() -> &'static aws_sdk_lambda::Client {
+    static CLIENT: OnceCell<aws_sdk_lambda::Client> = OnceCell::const_new();
+    CLIENT.get_or_init(|| async { aws_sdk_lambda::Client::new(aws_config().await) }).await
+}
+
+/// Pushes ticket changes to subscribed connections
+pub struct Broadcaster {
+    store: Arc<dyn ConnectionStore>,
+    sender: Arc<dyn ConnectionSender>,
+    /// Function to hand broadcasts to asynchronously; `None` posts them inline
+    function_name: Option<String>,
+}
+
+impl Broadcaster {
+    pub fn new(store: Arc<dyn ConnectionStore>, sender: Arc<dyn ConnectionSender>) -> Self {
+        Self {
+            store,
+            sender,
+            function_name: None,
+        }
+    }
+
+    /// Broadcaster for this deployment, if a WebSocket API is configured. In Lambda,
+    /// broadcasts go through an asynchronous invocation of the running function.
+    pub fn from_env(s3_client: &S3Client) -> Option<Self> {
+        let endpoint = std::env::var("WEBSOCKET_ENDPOINT").ok().filter(|e| !e.trim().is_empty())?;
+        Some(Self {
+            function_name: std::env::var("AWS_LAMBDA_FUNCTION_NAME").ok().filter(|f| !f.is_empty()),
+            ..Self::new(
+                Arc::from(connection_store_from_env(s3_client)),
+                Arc::new(ApiGatewaySender::shared(&endpoint)),
+            )
+        })
+    }
+
+    /// Post a broadcast to its subscribers, forgetting closed connections; returns how many got it
+    pub async fn deliver(&self, broadcast: &PendingBroadcast) -> Result<usize, String> {
+        let topics = [ALL_TICKETS.to_string(), ticket_topic(broadcast.ticket_id)];
+        let payload = broadcast.message.to_string();
+        let subscribers = self.store.subscribers(&topics).await?;
+        let delivered = stream::iter(subscribers)
+            .map(|connection_id| {
+                let (topics, payload) = (&topics, &payload);
+                async move {
+                    match self.sender.send(&connection_id, payload).await {
+                        Ok(()) => true,
+                        Err(PushError::Gone) => {
+                            self.forget(&connection_id, topics).await;
+                            false
+                        }
+                        Err(PushError::Failed(e)) => {
+                            eprintln!("Failed to push to {}: {}", connection_id, e);
+                            false
+                        }
+                    }
+                }
+            })
+            .buffer_unordered(PUSH_CONCURRENCY)
+            .filter(|delivered| std::future::ready(*delivered))
+            .count()
+            .await;
+        Ok(delivered)
+    }
+
+    /// Drop a closed connection, including index entries left without a connection record
+    async fn forget(&self, connection_id: &str, topics: &[String]) {
+        let result = match self.store.update_topics(connection_id, topics, false, usize::MAX).await {
+            Ok(_) => self.store.disconnect(connection_id).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to forget closed connection {}: {}", connection_id, e);
+        }
+    }
+
+    /// Hand a broadcast to an asynchronous invocation of `function_name`
+    async fn dispatch(&self, function_name: &str, broadcast: &PendingBroadcast) -> Result<(), String> {
+        let event = broadcast_event(broadcast, chrono::Utc::now().timestamp_millis());
+        lambda_client()
+            .await
+            .invoke()
+            .function_name(function_name)
+            .invocation_type(InvocationType::Event)
+            .payload(aws_sdk_lambda::primitives::Blob::new(event.to_string()))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to hand off broadcast for ticket {}: {:?}", broadcast.ticket_id, e))
+    }
+}
+
+#[async_trait]
+impl EventHandler for Broadcaster {
+    fn name(&self) -> &'static str {
+        "push"
+    }
+
+    async fn handle(&self, event: &TicketEvent) -> Result<(), String> {
+        let broadcast = PendingBroadcast::from(event);
+        if let Some(function_name) = &self.function_name {
+            match self.dispatch(function_name, &broadcast).await {
+                Ok(()) => return Ok(()),
+                // Still reach subscribers, just on this request's time
+                Err(e) => eprintln!("{}; broadcasting inline", e),
+            }
+        }
+        self.deliver(&broadcast).await.map(|_| ())
+    }
+}
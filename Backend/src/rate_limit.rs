@@ -0,0 +1,106 @@
+//! In-process per-client rate limiting with lockout after repeated failures
+//!
+//! State lives in the warm Lambda instance, so limits are per instance rather than
+//! global; that is enough to make guessing ticket numbers and phone digits impractical
+//! without adding a datastore to the request path.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Outcome of a rate limit check
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateDecision {
+    Allowed,
+    /// Too many requests, or locked out after failures; try again after the delay
+    Limited { retry_after: Duration },
+}
+
+#[derive(Debug)]
+struct ClientState {
+    window_start: Instant,
+    requests: u32,
+    failures: Vec<Instant>,
+    locked_until: Option<Instant>,
+}
+
+/// Fixed-window request limit plus a failure counter that locks a client out
+#[derive(Debug)]
+pub struct RateLimiter {
+    max_requests: u32,
+    window: Duration,
+    max_failures: usize,
+    failure_window: Duration,
+    lockout: Duration,
+    clients: Mutex<HashMap<String, ClientState>>,
+}
+
+impl RateLimiter {
+    /// Allow `max_requests` per `window`; `max_failures` within `failure_window` locks the
+    /// client out for `lockout`
+    pub fn new(max_requests: u32, window: Duration, max_failures: usize, failure_window: Duration, lockout: Duration) -> Self {
+        Self {
+            max_requests,
+            window,
+            max_failures,
+            failure_window,
+            lockout,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a request from `client` and decide whether to serve it
+    pub fn check(&self, client: &str, now: Instant) -> RateDecision {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        // Forget idle clients so the map doesn't grow without bound
+        clients.retain(|_, state| {
+            now.duration_since(state.window_start) < self.window.max(self.failure_window)
+                || state.locked_until.is_some_and(|until| until > now)
+        });
+
+        let state = clients.entry(client.to_string()).or_insert_with(|| ClientState {
+            window_start: now,
+            requests: 0,
+            failures: vec![],
+            locked_until: None,
+        });
+
+        if let Some(until) = state.locked_until {
+            if until > now {
+                return RateDecision::Limited { retry_after: until - now };
+            }
+            state.locked_until = None;
+        }
+
+        if now.duration_since(state.window_start) >= self.window {
+            state.window_start = now;
+            state.requests = 0;
+        }
+        state.requests += 1;
+        if state.requests > self.max_requests {
+            return RateDecision::Limited {
+                retry_after: self.window.saturating_sub(now.duration_since(state.window_start)),
+            };
+        }
+        RateDecision::Allowed
+    }
+
+    /// Record a failed attempt (e.g. a wrong phone number); returns true if it caused a lockout.
+    ///
+    /// Successes never clear failures: otherwise a client could look up its own ticket
+    /// between guesses and keep guessing forever.
+    pub fn record_failure(&self, client: &str, now: Instant) -> bool {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(state) = clients.get_mut(client) else {
+            return false;
+        };
+        state.failures.retain(|at| now.duration_since(*at) < self.failure_window);
+        state.failures.push(now);
+        if state.failures.len() >= self.max_failures {
+            state.failures.clear();
+            state.locked_until = Some(now + self.lockout);
+            return true;
+        }
+        false
+    }
+}
@@ -0,0 +1,284 @@
+//! Typed async client for the RepairShopr REST API
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use super::models::{
+    AttachmentUrl, Comment, CommentEnvelope, Customer, CustomerEnvelope, CustomerInput,
+    CustomersEnvelope, Estimate, EstimatesEnvelope, Listing, NewComment, NewTicket, Page, Ticket, TicketEnvelope, TicketUpdate,
+    TicketsEnvelope,
+};
+use super::RepairShoprError;
+use crate::config::UpstreamConfig;
+
+/// Upper bound on pages walked by the `list_all_*` helpers, so a bad query can't loop forever
+pub const MAX_PAGES: u32 = 50;
+
+/// Filters for listing tickets
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TicketQuery {
+    pub query: Option<String>,
+    pub number: Option<String>,
+    pub status: Option<String>,
+    pub customer_id: Option<i64>,
+    pub user_id: Option<i64>,
+    pub since_updated_at: Option<String>,
+}
+
+impl TicketQuery {
+    fn to_params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![];
+        if let Some(query) = &self.query {
+            params.push(("query", query.clone()));
+        }
+        if let Some(number) = &self.number {
+            params.push(("number", number.clone()));
+        }
+        if let Some(status) = &self.status {
+            params.push(("status", status.clone()));
+        }
+        if let Some(customer_id) = self.customer_id {
+            params.push(("customer_id", customer_id.to_string()));
+        }
+        if let Some(user_id) = self.user_id {
+            params.push(("user_id", user_id.to_string()));
+        }
+        if let Some(since) = &self.since_updated_at {
+            params.push(("since_updated_at", since.clone()));
+        }
+        params
+    }
+}
+
+/// Filters for listing customers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CustomerQuery {
+    pub query: Option<String>,
+    pub email: Option<String>,
+}
+
+impl CustomerQuery {
+    fn to_params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![];
+        if let Some(query) = &self.query {
+            params.push(("query", query.clone()));
+        }
+        if let Some(email) = &self.email {
+            params.push(("email", email.clone()));
+        }
+        params
+    }
+}
+
+/// Client for a single RepairShopr tenant
+#[derive(Debug, Clone)]
+pub struct RepairShoprClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+    upstream: UpstreamConfig,
+}
+
+impl RepairShoprClient {
+    /// Create a client for the API at `base_url` (e.g. `https://shop.repairshopr.com/api/v1`)
+    pub fn new(base_url: &str, api_key: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            upstream: UpstreamConfig::from_env(base_url),
+        }
+    }
+
+    /// Fetch a single ticket, including its comments and attachments
+    pub async fn get_ticket(&self, id: i64) -> Result<Ticket, RepairShoprError> {
+        let envelope: TicketEnvelope = self.get(&format!("/tickets/{}", id), &[]).await?;
+        Ok(envelope.ticket)
+    }
+
+    /// Fetch one page of tickets matching `query`
+    pub async fn list_tickets(&self, query: &TicketQuery, page: u32) -> Result<Page<Ticket>, RepairShoprError> {
+        let mut params = query.to_params();
+        params.push(("page", page.to_string()));
+        let envelope: TicketsEnvelope = self.get("/tickets", &params).await?;
+        Ok(Page {
+            items: envelope.tickets,
+            page,
+            total_pages: envelope.meta.total_pages.unwrap_or(page),
+        })
+    }
+
+    /// Fetch every ticket matching `query`, walking pages up to `max_pages`
+    pub async fn list_all_tickets(&self, query: &TicketQuery, max_pages: u32) -> Result<Listing<Ticket>, RepairShoprError> {
+        let mut tickets = vec![];
+        let mut page = 1;
+        loop {
+            let result = self.list_tickets(query, page).await?;
+            let last = result.items.is_empty() || page >= result.total_pages;
+            tickets.extend(result.items);
+            if last || page >= max_pages {
+                return Ok(Listing {
+                    items: tickets,
+                    truncated: !last,
+                });
+            }
+            page += 1;
+        }
+    }
+
+    /// Full-text ticket search (first page only, as RepairShopr ranks the results)
+    pub async fn search_tickets(&self, text: &str) -> Result<Vec<Ticket>, RepairShoprError> {
+        let query = TicketQuery {
+            query: Some(text.to_string()),
+            ..Default::default()
+        };
+        Ok(self.list_tickets(&query, 1).await?.items)
+    }
+
+    /// Create a ticket
+    pub async fn create_ticket(&self, ticket: &NewTicket) -> Result<Ticket, RepairShoprError> {
+        let envelope: TicketEnvelope = self.send(reqwest::Method::POST, "/tickets", ticket).await?;
+        Ok(envelope.ticket)
+    }
+
+    /// Update the fields set in `update` on a ticket
+    pub async fn update_ticket(&self, id: i64, update: &TicketUpdate) -> Result<Ticket, RepairShoprError> {
+        let envelope: TicketEnvelope = self
+            .send(reqwest::Method::PUT, &format!("/tickets/{}", id), update)
+            .await?;
+        Ok(envelope.ticket)
+    }
+
+    /// Add a comment to a ticket
+    pub async fn add_comment(&self, ticket_id: i64, comment: &NewComment) -> Result<Comment, RepairShoprError> {
+        let envelope: CommentEnvelope = self
+            .send(reqwest::Method::POST, &format!("/tickets/{}/comment", ticket_id), comment)
+            .await?;
+        Ok(envelope.comment)
+    }
+
+    /// Attach already-hosted files to a ticket by URL
+    pub async fn attach_file_url(&self, ticket_id: i64, files: &[AttachmentUrl]) -> Result<Value, RepairShoprError> {
+        self.send(
+            reqwest::Method::POST,
+            &format!("/tickets/{}/attach_file_url", ticket_id),
+            &json!({ "files": files }),
+        )
+        .await
+    }
+
+    /// Fetch the first page of a customer's estimates
+    pub async fn list_customer_estimates(&self, customer_id: i64) -> Result<Vec<Estimate>, RepairShoprError> {
+        let params = [("customer_id", customer_id.to_string())];
+        let envelope: EstimatesEnvelope = self.get("/estimates", &params).await?;
+        Ok(envelope.estimates)
+    }
+
+    /// Fetch a single customer
+    pub async fn get_customer(&self, id: i64) -> Result<Customer, RepairShoprError> {
+        let envelope: CustomerEnvelope = self.get(&format!("/customers/{}", id), &[]).await?;
+        Ok(envelope.customer)
+    }
+
+    /// Fetch one page of customers matching `query`
+    pub async fn list_customers(&self, query: &CustomerQuery, page: u32) -> Result<Page<Customer>, RepairShoprError> {
+        let mut params = query.to_params();
+        params.push(("page", page.to_string()));
+        let envelope: CustomersEnvelope = self.get("/customers", &params).await?;
+        Ok(Page {
+            items: envelope.customers,
+            page,
+            total_pages: envelope.meta.total_pages.unwrap_or(page),
+        })
+    }
+
+    /// Fetch every customer matching `query`, walking pages up to `max_pages`
+    pub async fn list_all_customers(
+        &self,
+        query: &CustomerQuery,
+        max_pages: u32,
+    ) -> Result<Listing<Customer>, RepairShoprError> {
+        let mut customers = vec![];
+        let mut page = 1;
+        loop {
+            let result = self.list_customers(query, page).await?;
+            let last = result.items.is_empty() || page >= result.total_pages;
+            customers.extend(result.items);
+            if last || page >= max_pages {
+                return Ok(Listing {
+                    items: customers,
+                    truncated: !last,
+                });
+            }
+            page += 1;
+        }
+    }
+
+    /// Full-text customer search (first page only)
+    pub async fn search_customers(&self, text: &str) -> Result<Vec<Customer>, RepairShoprError> {
+        let query = CustomerQuery {
+            query: Some(text.to_string()),
+            ..Default::default()
+        };
+        Ok(self.list_customers(&query, 1).await?.items)
+    }
+
+    /// Create a customer
+    pub async fn create_customer(&self, customer: &CustomerInput) -> Result<Customer, RepairShoprError> {
+        let envelope: CustomerEnvelope = self.send(reqwest::Method::POST, "/customers", customer).await?;
+        Ok(envelope.customer)
+    }
+
+    /// Update the fields set in `customer` on an existing customer
+    pub async fn update_customer(&self, id: i64, customer: &CustomerInput) -> Result<Customer, RepairShoprError> {
+        let envelope: CustomerEnvelope = self
+            .send(reqwest::Method::PUT, &format!("/customers/{}", id), customer)
+            .await?;
+        Ok(envelope.customer)
+    }
+
+    /// Start a request with the authentication and identifying headers applied
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.http
+            .request(method, format!("{}{}", self.base_url, path))
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .header("Accept", "application/json")
+            .header("User-Agent", &self.upstream.user_agent)
+            .header("Accept-Language", &self.upstream.accept_language)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, params: &[(&str, String)]) -> Result<T, RepairShoprError> {
+        let builder = self.request(reqwest::Method::GET, path).query(params);
+        Self::execute(path, builder).await
+    }
+
+    async fn send<B: Serialize + ?Sized, T: DeserializeOwned>(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: &B,
+    ) -> Result<T, RepairShoprError> {
+        let builder = self.request(method, path).json(body);
+        Self::execute(path, builder).await
+    }
+
+    async fn execute<T: DeserializeOwned>(path: &str, builder: reqwest::RequestBuilder) -> Result<T, RepairShoprError> {
+        let response = builder
+            .send()
+            .await
+            .map_err(|e| RepairShoprError::Transport(format!("{}: {}", path, e)))?;
+
+        let status = response.status().as_u16();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| RepairShoprError::Transport(format!("{}: {}", path, e)))?;
+
+        if !(200..300).contains(&status) {
+            return Err(RepairShoprError::Status { status, body });
+        }
+
+        serde_json::from_str(&body).map_err(|e| RepairShoprError::Decode(format!("{}: {}", path, e)))
+    }
+}
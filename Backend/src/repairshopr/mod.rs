@@ -0,0 +1,72 @@
+//! Typed access to the RepairShopr API
+//!
+//! The proxy passes requests through untouched; backend endpoints that need to
+//! understand tickets and customers use this module instead.
+
+pub mod client;
+pub mod models;
+
+pub use client::{CustomerQuery, RepairShoprClient, TicketQuery, MAX_PAGES};
+pub use models::{
+    Attachment, AttachmentUrl, Comment, Customer, CustomerInput, Estimate, IntakeModel, Listing, NewComment,
+    NewTicket, Ticket, TicketProperties, TicketUpdate, INTAKE_MODEL_PREFIX,
+};
+
+use crate::errors::ApiError;
+
+/// Failure talking to RepairShopr
+#[derive(Debug, Clone, PartialEq)]
+pub enum RepairShoprError {
+    /// The request never completed (DNS, TLS, timeout, connection reset)
+    Transport(String),
+    /// RepairShopr answered with a non-2xx status
+    Status { status: u16, body: String },
+    /// The response body didn't match the expected shape
+    Decode(String),
+}
+
+impl std::fmt::Display for RepairShoprError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RepairShoprError::Transport(e) => write!(f, "transport error: {}", e),
+            RepairShoprError::Status { status, body } => write!(f, "status {}: {}", status, body),
+            RepairShoprError::Decode(e) => write!(f, "decode error: {}", e),
+        }
+    }
+}
+
+impl From<RepairShoprError> for ApiError {
+    fn from(error: RepairShoprError) -> Self {
+        let detail = error.to_string();
+        match error {
+            RepairShoprError::Transport(_) => ApiError::upstream_unavailable(detail),
+            RepairShoprError::Status { status: 404, .. } => {
+                ApiError::new(404, "NOT_FOUND", "The requested record was not found", detail)
+            }
+            RepairShoprError::Status { status: 422, .. } => {
+                ApiError::new(422, "UPSTREAM_REJECTED", "RepairShopr rejected the change", detail)
+            }
+            RepairShoprError::Status { status: 429, .. } => ApiError::new(
+                429,
+                "UPSTREAM_RATE_LIMITED",
+                "RepairShopr is rate limiting requests, try again shortly",
+                detail,
+            ),
+            RepairShoprError::Status { status: 401 | 403, .. } => ApiError::new(
+                502,
+                "UPSTREAM_AUTH_FAILED",
+                "The server could not authenticate with RepairShopr",
+                detail,
+            ),
+            RepairShoprError::Status { .. } => {
+                ApiError::new(502, "UPSTREAM_ERROR", "RepairShopr returned an error", detail)
+            }
+            RepairShoprError::Decode(_) => ApiError::new(
+                502,
+                "UPSTREAM_INVALID_RESPONSE",
+                "RepairShopr returned an unexpected response",
+                detail,
+            ),
+        }
+    }
+}
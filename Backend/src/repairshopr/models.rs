@@ -0,0 +1,340 @@
+//! Serde models for RepairShopr API resources and request payloads
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Ticket custom properties (the free-form fields configured per ticket type)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TicketProperties {
+    #[serde(rename = "Model", default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(rename = "Password", default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(rename = "AC Charger", default, skip_serializing_if = "Option::is_none")]
+    pub ac_charger: Option<String>,
+    #[serde(rename = "Tech Notes", default, skip_serializing_if = "Option::is_none")]
+    pub tech_notes: Option<String>,
+    #[serde(rename = "IMEI or S/N", default, skip_serializing_if = "Option::is_none")]
+    pub imei_or_serial: Option<String>,
+    /// Any other properties, passed through untouched
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Intake details the frontend stores in the `Model` property as `vT{json}`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IntakeModel {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(rename = "itemsLeft", default)]
+    pub items_left: Vec<String>,
+    #[serde(rename = "estimatedTime", default, skip_serializing_if = "Option::is_none")]
+    pub estimated_time: Option<String>,
+}
+
+/// Prefix marking a `Model` property that holds an [`IntakeModel`]
+pub const INTAKE_MODEL_PREFIX: &str = "vT";
+
+impl TicketProperties {
+    /// Decode the intake details stored in the `Model` property, if present
+    pub fn intake_model(&self) -> Option<IntakeModel> {
+        let json = self.model.as_deref()?.strip_prefix(INTAKE_MODEL_PREFIX)?;
+        serde_json::from_str(json).ok()
+    }
+}
+
+/// Technician a ticket is assigned to
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TicketUser {
+    pub id: Option<i64>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub full_name: Option<String>,
+}
+
+/// A comment on a ticket
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Comment {
+    pub id: i64,
+    #[serde(default)]
+    pub ticket_id: Option<i64>,
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub tech: Option<String>,
+    #[serde(default)]
+    pub hidden: Option<bool>,
+    #[serde(default)]
+    pub user_id: Option<i64>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+}
+
+/// Stored file URLs for an attachment
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AttachmentFile {
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+/// A file attached to a ticket
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: i64,
+    #[serde(default)]
+    pub file_name: Option<String>,
+    #[serde(default)]
+    pub file: Option<AttachmentFile>,
+    #[serde(default)]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub private: Option<bool>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+}
+
+/// An estimate (quote) for a customer, optionally tied to a ticket
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Estimate {
+    pub id: i64,
+    #[serde(default)]
+    pub number: Option<String>,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub customer_id: Option<i64>,
+    #[serde(default)]
+    pub ticket_id: Option<i64>,
+    /// RepairShopr sends money amounts as strings
+    #[serde(default)]
+    pub total: Option<Value>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+}
+
+impl Estimate {
+    /// Whether the customer has approved this estimate
+    pub fn is_approved(&self) -> bool {
+        self.status.as_deref().is_some_and(|s| s.eq_ignore_ascii_case("approved"))
+    }
+}
+
+/// A repair ticket
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Ticket {
+    pub id: i64,
+    pub number: i64,
+    #[serde(default)]
+    pub subject: String,
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub customer_id: Option<i64>,
+    #[serde(default)]
+    pub customer_business_then_name: Option<String>,
+    #[serde(default)]
+    pub problem_type: Option<String>,
+    #[serde(default)]
+    pub ticket_type_id: Option<i64>,
+    #[serde(default)]
+    pub user_id: Option<i64>,
+    #[serde(default)]
+    pub user: Option<TicketUser>,
+    #[serde(default)]
+    pub due_date: Option<String>,
+    #[serde(default)]
+    pub resolved_at: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+    #[serde(default)]
+    pub properties: TicketProperties,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub comments: Vec<Comment>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+}
+
+/// A customer record
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Customer {
+    pub id: i64,
+    #[serde(default)]
+    pub firstname: Option<String>,
+    #[serde(default)]
+    pub lastname: Option<String>,
+    #[serde(default)]
+    pub fullname: Option<String>,
+    #[serde(default)]
+    pub business_name: Option<String>,
+    #[serde(default)]
+    pub business_then_name: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub phone: Option<String>,
+    #[serde(default)]
+    pub mobile: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub get_sms: Option<bool>,
+    #[serde(default)]
+    pub opt_out: Option<bool>,
+    #[serde(default)]
+    pub no_email: Option<bool>,
+    #[serde(default)]
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub updated_at: Option<String>,
+}
+
+/// Payload for creating a ticket
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NewTicket {
+    pub customer_id: i64,
+    pub subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ticket_type_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due_date: Option<String>,
+    pub properties: TicketProperties,
+}
+
+/// Payload for updating a ticket; only the fields that are set are sent
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TicketUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub customer_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<TicketProperties>,
+}
+
+/// Payload for adding a comment to a ticket
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NewComment {
+    pub subject: String,
+    pub body: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tech: Option<String>,
+    pub hidden: bool,
+    pub do_not_email: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sms_body: Option<String>,
+}
+
+/// Payload for creating or updating a customer; only the fields that are set are sent
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CustomerInput {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firstname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lastname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub business_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mobile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub get_sms: Option<bool>,
+}
+
+/// A file already hosted elsewhere that RepairShopr should attach to a ticket
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AttachmentUrl {
+    pub url: String,
+    pub filename: String,
+}
+
+/// Pagination metadata returned alongside list endpoints
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct PageMeta {
+    #[serde(default)]
+    pub total_pages: Option<u32>,
+    #[serde(default)]
+    pub total_entries: Option<u64>,
+    #[serde(default)]
+    pub page: Option<u32>,
+}
+
+/// One page of a list endpoint
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub page: u32,
+    pub total_pages: u32,
+}
+
+/// Everything a `list_all_*` helper fetched
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing<T> {
+    pub items: Vec<T>,
+    /// The page limit stopped the walk before RepairShopr's last page
+    pub truncated: bool,
+}
+
+#[derive(Deserialize)]
+pub(super) struct TicketEnvelope {
+    pub ticket: Ticket,
+}
+
+#[derive(Deserialize)]
+pub(super) struct TicketsEnvelope {
+    #[serde(default)]
+    pub tickets: Vec<Ticket>,
+    #[serde(default)]
+    pub meta: PageMeta,
+}
+
+#[derive(Deserialize)]
+pub(super) struct CustomerEnvelope {
+    pub customer: Customer,
+}
+
+#[derive(Deserialize)]
+pub(super) struct CustomersEnvelope {
+    #[serde(default)]
+    pub customers: Vec<Customer>,
+    #[serde(default)]
+    pub meta: PageMeta,
+}
+
+#[derive(Deserialize)]
+pub(super) struct EstimatesEnvelope {
+    #[serde(default)]
+    pub estimates: Vec<Estimate>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct CommentEnvelope {
+    pub comment: Comment,
+}
@@ -0,0 +1,253 @@
+//! Signed, expiring share links for customers
+//!
+//! A token is `<payload>.<signature>`, both base64url. The payload carries the link id,
+//! ticket id, expiry (unix seconds) and scopes; the signature is HMAC-SHA256 over the
+//! payload with SHARE_LINK_SECRET. Tokens are verified without any lookup except the
+//! revocation check, which lets staff kill a link before it expires.
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::config::state_bucket;
+use crate::errors::ApiError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What a share link lets the customer see or do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareScope {
+    Status,
+    Estimate,
+    Attachments,
+    /// Approve or decline an estimate (the link id is the approval request id)
+    Approval,
+}
+
+impl ShareScope {
+    fn as_str(self) -> &'static str {
+        match self {
+            ShareScope::Status => "status",
+            ShareScope::Estimate => "estimate",
+            ShareScope::Attachments => "attachments",
+            ShareScope::Approval => "approval",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "status" => Some(ShareScope::Status),
+            "estimate" => Some(ShareScope::Estimate),
+            "attachments" => Some(ShareScope::Attachments),
+            "approval" => Some(ShareScope::Approval),
+            _ => None,
+        }
+    }
+}
+
+/// Contents of a share token
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShareClaims {
+    pub link_id: String,
+    pub ticket_id: i64,
+    pub expires_at: i64,
+    pub scopes: Vec<ShareScope>,
+}
+
+impl ShareClaims {
+    pub fn allows(&self, scope: ShareScope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    fn payload(&self) -> String {
+        let scopes: Vec<&str> = self.scopes.iter().map(|s| s.as_str()).collect();
+        format!("{}|{}|{}|{}", self.link_id, self.ticket_id, self.expires_at, scopes.join(","))
+    }
+
+    fn from_payload(payload: &str) -> Option<Self> {
+        let mut parts = payload.split('|');
+        let link_id = parts.next()?.to_string();
+        let ticket_id = parts.next()?.parse().ok()?;
+        let expires_at = parts.next()?.parse().ok()?;
+        let scopes = parts
+            .next()?
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .map(ShareScope::parse)
+            .collect::<Option<Vec<_>>>()?;
+        if parts.next().is_some() || link_id.is_empty() {
+            return None;
+        }
+        Some(Self {
+            link_id,
+            ticket_id,
+            expires_at,
+            scopes,
+        })
+    }
+}
+
+/// Why a token was refused
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShareError {
+    Malformed,
+    BadSignature,
+    Expired,
+    Revoked,
+}
+
+impl From<ShareError> for ApiError {
+    fn from(error: ShareError) -> Self {
+        let (status, code, message) = match error {
+            ShareError::Expired => (410, "LINK_EXPIRED", "This link has expired"),
+            ShareError::Revoked => (410, "LINK_REVOKED", "This link is no longer valid"),
+            ShareError::Malformed | ShareError::BadSignature => (401, "INVALID_LINK", "This link is not valid"),
+        };
+        ApiError::new(status, code, message, format!("Share token rejected: {:?}", error))
+    }
+}
+
+fn mac(secret: &[u8]) -> HmacSha256 {
+    <HmacSha256 as Mac>::new_from_slice(secret).expect("HMAC accepts keys of any length")
+}
+
+/// Create a random link id
+pub fn new_link_id() -> String {
+    use rand::Rng;
+    hex::encode(rand::rng().random::<[u8; 12]>())
+}
+
+/// Sign claims into a URL-safe token
+pub fn sign_token(claims: &ShareClaims, secret: &[u8]) -> String {
+    let payload = claims.payload();
+    let mut mac = mac(secret);
+    mac.update(payload.as_bytes());
+    format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(payload),
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    )
+}
+
+/// Check a token's signature and expiry (`now` in unix seconds)
+pub fn verify_token(token: &str, secret: &[u8], now: i64) -> Result<ShareClaims, ShareError> {
+    let (payload, signature) = token.trim().split_once('.').ok_or(ShareError::Malformed)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| ShareError::Malformed)?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| ShareError::Malformed)?;
+
+    let mut mac = mac(secret);
+    mac.update(&payload);
+    mac.verify_slice(&signature).map_err(|_| ShareError::BadSignature)?;
+
+    let payload = String::from_utf8(payload).map_err(|_| ShareError::Malformed)?;
+    let claims = ShareClaims::from_payload(&payload).ok_or(ShareError::Malformed)?;
+    if claims.expires_at <= now {
+        return Err(ShareError::Expired);
+    }
+    Ok(claims)
+}
+
+/// Secret used to sign share links
+pub fn share_secret() -> Result<Vec<u8>, ApiError> {
+    match std::env::var("SHARE_LINK_SECRET") {
+        Ok(secret) if secret.len() >= 32 => Ok(secret.into_bytes()),
+        Ok(_) => Err(ApiError::configuration("SHARE_LINK_SECRET must be at least 32 characters")),
+        Err(_) => Err(ApiError::configuration("SHARE_LINK_SECRET environment variable not set")),
+    }
+}
+
+/// Where revoked link ids are kept
+#[async_trait]
+pub trait RevocationStore: Send + Sync {
+    /// Revoke a link; `expires_at` lets stores forget it once it would have expired anyway
+    async fn revoke(&self, link_id: &str, expires_at: Option<i64>) -> Result<(), String>;
+    async fn is_revoked(&self, link_id: &str) -> Result<bool, String>;
+}
+
+/// Revocations kept in process memory
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryRevocationStore {
+    revoked: Arc<Mutex<HashMap<String, Option<i64>>>>,
+}
+
+impl InMemoryRevocationStore {
+    /// Store shared by every request this instance handles
+    pub fn shared() -> Self {
+        static SHARED: LazyLock<InMemoryRevocationStore> = LazyLock::new(InMemoryRevocationStore::default);
+        SHARED.clone()
+    }
+}
+
+#[async_trait]
+impl RevocationStore for InMemoryRevocationStore {
+    async fn revoke(&self, link_id: &str, expires_at: Option<i64>) -> Result<(), String> {
+        self.revoked
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(link_id.to_string(), expires_at);
+        Ok(())
+    }
+
+    async fn is_revoked(&self, link_id: &str) -> Result<bool, String> {
+        Ok(self.revoked.lock().unwrap_or_else(|e| e.into_inner()).contains_key(link_id))
+    }
+}
+
+/// Revocations stored as one small S3 object per link id
+#[derive(Debug, Clone)]
+pub struct S3RevocationStore {
+    client: S3Client,
+    bucket: String,
+}
+
+impl S3RevocationStore {
+    pub fn new(client: S3Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+
+    fn key(link_id: &str) -> String {
+        format!("share-links/revoked/{}", link_id)
+    }
+}
+
+#[async_trait]
+impl RevocationStore for S3RevocationStore {
+    async fn revoke(&self, link_id: &str, expires_at: Option<i64>) -> Result<(), String> {
+        let body = serde_json::json!({ "link_id": link_id, "expires_at": expires_at }).to_string();
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(Self::key(link_id))
+            .content_type("application/json")
+            .body(ByteStream::from(body.into_bytes()))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to store revocation for {}: {:?}", link_id, e))
+    }
+
+    async fn is_revoked(&self, link_id: &str) -> Result<bool, String> {
+        match self.client.head_object().bucket(&self.bucket).key(Self::key(link_id)).send().await {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(false),
+            Err(e) => Err(format!("Failed to check revocation for {}: {:?}", link_id, e)),
+        }
+    }
+}
+
+/// Pick the revocation store for this deployment
+pub fn revocation_store_from_env(s3_client: &S3Client) -> Box<dyn RevocationStore> {
+    match state_bucket() {
+        Some(bucket) => Box::new(S3RevocationStore::new(s3_client.clone(), bucket)),
+        _ => Box::new(InMemoryRevocationStore::shared()),
+    }
+}
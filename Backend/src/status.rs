@@ -0,0 +1,147 @@
+//! Mapping between RepairShopr ticket statuses and the shop's display statuses
+//!
+//! The mapping is an ordered list of `(repairshopr, display)` pairs. Several RepairShopr
+//! statuses may share a display status ("Customer Reply" and "Ready!" both show as
+//! "Ready"); translating back always picks the first pair listed for that display status,
+//! so the reverse mapping is deterministic. Override the defaults by setting STATUS_MAP to
+//! a JSON array of pairs, e.g. `[["New","Diagnosing"],["Ready!","Ready"]]`.
+
+use std::sync::OnceLock;
+
+use serde_json::Value;
+
+/// Default mapping, matching the frontend's STATUS_MAP
+const DEFAULT_STATUS_MAP: [(&str, &str); 9] = [
+    ("New", "Diagnosing"),
+    ("Scheduled", "Finding Price"),
+    ("Call Customer", "Approval Needed"),
+    ("Waiting for Parts", "Waiting for Parts"),
+    ("Waiting on Customer", "Waiting (Other)"),
+    ("In Progress", "In Progress"),
+    ("Customer Reply", "Ready"),
+    ("Ready!", "Ready"),
+    ("Resolved", "Resolved"),
+];
+
+/// Ordered RepairShopr ↔ display status mapping
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusMap {
+    entries: Vec<(String, String)>,
+}
+
+impl Default for StatusMap {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_STATUS_MAP
+                .iter()
+                .map(|(rs, display)| (rs.to_string(), display.to_string()))
+                .collect(),
+        )
+    }
+}
+
+impl StatusMap {
+    /// Build a mapping from ordered `(repairshopr, display)` pairs
+    pub fn new(entries: Vec<(String, String)>) -> Self {
+        Self { entries }
+    }
+
+    /// Parse a mapping from a JSON array of `[repairshopr, display]` pairs
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let pairs: Vec<(String, String)> =
+            serde_json::from_str(json).map_err(|e| format!("Invalid STATUS_MAP: {}", e))?;
+        if pairs.is_empty() {
+            return Err("Invalid STATUS_MAP: no statuses listed".to_string());
+        }
+        Ok(Self::new(pairs))
+    }
+
+    /// Load the mapping from STATUS_MAP, falling back to the defaults
+    pub fn from_env() -> Self {
+        match std::env::var("STATUS_MAP") {
+            Ok(json) => Self::from_json(&json).unwrap_or_else(|e| {
+                eprintln!("Warning: {}; using default status map", e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Display status for a RepairShopr status (unknown statuses pass through unchanged)
+    pub fn to_display(&self, repairshopr_status: &str) -> String {
+        self.entries
+            .iter()
+            .find(|(rs, _)| rs == repairshopr_status)
+            .map(|(_, display)| display.clone())
+            .unwrap_or_else(|| repairshopr_status.to_string())
+    }
+
+    /// RepairShopr status to store for a display status.
+    ///
+    /// Uses the first pair listed for the display status; values that are already
+    /// RepairShopr statuses, or unknown, pass through unchanged.
+    pub fn to_repairshopr(&self, display_status: &str) -> String {
+        self.entries
+            .iter()
+            .find(|(_, display)| display == display_status)
+            .map(|(rs, _)| rs.clone())
+            .unwrap_or_else(|| display_status.to_string())
+    }
+
+    /// Every RepairShopr status that shows as `display_status`
+    pub fn repairshopr_statuses_for(&self, display_status: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(_, display)| display == display_status)
+            .map(|(rs, _)| rs.as_str())
+            .collect()
+    }
+
+    /// Distinct display statuses, in mapping order
+    pub fn display_statuses(&self) -> Vec<&str> {
+        let mut statuses: Vec<&str> = vec![];
+        for (_, display) in &self.entries {
+            if !statuses.contains(&display.as_str()) {
+                statuses.push(display);
+            }
+        }
+        statuses
+    }
+
+    /// Rewrite ticket statuses in a RepairShopr response to display statuses.
+    ///
+    /// Handles `{"ticket": {...}}` and `{"tickets": [...]}` bodies; the original value is
+    /// kept as `repairshopr_status` on each ticket.
+    pub fn translate_response(&self, body: &mut Value) {
+        let apply = |ticket: &mut Value| {
+            if let Some(status) = ticket.get("status").and_then(|s| s.as_str()).map(|s| s.to_string()) {
+                ticket["repairshopr_status"] = Value::String(status.clone());
+                ticket["status"] = Value::String(self.to_display(&status));
+            }
+        };
+
+        if let Some(ticket) = body.get_mut("ticket").filter(|t| t.is_object()) {
+            apply(ticket);
+        }
+        if let Some(tickets) = body.get_mut("tickets").and_then(|t| t.as_array_mut()) {
+            tickets.iter_mut().filter(|t| t.is_object()).for_each(apply);
+        }
+    }
+
+    /// Rewrite a display status in a ticket write body (`{"status"}` or `{"ticket": {"status"}}`)
+    pub fn translate_request(&self, body: &mut Value) {
+        let target = match body.get("ticket") {
+            Some(ticket) if ticket.is_object() => &mut body["ticket"],
+            _ => body,
+        };
+        if let Some(status) = target.get("status").and_then(|s| s.as_str()).map(|s| s.to_string()) {
+            target["status"] = Value::String(self.to_repairshopr(&status));
+        }
+    }
+}
+
+/// Process-wide mapping, loaded from the environment on first use
+pub fn status_map() -> &'static StatusMap {
+    static STATUS_MAP: OnceLock<StatusMap> = OnceLock::new();
+    STATUS_MAP.get_or_init(StatusMap::from_env)
+}
@@ -0,0 +1,382 @@
+//! Inbound RepairShopr webhooks: authentication, typed events and redelivery dedupe
+//!
+//! RepairShopr posts JSON to the URL configured in its Notification Center. A request is
+//! accepted when it carries either an HMAC-SHA256 signature of the raw body
+//! (`X-Signature: sha256=<hex>`) or the shared secret itself (`X-Webhook-Secret` header or
+//! `?secret=` in the URL, since RepairShopr can only be given a URL), both checked against
+//! WEBHOOK_SECRET.
+//!
+//! The event name (`ticket.status_changed`, `customer.updated`, ...) comes from the body's
+//! `event` field or the `?event=` query parameter; the record itself from `attributes`,
+//! the resource key (`ticket`, `customer`, `comment`) or the body root. Deliveries are
+//! identified by the `X-Delivery-Id` header, or the body hash when it is absent, and
+//! remembered in a [`DeliveryStore`] so redeliveries are acknowledged without being
+//! processed again.
+
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::Client as S3Client;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::cache::TtlCache;
+use crate::config::state_bucket;
+use crate::errors::ApiError;
+use crate::repairshopr::{Comment, Customer, Ticket};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How long in-memory dedupe remembers a delivery
+const DELIVERY_MEMORY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Secret shared with RepairShopr for webhook deliveries
+pub fn webhook_secret() -> Result<Vec<u8>, ApiError> {
+    match std::env::var("WEBHOOK_SECRET") {
+        Ok(secret) if secret.len() >= 16 => Ok(secret.into_bytes()),
+        Ok(_) => Err(ApiError::configuration("WEBHOOK_SECRET must be at least 16 characters")),
+        Err(_) => Err(ApiError::configuration("WEBHOOK_SECRET environment variable not set")),
+    }
+}
+
+/// Why a webhook delivery was refused
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebhookError {
+    /// Neither a valid signature nor the shared secret was provided
+    Unauthorized,
+    Malformed(String),
+}
+
+impl From<WebhookError> for ApiError {
+    fn from(error: WebhookError) -> Self {
+        match error {
+            WebhookError::Unauthorized => ApiError::new(
+                401,
+                "INVALID_WEBHOOK_SIGNATURE",
+                "Webhook signature or secret is missing or invalid",
+                "Webhook authentication failed".to_string(),
+            ),
+            WebhookError::Malformed(detail) => {
+                ApiError::new(400, "INVALID_WEBHOOK_PAYLOAD", "Webhook payload could not be read", detail)
+            }
+        }
+    }
+}
+
+/// Compare two byte strings without short-circuiting on the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Hex HMAC-SHA256 of `body`, as RepairShopr-compatible senders sign it
+#[cfg(test)]
+pub fn sign_webhook(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Accept a delivery signed with, or carrying, the shared secret
+pub fn verify_webhook(
+    secret: &[u8],
+    body: &[u8],
+    signature: Option<&str>,
+    provided_secret: Option<&str>,
+) -> Result<(), WebhookError> {
+    if let Some(signature) = signature {
+        let signature = signature.trim();
+        let hex_signature = signature.strip_prefix("sha256=").unwrap_or(signature);
+        let bytes = hex::decode(hex_signature).map_err(|_| WebhookError::Unauthorized)?;
+        let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+        mac.update(body);
+        return mac.verify_slice(&bytes).map_err(|_| WebhookError::Unauthorized);
+    }
+    match provided_secret {
+        Some(provided) if constant_time_eq(provided.as_bytes(), secret) => Ok(()),
+        _ => Err(WebhookError::Unauthorized),
+    }
+}
+
+/// What happened to the record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookAction {
+    Created,
+    Updated,
+    /// Only sent for tickets
+    StatusChanged,
+    Deleted,
+}
+
+impl WebhookAction {
+    fn parse(action: &str) -> Option<Self> {
+        match action {
+            "created" | "create" | "new" => Some(WebhookAction::Created),
+            "updated" | "update" => Some(WebhookAction::Updated),
+            "status_changed" | "status_change" => Some(WebhookAction::StatusChanged),
+            "deleted" | "delete" | "destroyed" => Some(WebhookAction::Deleted),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookAction::Created => "created",
+            WebhookAction::Updated => "updated",
+            WebhookAction::StatusChanged => "status_changed",
+            WebhookAction::Deleted => "deleted",
+        }
+    }
+}
+
+/// A webhook delivery decoded into RepairShopr models
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebhookEvent {
+    Ticket {
+        action: WebhookAction,
+        ticket: Box<Ticket>,
+        /// Status before the change, when the sender includes it
+        previous_status: Option<String>,
+    },
+    Customer {
+        action: WebhookAction,
+        customer: Customer,
+    },
+    Comment {
+        action: WebhookAction,
+        comment: Comment,
+    },
+}
+
+impl WebhookEvent {
+    /// Event name, e.g. `ticket.status_changed`
+    pub fn name(&self) -> String {
+        let (resource, action) = match self {
+            WebhookEvent::Ticket { action, .. } => ("ticket", action),
+            WebhookEvent::Customer { action, .. } => ("customer", action),
+            WebhookEvent::Comment { action, .. } => ("comment", action),
+        };
+        format!("{}.{}", resource, action.as_str())
+    }
+
+    /// Audit target, e.g. `ticket:42`
+    pub fn target(&self) -> String {
+        match self {
+            WebhookEvent::Ticket { ticket, .. } => format!("ticket:{}", ticket.id),
+            WebhookEvent::Customer { customer, .. } => format!("customer:{}", customer.id),
+            WebhookEvent::Comment { comment, .. } => format!("comment:{}", comment.id),
+        }
+    }
+}
+
+/// Decode a delivery; `Ok(None)` for events this backend doesn't handle
+pub fn parse_webhook(body: &[u8], event_hint: Option<&str>) -> Result<Option<WebhookEvent>, WebhookError> {
+    let json: Value =
+        serde_json::from_slice(body).map_err(|e| WebhookError::Malformed(format!("Body is not JSON: {}", e)))?;
+    let name = json
+        .get("event")
+        .or_else(|| json.get("type"))
+        .and_then(|v| v.as_str())
+        .or(event_hint)
+        .ok_or_else(|| WebhookError::Malformed("No event name in body or query".to_string()))?
+        .trim()
+        .to_lowercase();
+    let Some((resource, action)) = name.split_once('.') else {
+        return Ok(None);
+    };
+    let Some(action) = WebhookAction::parse(action) else {
+        return Ok(None);
+    };
+
+    let record = json
+        .get("attributes")
+        .or_else(|| json.get(resource))
+        .filter(|v| v.is_object())
+        .unwrap_or(&json)
+        .clone();
+    let decode_error = |e: serde_json::Error| WebhookError::Malformed(format!("Invalid {} record: {}", resource, e));
+
+    let event = match (resource, action) {
+        ("ticket", action) => WebhookEvent::Ticket {
+            action,
+            previous_status: json
+                .get("previous_status")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string()),
+            ticket: Box::new(serde_json::from_value(record).map_err(decode_error)?),
+        },
+        ("customer", WebhookAction::StatusChanged) | ("comment", WebhookAction::StatusChanged) => return Ok(None),
+        ("customer", action) => WebhookEvent::Customer {
+            action,
+            customer: serde_json::from_value(record).map_err(decode_error)?,
+        },
+        ("comment", action) => WebhookEvent::Comment {
+            action,
+            comment: serde_json::from_value(record).map_err(decode_error)?,
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(event))
+}
+
+/// Stable id for a delivery: the sender's delivery id, or the body itself, hashed
+pub fn delivery_id(header: Option<&str>, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    match header.map(str::trim).filter(|h| !h.is_empty()) {
+        Some(id) => hasher.update(id.as_bytes()),
+        None => hasher.update(body),
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Remembers which deliveries have been processed
+#[async_trait]
+pub trait DeliveryStore: Send + Sync {
+    /// Claim `delivery_id`, returning false if it was already claimed
+    async fn first_delivery(&self, delivery_id: &str) -> Result<bool, String>;
+    /// Release a claim whose processing failed, so a redelivery is processed again
+    async fn forget_delivery(&self, delivery_id: &str) -> Result<(), String>;
+    /// Whether `consumer` already handled the delivery, in an attempt that failed elsewhere
+    async fn consumer_done(&self, delivery_id: &str, consumer: &str) -> Result<bool, String>;
+    /// Record that `consumer` handled the delivery, so a retry skips it
+    async fn mark_consumer_done(&self, delivery_id: &str, consumer: &str) -> Result<(), String>;
+}
+
+/// Deliveries remembered in process memory
+#[derive(Debug, Clone)]
+pub struct InMemoryDeliveryStore {
+    seen: Arc<TtlCache<String, ()>>,
+}
+
+impl Default for InMemoryDeliveryStore {
+    fn default() -> Self {
+        Self {
+            seen: Arc::new(TtlCache::new(DELIVERY_MEMORY)),
+        }
+    }
+}
+
+impl InMemoryDeliveryStore {
+    /// Store shared by every request this instance handles
+    pub fn shared() -> Self {
+        static SHARED: LazyLock<InMemoryDeliveryStore> = LazyLock::new(InMemoryDeliveryStore::default);
+        SHARED.clone()
+    }
+}
+
+#[async_trait]
+impl DeliveryStore for InMemoryDeliveryStore {
+    async fn first_delivery(&self, delivery_id: &str) -> Result<bool, String> {
+        let key = delivery_id.to_string();
+        if self.seen.get(&key).is_some() {
+            return Ok(false);
+        }
+        self.seen.insert(key, ());
+        Ok(true)
+    }
+
+    async fn forget_delivery(&self, delivery_id: &str) -> Result<(), String> {
+        self.seen.remove(&delivery_id.to_string());
+        Ok(())
+    }
+
+    async fn consumer_done(&self, delivery_id: &str, consumer: &str) -> Result<bool, String> {
+        Ok(self.seen.get(&format!("{}/{}", delivery_id, consumer)).is_some())
+    }
+
+    async fn mark_consumer_done(&self, delivery_id: &str, consumer: &str) -> Result<(), String> {
+        self.seen.insert(format!("{}/{}", delivery_id, consumer), ());
+        Ok(())
+    }
+}
+
+/// Deliveries recorded as S3 objects, created only if absent so concurrent
+/// redeliveries can't both win
+#[derive(Debug, Clone)]
+pub struct S3DeliveryStore {
+    client: S3Client,
+    bucket: String,
+}
+
+impl S3DeliveryStore {
+    pub fn new(client: S3Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+
+    fn key(delivery_id: &str) -> String {
+        format!("webhooks/deliveries/{}", delivery_id)
+    }
+
+    fn consumer_key(delivery_id: &str, consumer: &str) -> String {
+        format!("webhooks/consumers/{}/{}", delivery_id, consumer)
+    }
+}
+
+#[async_trait]
+impl DeliveryStore for S3DeliveryStore {
+    async fn first_delivery(&self, delivery_id: &str) -> Result<bool, String> {
+        let result = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(Self::key(delivery_id))
+            .if_none_match("*")
+            .body(ByteStream::from(chrono::Utc::now().to_rfc3339().into_bytes()))
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if e.code() == Some("PreconditionFailed") => Ok(false),
+            Err(e) => Err(format!("Failed to record webhook delivery {}: {:?}", delivery_id, e)),
+        }
+    }
+
+    async fn forget_delivery(&self, delivery_id: &str) -> Result<(), String> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(Self::key(delivery_id))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to forget webhook delivery {}: {:?}", delivery_id, e))
+    }
+
+    async fn consumer_done(&self, delivery_id: &str, consumer: &str) -> Result<bool, String> {
+        let result = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(Self::consumer_key(delivery_id, consumer))
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(false),
+            Err(e) => Err(format!("Failed to read webhook delivery {} progress: {:?}", delivery_id, e)),
+        }
+    }
+
+    async fn mark_consumer_done(&self, delivery_id: &str, consumer: &str) -> Result<(), String> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(Self::consumer_key(delivery_id, consumer))
+            .body(ByteStream::from(chrono::Utc::now().to_rfc3339().into_bytes()))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to record webhook delivery {} progress: {:?}", delivery_id, e))
+    }
+}
+
+/// Pick the delivery store for this deployment
+pub fn delivery_store_from_env(s3_client: &S3Client) -> Box<dyn DeliveryStore> {
+    match state_bucket() {
+        Some(bucket) => Box::new(S3DeliveryStore::new(s3_client.clone(), bucket)),
+        _ => Box::new(InMemoryDeliveryStore::shared()),
+    }
+}